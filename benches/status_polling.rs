@@ -0,0 +1,42 @@
+use apalis::prelude::*;
+use apalis_sql::sqlite::{SqlitePool, SqliteStorage};
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TestJob;
+
+fn status_polling(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (storage, task_id) = rt.block_on(async {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        SqliteStorage::setup(&pool).await.unwrap();
+        let mut storage = SqliteStorage::<TestJob>::new(pool);
+        let parts = storage.push(TestJob).await.unwrap();
+        (storage, parts.task_id)
+    });
+
+    let mut group = c.benchmark_group("sqlite_status_polling");
+    group.bench_function("cached", |b| {
+        b.to_async(&rt)
+            .iter(|| async { storage.status(&task_id).await.unwrap() })
+    });
+    group.bench_function("uncached", |b| {
+        b.to_async(&rt).iter(|| async {
+            // What every poll paid before: render the sql and prepare it again
+            let query = format!("SELECT {} FROM {} WHERE {} = ?1", "status", "Jobs", "id");
+            let status: Option<String> = apalis_sql::sqlx::query_scalar(&query)
+                .persistent(false)
+                .bind(task_id.to_string())
+                .fetch_optional(storage.pool())
+                .await
+                .unwrap();
+            status
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, status_polling);
+criterion_main!(benches);
@@ -0,0 +1,550 @@
+use std::{fmt, marker::PhantomData, sync::Arc, time::Duration};
+
+use apalis_core::{
+    backend::Backend,
+    codec::{json::JsonCodec, Codec},
+    error::{BoxDynError, Error},
+    layers::{Ack, AckLayer},
+    mq::MessageQueue,
+    poller::{controller::Controller, stream::BackendStream, Poller},
+    request::{Request, RequestStream},
+    response::Response,
+    service_fn::FromRequest,
+    task::attempt::Attempt,
+    worker::{Context, Worker, WorkerId},
+};
+use futures::{
+    future::{self, Either},
+    stream, FutureExt, Stream, StreamExt,
+};
+use lapin::{
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicGetOptions, BasicNackOptions,
+        BasicPublishOptions, BasicQosOptions, QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable, LongString},
+    BasicProperties, Channel, Consumer,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The header quorum queues use to count deliveries of a message
+const DELIVERY_COUNT_HEADER: &str = "x-delivery-count";
+
+/// The context for a job delivered by the broker
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct AmqpContext {
+    #[serde(skip)]
+    delivery_tag: u64,
+}
+
+impl AmqpContext {
+    /// The tag the broker delivered the message with, scoped to the consuming channel
+    pub fn delivery_tag(&self) -> u64 {
+        self.delivery_tag
+    }
+}
+
+impl<Req> FromRequest<Request<Req, AmqpContext>> for AmqpContext {
+    fn from_request(req: &Request<Req, AmqpContext>) -> Result<Self, Error> {
+        Ok(req.parts.context.clone())
+    }
+}
+
+/// Errors that can occur while talking to the broker
+#[derive(thiserror::Error, Debug)]
+pub enum AmqpError {
+    /// The broker or the connection to it failed
+    #[error("AMQP error: {0}")]
+    Amqp(#[from] lapin::Error),
+
+    /// The job could not be encoded or decoded
+    #[error("Could not encode or decode the job: {0}")]
+    Codec(BoxDynError),
+}
+
+/// Config for an [AmqpBackend]
+#[derive(Clone, Debug)]
+pub struct Config {
+    queue: String,
+    poll_interval: Duration,
+    buffer_size: usize,
+    max_attempts: usize,
+    dead_letter_exchange: Option<String>,
+    dead_letter_routing_key: Option<String>,
+}
+
+impl Config {
+    /// Create a config for the queue named `queue`
+    pub fn new(queue: impl Into<String>) -> Self {
+        Self {
+            queue: queue.into(),
+            poll_interval: Duration::from_secs(1),
+            buffer_size: 10,
+            max_attempts: 25,
+            dead_letter_exchange: None,
+            dead_letter_routing_key: None,
+        }
+    }
+
+    /// Get the name of the queue
+    pub fn get_queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// Get how long a worker waits for a delivery before it is reported idle
+    pub fn get_poll_interval(&self) -> &Duration {
+        &self.poll_interval
+    }
+
+    /// Get the prefetch count
+    pub fn get_buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Get the max attempts
+    pub fn get_max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Get the dead-letter exchange
+    pub fn get_dead_letter_exchange(&self) -> Option<&str> {
+        self.dead_letter_exchange.as_deref()
+    }
+
+    /// Get the routing key of dead-lettered jobs
+    pub fn get_dead_letter_routing_key(&self) -> Option<&str> {
+        self.dead_letter_routing_key.as_deref()
+    }
+
+    /// Set how long a worker waits for a delivery before it is reported idle
+    pub fn set_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set the prefetch count, how many unacknowledged jobs a worker holds at once
+    pub fn set_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Set the max attempts
+    pub fn set_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Route jobs that are aborted or run out of attempts to `exchange`
+    ///
+    /// Without it the broker drops them.
+    pub fn set_dead_letter_exchange(mut self, exchange: impl Into<String>) -> Self {
+        self.dead_letter_exchange = Some(exchange.into());
+        self
+    }
+
+    /// Publish dead-lettered jobs with `routing_key` instead of their original one
+    pub fn set_dead_letter_routing_key(mut self, routing_key: impl Into<String>) -> Self {
+        self.dead_letter_routing_key = Some(routing_key.into());
+        self
+    }
+
+    /// The arguments the queue is declared with: a quorum queue with the dead-letter settings
+    pub fn queue_arguments(&self) -> FieldTable {
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-queue-type".into(),
+            AMQPValue::LongString(LongString::from("quorum")),
+        );
+        if let Some(exchange) = &self.dead_letter_exchange {
+            arguments.insert(
+                "x-dead-letter-exchange".into(),
+                AMQPValue::LongString(exchange.as_str().into()),
+            );
+        }
+        if let Some(routing_key) = &self.dead_letter_routing_key {
+            arguments.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(routing_key.as_str().into()),
+            );
+        }
+        arguments
+    }
+}
+
+/// Represents a [Backend] that consumes an AMQP queue
+pub struct AmqpBackend<T, C = JsonCodec<Vec<u8>>> {
+    channel: Channel,
+    config: Config,
+    controller: Controller,
+    job_type: PhantomData<T>,
+    codec: PhantomData<C>,
+}
+
+impl<T, C> fmt::Debug for AmqpBackend<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AmqpBackend")
+            .field("channel", &self.channel.id())
+            .field("config", &self.config)
+            .field("job_type", &std::any::type_name::<T>())
+            .field("codec", &std::any::type_name::<C>())
+            .finish()
+    }
+}
+
+impl<T, C> Clone for AmqpBackend<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            config: self.config.clone(),
+            controller: self.controller.clone(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<T, C> AmqpBackend<T, C> {
+    /// Create a backend for the queue in `config`
+    ///
+    /// Deliveries are acked on the channel they arrived on, so clones share `channel`.
+    pub fn new(channel: Channel, config: Config) -> Self {
+        Self {
+            channel,
+            config,
+            controller: Controller::new(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// Get the config used by the backend
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Declare the queue as a durable quorum queue with the configured dead-letter exchange
+    pub async fn declare_queue(&self) -> Result<(), AmqpError> {
+        self.channel
+            .queue_declare(
+                &self.config.queue,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                self.config.queue_arguments(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Put the job back on the queue for another attempt
+    pub async fn reschedule(&self, ctx: &AmqpContext) -> Result<(), AmqpError> {
+        self.nack(ctx, true).await
+    }
+
+    /// Reject the job to the dead-letter exchange, or drop it if there is none
+    pub async fn dead_letter(&self, ctx: &AmqpContext) -> Result<(), AmqpError> {
+        self.nack(ctx, false).await
+    }
+
+    async fn nack(&self, ctx: &AmqpContext, requeue: bool) -> Result<(), AmqpError> {
+        self.channel
+            .basic_nack(
+                ctx.delivery_tag,
+                BasicNackOptions {
+                    requeue,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl<T, C> AmqpBackend<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    C: Codec<Compact = Vec<u8>>,
+{
+    /// Publish a job to the queue
+    pub async fn push(&self, job: T) -> Result<(), AmqpError> {
+        let req: Request<T, AmqpContext> = Request::new(job);
+        let payload = C::encode(&req).map_err(|e| AmqpError::Codec(e.into()))?;
+        self.channel
+            .basic_publish(
+                "",
+                &self.config.queue,
+                BasicPublishOptions::default(),
+                &payload,
+                BasicProperties::default().with_delivery_mode(2),
+            )
+            .await?
+            .await?;
+        Ok(())
+    }
+
+    /// Consume the queue as `worker_id`, holding at most `buffer_size` unacknowledged jobs
+    ///
+    /// Deliveries are pushed by the broker rather than polled, so `interval` only decides how long
+    /// the stream waits for one before yielding `None` to report the worker idle.
+    pub fn consume(
+        &self,
+        worker_id: &WorkerId,
+        interval: Duration,
+        buffer_size: usize,
+    ) -> impl Stream<Item = Result<Option<Request<T, AmqpContext>>, AmqpError>> + Send + 'static
+    where
+        C: Send + 'static,
+    {
+        let channel = self.channel.clone();
+        let queue = self.config.queue.clone();
+        let consumer_tag = worker_id.to_string();
+        let consumer = async move {
+            let prefetch = buffer_size.try_into().unwrap_or(u16::MAX);
+            channel
+                .basic_qos(prefetch, BasicQosOptions::default())
+                .await?;
+            channel
+                .basic_consume(
+                    &queue,
+                    &consumer_tag,
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+        };
+        stream::once(consumer)
+            .map(move |consumer| match consumer {
+                Ok(consumer) => deliveries::<T, C>(consumer, interval).left_stream(),
+                Err(e) => stream::once(future::ready(Err(e.into()))).right_stream(),
+            })
+            .flatten()
+    }
+}
+
+/// Yields decoded deliveries, or `None` when none arrives within `interval`
+fn deliveries<T, C>(
+    consumer: Consumer,
+    interval: Duration,
+) -> impl Stream<Item = Result<Option<Request<T, AmqpContext>>, AmqpError>>
+where
+    T: DeserializeOwned,
+    C: Codec<Compact = Vec<u8>>,
+{
+    stream::unfold(consumer, move |mut consumer| async move {
+        let next = consumer.next();
+        let idle = apalis_core::sleep(interval).boxed();
+        match future::select(next, idle).await {
+            Either::Left((Some(delivery), _)) => {
+                let req = delivery.map_err(AmqpError::from).and_then(|delivery| {
+                    decode_message::<T, C>(
+                        delivery.data,
+                        delivery.delivery_tag,
+                        delivery.redelivered,
+                        &delivery.properties,
+                    )
+                });
+                Some((req.map(Some), consumer))
+            }
+            Either::Left((None, _)) => None,
+            Either::Right(_) => Some((Ok(None), consumer)),
+        }
+    })
+}
+
+/// Decodes a delivery, taking the attempt from how often the broker delivered it
+fn decode_message<T, C>(
+    data: Vec<u8>,
+    delivery_tag: u64,
+    redelivered: bool,
+    properties: &BasicProperties,
+) -> Result<Request<T, AmqpContext>, AmqpError>
+where
+    T: DeserializeOwned,
+    C: Codec<Compact = Vec<u8>>,
+{
+    let mut req: Request<T, AmqpContext> =
+        C::decode(data).map_err(|e| AmqpError::Codec(e.into()))?;
+    req.parts.attempt = Attempt::new_with_value(attempt(redelivered, properties));
+    req.parts.context = AmqpContext { delivery_tag };
+    Ok(req)
+}
+
+/// The attempt a delivery is on, counting this one
+///
+/// Classic queues only flag redeliveries, so there the count stops at two.
+fn attempt(redelivered: bool, properties: &BasicProperties) -> usize {
+    let delivery_count = properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(DELIVERY_COUNT_HEADER))
+        .and_then(|count| {
+            count
+                .as_long_long_int()
+                .or_else(|| count.as_long_int().map(i64::from))
+                .or_else(|| count.as_long_uint().map(i64::from))
+        });
+    match delivery_count {
+        Some(count) => usize::try_from(count).unwrap_or_default() + 1,
+        None if redelivered => 2,
+        None => 1,
+    }
+}
+
+impl<T, C, Res> Backend<Request<T, AmqpContext>, Res> for AmqpBackend<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    C: Codec<Compact = Vec<u8>> + Send + Sync + 'static,
+    Res: Send + Sync,
+{
+    type Stream = BackendStream<RequestStream<Request<T, AmqpContext>>>;
+
+    type Layer = AckLayer<Self, T, AmqpContext, Res>;
+
+    fn poll<Svc>(self, worker: &Worker<Context>) -> Poller<Self::Stream, Self::Layer> {
+        let stream = self
+            .consume(
+                worker.id(),
+                self.config.poll_interval,
+                self.config.buffer_size,
+            )
+            .map(|res| res.map_err(|e| Error::SourceError(Arc::new(Box::new(e)))))
+            .boxed();
+        Poller::new_with_layer(
+            BackendStream::new(stream, self.controller.clone()),
+            future::pending(),
+            AckLayer::new(self),
+        )
+    }
+}
+
+impl<T, C, Res> Ack<T, Res> for AmqpBackend<T, C>
+where
+    T: Send + Sync,
+    C: Send + Sync,
+    Res: Send + Sync,
+{
+    type Context = AmqpContext;
+
+    type AckError = AmqpError;
+
+    async fn ack(&mut self, ctx: &AmqpContext, res: &Response<Res>) -> Result<(), AmqpError> {
+        match &res.inner {
+            Ok(_) => {
+                self.channel
+                    .basic_ack(ctx.delivery_tag, BasicAckOptions::default())
+                    .await?;
+                Ok(())
+            }
+            Err(Error::Abort(_)) => self.dead_letter(ctx).await,
+            Err(_) if res.attempt.current() >= self.config.max_attempts => {
+                self.dead_letter(ctx).await
+            }
+            Err(_) => self.reschedule(ctx).await,
+        }
+    }
+}
+
+impl<T, C> MessageQueue<T> for AmqpBackend<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    C: Codec<Compact = Vec<u8>> + Send + Sync,
+{
+    type Error = AmqpError;
+
+    async fn enqueue(&mut self, message: T) -> Result<(), AmqpError> {
+        self.push(message).await
+    }
+
+    async fn dequeue(&mut self) -> Result<Option<T>, AmqpError> {
+        let Some(message) = self
+            .channel
+            .basic_get(&self.config.queue, BasicGetOptions::default())
+            .await?
+        else {
+            return Ok(None);
+        };
+        let delivery = message.delivery;
+        let req = decode_message::<T, C>(
+            delivery.data,
+            delivery.delivery_tag,
+            delivery.redelivered,
+            &delivery.properties,
+        )?;
+        self.channel
+            .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+            .await?;
+        Ok(Some(req.args))
+    }
+
+    async fn size(&mut self) -> Result<usize, AmqpError> {
+        let queue = self
+            .channel
+            .queue_declare(
+                &self.config.queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(queue.message_count() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Email {
+        to: String,
+    }
+
+    #[test]
+    fn test_decode_message_counts_deliveries() {
+        let req: Request<Email, AmqpContext> = Request::new(Email {
+            to: "test@example.com".to_owned(),
+        });
+        let data = JsonCodec::<Vec<u8>>::encode(&req).unwrap();
+        let mut headers = FieldTable::default();
+        headers.insert(DELIVERY_COUNT_HEADER.into(), AMQPValue::LongLongInt(2));
+        let properties = BasicProperties::default().with_headers(headers);
+
+        let decoded =
+            decode_message::<Email, JsonCodec<Vec<u8>>>(data, 7, true, &properties).unwrap();
+        assert_eq!(decoded.args, req.args);
+        assert_eq!(decoded.parts.task_id, req.parts.task_id);
+        assert_eq!(decoded.parts.attempt.current(), 3);
+        assert_eq!(decoded.parts.context.delivery_tag(), 7);
+    }
+
+    #[test]
+    fn test_attempt_falls_back_to_the_redelivered_flag() {
+        let properties = BasicProperties::default();
+        assert_eq!(attempt(false, &properties), 1);
+        assert_eq!(attempt(true, &properties), 2);
+    }
+
+    #[test]
+    fn test_queue_arguments_carry_dead_letter_config() {
+        let arguments = Config::new("emails")
+            .set_dead_letter_exchange("emails-dlx")
+            .set_dead_letter_routing_key("dead")
+            .queue_arguments();
+        let get = |key: &str| {
+            arguments
+                .inner()
+                .get(key)
+                .and_then(AMQPValue::as_long_string)
+                .map(ToString::to_string)
+        };
+        assert_eq!(get("x-queue-type").as_deref(), Some("quorum"));
+        assert_eq!(get("x-dead-letter-exchange").as_deref(), Some("emails-dlx"));
+        assert_eq!(get("x-dead-letter-routing-key").as_deref(), Some("dead"));
+
+        let arguments = Config::new("emails").queue_arguments();
+        assert!(!arguments.contains_key("x-dead-letter-exchange"));
+    }
+}
@@ -0,0 +1,55 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! apalis backend using RabbitMQ, or any AMQP 0.9.1 broker
+//!
+//! Workers consume the queue with `basic.consume`, and the prefetch count caps how many
+//! unacknowledged jobs a worker holds. Successful jobs are acked, failed ones are
+//! nacked back onto the queue for another attempt, and jobs that are aborted or run out of
+//! attempts are rejected to the queue's dead-letter exchange.
+//!
+//! Attempts are read from the `x-delivery-count` header that quorum queues keep,
+//! so [`AmqpBackend::declare_queue`] declares a quorum queue.
+//! ```rust,no_run
+//! use apalis::prelude::*;
+//! use apalis_amqp::{AmqpBackend, Config};
+//! use lapin::{Connection, ConnectionProperties};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, Serialize)]
+//! struct Email {
+//!     to: String,
+//! }
+//!
+//! async fn send_email(job: Email) -> Result<(), Error> {
+//!     Ok(())
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let amqp_url = std::env::var("AMQP_URL").expect("Missing env variable AMQP_URL");
+//!     let conn = Connection::connect(&amqp_url, ConnectionProperties::default())
+//!         .await
+//!         .expect("Could not connect");
+//!     let channel = conn.create_channel().await.unwrap();
+//!     let config = Config::new("emails").set_dead_letter_exchange("emails-dlx");
+//!     let backend: AmqpBackend<Email> = AmqpBackend::new(channel, config);
+//!     backend.declare_queue().await.unwrap();
+//!     let worker = WorkerBuilder::new("tasty-guava")
+//!         .backend(backend)
+//!         .build_fn(send_email);
+//!
+//!     worker.run().await;
+//! }
+//! ```
+
+mod backend;
+pub use backend::AmqpBackend;
+pub use backend::AmqpContext;
+pub use backend::AmqpError;
+pub use backend::Config;
+pub use lapin::Channel;
@@ -1,4 +1,4 @@
-use std::{any::type_name, future::Future};
+use std::{any::type_name, future::Future, time::SystemTime};
 
 use futures::Stream;
 use serde::{Deserialize, Serialize};
@@ -53,10 +53,18 @@ where
         status: &State,
         page: i32,
     ) -> impl Future<Output = Result<Vec<Self::Request>, Self::Error>> + Send;
+
+    /// Returns the current time according to the backend
+    ///
+    /// Use this rather than the local clock when computing times the backend compares against, eg when scheduling,
+    /// so clock skew between machines doesn't shift them. Defaults to the local clock.
+    fn now(&self) -> impl Future<Output = Result<SystemTime, Self::Error>> + Send {
+        async { Ok(SystemTime::now()) }
+    }
 }
 
 /// Represents the current statistics of a backend
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct Stat {
     /// Represents pending tasks
     pub pending: usize,
@@ -64,6 +72,8 @@ pub struct Stat {
     pub running: usize,
     /// Represents dead tasks
     pub dead: usize,
+    /// Represents failed tasks waiting for another attempt
+    pub retry: usize,
     /// Represents failed tasks
     pub failed: usize,
     /// Represents successful tasks
@@ -77,6 +87,12 @@ pub struct WorkerState {
     pub r#type: String,
     /// The type of job stream
     pub source: String,
+    /// How many jobs the worker runs at once, if the backend knows
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// How many jobs the worker was running at its last heartbeat, if the backend knows
+    #[serde(default)]
+    pub in_flight: Option<usize>,
     // TODO: // The layers that were loaded for worker.
     // TODO: // pub layers: Vec<Layer>,
     // TODO: // last_seen: Timestamp,
@@ -87,6 +103,15 @@ impl WorkerState {
         Self {
             r#type,
             source: type_name::<S>().to_string(),
+            concurrency: None,
+            in_flight: None,
         }
     }
+
+    /// Attach the load last reported by the worker
+    pub fn with_load(mut self, concurrency: Option<usize>, in_flight: Option<usize>) -> Self {
+        self.concurrency = concurrency;
+        self.in_flight = in_flight;
+        self
+    }
 }
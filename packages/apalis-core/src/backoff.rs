@@ -0,0 +1,354 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use tower::{Layer, Service};
+
+use crate::{
+    error::{BoxDynError, Error},
+    request::Request,
+    storage::Storage,
+};
+
+/// Computes how long to wait before the next attempt of something that failed
+///
+/// Shared by everything that retries, so the same policies can be reused everywhere.
+pub trait Backoff {
+    /// The delay before `attempt`, counting from 1 for the first retry
+    fn next_delay(&mut self, attempt: u32) -> Duration;
+}
+
+impl<F: FnMut(u32) -> Duration> Backoff for F {
+    fn next_delay(&mut self, attempt: u32) -> Duration {
+        self(attempt)
+    }
+}
+
+/// Always waits the same delay
+#[derive(Debug, Clone, Copy)]
+pub struct FixedBackoff {
+    delay: Duration,
+}
+
+impl FixedBackoff {
+    /// Build a backoff waiting `delay` before every attempt
+    pub fn new(delay: Duration) -> Self {
+        Self { delay }
+    }
+}
+
+impl Backoff for FixedBackoff {
+    fn next_delay(&mut self, _attempt: u32) -> Duration {
+        self.delay
+    }
+}
+
+/// Multiplies the delay by `factor` on every attempt, up to `max`
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    factor: u32,
+    max: Duration,
+}
+
+impl ExponentialBackoff {
+    /// Build a backoff starting at `initial` and doubling on every attempt, up to `max`
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            factor: 2,
+            max,
+        }
+    }
+
+    /// Grow the delay by `factor` instead of doubling it
+    pub fn with_factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn next_delay(&mut self, attempt: u32) -> Duration {
+        self.factor
+            .checked_pow(attempt.saturating_sub(1))
+            .and_then(|growth| self.initial.checked_mul(growth))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// Spreads the delays of another backoff randomly by up to `jitter` of their length either way
+///
+/// Keeps many clients that failed together from all retrying at the same moment.
+#[derive(Debug, Clone)]
+pub struct JitteredBackoff<B> {
+    inner: B,
+    jitter: f64,
+    state: u64,
+}
+
+impl<B> JitteredBackoff<B> {
+    /// Build a backoff spreading the delays of `inner` by up to `jitter`, a fraction between 0 and 1
+    pub fn new(inner: B, jitter: f64) -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Self {
+            inner,
+            jitter: jitter.clamp(0.0, 1.0),
+            // xorshift must not start from zero
+            state: hasher.finish() | 1,
+        }
+    }
+
+    /// A random number in `[0, 1)`
+    fn next_random(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl<B: Backoff> Backoff for JitteredBackoff<B> {
+    fn next_delay(&mut self, attempt: u32) -> Duration {
+        let delay = self.inner.next_delay(attempt);
+        let spread = 1.0 - self.jitter + 2.0 * self.jitter * self.next_random();
+        delay.mul_f64(spread)
+    }
+}
+
+/// Reschedules failed jobs through their storage, waiting longer after every attempt
+///
+/// Without it a failed job is eligible again straight away. Each worker gets its own layer,
+/// so the backoff and attempt limit are set per job type:
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use apalis_core::backoff::{BackoffLayer, ExponentialBackoff, JitteredBackoff};
+/// # use apalis_core::builder::{WorkerBuilder, WorkerFactoryFn};
+/// # use apalis_core::error::Error;
+/// # use apalis_core::memory::MemoryStorage;
+/// async fn send_email(email: String) -> Result<(), Error> {
+///     Ok(())
+/// }
+///
+/// let storage = MemoryStorage::<String>::new();
+/// let backoff = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(300));
+/// let worker = WorkerBuilder::new("tasty-fig")
+///     .layer(BackoffLayer::new(storage.clone(), JitteredBackoff::new(backoff, 0.2)).with_max_attempts(10))
+///     .backend(storage)
+///     .build_fn(send_email);
+/// ```
+///
+/// Aborted jobs and jobs on their last attempt are left alone, as is the result of every attempt,
+/// which still reaches the backend as usual.
+#[derive(Debug)]
+pub struct BackoffLayer<S, B> {
+    storage: S,
+    backoff: Arc<Mutex<B>>,
+    max_attempts: usize,
+}
+
+impl<S: Clone, B> Clone for BackoffLayer<S, B> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            backoff: self.backoff.clone(),
+            max_attempts: self.max_attempts,
+        }
+    }
+}
+
+impl<S, B> BackoffLayer<S, B> {
+    /// Reschedule failed jobs into `storage`, after the delays of `backoff`
+    pub fn new(storage: S, backoff: B) -> Self {
+        Self {
+            storage,
+            backoff: Arc::new(Mutex::new(backoff)),
+            max_attempts: 25,
+        }
+    }
+
+    /// Stop rescheduling once a job has been attempted `max_attempts` times
+    ///
+    /// Defaults to 25
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+impl<S: Clone, B, Svc> Layer<Svc> for BackoffLayer<S, B> {
+    type Service = BackoffService<Svc, S, B>;
+
+    fn layer(&self, service: Svc) -> Self::Service {
+        BackoffService {
+            service,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service built by a [`BackoffLayer`]
+#[derive(Debug)]
+pub struct BackoffService<Svc, S, B> {
+    service: Svc,
+    layer: BackoffLayer<S, B>,
+}
+
+impl<Svc: Clone, S: Clone, B> Clone for BackoffService<Svc, S, B> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<Svc, S, B, Req, Ctx> Service<Request<Req, Ctx>> for BackoffService<Svc, S, B>
+where
+    Svc: Service<Request<Req, Ctx>>,
+    Svc::Error: Into<BoxDynError>,
+    Svc::Future: Send + 'static,
+    S: Storage<Job = Req, Context = Ctx> + Clone + Send + 'static,
+    S::Error: std::fmt::Display,
+    B: Backoff + Send + 'static,
+    Req: Clone + Send + 'static,
+    Ctx: Clone + Send + 'static,
+{
+    type Response = Svc::Response;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service
+            .poll_ready(cx)
+            .map_err(|e| Error::Failed(Arc::new(e.into())))
+    }
+
+    fn call(&mut self, request: Request<Req, Ctx>) -> Self::Future {
+        let attempt = request.parts.attempt.current();
+        let retry = (attempt < self.layer.max_attempts).then(|| request.clone());
+        let mut storage = self.layer.storage.clone();
+        let backoff = self.layer.backoff.clone();
+        let fut = self.service.call(request);
+        async move {
+            let err = match fut.await {
+                Ok(res) => return Ok(res),
+                Err(err) => {
+                    let e: BoxDynError = err.into();
+                    // Keep the error as is if it is already of type `Error`
+                    match e.downcast_ref::<Error>() {
+                        Some(e) => e.clone(),
+                        None => Error::Failed(Arc::new(e)),
+                    }
+                }
+            };
+            let request = match (&err, retry) {
+                (Error::Abort(_), _) | (_, None) => return Err(err),
+                (_, Some(request)) => request,
+            };
+            let delay = backoff
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .next_delay(u32::try_from(attempt.max(1)).unwrap_or(u32::MAX));
+            match storage.reschedule(request, delay).await {
+                Ok(()) => Err(err),
+                Err(e) => Err(Error::Failed(Arc::new(
+                    format!("{err}, and the job could not be rescheduled: {e}").into(),
+                ))),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        memory::MemoryStorage, request::State, service_fn::service_fn, task::attempt::Attempt,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_fixed_backoff() {
+        let mut backoff = FixedBackoff::new(Duration::from_secs(3));
+        let delays: Vec<_> = (1..=3).map(|a| backoff.next_delay(a)).collect();
+        assert_eq!(delays, vec![Duration::from_secs(3); 3]);
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let mut backoff = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<_> = (1..=6).map(|a| backoff.next_delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+        // Overflowing the growth is capped too
+        assert_eq!(backoff.next_delay(200), Duration::from_secs(10));
+
+        let mut backoff = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(100))
+            .with_factor(3);
+        let delays: Vec<_> = (1..=4).map(|a| backoff.next_delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 3, 9, 27]);
+    }
+
+    #[test]
+    fn test_jittered_backoff_stays_within_bounds() {
+        let mut backoff = JitteredBackoff::new(FixedBackoff::new(Duration::from_secs(10)), 0.2);
+        let delays: Vec<_> = (1..=1000).map(|a| backoff.next_delay(a)).collect();
+        for delay in &delays {
+            assert!(*delay >= Duration::from_secs(8) && *delay <= Duration::from_secs(12));
+        }
+        // The delays really are spread
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        let mut backoff = JitteredBackoff::new(FixedBackoff::new(Duration::from_secs(10)), 0.0);
+        assert_eq!(backoff.next_delay(1), Duration::from_secs(10));
+    }
+
+    async fn fail_with(error: Error, attempt: usize) -> (Option<State>, Result<(), Error>) {
+        let mut storage = MemoryStorage::<u32>::new();
+        let parts = storage.push(7).await.unwrap();
+        let mut request = storage.fetch_by_id(&parts.task_id).await.unwrap().unwrap();
+        request.parts.attempt = Attempt::new_with_value(attempt);
+        let layer = BackoffLayer::new(storage.clone(), FixedBackoff::new(Duration::from_secs(60)))
+            .with_max_attempts(3);
+        let mut service = layer.layer(service_fn(move |_: u32| {
+            let error = error.clone();
+            async move { Err::<(), _>(error) }
+        }));
+        let res = service.call(request).await;
+        (storage.status(&parts.task_id), res)
+    }
+
+    #[tokio::test]
+    async fn test_backoff_layer_reschedules_failed_jobs() {
+        let (status, res) = fail_with(Error::Failed(Arc::new("smtp down".into())), 1).await;
+        assert_eq!(status, Some(State::Scheduled));
+        assert!(res.unwrap_err().to_string().contains("smtp down"));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_layer_skips_aborted_and_exhausted_jobs() {
+        let (status, _) = fail_with(Error::Abort(Arc::new("bad input".into())), 1).await;
+        assert_eq!(status, Some(State::Pending));
+
+        let (status, _) = fail_with(Error::Failed(Arc::new("smtp down".into())), 3).await;
+        assert_eq!(status, Some(State::Pending));
+    }
+
+    #[test]
+    fn test_backoff_layer_shares_its_backoff() {
+        // Clones of a jittered backoff would all draw the same delays
+        let layer = BackoffLayer::new((), FixedBackoff::new(Duration::from_secs(1)));
+        let first = layer.layer(());
+        let second = first.clone();
+        assert!(Arc::ptr_eq(&first.layer.backoff, &second.layer.backoff));
+        assert!(Arc::ptr_eq(&layer.backoff, &layer.layer(()).layer.backoff));
+    }
+}
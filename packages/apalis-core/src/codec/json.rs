@@ -4,6 +4,17 @@ use crate::codec::Codec;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+/// Upgrades payloads written by an older version of a job before they are decoded
+///
+/// Useful when a job gains a field that old queued payloads lack.
+/// Storages that support it call [`Migrate::migrate`] on every payload they read.
+pub trait Migrate {
+    /// Rewrite an old payload into the current shape, eg by filling in new fields. Defaults to leaving it untouched
+    fn migrate(value: Value) -> Value {
+        value
+    }
+}
+
 /// Json encoding and decoding
 #[derive(Debug, Clone, Default)]
 pub struct JsonCodec<Output> {
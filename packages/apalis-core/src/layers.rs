@@ -8,7 +8,10 @@ use serde::Serialize;
 use std::marker::PhantomData;
 use std::{fmt, sync::Arc};
 pub use tower::{
-    layer::layer_fn, layer::util::Identity, util::BoxCloneService, Layer, Service, ServiceBuilder,
+    layer::layer_fn,
+    layer::util::{Identity, Stack},
+    util::BoxCloneService,
+    Layer, Service, ServiceBuilder,
 };
 
 /// A generic layer that has been stripped off types.
@@ -27,6 +27,8 @@ pub mod builder;
 
 /// Represents a task source eg Postgres or Redis
 pub mod backend;
+/// Delays between retries
+pub mod backoff;
 /// Includes all possible error types.
 pub mod error;
 /// Represents middleware offered through [`tower`]
@@ -52,6 +54,8 @@ pub mod mq;
 pub mod notify;
 /// Controlled polling and streaming
 pub mod poller;
+/// Pluggable sinks for job events
+pub mod sink;
 
 /// In-memory utilities for testing and mocking
 pub mod memory;
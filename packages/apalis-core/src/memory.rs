@@ -1,38 +1,117 @@
 use crate::{
     backend::Backend,
+    error::Error,
+    layers::{Ack, AckLayer},
     mq::MessageQueue,
     poller::Poller,
     poller::{controller::Controller, stream::BackendStream},
-    request::{Request, RequestStream},
+    request::{Parts, Request, RequestStream, State},
+    response::Response,
+    storage::Storage,
+    task::{cancellation::CancellationToken, task_id::TaskId},
     worker::{self, Worker},
 };
-use futures::{
-    channel::mpsc::{channel, Receiver, Sender},
-    Stream, StreamExt,
-};
+use futures::{future::poll_fn, StreamExt};
 use std::{
-    pin::Pin,
-    sync::Arc,
-    task::{Context, Poll},
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tower::layer::util::Identity;
 
+/// The attempts a job gets by default before it is left [`State::Failed`]
+pub const DEFAULT_MAX_ATTEMPTS: usize = 25;
+
+/// How often an idle worker checks for scheduled jobs that have come due
+#[cfg(feature = "sleep")]
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An in-memory [Storage], for development and tests
+///
+/// Jobs are kept in process, so they are lost when it exits. Successful jobs end up [`State::Done`],
+/// aborted ones [`State::Killed`] and other failures are retried until they run out of attempts,
+/// after which they are left [`State::Failed`]. Scheduled jobs are picked up once due; without the
+/// `sleep` feature an idle worker only notices them when another job is pushed or acknowledged.
+/// Jobs enqueued through [MessageQueue] are handed over to the worker rather than copied,
+/// so job types need not be [Clone], but those jobs are not retried.
+/// Each job carries a [CancellationToken], cancelled by [`Storage::cancel`].
 #[derive(Debug)]
-/// An example of the basics of a backend
 pub struct MemoryStorage<T> {
     /// Required for [Poller] to control polling.
     controller: Controller,
-    /// This would be the backend you are targeting, eg a connection poll
-    inner: MemoryWrapper<T>,
+    jobs: Arc<Mutex<Jobs<T>>>,
 }
+
 impl<T> MemoryStorage<T> {
     /// Create a new in-memory storage
     pub fn new() -> Self {
         Self {
             controller: Controller::new(),
-            inner: MemoryWrapper::new(),
+            jobs: Arc::new(Mutex::new(Jobs {
+                jobs: HashMap::new(),
+                ready: VecDeque::new(),
+                scheduled: BTreeMap::new(),
+                seq: 0,
+                max_attempts: DEFAULT_MAX_ATTEMPTS,
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Sets how many attempts a job gets before it is left [`State::Failed`]
+    pub fn with_max_attempts(self, max_attempts: usize) -> Self {
+        self.lock().max_attempts = max_attempts;
+        self
+    }
+
+    /// The state of a job, if the storage still holds it
+    pub fn status(&self, job_id: &TaskId) -> Option<State> {
+        self.lock().jobs.get(job_id).map(|job| job.state.clone())
+    }
+
+    /// The error the job last failed with
+    pub fn last_error(&self, job_id: &TaskId) -> Option<String> {
+        self.lock()
+            .jobs
+            .get(job_id)
+            .and_then(|job| job.last_error.clone())
+    }
+
+    /// Kill a job that has not finished yet, returning whether it was killed
+    pub fn kill(&self, job_id: &TaskId) -> bool {
+        let mut jobs = self.lock();
+        match jobs.jobs.get_mut(job_id) {
+            Some(job) if !matches!(job.state, State::Done | State::Killed) => {
+                job.state = State::Killed;
+                true
+            }
+            _ => false,
         }
     }
+
+    /// Puts a job that is not running or done straight back into the queue, returning whether it was queued
+    pub fn retry(&self, job_id: &TaskId) -> bool {
+        let mut jobs = self.lock();
+        match jobs.jobs.get_mut(job_id) {
+            Some(job)
+                if !matches!(job.state, State::Running | State::Done) && job.args.is_some() =>
+            {
+                job.state = State::Pending;
+                job.run_at = now();
+                // A cancelled job runs again with a token that is not
+                job.parts.data.insert(CancellationToken::new());
+                jobs.ready.push_back(job_id.clone());
+                jobs.wake();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs<T>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
 impl<T> Default for MemoryStorage<T> {
@@ -45,70 +124,320 @@ impl<T> Clone for MemoryStorage<T> {
     fn clone(&self) -> Self {
         Self {
             controller: self.controller.clone(),
-            inner: self.inner.clone(),
+            jobs: self.jobs.clone(),
         }
     }
 }
 
-/// In-memory queue that implements [Stream]
 #[derive(Debug)]
-pub struct MemoryWrapper<T> {
-    sender: Sender<Request<T, ()>>,
-    receiver: Arc<futures::lock::Mutex<Receiver<Request<T, ()>>>>,
+struct Job<T> {
+    /// `None` once handed over to a worker without keeping a copy
+    args: Option<T>,
+    parts: Parts<()>,
+    /// Copies the arguments for each attempt, without it they are moved out when the job is claimed
+    copy: Option<fn(&T) -> T>,
+    state: State,
+    run_at: i64,
+    last_error: Option<String>,
 }
 
-impl<T> Clone for MemoryWrapper<T> {
-    fn clone(&self) -> Self {
-        Self {
-            receiver: self.receiver.clone(),
-            sender: self.sender.clone(),
+/// The jobs shared by every clone of a [MemoryStorage]
+#[derive(Debug)]
+struct Jobs<T> {
+    jobs: HashMap<TaskId, Job<T>>,
+    /// Jobs ready to run, in the order they became ready
+    ready: VecDeque<TaskId>,
+    /// Jobs waiting for their `run_at`, keyed by it and then by insertion order
+    scheduled: BTreeMap<(i64, u64), TaskId>,
+    seq: u64,
+    max_attempts: usize,
+    /// Consumers waiting for a job to become ready
+    wakers: Vec<Waker>,
+}
+
+impl<T> Jobs<T> {
+    fn insert(
+        &mut self,
+        mut request: Request<T, ()>,
+        run_at: Option<i64>,
+        copy: Option<fn(&T) -> T>,
+    ) {
+        request.parts.data.insert(CancellationToken::new());
+        let task_id = request.parts.task_id.clone();
+        let state = match run_at {
+            Some(run_at) => {
+                self.schedule(task_id.clone(), run_at);
+                State::Scheduled
+            }
+            None => {
+                self.ready.push_back(task_id.clone());
+                State::Pending
+            }
+        };
+        let job = Job {
+            args: Some(request.args),
+            parts: request.parts,
+            copy,
+            state,
+            run_at: run_at.unwrap_or_else(now),
+            last_error: None,
+        };
+        self.jobs.insert(task_id, job);
+        self.wake();
+    }
+
+    fn schedule(&mut self, task_id: TaskId, run_at: i64) {
+        self.seq += 1;
+        self.scheduled.insert((run_at, self.seq), task_id);
+    }
+
+    /// Moves scheduled jobs that are due into the ready queue
+    fn promote(&mut self, now: i64) {
+        while let Some(&key) = self.scheduled.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            let Some(task_id) = self.scheduled.remove(&key) else {
+                continue;
+            };
+            // The job may have been rescheduled, killed or retried since
+            if let Some(job) = self.jobs.get_mut(&task_id) {
+                if job.state == State::Scheduled && job.run_at <= now {
+                    job.state = State::Pending;
+                    self.ready.push_back(task_id);
+                }
+            }
         }
     }
-}
 
-impl<T> MemoryWrapper<T> {
-    /// Build a new basic queue channel
-    pub fn new() -> Self {
-        let (sender, receiver) = channel(100);
+    /// The next job that is ready to run, skipping queue entries that went stale
+    fn next_ready(&mut self) -> Option<TaskId> {
+        self.promote(now());
+        while let Some(task_id) = self.ready.pop_front() {
+            if let Some(job) = self.jobs.get(&task_id) {
+                if matches!(job.state, State::Pending | State::Retry) && job.args.is_some() {
+                    return Some(task_id);
+                }
+            }
+        }
+        None
+    }
 
-        Self {
-            sender,
-            receiver: Arc::new(futures::lock::Mutex::new(receiver)),
+    fn poll_claim(&mut self, cx: &mut Context<'_>) -> Poll<Request<T, ()>> {
+        while let Some(task_id) = self.next_ready() {
+            let Some(job) = self.jobs.get_mut(&task_id) else {
+                continue;
+            };
+            let args = match job.copy {
+                Some(copy) => job.args.as_ref().map(copy),
+                None => job.args.take(),
+            };
+            if let Some(args) = args {
+                job.state = State::Running;
+                job.parts.attempt.increment();
+                return Poll::Ready(Request::new_with_parts(args, job.parts.clone()));
+            }
         }
+        self.register(cx)
     }
-}
 
-impl<T> Default for MemoryWrapper<T> {
-    fn default() -> Self {
-        Self::new()
+    fn poll_take(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        match self
+            .next_ready()
+            .and_then(|task_id| self.jobs.remove(&task_id))
+            .and_then(|job| job.args)
+        {
+            Some(args) => Poll::Ready(args),
+            None => self.register(cx),
+        }
     }
-}
 
-impl<T> Stream for MemoryWrapper<T> {
-    type Item = Request<T, ()>;
+    fn register<R>(&mut self, cx: &mut Context<'_>) -> Poll<R> {
+        if !self.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            self.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if let Some(mut receiver) = self.receiver.try_lock() {
-            receiver.poll_next_unpin(cx)
+    fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn ack<Res>(&mut self, task_id: &TaskId, result: &Result<Res, Error>, attempts: usize) {
+        let Some(job) = self.jobs.get_mut(task_id) else {
+            return;
+        };
+        // Duplicate and stale acks leave the job alone
+        if job.state != State::Running {
+            return;
+        }
+        let Err(err) = result else {
+            job.state = State::Done;
+            return;
+        };
+        job.last_error = Some(err.to_string());
+        if matches!(err, Error::Abort(_)) {
+            job.state = State::Killed;
+        } else if attempts < self.max_attempts && job.args.is_some() {
+            job.state = State::Retry;
+            self.ready.push_back(task_id.clone());
+            self.wake();
         } else {
-            Poll::Pending
+            job.state = State::Failed;
         }
     }
+
+    fn pending(&self) -> usize {
+        self.jobs
+            .values()
+            .filter(|job| matches!(job.state, State::Pending | State::Retry | State::Scheduled))
+            .count()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Waits for the next ready job and marks it running
+async fn claim<T>(jobs: &Mutex<Jobs<T>>) -> Request<T, ()> {
+    let lock = || jobs.lock().unwrap_or_else(PoisonError::into_inner);
+    #[cfg(feature = "sleep")]
+    loop {
+        use futures::{future::Either, FutureExt};
+        let ready = poll_fn(|cx| lock().poll_claim(cx));
+        let tick = crate::sleep(SCHEDULE_CHECK_INTERVAL).boxed();
+        if let Either::Left((request, _)) = futures::future::select(Box::pin(ready), tick).await {
+            return request;
+        }
+    }
+    #[cfg(not(feature = "sleep"))]
+    poll_fn(|cx| lock().poll_claim(cx)).await
 }
 
 // MemoryStorage as a Backend
-impl<T: Send + 'static + Sync, Res> Backend<Request<T, ()>, Res> for MemoryStorage<T> {
+impl<T: Send + 'static + Sync, Res: Send + Sync> Backend<Request<T, ()>, Res> for MemoryStorage<T> {
     type Stream = BackendStream<RequestStream<Request<T, ()>>>;
 
-    type Layer = Identity;
+    type Layer = AckLayer<MemoryStorage<T>, T, (), Res>;
+
+    fn poll<Svc>(self, _worker: &Worker<worker::Context>) -> Poller<Self::Stream, Self::Layer> {
+        let jobs = self.jobs.clone();
+        let stream = futures::stream::unfold(jobs, |jobs| async move {
+            let request = claim(&jobs).await;
+            Some((Ok(Some(request)), jobs))
+        })
+        .boxed();
+        Poller::new_with_layer(
+            BackendStream::new(stream, self.controller.clone()),
+            futures::future::pending(),
+            AckLayer::new(self),
+        )
+    }
+}
+
+impl<T: Send + Sync, Res: Send + Sync> Ack<T, Res> for MemoryStorage<T> {
+    type Context = ();
+    type AckError = Infallible;
+
+    async fn ack(&mut self, _ctx: &(), response: &Response<Res>) -> Result<(), Infallible> {
+        self.lock().ack(
+            &response.task_id,
+            &response.inner,
+            response.attempt.current(),
+        );
+        Ok(())
+    }
+}
+
+impl<T: Clone + Send + Sync> Storage for MemoryStorage<T> {
+    type Job = T;
+
+    type Error = Infallible;
+
+    type Context = ();
+
+    async fn push_request(&mut self, req: Request<T, ()>) -> Result<Parts<()>, Infallible> {
+        let parts = req.parts.clone();
+        self.lock().insert(req, None, Some(T::clone));
+        Ok(parts)
+    }
+
+    async fn schedule_request(
+        &mut self,
+        req: Request<T, ()>,
+        on: i64,
+    ) -> Result<Parts<()>, Infallible> {
+        let parts = req.parts.clone();
+        self.lock().insert(req, Some(on), Some(T::clone));
+        Ok(parts)
+    }
+
+    async fn len(&mut self) -> Result<i64, Infallible> {
+        Ok(self.lock().pending() as i64)
+    }
+
+    async fn fetch_by_id(&mut self, job_id: &TaskId) -> Result<Option<Request<T, ()>>, Infallible> {
+        Ok(self.lock().jobs.get(job_id).and_then(|job| {
+            let args = job.args.clone()?;
+            Some(Request::new_with_parts(args, job.parts.clone()))
+        }))
+    }
+
+    async fn update(&mut self, job: Request<T, ()>) -> Result<(), Infallible> {
+        if let Some(stored) = self.lock().jobs.get_mut(&job.parts.task_id) {
+            stored.args = Some(job.args);
+            stored.parts = job.parts;
+        }
+        Ok(())
+    }
+
+    async fn reschedule(&mut self, job: Request<T, ()>, wait: Duration) -> Result<(), Infallible> {
+        let run_at = now() + wait.as_secs() as i64;
+        let mut jobs = self.lock();
+        let task_id = job.parts.task_id.clone();
+        match jobs.jobs.get_mut(&task_id) {
+            Some(stored) => {
+                stored.args = Some(job.args);
+                stored.parts = job.parts;
+                stored.state = State::Scheduled;
+                stored.run_at = run_at;
+                jobs.schedule(task_id, run_at);
+                jobs.wake();
+            }
+            None => jobs.insert(job, Some(run_at), Some(T::clone)),
+        }
+        Ok(())
+    }
+
+    async fn is_empty(&mut self) -> Result<bool, Infallible> {
+        Ok(self.lock().pending() == 0)
+    }
+
+    async fn vacuum(&mut self) -> Result<usize, Infallible> {
+        let mut jobs = self.lock();
+        let before = jobs.jobs.len();
+        jobs.jobs
+            .retain(|_, job| !matches!(job.state, State::Done | State::Killed));
+        Ok(before - jobs.jobs.len())
+    }
 
-    fn poll<Svc>(self, _worker: &Worker<worker::Context>) -> Poller<Self::Stream> {
-        let stream = self.inner.map(|r| Ok(Some(r))).boxed();
-        Poller {
-            stream: BackendStream::new(stream, self.controller),
-            heartbeat: Box::pin(futures::future::pending()),
-            layer: Identity::new(),
-            _priv: (),
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, Infallible> {
+        let mut jobs = self.lock();
+        match jobs.jobs.get_mut(job_id) {
+            Some(job) if !matches!(job.state, State::Done | State::Killed | State::Failed) => {
+                job.state = State::Killed;
+                if let Some(token) = job.parts.data.get::<CancellationToken>() {
+                    token.cancel();
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
 }
@@ -116,25 +445,155 @@ impl<T: Send + 'static + Sync, Res> Backend<Request<T, ()>, Res> for MemoryStora
 impl<Message: Send + 'static + Sync> MessageQueue<Message> for MemoryStorage<Message> {
     type Error = ();
     async fn enqueue(&mut self, message: Message) -> Result<(), Self::Error> {
-        self.inner
-            .sender
-            .try_send(Request::new(message))
-            .map_err(|_| ())?;
+        self.lock().insert(Request::new(message), None, None);
         Ok(())
     }
 
     async fn dequeue(&mut self) -> Result<Option<Message>, ()> {
-        Ok(self
-            .inner
-            .receiver
-            .lock()
-            .await
-            .next()
-            .await
-            .map(|r| r.args))
+        let message = poll_fn(|cx| self.lock().poll_take(cx)).await;
+        Ok(Some(message))
     }
 
     async fn size(&mut self) -> Result<usize, ()> {
-        Ok(self.inner.receiver.lock().await.size_hint().0)
+        Ok(self.lock().pending())
+    }
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use std::io;
+
+    use crate::{
+        generic_storage_test,
+        test_utils::{apalis_test_service_fn, TestWrapper},
+    };
+
+    use super::*;
+
+    async fn setup() -> MemoryStorage<u32> {
+        MemoryStorage::new()
+    }
+
+    generic_storage_test!(setup);
+
+    #[tokio::test]
+    async fn it_acks_retries_kills_and_schedules() {
+        let storage = MemoryStorage::new().with_max_attempts(2);
+        let service = apalis_test_service_fn(|request: Request<u32, ()>| async move {
+            match request.args {
+                0 => Ok(request.args),
+                1 => Err(Error::Abort(Arc::new("aborted".into()))),
+                _ => Err(Error::Failed(Arc::new("failed".into()))),
+            }
+        });
+        let (mut t, poller) = TestWrapper::new_with_service(storage, service);
+        tokio::spawn(poller);
+
+        let done = t.push(0).await.unwrap().task_id;
+        assert_eq!(t.execute_next().await, (done.clone(), Ok("0".to_owned())));
+        assert_eq!(t.status(&done), Some(State::Done));
+
+        let aborted = t.push(1).await.unwrap().task_id;
+        assert_eq!(
+            t.execute_next().await.1,
+            Err("AbortError: aborted".to_owned())
+        );
+        assert_eq!(t.status(&aborted), Some(State::Killed));
+        assert_eq!(
+            t.last_error(&aborted).as_deref(),
+            Some("AbortError: aborted")
+        );
+
+        let failing = t.push(2).await.unwrap().task_id;
+        // Requeued after the first failure, then left failed once out of attempts
+        assert_eq!(t.execute_next().await.0, failing);
+        assert_eq!(t.execute_next().await.0, failing);
+        assert_eq!(t.status(&failing), Some(State::Failed));
+        let failed = t.fetch_by_id(&failing).await.unwrap().unwrap();
+        assert_eq!(failed.parts.attempt.current(), 2);
+
+        let scheduled = t.schedule(0, now() + 3600).await.unwrap().task_id;
+        assert_eq!(t.status(&scheduled), Some(State::Scheduled));
+        assert_eq!(t.len().await.unwrap(), 1);
+        assert!(t.kill(&scheduled));
+        assert!(!t.kill(&scheduled));
+        assert!(t.is_empty().await.unwrap());
+
+        // Done, aborted and killed jobs go, the failed one stays around for inspection
+        assert_eq!(t.vacuum().await.unwrap(), 3);
+        assert_eq!(t.status(&failing), Some(State::Failed));
+        assert!(t.retry(&failing));
+        assert_eq!(t.execute_next().await.0, failing);
+        assert_eq!(t.status(&failing), Some(State::Failed));
+    }
+
+    #[tokio::test]
+    async fn it_hands_over_messages_that_cannot_be_cloned() {
+        #[derive(Debug)]
+        struct Letter(u32);
+
+        let storage = MemoryStorage::new().with_max_attempts(2);
+        let service = apalis_test_service_fn(|request: Request<Letter, ()>| async move {
+            match request.args.0 {
+                0 => Err(Error::Failed(Arc::new("failed".into()))),
+                n => Ok(n),
+            }
+        });
+        let (mut t, poller) = TestWrapper::new_with_service(storage, service);
+        tokio::spawn(poller);
+
+        t.enqueue(Letter(1)).await.unwrap();
+        let (sent, res) = t.execute_next().await;
+        assert_eq!(res, Ok("1".to_owned()));
+        assert_eq!(t.status(&sent), Some(State::Done));
+
+        // Nothing is left to retry with once the message was handed over
+        t.enqueue(Letter(0)).await.unwrap();
+        let (failing, _) = t.execute_next().await;
+        assert_eq!(t.status(&failing), Some(State::Failed));
+        assert!(!t.retry(&failing));
+    }
+
+    #[tokio::test]
+    async fn it_cancels_waiting_and_running_jobs() {
+        use crate::service_fn::FromRequest;
+
+        let mut storage = MemoryStorage::new();
+        let mut canceller = storage.clone();
+        let waiting = storage.schedule(1, now() + 3600).await.unwrap().task_id;
+        assert!(canceller.cancel(&waiting).await.unwrap());
+        assert_eq!(storage.status(&waiting), Some(State::Killed));
+
+        let service = apalis_test_service_fn(|request: Request<u32, ()>| async move {
+            let token = CancellationToken::from_request(&request).unwrap();
+            token.cancelled().await;
+            Ok::<_, io::Error>(request.args)
+        });
+        let (mut t, poller) = TestWrapper::new_with_service(storage, service);
+        tokio::spawn(poller);
+        let running = t.push(2).await.unwrap().task_id;
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(canceller.cancel(&running).await.unwrap());
+        };
+        let ((cancelled, _), ()) = tokio::join!(t.execute_next(), cancel);
+        assert_eq!(cancelled, running);
+        assert_eq!(t.status(&running), Some(State::Killed));
+        assert!(!canceller.cancel(&running).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_runs_scheduled_jobs_once_due() {
+        let mut storage = MemoryStorage::new();
+        let later = storage.schedule(1, now() + 3600).await.unwrap().task_id;
+        let due = storage.schedule(2, now() - 1).await.unwrap().task_id;
+        let service = apalis_test_service_fn(|request: Request<u32, ()>| async move {
+            Ok::<_, io::Error>(request.args)
+        });
+        let (mut t, poller) = TestWrapper::new_with_service(storage, service);
+        tokio::spawn(poller);
+
+        assert_eq!(t.execute_next().await, (due, Ok("2".to_owned())));
+        assert_eq!(t.status(&later), Some(State::Scheduled));
     }
 }
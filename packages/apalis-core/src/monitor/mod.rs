@@ -26,6 +26,8 @@ pub struct Monitor {
     terminator: Option<BoxFuture<'static, ()>>,
     shutdown: Shutdown,
     event_handler: EventHandler,
+    #[cfg(feature = "sleep")]
+    stagger: Option<std::time::Duration>,
 }
 
 impl Debug for Monitor {
@@ -131,8 +133,12 @@ impl Monitor {
         let shutdown = self.shutdown.clone();
         let shutdown_after = self.shutdown.shutdown_after(signal);
         if let Some(terminator) = self.terminator {
+            #[cfg(feature = "sleep")]
+            let futures = stagger_futures(self.futures, self.stagger);
+            #[cfg(not(feature = "sleep"))]
+            let futures = self.futures;
             let _res = futures::future::select(
-                futures::future::join_all(self.futures)
+                futures::future::join_all(futures)
                     .map(|_| shutdown.start_shutdown())
                     .boxed(),
                 async {
@@ -143,7 +149,7 @@ impl Monitor {
             )
             .await;
         } else {
-            let runner = self.run();
+            let runner = self.run_workers();
             let _res = futures::join!(shutdown_after, runner); // If no terminator is provided, we wait for both the shutdown call and all workers to complete
         }
         Ok(())
@@ -158,11 +164,57 @@ impl Monitor {
     /// # Remarks
     ///
     /// If all workers have completed execution, then by default the monitor will start a shutdown
+    ///
+    /// With the `signal` feature, SIGTERM and SIGINT (Ctrl+C on windows) also start a graceful shutdown:
+    /// workers stop taking new jobs and in-flight jobs are allowed to finish.
+    /// Pair it with [`Monitor::shutdown_timeout`] set a little below the platform's grace period
+    /// (eg kubernetes' `terminationGracePeriodSeconds`) so the process exits before it is killed.
     pub async fn run(self) -> std::io::Result<()> {
+        #[cfg(feature = "signal")]
+        {
+            self.run_until_signal(termination_signal()).await
+        }
+        #[cfg(not(feature = "signal"))]
+        {
+            self.run_workers().await
+        }
+    }
+
+    /// Runs until all workers complete, or until `signal` resolves and the workers drain
+    #[cfg(feature = "signal")]
+    async fn run_until_signal<S>(mut self, signal: S) -> std::io::Result<()>
+    where
+        S: Send + Future<Output = std::io::Result<()>>,
+    {
+        use futures::future::{select, Either};
+        let shutdown = self.shutdown.clone();
+        let terminator = self.terminator.take();
+        let runner = self.run_workers().boxed();
+        match select(runner, signal.boxed()).await {
+            Either::Left((res, _)) => res,
+            Either::Right((signal, runner)) => {
+                signal?;
+                shutdown.start_shutdown();
+                match terminator {
+                    Some(terminator) => {
+                        select(runner, terminator).await;
+                    }
+                    None => runner.await?,
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn run_workers(self) -> std::io::Result<()> {
         let shutdown = self.shutdown.clone();
         let shutdown_future = self.shutdown.boxed().map(|_| ());
+        #[cfg(feature = "sleep")]
+        let futures = stagger_futures(self.futures, self.stagger);
+        #[cfg(not(feature = "sleep"))]
+        let futures = self.futures;
         futures::join!(
-            futures::future::join_all(self.futures).map(|_| shutdown.start_shutdown()),
+            futures::future::join_all(futures).map(|_| shutdown.start_shutdown()),
             shutdown_future,
         );
 
@@ -186,10 +238,49 @@ impl Default for Monitor {
             terminator: None,
             event_handler: Arc::default(),
             workers: Vec::new(),
+            #[cfg(feature = "sleep")]
+            stagger: None,
         }
     }
 }
 
+/// Resolves on the first SIGTERM or SIGINT
+#[cfg(feature = "signal")]
+async fn termination_signal() -> std::io::Result<()> {
+    use async_signal::{Signal, Signals};
+    use futures::StreamExt;
+    #[cfg(unix)]
+    let mut signals = Signals::new([Signal::Term, Signal::Int])?;
+    #[cfg(not(unix))]
+    let mut signals = Signals::new([Signal::Int])?;
+    signals.next().await.transpose()?;
+    Ok(())
+}
+
+/// Delays each worker's start by an even share of `max`, in registration order
+#[cfg(feature = "sleep")]
+fn stagger_futures(
+    futures: Vec<BoxFuture<'static, ()>>,
+    stagger: Option<std::time::Duration>,
+) -> Vec<BoxFuture<'static, ()>> {
+    let Some(max) = stagger else {
+        return futures;
+    };
+    let count = futures.len().max(1) as u32;
+    futures
+        .into_iter()
+        .enumerate()
+        .map(|(index, fut)| {
+            let delay = max * index as u32 / count;
+            async move {
+                crate::sleep(delay).await;
+                fut.await
+            }
+            .boxed()
+        })
+        .collect()
+}
+
 impl Monitor {
     /// Creates a new monitor instance.
     ///
@@ -225,6 +316,20 @@ impl Monitor {
         self.terminator = Some(fut.boxed());
         self
     }
+
+    /// Staggers the start of registered workers over `max`.
+    ///
+    /// Workers are started in registration order, each delayed by an even share of `max`,
+    /// so a fresh deployment does not send every worker to the backend at the same instant.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The delay before the last registered worker starts polling.
+    #[cfg(feature = "sleep")]
+    pub fn stagger(mut self, max: std::time::Duration) -> Self {
+        self.stagger = Some(max);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -305,4 +410,89 @@ mod tests {
         sleep(Duration::from_millis(1000)).await;
         assert!(result.is_ok());
     }
+
+    #[cfg(feature = "sleep")]
+    #[tokio::test]
+    async fn test_monitor_staggers_worker_starts() {
+        use crate::worker::Event;
+        use std::sync::{Arc, Mutex};
+        use std::time::Instant;
+
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = starts.clone();
+        let mut monitor = Monitor::new()
+            .stagger(Duration::from_millis(300))
+            .on_event(move |e| {
+                if let Event::Start = e.inner() {
+                    recorded.lock().unwrap().push(Instant::now());
+                }
+            });
+        for index in 0..3 {
+            let service = tower::service_fn(|request: Request<u32, ()>| async {
+                Ok::<_, io::Error>(request)
+            });
+            let worker = WorkerBuilder::new(format!("staggered-{index}"))
+                .backend(MemoryStorage::new())
+                .build(service);
+            monitor = monitor.register(worker);
+        }
+        let shutdown = monitor.shutdown.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(500)).await;
+            shutdown.start_shutdown();
+        });
+        monitor.run().await.unwrap();
+
+        let mut starts = starts.lock().unwrap().clone();
+        starts.sort();
+        assert_eq!(starts.len(), 3);
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(80));
+        }
+    }
+
+    #[cfg(feature = "signal")]
+    #[tokio::test]
+    async fn test_monitor_drains_in_flight_jobs_on_signal() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let backend = MemoryStorage::new();
+        let mut handle = backend.clone();
+        handle.enqueue(1).await.unwrap();
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        let service = tower::service_fn(move |request: Request<u32, ()>| {
+            let counter = counter.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, io::Error>(request)
+            }
+        });
+        let worker = WorkerBuilder::new("draining")
+            .backend(backend)
+            .build(service);
+        let monitor = Monitor::new()
+            .shutdown_timeout(Duration::from_secs(5))
+            .register(worker);
+
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            tx.send(()).unwrap();
+        });
+        monitor
+            .run_until_signal(async move {
+                rx.await.ok();
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
 }
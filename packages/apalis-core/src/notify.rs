@@ -1,6 +1,12 @@
 use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
 };
 
@@ -14,8 +20,10 @@ use futures::{
 #[derive(Debug)]
 
 pub struct Notify<T> {
-    sender: Sender<T>,
+    sender: Arc<Mutex<Sender<T>>>,
     receiver: Arc<futures::lock::Mutex<Receiver<T>>>,
+    bounded: bool,
+    dropped: Arc<AtomicU64>,
 }
 
 impl<T> Clone for Notify<T> {
@@ -23,6 +31,8 @@ impl<T> Clone for Notify<T> {
         Self {
             sender: self.sender.clone(),
             receiver: self.receiver.clone(),
+            bounded: self.bounded,
+            dropped: self.dropped.clone(),
         }
     }
 }
@@ -30,18 +40,49 @@ impl<T> Clone for Notify<T> {
 impl<T> Notify<T> {
     /// Creates a new instance of `Notify`.
     /// It initializes a channel with a buffer size of 1 and wraps the receiver in an `Arc<Mutex>`.
+    /// Every notification is sent from its own clone of the sender, which the channel always makes room for,
+    /// so none are dropped however far behind the receiver is. See [`Notify::bounded`] to cap the backlog instead.
     pub fn new() -> Self {
-        let (sender, receiver) = channel(1);
+        Self::with_channel(1, false)
+    }
+
+    /// Creates a `Notify` holding at most `buffer + 1` pending notifications.
+    /// Notifications sent while it is full are dropped and counted in [`Notify::dropped_count`],
+    /// for wakeups where only the latest ones matter.
+    pub fn bounded(buffer: usize) -> Self {
+        Self::with_channel(buffer, true)
+    }
+
+    fn with_channel(buffer: usize, bounded: bool) -> Self {
+        let (sender, receiver) = channel(buffer);
 
         Self {
-            sender,
+            sender: Arc::new(Mutex::new(sender)),
             receiver: Arc::new(futures::lock::Mutex::new(receiver)),
+            bounded,
+            dropped: Arc::default(),
         }
     }
 
     /// Sends a notification of type `T` to the receiver.
+    /// A notification that cannot be sent is counted in [`Notify::dropped_count`].
     pub fn notify(&self, value: T) -> Result<(), TrySendError<T>> {
-        self.sender.clone().try_send(value)
+        let mut sender = self.sender.lock().unwrap();
+        let res = if self.bounded {
+            sender.try_send(value)
+        } else {
+            sender.clone().try_send(value)
+        };
+        if res.is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+
+    /// The number of notifications that could not be sent, across all clones.
+    /// A count that keeps climbing means the receiver does not keep up and wakeups are lost.
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
     }
 
     /// Waits for and retrieves the next notification.
@@ -74,3 +115,101 @@ impl<T> Stream for Notify<T> {
         }
     }
 }
+
+/// A set of [`Notify`] channels keyed by `K`, usually the job type.
+///
+/// Sharing one [`Notify`] between workers of different job types wakes all of them on every push.
+/// With `TypedNotify` each worker subscribes to its own key and only wakes for notifications sent to it.
+#[derive(Debug)]
+pub struct TypedNotify<K, T> {
+    channels: Arc<Mutex<HashMap<K, Notify<T>>>>,
+}
+
+impl<K, T> Clone for TypedNotify<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            channels: self.channels.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> TypedNotify<K, T> {
+    /// Creates a new instance of `TypedNotify` without any subscribers.
+    pub fn new() -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Gets the [`Notify`] receiving the notifications sent to `key`.
+    /// Subscribers of the same key share a single channel.
+    pub fn subscribe(&self, key: K) -> Notify<T> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// Sends a notification to the subscribers of `key`.
+    /// Nothing is sent if no one subscribed to `key`.
+    pub fn notify<Q>(&self, key: &Q, value: T) -> Result<(), TrySendError<T>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.channels.lock().unwrap().get(key) {
+            Some(channel) => channel.notify(value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> Default for TypedNotify<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn test_typed_notify_only_wakes_matching_type() {
+        let notify = TypedNotify::<String, ()>::new();
+        let mut type_a = notify.subscribe("A".to_owned());
+        let mut type_b = notify.subscribe("B".to_owned());
+
+        notify.notify("A", ()).unwrap();
+        assert_eq!(type_a.next().now_or_never(), Some(Some(())));
+        assert_eq!(type_b.next().now_or_never(), None);
+        // Notifying a type without workers is not an error
+        notify.notify("C", ()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_count_tracks_failed_sends() {
+        let mut notify = Notify::<u32>::bounded(1);
+        let producer = notify.clone();
+        assert!(producer.notify(0).is_ok());
+        assert!(producer.notify(1).is_ok());
+        for n in 2..5 {
+            assert!(producer.notify(n).unwrap_err().is_full());
+        }
+        assert_eq!(notify.dropped_count(), 3);
+
+        assert_eq!(notify.next().now_or_never(), Some(Some(0)));
+        assert!(producer.notify(5).is_ok());
+        assert_eq!(producer.dropped_count(), 3);
+
+        // Unbounded notifications always get through
+        let unbounded = Notify::<u32>::new();
+        for n in 0..100 {
+            unbounded.notify(n).unwrap();
+        }
+        assert_eq!(unbounded.dropped_count(), 0);
+    }
+}
@@ -3,6 +3,8 @@ use std::sync::{
     Arc,
 };
 
+use futures::task::AtomicWaker;
+
 use super::{PLUGGED, STOPPED, UNPLUGGED};
 
 /// The `Controller` struct represents a thread-safe state manager.
@@ -11,6 +13,7 @@ use super::{PLUGGED, STOPPED, UNPLUGGED};
 #[derive(Debug, Clone)]
 pub struct Controller {
     pub(super) state: Arc<AtomicUsize>,
+    pub(super) waker: Arc<AtomicWaker>,
 }
 
 impl Controller {
@@ -18,12 +21,14 @@ impl Controller {
     pub fn new() -> Self {
         Controller {
             state: Arc::new(AtomicUsize::new(PLUGGED)),
+            waker: Arc::new(AtomicWaker::new()),
         }
     }
 
     /// Sets the state of the controller to `PLUGGED`.
     pub fn plug(&self) {
         self.state.store(PLUGGED, Ordering::Relaxed);
+        self.waker.wake();
     }
 
     /// Sets the state of the controller to `UNPLUGGED`.
@@ -37,8 +42,11 @@ impl Controller {
     }
 
     /// Sets the state of the controller to `Stopped`.
+    ///
+    /// A [`BackendStream`](super::stream::BackendStream) waiting on its inner stream is woken and ends right away.
     pub fn stop(&self) {
         self.state.store(STOPPED, Ordering::Relaxed);
+        self.waker.wake();
     }
 
     /// Returns `true` if the current state is `STOPPED`.
@@ -15,6 +15,8 @@ pub struct Poller<S, L = Identity> {
     pub heartbeat: BoxFuture<'static, ()>,
     /// The tower middleware provided by the backend
     pub layer: L,
+    /// Run once the worker has drained its jobs and stopped polling the heartbeat, eg to deregister the worker
+    pub shutdown: Option<BoxFuture<'static, ()>>,
     pub(crate) _priv: (),
 }
 
@@ -34,11 +36,23 @@ impl<S> Poller<S, Identity> {
             stream,
             heartbeat: heartbeat.boxed(),
             layer,
+            shutdown: None,
             _priv: (),
         }
     }
 }
 
+impl<S, L> Poller<S, L> {
+    /// Run `shutdown` when the worker stops
+    ///
+    /// The worker first waits for its running jobs to finish while the heartbeat keeps going,
+    /// then stops polling the heartbeat and awaits `shutdown` before exiting.
+    pub fn with_shutdown(mut self, shutdown: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(shutdown.boxed());
+        self
+    }
+}
+
 impl<S, L> Debug for Poller<S, L>
 where
     S: Debug,
@@ -49,6 +63,7 @@ where
             .field("stream", &self.stream)
             .field("heartbeat", &"...")
             .field("layer", &self.layer)
+            .field("shutdown", &self.shutdown.as_ref().map(|_| "..."))
             .finish()
     }
 }
@@ -13,6 +13,7 @@ use super::{controller::Controller, STOPPED};
 pin_project! {
     /// `BackendStream` is a wrapper around another stream `S`.
     /// It controls the flow of the stream based on the `Controller` state.
+    /// Once the controller is stopped the stream ends, even if `S` is still waiting, eg on a poll interval.
     #[derive(Debug)]
     pub struct BackendStream<S> {
         #[pin]
@@ -32,6 +33,8 @@ impl<S: Stream<Item = T> + Unpin, T> Stream for BackendStream<S> {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
+        // Registered before reading the state so a `plug` or `stop` in between still wakes us
+        this.controller.waker.register(cx.waker());
         if this.controller.is_plugged() {
             match this.stream.poll_next_unpin(cx) {
                 Poll::Ready(Some(item)) => Poll::Ready(Some(item)),
@@ -178,4 +181,27 @@ mod tests {
             .await
             .expect("Expected an item from the stream");
     }
+
+    #[tokio::test]
+    async fn test_backend_stream_ends_when_stopped_mid_interval() {
+        let controller = Controller::new();
+        let mut backend_stream =
+            BackendStream::new(interval_stream(Duration::from_secs(60)), controller.clone());
+        // The first tick is immediate, the next one is a minute away
+        backend_stream
+            .next()
+            .await
+            .expect("Expected an item from the stream");
+
+        let stopper = controller.clone();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(50)).await;
+            stopper.stop();
+        });
+        let next = tokio::time::timeout(Duration::from_secs(1), backend_stream.next())
+            .await
+            .expect("Stream should end promptly once stopped");
+        assert!(next.is_none());
+        assert!(backend_stream.is_terminated());
+    }
 }
@@ -2,7 +2,7 @@ use futures::{future::BoxFuture, Stream};
 use serde::{Deserialize, Serialize};
 use tower::layer::util::Identity;
 
-use std::{fmt, fmt::Debug, pin::Pin, str::FromStr};
+use std::{any::type_name, fmt, fmt::Debug, pin::Pin, str::FromStr, time::SystemTime};
 
 use crate::{
     backend::Backend,
@@ -86,6 +86,36 @@ impl<T, Ctx: Default> Request<T, Ctx> {
     }
 }
 
+impl<T, Ctx> Request<T, Ctx> {
+    /// Summarize the request for an audit log, leaving out the arguments
+    ///
+    /// The arguments may hold personal data, so only the id, type, namespace and attempts are kept.
+    pub fn audit_record(&self) -> AuditRecord {
+        AuditRecord {
+            task_id: self.parts.task_id.clone(),
+            job_type: type_name::<T>().to_owned(),
+            namespace: self.parts.namespace.as_ref().map(|ns| ns.0.clone()),
+            attempts: self.parts.attempt.current(),
+            recorded_at: SystemTime::now(),
+        }
+    }
+}
+
+/// A summary of a [`Request`] without its arguments, see [`Request::audit_record`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// The request's id
+    pub task_id: TaskId,
+    /// The type name of the arguments
+    pub job_type: String,
+    /// The namespace, if the backend set one
+    pub namespace: Option<String>,
+    /// The attempts made so far
+    pub attempts: usize,
+    /// When the record was taken
+    pub recorded_at: SystemTime,
+}
+
 impl<T, Ctx> std::ops::Deref for Request<T, Ctx> {
     type Target = Extensions;
     fn deref(&self) -> &Self::Target {
@@ -111,10 +141,14 @@ pub enum State {
     Running,
     /// Job was done successfully
     Done,
+    /// Job has failed and is waiting for another attempt
+    Retry,
     /// Job has failed. Check `last_error`
     Failed,
     /// Job has been killed
     Killed,
+    /// Job ran out of attempts and will not be retried unless asked to
+    Dead,
 }
 
 impl Default for State {
@@ -131,8 +165,10 @@ impl FromStr for State {
             "Pending" | "Latest" => Ok(State::Pending),
             "Running" => Ok(State::Running),
             "Done" => Ok(State::Done),
+            "Retry" => Ok(State::Retry),
             "Failed" => Ok(State::Failed),
             "Killed" => Ok(State::Killed),
+            "Dead" => Ok(State::Dead),
             "Scheduled" => Ok(State::Scheduled),
             _ => Err(Error::MissingData("Invalid Job state".to_string())),
         }
@@ -145,8 +181,10 @@ impl fmt::Display for State {
             State::Pending => write!(f, "Pending"),
             State::Running => write!(f, "Running"),
             State::Done => write!(f, "Done"),
+            State::Retry => write!(f, "Retry"),
             State::Failed => write!(f, "Failed"),
             State::Killed => write!(f, "Killed"),
+            State::Dead => write!(f, "Dead"),
             State::Scheduled => write!(f, "Scheduled"),
         }
     }
@@ -170,7 +208,38 @@ impl<T, Res, Ctx> Backend<Request<T, Ctx>, Res> for RequestStream<Request<T, Ctx
             stream: self,
             heartbeat: Box::pin(futures::future::pending()),
             layer: Identity::new(),
+            shutdown: None,
             _priv: (),
         }
     }
 }
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Signup {
+        email: String,
+    }
+
+    #[test]
+    fn audit_record_leaves_out_args() {
+        let mut req: Request<Signup, ()> = Request::new(Signup {
+            email: "jane@example.com".to_owned(),
+        });
+        req.parts.namespace = Some(Namespace("signups".to_owned()));
+        req.parts.attempt.increment();
+
+        let record = req.audit_record();
+        assert_eq!(record.task_id, req.parts.task_id);
+        assert!(record.job_type.ends_with("Signup"));
+        assert_eq!(record.namespace.as_deref(), Some("signups"));
+        assert_eq!(record.attempts, 1);
+
+        let logged = serde_json::to_string(&record).unwrap();
+        assert!(logged.contains(&req.parts.task_id.to_string()));
+        assert!(!logged.contains("jane@example.com"));
+        assert!(!logged.contains("email"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+
+use crate::{request::Request, task::task_id::TaskId};
+
+/// Something that happened to a job, as seen by a worker or its backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobEvent {
+    /// A worker took the job
+    Claimed(TaskId),
+    /// The job ran successfully
+    Succeeded(TaskId),
+    /// The job returned an error
+    Failed {
+        /// The job
+        task_id: TaskId,
+        /// The error, formatted
+        error: String,
+    },
+    /// The job failed and will be attempted again
+    Rescheduled(TaskId),
+    /// The job failed for good and will not be attempted again
+    DeadLettered(TaskId),
+}
+
+impl JobEvent {
+    /// The job the event is about
+    pub fn task_id(&self) -> &TaskId {
+        match self {
+            JobEvent::Claimed(task_id)
+            | JobEvent::Succeeded(task_id)
+            | JobEvent::Failed { task_id, .. }
+            | JobEvent::Rescheduled(task_id)
+            | JobEvent::DeadLettered(task_id) => task_id,
+        }
+    }
+}
+
+impl fmt::Display for JobEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobEvent::Claimed(task_id) => write!(f, "Job [{task_id}] claimed"),
+            JobEvent::Succeeded(task_id) => write!(f, "Job [{task_id}] succeeded"),
+            JobEvent::Failed { task_id, error } => write!(f, "Job [{task_id}] failed: {error}"),
+            JobEvent::Rescheduled(task_id) => write!(f, "Job [{task_id}] rescheduled"),
+            JobEvent::DeadLettered(task_id) => write!(f, "Job [{task_id}] dead lettered"),
+        }
+    }
+}
+
+/// Receives the [`JobEvent`]s of a worker and its backend
+///
+/// Implement this to forward job events to a queue, a webhook or a metrics system.
+/// `send` is called inline while the job is processed, so it should hand the event off rather than block.
+pub trait Sink: fmt::Debug + Send + Sync {
+    /// Handle an event
+    fn send(&self, event: JobEvent);
+}
+
+impl<S: Sink + ?Sized> Sink for Arc<S> {
+    fn send(&self, event: JobEvent) {
+        (**self).send(event)
+    }
+}
+
+/// A [`Sink`] that drops every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl Sink for NoopSink {
+    fn send(&self, _event: JobEvent) {}
+}
+
+/// A [`Sink`] that prints every event to stderr
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingSink;
+
+impl Sink for LoggingSink {
+    fn send(&self, event: JobEvent) {
+        eprintln!("{event}");
+    }
+}
+
+/// A layer that sends [`JobEvent::Claimed`] when a job starts and its outcome when it ends
+///
+/// Backends that know whether a failed job will be retried send
+/// [`JobEvent::Rescheduled`] and [`JobEvent::DeadLettered`] to the same sink themselves.
+#[derive(Debug, Clone)]
+pub struct SinkLayer<S> {
+    sink: S,
+}
+
+impl<S> SinkLayer<S> {
+    /// Send job events to `sink`
+    pub fn new(sink: S) -> Self {
+        Self { sink }
+    }
+}
+
+impl<S: Clone, Svc> Layer<Svc> for SinkLayer<S> {
+    type Service = SinkService<S, Svc>;
+
+    fn layer(&self, service: Svc) -> Self::Service {
+        SinkService {
+            sink: self.sink.clone(),
+            service,
+        }
+    }
+}
+
+/// The service built by a [`SinkLayer`]
+#[derive(Debug, Clone)]
+pub struct SinkService<S, Svc> {
+    sink: S,
+    service: Svc,
+}
+
+impl<S, Svc, Req, Ctx> Service<Request<Req, Ctx>> for SinkService<S, Svc>
+where
+    S: Sink + Clone + 'static,
+    Svc: Service<Request<Req, Ctx>>,
+    Svc::Future: Send + 'static,
+    Svc::Error: fmt::Display,
+{
+    type Response = Svc::Response;
+    type Error = Svc::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Req, Ctx>) -> Self::Future {
+        let sink = self.sink.clone();
+        let task_id = request.parts.task_id.clone();
+        sink.send(JobEvent::Claimed(task_id.clone()));
+        let fut = self.service.call(request);
+        async move {
+            let res = fut.await;
+            match &res {
+                Ok(_) => sink.send(JobEvent::Succeeded(task_id)),
+                Err(e) => sink.send(JobEvent::Failed {
+                    task_id,
+                    error: e.to_string(),
+                }),
+            }
+            res
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Collect(Mutex<Vec<JobEvent>>);
+
+    impl Sink for Collect {
+        fn send(&self, event: JobEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn sends_claimed_then_outcome() {
+        let sink = Arc::new(Collect::default());
+        let service =
+            SinkLayer::new(sink.clone()).layer(service_fn(|n: Request<u32, ()>| async move {
+                match n.args {
+                    0 => Err("zero"),
+                    n => Ok(n),
+                }
+            }));
+        let ok = Request::new(1);
+        let err = Request::new(0);
+        let (ok_id, err_id) = (ok.parts.task_id.clone(), err.parts.task_id.clone());
+
+        futures::executor::block_on(async {
+            assert_eq!(service.clone().oneshot(ok).await, Ok(1));
+            assert_eq!(service.oneshot(err).await, Err("zero"));
+        });
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [
+                JobEvent::Claimed(ok_id.clone()),
+                JobEvent::Succeeded(ok_id),
+                JobEvent::Claimed(err_id.clone()),
+                JobEvent::Failed {
+                    task_id: err_id,
+                    error: "zero".to_owned()
+                },
+            ]
+        );
+    }
+}
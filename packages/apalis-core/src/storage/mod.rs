@@ -75,4 +75,12 @@ pub trait Storage {
 
     /// Vacuum the storage, removes done and killed jobs
     fn vacuum(&mut self) -> impl Future<Output = Result<usize, Self::Error>> + Send;
+
+    /// Cancel a job that has not finished yet, returning whether there was one to cancel
+    ///
+    /// A waiting job is killed and never runs. A running job is killed too, and backends that can
+    /// reach its handler cancel the [`CancellationToken`](crate::task::cancellation::CancellationToken)
+    /// in its request so it can stop cleanly.
+    fn cancel(&mut self, job_id: &TaskId)
+        -> impl Future<Output = Result<bool, Self::Error>> + Send;
 }
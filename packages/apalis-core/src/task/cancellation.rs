@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Poll, Waker};
+
+use crate::{error::Error, request::Request, service_fn::FromRequest};
+
+/// Tells a running task that it was cancelled, so it can stop cleanly
+///
+/// Backends that support cancellation put one in the request's extensions.
+/// Cancelling is cooperative: the task decides when to check [`CancellationToken::is_cancelled`]
+/// or to await [`CancellationToken::cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    /// Build a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the task, waking everything awaiting [`CancellationToken::cancelled`]
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let wakers =
+            std::mem::take(&mut *self.0.wakers.lock().unwrap_or_else(PoisonError::into_inner));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Whether the task was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the task is cancelled
+    pub async fn cancelled(&self) {
+        futures::future::poll_fn(|cx| {
+            if self.is_cancelled() {
+                return Poll::Ready(());
+            }
+            let mut wakers = self.0.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+            // Checked again under the lock, `cancel` may have drained the wakers in between
+            if self.is_cancelled() {
+                return Poll::Ready(());
+            }
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+// Backends that do not support cancellation hand out a token that is never cancelled
+impl<Req, Ctx> FromRequest<Request<Req, Ctx>> for CancellationToken {
+    fn from_request(req: &Request<Req, Ctx>) -> Result<Self, Error> {
+        Ok(req.parts.data.get::<Self>().cloned().unwrap_or_default())
+    }
+}
@@ -1,5 +1,7 @@
 /// A unique tracker for number of attempts
 pub mod attempt;
+/// A token telling a running task it was cancelled
+pub mod cancellation;
 /// A wrapper type for storing the namespace
 pub mod namespace;
 /// A unique ID that can be used by a backend
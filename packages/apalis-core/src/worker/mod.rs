@@ -19,6 +19,8 @@ use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::task::{Context as TaskCtx, Poll, Waker};
+#[cfg(feature = "sleep")]
+use std::time::Duration;
 use thiserror::Error;
 use tower::{Layer, Service, ServiceBuilder};
 
@@ -56,6 +58,21 @@ impl WorkerId {
         }
     }
 
+    /// Build a worker ref that stays the same across restarts on the same host
+    ///
+    /// The host is read from `HOSTNAME` (`COMPUTERNAME` on windows), which in kubernetes is the pod name,
+    /// and falls back to `localhost`. A restarted worker with the same id can reclaim the jobs it
+    /// held before it died, instead of waiting for them to be treated as orphans.
+    /// Two live workers must never share an id, or they will take each other's jobs.
+    pub fn from_host_and_name<T: AsRef<str>>(name: T) -> Self {
+        let host = std::env::var("HOSTNAME")
+            .or_else(|_| std::env::var("COMPUTERNAME"))
+            .ok()
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "localhost".to_owned());
+        Self::new(format!("{host}-{}", name.as_ref()))
+    }
+
     /// Get the name of the worker
     pub fn name(&self) -> &str {
         &self.name
@@ -117,6 +134,8 @@ pub struct Ready<S, P> {
     backend: P,
     pub(crate) shutdown: Option<Shutdown>,
     pub(crate) event_handler: EventHandler,
+    #[cfg(feature = "sleep")]
+    pub(crate) warm_up: Option<Duration>,
 }
 
 impl<S, P> fmt::Debug for Ready<S, P>
@@ -145,6 +164,8 @@ where
             backend: self.backend.clone(),
             shutdown: self.shutdown.clone(),
             event_handler: self.event_handler.clone(),
+            #[cfg(feature = "sleep")]
+            warm_up: self.warm_up,
         }
     }
 }
@@ -157,10 +178,22 @@ impl<S, P> Ready<S, P> {
             backend: poller,
             shutdown: None,
             event_handler: EventHandler::default(),
+            #[cfg(feature = "sleep")]
+            warm_up: None,
         }
     }
 }
 
+/// Picks a random duration in `[0, max)`
+#[cfg(feature = "sleep")]
+pub(crate) fn random_delay(max: Duration) -> Duration {
+    let nanos = max.as_nanos();
+    if nanos == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos((ulid::Ulid::new().random() % nanos) as u64)
+}
+
 /// Represents a generic [Worker] that can be in many different states
 #[derive(Debug, Clone, Serialize)]
 pub struct Worker<T> {
@@ -233,6 +266,15 @@ impl<S, P> Worker<Ready<S, P>> {
         self
     }
 
+    /// Wait a random delay of up to `max` before the worker starts polling
+    ///
+    /// This spreads out the first fetches when many workers start at once, eg. on a fresh deployment.
+    #[cfg(feature = "sleep")]
+    pub fn warm_up(mut self, max: Duration) -> Self {
+        self.state.warm_up = Some(max);
+        self
+    }
+
     fn poll_jobs<Svc, Stm, Req, Res, Ctx>(
         worker: Worker<Context>,
         service: Svc,
@@ -311,11 +353,19 @@ impl<S, P> Worker<Ready<S, P>> {
             id: worker_id.clone(),
             state: ctx.clone(),
         };
+        #[cfg(feature = "sleep")]
+        let warm_up = self
+            .state
+            .warm_up
+            .map(|max| crate::sleep(random_delay(max)).boxed());
+        #[cfg(not(feature = "sleep"))]
+        let warm_up = None;
         let backend = self.state.backend;
         let service = self.state.service;
         let poller = backend.poll::<S>(&worker);
         let stream = poller.stream;
         let heartbeat = poller.heartbeat.boxed();
+        let shutdown = poller.shutdown;
         let layer = poller.layer;
         let service = ServiceBuilder::new()
             .layer(TrackerLayer::new(worker.state.clone()))
@@ -327,8 +377,11 @@ impl<S, P> Worker<Ready<S, P>> {
         Runnable {
             poller: Self::poll_jobs(worker.clone(), service, stream),
             heartbeat,
+            shutdown,
             worker,
             running: false,
+            stopped: false,
+            warm_up,
         }
     }
 }
@@ -338,12 +391,19 @@ impl<S, P> Worker<Ready<S, P>> {
 /// The `Runnable` struct is responsible for coordinating the core tasks of a worker, such as polling for jobs,
 /// maintaining heartbeats, and tracking its running state. It integrates various components required for
 /// the worker to operate effectively within an asynchronous runtime.
+///
+/// Jobs are processed concurrently on the task awaiting the `Runnable`, nothing is spawned.
+/// So it can be driven by a single threaded runtime, eg `#[tokio::main(flavor = "current_thread")]`,
+/// or by a bare executor such as [`futures::executor::block_on`] on targets without one.
 #[must_use = "A Runnable must be awaited of no jobs will be consumed"]
 pub struct Runnable {
     poller: BoxStream<'static, ()>,
     heartbeat: BoxFuture<'static, ()>,
+    shutdown: Option<BoxFuture<'static, ()>>,
     worker: Worker<Context>,
     running: bool,
+    stopped: bool,
+    warm_up: Option<BoxFuture<'static, ()>>,
 }
 
 impl Runnable {
@@ -358,8 +418,11 @@ impl fmt::Debug for Runnable {
         f.debug_struct("Runnable")
             .field("poller", &"<stream>")
             .field("heartbeat", &"<future>")
+            .field("shutdown", &self.shutdown.as_ref().map(|_| "<future>"))
             .field("worker", &self.worker)
             .field("running", &self.running)
+            .field("stopped", &self.stopped)
+            .field("warm_up", &self.warm_up.is_some())
             .finish()
     }
 }
@@ -369,30 +432,44 @@ impl Future for Runnable {
 
     fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-        let poller = &mut this.poller;
-        let heartbeat = &mut this.heartbeat;
-        let worker = &mut this.worker;
+        if let Some(warm_up) = this.warm_up.as_mut() {
+            match warm_up.as_mut().poll(cx) {
+                Poll::Ready(()) => this.warm_up = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        if !this.stopped {
+            let poller = &mut this.poller;
+            let heartbeat = &mut this.heartbeat;
+            let worker = &mut this.worker;
 
-        let poller_future = async { while (poller.next().await).is_some() {} };
+            let poller_future = async { while (poller.next().await).is_some() {} };
 
-        if !this.running {
-            worker.start();
-            this.running = true;
+            if !this.running {
+                worker.start();
+                this.running = true;
+            }
+            let combined = Box::pin(join(poller_future, heartbeat.as_mut()));
+
+            let mut combined = select(
+                combined,
+                worker.state.clone().map(|_| worker.emit(Event::Stop)),
+            )
+            .boxed();
+            match Pin::new(&mut combined).poll(cx) {
+                Poll::Ready(_) => this.stopped = true,
+                Poll::Pending => return Poll::Pending,
+            }
         }
-        let combined = Box::pin(join(poller_future, heartbeat.as_mut()));
-
-        let mut combined = select(
-            combined,
-            worker.state.clone().map(|_| worker.emit(Event::Stop)),
-        )
-        .boxed();
-        match Pin::new(&mut combined).poll(cx) {
-            Poll::Ready(_) => {
-                worker.emit(Event::Exit);
-                Poll::Ready(())
+        // Running jobs are done and the heartbeat is no longer polled, let the backend wrap up
+        if let Some(shutdown) = this.shutdown.as_mut() {
+            match shutdown.as_mut().poll(cx) {
+                Poll::Ready(()) => this.shutdown = None,
+                Poll::Pending => return Poll::Pending,
             }
-            Poll::Pending => Poll::Pending,
         }
+        this.worker.emit(Event::Exit);
+        Poll::Ready(())
     }
 }
 
@@ -676,6 +753,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_builds_stable_worker_ids() {
+        let id = WorkerId::from_host_and_name("emailer");
+        assert_eq!(id, WorkerId::from_host_and_name("emailer"));
+        assert!(id.name().ends_with("-emailer"));
+    }
+
     #[tokio::test]
     async fn it_works() {
         let in_memory = MemoryStorage::new();
@@ -709,4 +793,29 @@ mod tests {
         let worker = worker.build_fn(task);
         worker.run().await;
     }
+
+    #[test]
+    fn it_runs_without_a_runtime() {
+        const JOBS: u32 = 10;
+        let mut in_memory = MemoryStorage::new();
+        futures::executor::block_on(async {
+            for i in 0..JOBS {
+                in_memory.enqueue(i).await.unwrap();
+            }
+        });
+
+        async fn task(job: u32, count: Data<Arc<AtomicUsize>>, worker: Worker<Context>) {
+            count.fetch_add(1, Ordering::Relaxed);
+            if job == JOBS - 1 {
+                worker.stop();
+            }
+        }
+        let count = Arc::new(AtomicUsize::new(0));
+        let worker = WorkerBuilder::new("embedded")
+            .data(count.clone())
+            .backend(in_memory)
+            .build_fn(task);
+        futures::executor::block_on(worker.run());
+        assert_eq!(count.load(Ordering::Relaxed), JOBS as usize);
+    }
 }
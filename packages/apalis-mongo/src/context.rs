@@ -0,0 +1,114 @@
+use apalis_core::error::Error;
+use apalis_core::request::{Request, State};
+use apalis_core::service_fn::FromRequest;
+use apalis_core::worker::WorkerId;
+use serde::{Deserialize, Serialize};
+
+/// The context for a job stored in MongoDB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MongoContext {
+    status: State,
+    run_at: i64,
+    max_attempts: i32,
+    last_error: Option<String>,
+    lock_at: Option<i64>,
+    lock_by: Option<WorkerId>,
+    done_at: Option<i64>,
+}
+
+impl Default for MongoContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MongoContext {
+    /// Build a new context with defaults
+    pub fn new() -> Self {
+        MongoContext {
+            status: State::Pending,
+            run_at: 0,
+            max_attempts: 25,
+            last_error: None,
+            lock_at: None,
+            lock_by: None,
+            done_at: None,
+        }
+    }
+
+    /// Set the number of attempts
+    pub fn set_max_attempts(&mut self, max_attempts: i32) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Gets the maximum attempts for a job. Default 25
+    pub fn max_attempts(&self) -> i32 {
+        self.max_attempts
+    }
+
+    /// Get the time a job was done, as a unix timestamp in seconds
+    pub fn done_at(&self) -> &Option<i64> {
+        &self.done_at
+    }
+
+    /// Set the time a job was done
+    pub fn set_done_at(&mut self, done_at: Option<i64>) {
+        self.done_at = done_at;
+    }
+
+    /// Get the time a job is supposed to start, as a unix timestamp in seconds
+    pub fn run_at(&self) -> i64 {
+        self.run_at
+    }
+
+    /// Set the time a job should run
+    pub fn set_run_at(&mut self, run_at: i64) {
+        self.run_at = run_at;
+    }
+
+    /// Get the time a job was locked, as a unix timestamp in seconds
+    pub fn lock_at(&self) -> &Option<i64> {
+        &self.lock_at
+    }
+
+    /// Set the lock_at value
+    pub fn set_lock_at(&mut self, lock_at: Option<i64>) {
+        self.lock_at = lock_at;
+    }
+
+    /// Get the job status
+    pub fn status(&self) -> &State {
+        &self.status
+    }
+
+    /// Set the job status
+    pub fn set_status(&mut self, status: State) {
+        self.status = status;
+    }
+
+    /// Get the worker that has claimed the job
+    pub fn lock_by(&self) -> &Option<WorkerId> {
+        &self.lock_by
+    }
+
+    /// Set `lock_by`
+    pub fn set_lock_by(&mut self, lock_by: Option<WorkerId>) {
+        self.lock_by = lock_by;
+    }
+
+    /// Get the error of the last failed attempt
+    pub fn last_error(&self) -> &Option<String> {
+        &self.last_error
+    }
+
+    /// Record an error
+    pub fn set_last_error(&mut self, error: Option<String>) {
+        self.last_error = error;
+    }
+}
+
+impl<Req> FromRequest<Request<Req, MongoContext>> for MongoContext {
+    fn from_request(req: &Request<Req, MongoContext>) -> Result<Self, Error> {
+        Ok(req.parts.context.clone())
+    }
+}
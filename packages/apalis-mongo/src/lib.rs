@@ -0,0 +1,53 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! apalis storage using MongoDB
+//!
+//! Jobs live in the `jobs` collection of the given database. Workers claim them one at a time
+//! with `findOneAndUpdate`, so a job is only ever locked by one worker, and report to the
+//! `workers` collection so that jobs of a worker that went silent are requeued.
+//! Done and killed jobs are deleted by a TTL index once [`Config::set_done_job_ttl`] has passed.
+//!
+//! [`MongoStorage`] implements [`BackendExpose`](apalis_core::backend::BackendExpose),
+//! giving the same stats, job and worker listings as the SQL storages.
+//! ```rust,no_run
+//! use apalis::prelude::*;
+//! use apalis_mongo::{Client, Config, MongoStorage};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, Serialize)]
+//! struct Email {
+//!     to: String,
+//! }
+//!
+//! async fn send_email(job: Email) -> Result<(), Error> {
+//!     Ok(())
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let mongo_url = std::env::var("MONGO_URL").expect("Missing env variable MONGO_URL");
+//!     let client = Client::with_uri_str(&mongo_url).await.unwrap();
+//!     let db = client.database("apalis");
+//!     let config = Config::new("apalis::Email");
+//!     MongoStorage::setup(&db, &config).await.unwrap();
+//!     let storage: MongoStorage<Email> = MongoStorage::new_with_config(&db, config);
+//!     let worker = WorkerBuilder::new("tasty-papaya")
+//!         .backend(storage)
+//!         .build_fn(send_email);
+//!
+//!     worker.run().await;
+//! }
+//! ```
+
+mod context;
+mod storage;
+pub use context::MongoContext;
+pub use mongodb::{Client, Database};
+pub use storage::Config;
+pub use storage::MongoError;
+pub use storage::MongoStorage;
@@ -0,0 +1,933 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use apalis_core::{
+    backend::{Backend, BackendExpose, Stat, WorkerState},
+    codec::{json::JsonCodec, Codec},
+    error::{BoxDynError, Error},
+    layers::{Ack, AckLayer},
+    poller::{controller::Controller, stream::BackendStream, Poller},
+    request::{Parts, Request, RequestStream, State},
+    response::Response,
+    storage::Storage,
+    task::{attempt::Attempt, namespace::Namespace, task_id::TaskId},
+    worker::{Context, Event, Worker, WorkerId},
+};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{self, doc, Bson, DateTime, Document},
+    options::{IndexOptions, ReturnDocument},
+    Collection, Database, IndexModel,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::MongoContext;
+
+/// The collection jobs are stored in
+const JOBS: &str = "jobs";
+
+/// The collection workers report to
+const WORKERS: &str = "workers";
+
+/// How many jobs [`BackendExpose::list_jobs`] returns per page
+const PAGE_SIZE: i64 = 10;
+
+/// Errors that can occur while talking to MongoDB
+#[derive(thiserror::Error, Debug)]
+pub enum MongoError {
+    /// The request to MongoDB failed
+    #[error("MongoDB request failed: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+
+    /// The job could not be encoded or decoded
+    #[error("Could not encode or decode the job: {0}")]
+    Codec(BoxDynError),
+
+    /// A stored job could not be read back
+    #[error("Invalid job document: {0}")]
+    InvalidDocument(String),
+}
+
+/// Config for a [MongoStorage]
+#[derive(Clone, Debug)]
+pub struct Config {
+    namespace: String,
+    poll_interval: Duration,
+    keep_alive: Duration,
+    reenqueue_orphaned_after: Duration,
+    done_job_ttl: Option<Duration>,
+}
+
+impl Config {
+    /// Create a config for the jobs of `namespace`
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_owned(),
+            poll_interval: Duration::from_millis(100),
+            keep_alive: Duration::from_secs(30),
+            reenqueue_orphaned_after: Duration::from_secs(300),
+            done_job_ttl: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+        }
+    }
+
+    /// Get the namespace
+    pub fn get_namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Get how long a worker waits before polling again once the queue is empty
+    pub fn get_poll_interval(&self) -> &Duration {
+        &self.poll_interval
+    }
+
+    /// Get how often a worker reports that it is alive
+    pub fn get_keep_alive(&self) -> &Duration {
+        &self.keep_alive
+    }
+
+    /// Get how long a worker may stay silent before its running jobs are requeued
+    pub fn get_reenqueue_orphaned_after(&self) -> &Duration {
+        &self.reenqueue_orphaned_after
+    }
+
+    /// Get how long done and killed jobs are kept before MongoDB deletes them
+    pub fn get_done_job_ttl(&self) -> Option<&Duration> {
+        self.done_job_ttl.as_ref()
+    }
+
+    /// Set the namespace
+    pub fn set_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_owned();
+        self
+    }
+
+    /// Set how long a worker waits before polling again once the queue is empty
+    pub fn set_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Set how often a worker reports that it is alive
+    pub fn set_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set how long a worker may stay silent before its running jobs are requeued
+    pub fn set_reenqueue_orphaned_after(mut self, after: Duration) -> Self {
+        self.reenqueue_orphaned_after = after;
+        self
+    }
+
+    /// Set how long done and killed jobs are kept, or `None` to keep them until vacuumed
+    ///
+    /// This becomes the TTL index created by [`MongoStorage::setup`]. MongoDB refuses to
+    /// recreate an index with different options, so change an existing TTL with `collMod`.
+    pub fn set_done_job_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.done_job_ttl = ttl;
+        self
+    }
+}
+
+/// A job as it is stored in the `jobs` collection
+#[derive(Debug, Serialize, Deserialize)]
+struct JobDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    job: Bson,
+    job_type: String,
+    status: String,
+    attempts: i32,
+    max_attempts: i32,
+    run_at: DateTime,
+    lock_by: Option<String>,
+    lock_at: Option<DateTime>,
+    done_at: Option<DateTime>,
+    last_error: Option<String>,
+}
+
+/// A worker as it is stored in the `workers` collection
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    worker_type: String,
+    storage_name: String,
+    layers: String,
+    last_seen: DateTime,
+}
+
+/// Represents a [Storage] that persists to MongoDB
+pub struct MongoStorage<T, C = JsonCodec<Value>> {
+    jobs: Collection<JobDocument>,
+    workers: Collection<WorkerDocument>,
+    config: Config,
+    controller: Controller,
+    job_type: PhantomData<T>,
+    codec: PhantomData<C>,
+}
+
+impl<T, C> fmt::Debug for MongoStorage<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MongoStorage")
+            .field("jobs", &self.jobs)
+            .field("workers", &self.workers)
+            .field("config", &self.config)
+            .field("controller", &self.controller)
+            .field("job_type", &std::any::type_name::<T>())
+            .field("codec", &std::any::type_name::<C>())
+            .finish()
+    }
+}
+
+impl<T, C> Clone for MongoStorage<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            jobs: self.jobs.clone(),
+            workers: self.workers.clone(),
+            config: self.config.clone(),
+            controller: self.controller.clone(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl MongoStorage<(), JsonCodec<Value>> {
+    /// Create the indexes the storage relies on
+    ///
+    /// Done and killed jobs get a TTL index on `done_at` according to
+    /// [`Config::set_done_job_ttl`]. Its partial filter uses `$in`, which needs MongoDB 6.0.
+    pub async fn setup(db: &Database, config: &Config) -> Result<(), MongoError> {
+        let jobs = db.collection::<JobDocument>(JOBS);
+        jobs.create_indexes(job_indexes(config.done_job_ttl))
+            .await?;
+        db.collection::<WorkerDocument>(WORKERS)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "worker_type": 1, "last_seen": -1 })
+                    .build(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl<T> MongoStorage<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Create a new instance storing jobs in `db`
+    pub fn new(db: &Database) -> Self {
+        Self::new_with_config(db, Config::new(std::any::type_name::<T>()))
+    }
+}
+
+impl<T, C> MongoStorage<T, C> {
+    /// Create a new instance storing jobs in `db` with a custom config
+    pub fn new_with_config(db: &Database, config: Config) -> Self {
+        Self {
+            jobs: db.collection(JOBS),
+            workers: db.collection(WORKERS),
+            config,
+            controller: Controller::new(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// Get the config used by the storage
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Kill a job
+    pub async fn kill(&mut self, worker_id: &WorkerId, job_id: &TaskId) -> Result<(), MongoError> {
+        self.jobs
+            .update_one(
+                doc! { "_id": job_id.to_string(), "lock_by": worker_id.to_string() },
+                doc! { "$set": { "status": State::Killed.to_string(), "done_at": DateTime::now() } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Puts the job instantly back into the queue
+    pub async fn retry(&mut self, worker_id: &WorkerId, job_id: &TaskId) -> Result<(), MongoError> {
+        self.jobs
+            .update_one(
+                doc! { "_id": job_id.to_string(), "lock_by": worker_id.to_string() },
+                doc! { "$set": {
+                    "status": State::Pending.to_string(),
+                    "done_at": Bson::Null,
+                    "lock_by": Bson::Null,
+                } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Readd jobs whose worker has not been seen since `dead_since` to the queue
+    pub async fn reenqueue_orphaned(&self, dead_since: SystemTime) -> Result<u64, MongoError> {
+        let dead_workers: Vec<String> = self
+            .workers
+            .find(doc! {
+                "worker_type": &self.config.namespace,
+                "last_seen": { "$lt": DateTime::from_system_time(dead_since) },
+            })
+            .await?
+            .map_ok(|worker| worker.id)
+            .try_collect()
+            .await?;
+        if dead_workers.is_empty() {
+            return Ok(0);
+        }
+        let res = self
+            .jobs
+            .update_many(
+                doc! {
+                    "job_type": &self.config.namespace,
+                    "status": State::Running.to_string(),
+                    "lock_by": { "$in": dead_workers },
+                },
+                doc! { "$set": {
+                    "status": State::Pending.to_string(),
+                    "done_at": Bson::Null,
+                    "lock_by": Bson::Null,
+                    "lock_at": Bson::Null,
+                    "last_error": "Job was abandoned",
+                } },
+            )
+            .await?;
+        Ok(res.modified_count)
+    }
+
+    async fn keep_alive<Service>(&self, worker_id: &WorkerId) -> Result<(), MongoError> {
+        self.workers
+            .update_one(
+                doc! { "_id": worker_id.to_string() },
+                doc! { "$set": {
+                    "worker_type": &self.config.namespace,
+                    "storage_name": std::any::type_name::<Self>(),
+                    "layers": std::any::type_name::<Service>(),
+                    "last_seen": DateTime::now(),
+                } },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+}
+
+impl<T, C> MongoStorage<T, C>
+where
+    T: DeserializeOwned,
+    C: Codec<Compact = Value>,
+{
+    /// Atomically claims the next due job for `worker_id`
+    async fn claim(
+        &self,
+        worker_id: &WorkerId,
+    ) -> Result<Option<Request<T, MongoContext>>, MongoError> {
+        let now = DateTime::now();
+        let job = self
+            .jobs
+            .find_one_and_update(
+                claimable(&self.config.namespace, now),
+                claim_update(worker_id, now),
+            )
+            .sort(doc! { "run_at": 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+        job.map(|job| self.decode(job)).transpose()
+    }
+
+    fn decode(&self, job: JobDocument) -> Result<Request<T, MongoContext>, MongoError> {
+        let (args, mut parts) = from_document(job)?;
+        let args: Value = bson::from_bson(args).map_err(|e| MongoError::Codec(Box::new(e)))?;
+        let args = C::decode(args).map_err(|e| MongoError::Codec(e.into()))?;
+        parts.namespace = Some(Namespace(self.config.namespace.clone()));
+        Ok(Request::new_with_parts(args, parts))
+    }
+
+    fn encode(
+        &self,
+        req: Request<T, MongoContext>,
+        run_at: DateTime,
+    ) -> Result<(JobDocument, Parts<MongoContext>), MongoError>
+    where
+        T: Serialize,
+    {
+        let (args, parts) = req.take_parts();
+        let args = C::encode(&args).map_err(|e| MongoError::Codec(e.into()))?;
+        let args = bson::to_bson(&args).map_err(|e| MongoError::Codec(Box::new(e)))?;
+        let job = JobDocument {
+            id: parts.task_id.to_string(),
+            job: args,
+            job_type: self.config.namespace.clone(),
+            status: State::Pending.to_string(),
+            attempts: 0,
+            max_attempts: parts.context.max_attempts(),
+            run_at,
+            lock_by: None,
+            lock_at: None,
+            done_at: None,
+            last_error: None,
+        };
+        Ok((job, parts))
+    }
+}
+
+/// The indexes of the `jobs` collection
+fn job_indexes(done_job_ttl: Option<Duration>) -> Vec<IndexModel> {
+    let mut indexes = vec![
+        IndexModel::builder()
+            .keys(doc! { "job_type": 1, "status": 1, "run_at": 1 })
+            .build(),
+        IndexModel::builder()
+            .keys(doc! { "lock_by": 1, "status": 1 })
+            .build(),
+    ];
+    if let Some(ttl) = done_job_ttl {
+        indexes.push(
+            IndexModel::builder()
+                .keys(doc! { "done_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .name("done_at_ttl".to_owned())
+                        .expire_after(ttl)
+                        .partial_filter_expression(doc! {
+                            "status": { "$in": [State::Done.to_string(), State::Killed.to_string()] },
+                        })
+                        .build(),
+                )
+                .build(),
+        );
+    }
+    indexes
+}
+
+/// Matches the jobs of `job_type` that are due at `now` and free to claim
+fn claimable(job_type: &str, now: DateTime) -> Document {
+    doc! {
+        "job_type": job_type,
+        "run_at": { "$lte": now },
+        "$or": [
+            { "status": State::Pending.to_string(), "lock_by": Bson::Null },
+            { "status": State::Retry.to_string() },
+            {
+                "status": State::Failed.to_string(),
+                "$expr": { "$lt": ["$attempts", "$max_attempts"] },
+            },
+        ],
+    }
+}
+
+/// Locks a job to `worker_id` and counts the attempt
+fn claim_update(worker_id: &WorkerId, now: DateTime) -> Document {
+    doc! {
+        "$set": {
+            "status": State::Running.to_string(),
+            "lock_by": worker_id.to_string(),
+            "lock_at": now,
+        },
+        "$inc": { "attempts": 1 },
+    }
+}
+
+/// Records the outcome of an attempt
+fn ack_update<Res>(res: &Result<Res, Error>, now: DateTime) -> Document {
+    let last_error = match res {
+        Ok(_) => Bson::Null,
+        Err(e) => Bson::String(e.to_string()),
+    };
+    doc! {
+        "$set": {
+            "status": calculate_status(res).to_string(),
+            "done_at": now,
+            "last_error": last_error,
+        },
+    }
+}
+
+fn calculate_status<Res>(res: &Result<Res, Error>) -> State {
+    match res {
+        Ok(_) => State::Done,
+        Err(Error::Abort(_)) => State::Killed,
+        Err(_) => State::Failed,
+    }
+}
+
+/// Splits a stored job into its still encoded arguments and its parts
+fn from_document(job: JobDocument) -> Result<(Bson, Parts<MongoContext>), MongoError> {
+    let mut parts = Parts::<MongoContext>::default();
+    parts.task_id = TaskId::from_str(&job.id)
+        .map_err(|e| MongoError::InvalidDocument(format!("task id {}: {e}", job.id)))?;
+    parts.attempt = Attempt::new_with_value(job.attempts.max(0) as usize);
+    let context = &mut parts.context;
+    context.set_status(
+        State::from_str(&job.status)
+            .map_err(|_| MongoError::InvalidDocument(format!("status {}", job.status)))?,
+    );
+    context.set_max_attempts(job.max_attempts);
+    context.set_run_at(seconds(job.run_at));
+    context.set_lock_by(job.lock_by.map(WorkerId::new));
+    context.set_lock_at(job.lock_at.map(seconds));
+    context.set_done_at(job.done_at.map(seconds));
+    context.set_last_error(job.last_error);
+    Ok((job.job, parts))
+}
+
+fn seconds(at: DateTime) -> i64 {
+    at.timestamp_millis().div_euclid(1000)
+}
+
+fn from_seconds(at: i64) -> DateTime {
+    DateTime::from_millis(at.saturating_mul(1000))
+}
+
+/// Tallies the `{ _id: status, count }` groups of the stats aggregation
+fn stat_from_counts(counts: impl IntoIterator<Item = (String, usize)>) -> Stat {
+    let mut stat = Stat::default();
+    for (status, count) in counts {
+        match State::from_str(&status) {
+            Ok(State::Pending) | Ok(State::Scheduled) => stat.pending += count,
+            Ok(State::Running) => stat.running += count,
+            Ok(State::Done) => stat.success += count,
+            Ok(State::Retry) => stat.retry += count,
+            Ok(State::Failed) => stat.failed += count,
+            Ok(State::Killed | State::Dead) => stat.dead += count,
+            Err(_) => {}
+        }
+    }
+    stat
+}
+
+impl<T, C> Storage for MongoStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + Unpin + 'static,
+    C: Codec<Compact = Value> + Send + Sync,
+{
+    type Job = T;
+
+    type Error = MongoError;
+
+    type Context = MongoContext;
+
+    async fn push_request(
+        &mut self,
+        req: Request<T, MongoContext>,
+    ) -> Result<Parts<MongoContext>, MongoError> {
+        let (job, parts) = self.encode(req, DateTime::now())?;
+        self.jobs.insert_one(job).await?;
+        Ok(parts)
+    }
+
+    async fn schedule_request(
+        &mut self,
+        req: Request<T, MongoContext>,
+        on: i64,
+    ) -> Result<Parts<MongoContext>, MongoError> {
+        let (job, parts) = self.encode(req, from_seconds(on))?;
+        self.jobs.insert_one(job).await?;
+        Ok(parts)
+    }
+
+    async fn len(&mut self) -> Result<i64, MongoError> {
+        let count = self
+            .jobs
+            .count_documents(doc! {
+                "job_type": &self.config.namespace,
+                "status": State::Pending.to_string(),
+            })
+            .await?;
+        Ok(count.try_into().unwrap_or(i64::MAX))
+    }
+
+    async fn fetch_by_id(
+        &mut self,
+        job_id: &TaskId,
+    ) -> Result<Option<Request<T, MongoContext>>, MongoError> {
+        let job = self
+            .jobs
+            .find_one(doc! { "_id": job_id.to_string() })
+            .await?;
+        job.map(|job| self.decode(job)).transpose()
+    }
+
+    async fn update(&mut self, job: Request<T, MongoContext>) -> Result<(), MongoError> {
+        let ctx = &job.parts.context;
+        let attempts: i32 = job.parts.attempt.current().try_into().unwrap_or(i32::MAX);
+        self.jobs
+            .update_one(
+                doc! { "_id": job.parts.task_id.to_string() },
+                doc! { "$set": {
+                    "status": ctx.status().to_string(),
+                    "attempts": attempts,
+                    "done_at": ctx.done_at().map(from_seconds),
+                    "lock_by": ctx.lock_by().as_ref().map(ToString::to_string),
+                    "lock_at": ctx.lock_at().map(from_seconds),
+                    "last_error": ctx.last_error().clone(),
+                } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn reschedule(
+        &mut self,
+        job: Request<T, MongoContext>,
+        wait: Duration,
+    ) -> Result<(), MongoError> {
+        let run_at = SystemTime::now() + wait;
+        self.jobs
+            .update_one(
+                doc! { "_id": job.parts.task_id.to_string() },
+                doc! { "$set": {
+                    "status": State::Pending.to_string(),
+                    "done_at": Bson::Null,
+                    "lock_by": Bson::Null,
+                    "lock_at": Bson::Null,
+                    "run_at": DateTime::from_system_time(run_at),
+                } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn is_empty(&mut self) -> Result<bool, MongoError> {
+        Ok(self.len().await? == 0)
+    }
+
+    async fn vacuum(&mut self) -> Result<usize, MongoError> {
+        let res = self
+            .jobs
+            .delete_many(doc! {
+                "job_type": &self.config.namespace,
+                "status": { "$in": [State::Done.to_string(), State::Killed.to_string()] },
+            })
+            .await?;
+        Ok(res.deleted_count.try_into().unwrap_or(usize::MAX))
+    }
+
+    /// Cancel a job that has not finished yet, see [`Storage::cancel`]
+    ///
+    /// A running handler is not told, the job is only left killed once it is acknowledged.
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, MongoError> {
+        let res = self
+            .jobs
+            .update_one(
+                doc! {
+                    "_id": job_id.to_string(),
+                    "$or": [
+                        { "status": { "$in": [
+                            State::Pending.to_string(),
+                            State::Retry.to_string(),
+                            State::Running.to_string(),
+                        ] } },
+                        {
+                            "status": State::Failed.to_string(),
+                            "$expr": { "$lt": ["$attempts", "$max_attempts"] },
+                        },
+                    ],
+                },
+                doc! { "$set": { "status": State::Killed.to_string(), "done_at": DateTime::now() } },
+            )
+            .await?;
+        Ok(res.modified_count > 0)
+    }
+}
+
+impl<T, C, Res> Backend<Request<T, MongoContext>, Res> for MongoStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + Unpin + 'static,
+    C: Codec<Compact = Value> + Send + Sync + 'static,
+    Res: Send + Sync,
+{
+    type Stream = BackendStream<RequestStream<Request<T, MongoContext>>>;
+
+    type Layer = AckLayer<Self, T, MongoContext, Res>;
+
+    fn poll<Svc>(self, worker: &Worker<Context>) -> Poller<Self::Stream, Self::Layer> {
+        let interval = self.config.poll_interval;
+        let stream = futures::stream::unfold(
+            (self.clone(), worker.clone()),
+            move |(storage, worker)| async move {
+                if !worker.is_ready() {
+                    apalis_core::sleep(interval).await;
+                    return Some((Ok(None), (storage, worker)));
+                }
+                let claimed = match storage.claim(worker.id()).await {
+                    Ok(Some(req)) => Ok(Some(req)),
+                    Ok(None) => {
+                        apalis_core::sleep(interval).await;
+                        Ok(None)
+                    }
+                    Err(e) => {
+                        apalis_core::sleep(interval).await;
+                        Err(Error::SourceError(Arc::new(Box::new(e))))
+                    }
+                };
+                Some((claimed, (storage, worker)))
+            },
+        )
+        .boxed();
+        let keep_alive = {
+            let storage = self.clone();
+            let worker = worker.clone();
+            async move {
+                let mut ticks = apalis_core::interval::interval(storage.config.keep_alive);
+                while ticks.next().await.is_some() {
+                    if let Err(e) = storage.keep_alive::<Self::Layer>(worker.id()).await {
+                        worker.emit(Event::Error(Box::new(e)));
+                    }
+                }
+            }
+        };
+        let reenqueue_orphaned = {
+            let storage = self.clone();
+            let worker = worker.clone();
+            async move {
+                let mut ticks = apalis_core::interval::interval(storage.config.poll_interval);
+                while ticks.next().await.is_some() {
+                    let dead_since = SystemTime::now()
+                        .checked_sub(storage.config.reenqueue_orphaned_after)
+                        .unwrap_or(UNIX_EPOCH);
+                    if let Err(e) = storage.reenqueue_orphaned(dead_since).await {
+                        worker.emit(Event::Error(Box::new(e)));
+                    }
+                }
+            }
+        };
+        Poller::new_with_layer(
+            BackendStream::new(stream, self.controller.clone()),
+            async {
+                futures::join!(keep_alive, reenqueue_orphaned);
+            }
+            .boxed(),
+            AckLayer::new(self),
+        )
+    }
+}
+
+impl<T, C, Res> Ack<T, Res> for MongoStorage<T, C>
+where
+    T: Send + Sync,
+    C: Send + Sync,
+    Res: Send + Sync,
+{
+    type Context = MongoContext;
+
+    type AckError = MongoError;
+
+    async fn ack(&mut self, ctx: &MongoContext, res: &Response<Res>) -> Result<(), MongoError> {
+        let worker_id = ctx.lock_by().as_ref().map(ToString::to_string);
+        self.jobs
+            .update_one(
+                doc! {
+                    "_id": res.task_id.to_string(),
+                    "lock_by": worker_id,
+                    // A cancelled job stays killed
+                    "status": { "$ne": State::Killed.to_string() },
+                },
+                ack_update(&res.inner, DateTime::now()),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl<T, C> BackendExpose<T> for MongoStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + Unpin + 'static,
+    C: Codec<Compact = Value> + Send + Sync + 'static,
+{
+    type Request = Request<T, Parts<MongoContext>>;
+
+    type Error = MongoError;
+
+    async fn stats(&self) -> Result<Stat, MongoError> {
+        let counts: Vec<Document> = self
+            .jobs
+            .aggregate([
+                doc! { "$match": { "job_type": &self.config.namespace } },
+                doc! { "$group": { "_id": "$status", "count": { "$sum": 1 } } },
+            ])
+            .await?
+            .try_collect()
+            .await?;
+        Ok(stat_from_counts(counts.iter().filter_map(|group| {
+            let status = group.get_str("_id").ok()?;
+            let count = match group.get("count")? {
+                Bson::Int32(count) => i64::from(*count),
+                Bson::Int64(count) => *count,
+                _ => return None,
+            };
+            Some((status.to_owned(), count.try_into().ok()?))
+        })))
+    }
+
+    async fn list_jobs(&self, status: &State, page: i32) -> Result<Vec<Self::Request>, MongoError> {
+        let skip = u64::try_from(page.saturating_sub(1)).unwrap_or_default() * PAGE_SIZE as u64;
+        let jobs: Vec<JobDocument> = self
+            .jobs
+            .find(doc! {
+                "job_type": &self.config.namespace,
+                "status": status.to_string(),
+            })
+            .sort(doc! { "done_at": -1, "run_at": -1 })
+            .skip(skip)
+            .limit(PAGE_SIZE)
+            .await?
+            .try_collect()
+            .await?;
+        jobs.into_iter()
+            .map(|job| {
+                let (args, parts) = self.decode(job)?.take_parts();
+                Ok(Request::new_with_ctx(args, parts))
+            })
+            .collect()
+    }
+
+    async fn list_workers(&self) -> Result<Vec<Worker<WorkerState>>, MongoError> {
+        let workers: Vec<WorkerDocument> = self
+            .workers
+            .find(doc! { "worker_type": &self.config.namespace })
+            .sort(doc! { "last_seen": -1 })
+            .limit(20)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(workers
+            .into_iter()
+            .map(|w| Worker::new(WorkerId::new(w.id), WorkerState::new::<Self>(w.layers)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claimable_only_matches_due_jobs_of_the_namespace() {
+        let now = DateTime::from_millis(1_700_000_000_000);
+        let filter = claimable("emails", now);
+        assert_eq!(filter.get_str("job_type").unwrap(), "emails");
+        assert_eq!(
+            filter.get_document("run_at").unwrap(),
+            &doc! { "$lte": now }
+        );
+        let statuses: Vec<&str> = filter
+            .get_array("$or")
+            .unwrap()
+            .iter()
+            .map(|branch| branch.as_document().unwrap().get_str("status").unwrap())
+            .collect();
+        assert_eq!(statuses, ["Pending", "Retry", "Failed"]);
+    }
+
+    #[test]
+    fn claim_locks_the_job_and_counts_the_attempt() {
+        let now = DateTime::from_millis(1_700_000_000_000);
+        let update = claim_update(&WorkerId::new("worker-1"), now);
+        assert_eq!(
+            update,
+            doc! {
+                "$set": { "status": "Running", "lock_by": "worker-1", "lock_at": now },
+                "$inc": { "attempts": 1 },
+            }
+        );
+    }
+
+    #[test]
+    fn ack_records_status_and_error() {
+        let now = DateTime::from_millis(1_700_000_000_000);
+        let done = ack_update::<()>(&Ok(()), now);
+        assert_eq!(
+            done.get_document("$set").unwrap(),
+            &doc! { "status": "Done", "done_at": now, "last_error": Bson::Null }
+        );
+        let aborted = ack_update::<()>(&Err(Error::Abort(Arc::new("bad input".into()))), now);
+        let set = aborted.get_document("$set").unwrap();
+        assert_eq!(set.get_str("status").unwrap(), "Killed");
+        assert!(set.get_str("last_error").unwrap().contains("bad input"));
+        let failed = ack_update::<()>(&Err(Error::Failed(Arc::new("timeout".into()))), now);
+        assert_eq!(
+            failed
+                .get_document("$set")
+                .unwrap()
+                .get_str("status")
+                .unwrap(),
+            "Failed"
+        );
+    }
+
+    #[test]
+    fn ttl_index_only_covers_finished_jobs() {
+        let indexes = job_indexes(Some(Duration::from_secs(60)));
+        let ttl = indexes.last().unwrap().options.as_ref().unwrap();
+        assert_eq!(ttl.expire_after, Some(Duration::from_secs(60)));
+        assert_eq!(
+            ttl.partial_filter_expression,
+            Some(doc! { "status": { "$in": ["Done", "Killed"] } })
+        );
+        assert_eq!(job_indexes(None).len(), 2);
+    }
+
+    #[test]
+    fn documents_round_trip_their_parts() {
+        let task_id = TaskId::new();
+        let job = JobDocument {
+            id: task_id.to_string(),
+            job: Bson::Document(doc! { "to": "a@example.com" }),
+            job_type: "emails".to_owned(),
+            status: "Running".to_owned(),
+            attempts: 2,
+            max_attempts: 5,
+            run_at: from_seconds(1_700_000_000),
+            lock_by: Some("worker-1".to_owned()),
+            lock_at: Some(from_seconds(1_700_000_010)),
+            done_at: None,
+            last_error: Some("timeout".to_owned()),
+        };
+        let (args, parts) = from_document(job).unwrap();
+        assert_eq!(args, Bson::Document(doc! { "to": "a@example.com" }));
+        assert_eq!(parts.task_id, task_id);
+        assert_eq!(parts.attempt.current(), 2);
+        assert_eq!(parts.context.status(), &State::Running);
+        assert_eq!(parts.context.max_attempts(), 5);
+        assert_eq!(parts.context.run_at(), 1_700_000_000);
+        assert_eq!(parts.context.lock_by(), &Some(WorkerId::new("worker-1")));
+        assert_eq!(parts.context.lock_at(), &Some(1_700_000_010));
+        assert_eq!(parts.context.last_error().as_deref(), Some("timeout"));
+    }
+
+    #[test]
+    fn stats_tally_status_groups() {
+        let stat = stat_from_counts([
+            ("Pending".to_owned(), 3),
+            ("Running".to_owned(), 1),
+            ("Done".to_owned(), 7),
+            ("Failed".to_owned(), 2),
+            ("Killed".to_owned(), 1),
+        ]);
+        assert_eq!(
+            stat,
+            Stat {
+                pending: 3,
+                running: 1,
+                dead: 1,
+                retry: 0,
+                failed: 2,
+                success: 7,
+            }
+        );
+    }
+}
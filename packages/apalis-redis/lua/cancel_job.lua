@@ -0,0 +1,35 @@
+-- KEYS[1]: the job stream
+-- KEYS[2]: the stream entries hash
+-- KEYS[3]: the scheduled jobs set
+-- KEYS[4]: the dead jobs set
+-- KEYS[5]: the job data hash
+
+-- ARGV[1]: the consumer group
+-- ARGV[2]: the job ID
+-- ARGV[3]: the current time
+
+-- Returns: 1 if the job was cancelled, 0 if it had already finished or does not exist
+
+-- Take the job out of the scheduled set
+local cancelled = redis.call("zrem", KEYS[3], ARGV[2])
+
+-- Drop its entry, whether it is waiting in the stream or pending with a consumer
+local entry = redis.call("hget", KEYS[2], ARGV[2])
+if entry then
+  -- There is nothing to acknowledge before the first consumer created the group
+  redis.pcall("xack", KEYS[1], ARGV[1], entry)
+  redis.call("xdel", KEYS[1], entry)
+  redis.call("hdel", KEYS[2], ARGV[2])
+  cancelled = 1
+end
+
+if cancelled == 1 then
+  -- Push the job on to the dead jobs set
+  redis.call("zadd", KEYS[4], ARGV[3], ARGV[2])
+
+  -- Save the result of the job
+  local ns = "::result"
+  redis.call("hmset", KEYS[5] .. ns, ARGV[2], "Cancelled")
+end
+
+return cancelled
@@ -1,21 +1,32 @@
--- KEYS[1]: this consumer's inflight set
--- KEYS[2]: the done jobs set
--- KEYS[3]: the job data hash
+-- KEYS[1]: the job stream
+-- KEYS[2]: the stream entries hash
+-- KEYS[3]: the done jobs set
+-- KEYS[4]: the job data hash
 
--- ARGV[1]: the job ID
--- ARGV[2]: the current time
--- ARGV[3]: the result of the job
+-- ARGV[1]: the consumer group
+-- ARGV[2]: the job ID
+-- ARGV[3]: the current time
+-- ARGV[4]: the result of the job
 
--- Returns: bool
+-- Returns: 1 if the job was acknowledged, 0 if it was no longer running
+
+local entry = redis.call("hget", KEYS[2], ARGV[2])
+if not entry then
+  return 0
+end
+
+-- Acknowledge the job and drop its entry
+local acked = redis.call("xack", KEYS[1], ARGV[1], entry)
+if acked == 1 then
+  redis.call("xdel", KEYS[1], entry)
+  redis.call("hdel", KEYS[2], ARGV[2])
 
--- Remove the job from this consumer's inflight set
-local removed = redis.call("srem", KEYS[1], ARGV[1])
-local ns = "::result"
-if removed == 1 then
   -- Push the job on to the done jobs set
-  redis.call("zadd", KEYS[2], ARGV[2], ARGV[1])
-  redis.call("hmset", KEYS[3].. ns, ARGV[1], ARGV[3] )
-  return true
+  redis.call("zadd", KEYS[3], ARGV[3], ARGV[2])
+
+  -- Save the result of the job
+  local ns = "::result"
+  redis.call("hmset", KEYS[4] .. ns, ARGV[2], ARGV[4])
 end
 
-return false
+return acked
@@ -1,11 +1,12 @@
 -- KEYS[1]: the active consumers set
--- KEYS[2]: the active job list
--- KEYS[3]: this consumer's inflight set
--- KEYS[4]: the job data hash
--- KEYS[5]: the signal list
+-- KEYS[2]: the job stream
+-- KEYS[3]: the job data hash
+-- KEYS[4]: the stream entries hash
 
--- ARGV[1]: the max number of jobs to get
--- ARGV[2]: this consumer's inflight set
+-- ARGV[1]: the consumer group
+-- ARGV[2]: this consumer's name
+-- ARGV[3]: the max number of jobs to get
+-- ARGV[4]: the time in milliseconds after which a job left idle is reclaimed
 
 -- Returns: the jobs
 
@@ -15,25 +16,42 @@ if not registered then
   error("consumer not registered")
 end
 
--- Get the jobs out of the active job list
-local job_ids = redis.call("lrange", KEYS[2], 0, ARGV[1] - 1)
-local count = table.getn(job_ids)
-local results = {}
-
-if count > 0 then
-  -- Add the jobs to the active set
-  redis.call("sadd", KEYS[3], unpack(job_ids))
+local limit = tonumber(ARGV[3])
+local entries = {}
 
-  -- Remove the jobs from the active job list
-  redis.call("ltrim", KEYS[2], count, -1)
+-- Reclaim the jobs of consumers that stopped refreshing them first
+local claimed = redis.call("xautoclaim", KEYS[2], ARGV[1], ARGV[2], ARGV[4], "0-0", "COUNT", limit)
+for _,entry in ipairs(claimed[2]) do
+  -- Entries deleted while pending come back without fields on older servers
+  if type(entry) == "table" and entry[2] then
+    table.insert(entries, entry)
+  end
+end
 
-  -- Return the job data
-  results = redis.call("hmget", KEYS[4], unpack(job_ids))
+-- Then read new jobs off the stream
+local remaining = limit - table.getn(entries)
+if remaining > 0 then
+  local read = redis.call("xreadgroup", "GROUP", ARGV[1], ARGV[2], "COUNT", remaining, "STREAMS", KEYS[2], ">")
+  if read then
+    for _,entry in ipairs(read[1][2]) do
+      table.insert(entries, entry)
+    end
+  end
 end
 
--- Signal to the other consumers to wait
-if count < tonumber(ARGV[1]) then
-  redis.call("del", KEYS[5])
+-- Return the job data
+local results = {}
+for _,entry in ipairs(entries) do
+  local job_id = entry[2][2]
+  local job = redis.call("hget", KEYS[3], job_id)
+  if job then
+    table.insert(results, job)
+  else
+    -- The job is gone, drop its entry
+    redis.call("xack", KEYS[2], ARGV[1], entry[1])
+    redis.call("xdel", KEYS[2], entry[1])
+    redis.call("hdel", KEYS[4], job_id)
+  end
 end
 
 return results
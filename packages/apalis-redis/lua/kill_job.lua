@@ -1,22 +1,32 @@
--- KEYS[1]: this consumer's inflight set
--- KEYS[2]: the dead jobs set
--- KEYS[3]: the job data hash
--- ARGV[1]: the job ID
--- ARGV[2]: the current time
--- ARGV[3]: the result of the job
--- Returns: nil
--- Remove the job from this consumer's inflight set
-local removed = redis.call("srem", KEYS[1], ARGV[1])
+-- KEYS[1]: the job stream
+-- KEYS[2]: the stream entries hash
+-- KEYS[3]: the dead jobs set
+-- KEYS[4]: the job data hash
 
-if removed == 1 then
-    -- Push the job on to the dead jobs set
-    redis.call("zadd", KEYS[2], ARGV[2], ARGV[1])
+-- ARGV[1]: the consumer group
+-- ARGV[2]: the job ID
+-- ARGV[3]: the current time
+-- ARGV[4]: the result of the job
 
-    -- Save the result of the job
-    local ns = "::result"
-    redis.call("hmset", KEYS[3] .. ns, ARGV[1], ARGV[3])
+-- Returns: 1 if the job was acknowledged, 0 if it was no longer running
 
-    return 1
+local entry = redis.call("hget", KEYS[2], ARGV[2])
+if not entry then
+  return 0
 end
 
-return 0
+-- Acknowledge the job and drop its entry
+local acked = redis.call("xack", KEYS[1], ARGV[1], entry)
+if acked == 1 then
+  redis.call("xdel", KEYS[1], entry)
+  redis.call("hdel", KEYS[2], ARGV[2])
+
+  -- Push the job on to the dead jobs set
+  redis.call("zadd", KEYS[3], ARGV[3], ARGV[2])
+
+  -- Save the result of the job
+  local ns = "::result"
+  redis.call("hmset", KEYS[4] .. ns, ARGV[2], ARGV[4])
+end
+
+return acked
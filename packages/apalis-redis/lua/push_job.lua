@@ -1,6 +1,6 @@
 -- KEYS[1]: the job data hash
--- KEYS[2]: the active job list
--- KEYS[3]: the signal list
+-- KEYS[2]: the job stream
+-- KEYS[3]: the stream entries hash
 
 -- ARGV[1]: the job ID
 -- ARGV[2]: the serialized job data
@@ -11,12 +11,9 @@
 local set = redis.call("hsetnx", KEYS[1], ARGV[1], ARGV[2])
 
 if set == 1 then
-  -- If it was set, push the job on to the active list
-  redis.call("rpush", KEYS[2], ARGV[1])
-
-  -- Signal that there are jobs in the queue
-  redis.call("del", KEYS[3])
-  redis.call("lpush", KEYS[3], 1)
+  -- If it was set, add the job to the stream and remember its entry
+  local entry = redis.call("xadd", KEYS[2], "*", "job_id", ARGV[1])
+  redis.call("hset", KEYS[3], ARGV[1], entry)
 end
 
 return set
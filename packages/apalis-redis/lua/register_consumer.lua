@@ -1,10 +1,36 @@
 -- KEYS[1]: the active consumers set
+-- KEYS[2]: the job stream
 
 -- ARGV[1]: the current time
--- ARGV[2]: this consumer's inflight set
+-- ARGV[2]: this consumer's name
+-- ARGV[3]: the consumer group
 
 -- Returns: nil
 
 -- Update the consumer in the active consumer set
 redis.call("zadd", KEYS[1], ARGV[1], ARGV[2])
+
+-- Create the consumer group, along with the stream, unless it already exists
+local created = redis.pcall("xgroup", "create", KEYS[2], ARGV[3], "0", "MKSTREAM")
+if type(created) == "table" and created.err and not string.find(created.err, "BUSYGROUP") then
+  return redis.error_reply(created.err)
+end
+
+-- Reset the idle time of the jobs this consumer is running so they are not reclaimed
+local page = 100
+local start = "-"
+repeat
+  local pending = redis.call("xpending", KEYS[2], ARGV[3], start, "+", page, ARGV[2])
+  local count = table.getn(pending)
+  if count > 0 then
+    local claim = {"xclaim", KEYS[2], ARGV[3], ARGV[2], 0}
+    for _,entry in ipairs(pending) do
+      table.insert(claim, entry[1])
+    end
+    table.insert(claim, "JUSTID")
+    redis.call(unpack(claim))
+    start = "(" .. pending[count][1]
+  end
+until count < page
+
 return true
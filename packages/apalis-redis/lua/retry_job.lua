@@ -1,29 +1,41 @@
--- KEYS[1]: this consumer's inflight set
--- KEYS[2]: the scheduled jobs set
--- KEYS[3]: the job data hash
-
--- ARGV[1]: the job ID
--- ARGV[2]: the time at which to retry
--- ARGV[3]: the result of the job
-
--- Returns: nil
-
--- Remove the job from this consumer's inflight set
-local removed = redis.call("srem", KEYS[1], ARGV[1])
+-- KEYS[1]: the job stream
+-- KEYS[2]: the stream entries hash
+-- KEYS[3]: the scheduled jobs set
+-- KEYS[4]: the failed jobs set
+-- KEYS[5]: the job data hash
+
+-- ARGV[1]: the consumer group
+-- ARGV[2]: the job ID
+-- ARGV[3]: the current time
+-- ARGV[4]: the time at which to retry
+-- ARGV[5]: the serialized job data
+-- ARGV[6]: the result of the job, if any
+
+-- Returns: 1 if the job was rescheduled, 0 if it was no longer running
+
+local entry = redis.call("hget", KEYS[2], ARGV[2])
+if not entry then
+  return 0
+end
 
-if removed == 1 then
-  -- Push the job on to the scheduled set
-  redis.call("zadd", KEYS[2], ARGV[2], ARGV[1])
+-- Acknowledge the job and drop its entry
+local acked = redis.call("xack", KEYS[1], ARGV[1], entry)
+if acked == 1 then
+  redis.call("xdel", KEYS[1], entry)
+  redis.call("hdel", KEYS[2], ARGV[2])
 
-  local job = redis.call('HGET', KEYS[3], ARGV[1])
+  -- Record the failure and push the job on to the scheduled set
+  redis.call("zadd", KEYS[4], ARGV[3], ARGV[2])
+  redis.call("zadd", KEYS[3], ARGV[4], ARGV[2])
 
-  -- Reset the job data
-  redis.call("hset", KEYS[3], ARGV[1], job)
+  -- Save the job with its attempts
+  redis.call("hset", KEYS[5], ARGV[2], ARGV[5])
 
   -- Save the result of the job
-  local ns = "::result"
-  redis.call("hmset", KEYS[3] .. ns, ARGV[1], ARGV[3] )
-
+  if ARGV[6] then
+    local ns = "::result"
+    redis.call("hmset", KEYS[5] .. ns, ARGV[2], ARGV[6])
+  end
 end
 
-return removed
+return acked
@@ -24,14 +24,21 @@ where
         let mut conn = self.get_connection().clone();
         let queue = self.get_config();
         let script = r#"
-            local pending_jobs_set = KEYS[1]
-            local running_jobs_set = KEYS[2]
+            local job_stream = KEYS[1]
+            local scheduled_jobs_set = KEYS[2]
             local dead_jobs_set = KEYS[3]
             local failed_jobs_set = KEYS[4]
             local success_jobs_set = KEYS[5]
+            local consumer_group = ARGV[1]
 
-            local pending_count = redis.call('ZCARD', pending_jobs_set)
-            local running_count = redis.call('ZCARD', running_jobs_set)
+            -- Jobs stay in the stream until they finish, the pending ones are running
+            local running_count = 0
+            local pending = redis.pcall('XPENDING', job_stream, consumer_group)
+            if type(pending) == 'table' and not pending.err then
+                running_count = pending[1]
+            end
+            local pending_count = redis.call('XLEN', job_stream) - running_count
+                + redis.call('ZCARD', scheduled_jobs_set)
             local dead_count = redis.call('ZCARD', dead_jobs_set)
             local failed_count = redis.call('ZCARD', failed_jobs_set)
             local success_count = redis.call('ZCARD', success_jobs_set)
@@ -40,8 +47,8 @@ where
     "#;
 
         let keys = vec![
-            queue.inflight_jobs_set().to_string(),
-            queue.active_jobs_list().to_string(),
+            queue.job_stream().to_string(),
+            queue.scheduled_jobs_set().to_string(),
             queue.dead_jobs_set().to_string(),
             queue.failed_jobs_set().to_string(),
             queue.done_jobs_set().to_string(),
@@ -51,6 +58,7 @@ where
             .arg(script)
             .arg(keys.len().to_string())
             .arg(keys)
+            .arg(queue.consumer_group())
             .query_async(&mut conn)
             .await?;
 
@@ -58,6 +66,7 @@ where
             pending: results[0],
             running: results[1],
             dead: results[2],
+            retry: 0,
             failed: results[3],
             success: results[4],
         })
@@ -70,11 +79,58 @@ where
         let mut conn = self.get_connection().clone();
         let queue = self.get_config();
         match status {
-            State::Pending | State::Scheduled => {
-                let active_jobs_list = &queue.active_jobs_list();
+            State::Pending => {
+                // Entries past the last one delivered to the consumer group are still waiting
+                let script = r#"
+                    local job_stream = KEYS[1]
+                    local job_data_hash = KEYS[2]
+                    local consumer_group = ARGV[1]
+                    local offset = tonumber(ARGV[2])
+                    local count = tonumber(ARGV[3])
+
+                    local last_delivered = '0-0'
+                    local groups = redis.pcall('XINFO', 'GROUPS', job_stream)
+                    if type(groups) == 'table' and not groups.err then
+                        for _, group in ipairs(groups) do
+                            local name, delivered
+                            for i = 1, table.getn(group), 2 do
+                                if group[i] == 'name' then name = group[i + 1] end
+                                if group[i] == 'last-delivered-id' then delivered = group[i + 1] end
+                            end
+                            if name == consumer_group then last_delivered = delivered end
+                        end
+                    end
+
+                    local entries = redis.call('XRANGE', job_stream, '(' .. last_delivered, '+', 'COUNT', offset + count)
+                    local ids = {}
+                    for i = offset + 1, table.getn(entries) do
+                        table.insert(ids, entries[i][2][2])
+                    end
+                    if table.getn(ids) == 0 then
+                        return {}
+                    end
+                    return redis.call('HMGET', job_data_hash, unpack(ids))
+                "#;
+                let data: Option<Value> = redis::cmd("EVAL")
+                    .arg(script)
+                    .arg(2)
+                    .arg(queue.job_stream())
+                    .arg(queue.job_data_hash())
+                    .arg(queue.consumer_group())
+                    .arg((page - 1) * 10)
+                    .arg(10)
+                    .query_async(&mut conn)
+                    .await?;
+
+                let jobs: Vec<Request<T, RedisContext>> =
+                    deserialize_multiple_jobs::<_, RedisCodec>(data.as_ref()).unwrap();
+                Ok(jobs)
+            }
+            State::Scheduled => {
+                let scheduled_jobs_set = &queue.scheduled_jobs_set();
                 let job_data_hash = &queue.job_data_hash();
-                let ids: Vec<String> = redis::cmd("LRANGE")
-                    .arg(active_jobs_list)
+                let ids: Vec<String> = redis::cmd("ZRANGE")
+                    .arg(scheduled_jobs_set)
                     .arg(((page - 1) * 10).to_string())
                     .arg((page * 10).to_string())
                     .query_async(&mut conn)
@@ -94,40 +150,44 @@ where
                 Ok(jobs)
             }
             State::Running => {
-                let consumers_set = &queue.consumers_set();
-                let job_data_hash = &queue.job_data_hash();
-                let workers: Vec<String> = redis::cmd("ZRANGE")
-                    .arg(consumers_set)
-                    .arg("0")
-                    .arg("-1")
+                // The pending entries of the consumer group are the running jobs
+                let script = r#"
+                    local job_stream = KEYS[1]
+                    local job_data_hash = KEYS[2]
+                    local consumer_group = ARGV[1]
+                    local offset = tonumber(ARGV[2])
+                    local count = tonumber(ARGV[3])
+
+                    local pending = redis.pcall('XPENDING', job_stream, consumer_group, '-', '+', offset + count)
+                    if type(pending) ~= 'table' or pending.err then
+                        return {}
+                    end
+                    local ids = {}
+                    for i = offset + 1, table.getn(pending) do
+                        local entry = redis.call('XRANGE', job_stream, pending[i][1], pending[i][1])
+                        if entry[1] then
+                            table.insert(ids, entry[1][2][2])
+                        end
+                    end
+                    if table.getn(ids) == 0 then
+                        return {}
+                    end
+                    return redis.call('HMGET', job_data_hash, unpack(ids))
+                "#;
+                let data: Option<Value> = redis::cmd("EVAL")
+                    .arg(script)
+                    .arg(2)
+                    .arg(queue.job_stream())
+                    .arg(queue.job_data_hash())
+                    .arg(queue.consumer_group())
+                    .arg((page - 1) * 10)
+                    .arg(10)
                     .query_async(&mut conn)
                     .await?;
 
-                if workers.is_empty() {
-                    return Ok(Vec::new());
-                }
-                let mut all_jobs = Vec::new();
-                for worker in workers {
-                    let ids: Vec<String> = redis::cmd("SMEMBERS")
-                        .arg(&worker)
-                        .query_async(&mut conn)
-                        .await?;
-
-                    if ids.is_empty() {
-                        continue;
-                    };
-                    let data: Option<Value> = redis::cmd("HMGET")
-                        .arg(job_data_hash.clone())
-                        .arg(&ids)
-                        .query_async(&mut conn)
-                        .await?;
-
-                    let jobs: Vec<Request<T, RedisContext>> =
-                        deserialize_multiple_jobs::<_, RedisCodec>(data.as_ref()).unwrap();
-                    all_jobs.extend(jobs);
-                }
-
-                Ok(all_jobs)
+                let jobs: Vec<Request<T, RedisContext>> =
+                    deserialize_multiple_jobs::<_, RedisCodec>(data.as_ref()).unwrap();
+                Ok(jobs)
             }
             State::Done => {
                 let done_jobs_set = &queue.done_jobs_set();
@@ -152,7 +212,7 @@ where
                     deserialize_multiple_jobs::<_, RedisCodec>(data.as_ref()).unwrap();
                 Ok(jobs)
             }
-            // State::Retry => Ok(Vec::new()),
+            State::Retry => Ok(Vec::new()),
             State::Failed => {
                 let failed_jobs_set = &queue.failed_jobs_set();
                 let job_data_hash = &queue.job_data_hash();
@@ -175,7 +235,7 @@ where
 
                 Ok(jobs)
             }
-            State::Killed => {
+            State::Killed | State::Dead => {
                 let dead_jobs_set = &queue.dead_jobs_set();
                 let job_data_hash = &queue.job_data_hash();
                 let ids: Vec<String> = redis::cmd("ZRANGE")
@@ -215,7 +275,7 @@ where
             .into_iter()
             .map(|w| {
                 Worker::new(
-                    WorkerId::new(w.replace(&format!("{}:", &queue.inflight_jobs_set()), "")),
+                    WorkerId::new(w),
                     WorkerState::new::<Self>(queue.get_namespace().to_owned()),
                 )
             })
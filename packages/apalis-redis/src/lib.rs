@@ -6,6 +6,10 @@
 )]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 //! apalis storage using Redis as a backend
+//!
+//! Jobs are delivered through a Redis stream read by a consumer group: workers claim them with
+//! `XREADGROUP` and acknowledge them with `XACK`, and reclaim the pending jobs of workers that
+//! stopped refreshing them. Delayed jobs wait in a sorted set until they are due. Requires Redis 6.2 or newer.
 //! ```rust,no_run
 //! use apalis::prelude::*;
 //! use apalis_redis::{RedisStorage, Config};
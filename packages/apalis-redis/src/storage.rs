@@ -8,11 +8,12 @@ use apalis_core::request::{Parts, Request, RequestStream};
 use apalis_core::response::Response;
 use apalis_core::service_fn::FromRequest;
 use apalis_core::storage::Storage;
+use apalis_core::task::attempt::Attempt;
 use apalis_core::task::namespace::Namespace;
 use apalis_core::task::task_id::TaskId;
 use apalis_core::worker::{Event, Worker, WorkerId};
 use apalis_core::{backend::Backend, codec::Codec};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use futures::channel::mpsc::{self, SendError, Sender};
 use futures::{select, FutureExt, SinkExt, StreamExt, TryFutureExt};
 use log::*;
@@ -34,23 +35,23 @@ pub async fn connect<S: IntoConnectionInfo>(redis: S) -> Result<ConnectionManage
     Ok(conn)
 }
 
-const ACTIVE_JOBS_LIST: &str = "{queue}:active";
+const CONSUMER_GROUP: &str = "{queue}:workers";
 const CONSUMERS_SET: &str = "{queue}:consumers";
 const DEAD_JOBS_SET: &str = "{queue}:dead";
 const DONE_JOBS_SET: &str = "{queue}:done";
 const FAILED_JOBS_SET: &str = "{queue}:failed";
-const INFLIGHT_JOB_SET: &str = "{queue}:inflight";
 const JOB_DATA_HASH: &str = "{queue}:data";
+const JOB_STREAM: &str = "{queue}:stream";
 const SCHEDULED_JOBS_SET: &str = "{queue}:scheduled";
-const SIGNAL_LIST: &str = "{queue}:signal";
+const STREAM_ENTRIES_HASH: &str = "{queue}:entries";
 
 /// Represents redis key names for various components of the RedisStorage.
 ///
 /// This struct defines keys used in Redis to manage jobs and their lifecycle in the storage.
 #[derive(Clone, Debug)]
 pub struct RedisQueueInfo {
-    /// Key for the list of currently active jobs.
-    pub active_jobs_list: String,
+    /// Name of the consumer group the workers read the job stream with.
+    pub consumer_group: String,
 
     /// Key for the set of active consumers.
     pub consumers_set: String,
@@ -64,28 +65,27 @@ pub struct RedisQueueInfo {
     /// Key for the set of jobs that have failed.
     pub failed_jobs_set: String,
 
-    /// Key for the set of jobs that are currently being processed.
-    pub inflight_jobs_set: String,
-
     /// Key for the hash storing data for each job.
     pub job_data_hash: String,
 
+    /// Key for the stream of jobs waiting for or being processed by a worker.
+    pub job_stream: String,
+
     /// Key for the set of jobs scheduled for future execution.
     pub scheduled_jobs_set: String,
 
-    /// Key for the list used for signaling and communication between consumers and producers.
-    pub signal_list: String,
+    /// Key for the hash mapping each job in the stream to its entry.
+    pub stream_entries_hash: String,
 }
 
 #[derive(Clone, Debug)]
 struct RedisScript {
+    cancel_job: Script,
     done_job: Script,
     enqueue_scheduled: Script,
     get_jobs: Script,
     kill_job: Script,
     push_job: Script,
-    reenqueue_active: Script,
-    reenqueue_orphaned: Script,
     register_consumer: Script,
     retry_job: Script,
     schedule_job: Script,
@@ -128,10 +128,6 @@ pub enum RedisPollError {
     /// Error during acknowledgment of tasks.
     #[error("Ack heartbeat encountered an error: `{0}`")]
     AckError(RedisError),
-
-    /// Error during re-enqueuing orphaned tasks.
-    #[error("ReenqueueOrphaned heartbeat encountered an error: `{0}`")]
-    ReenqueueOrphanedError(RedisError),
 }
 
 /// Config for a [RedisStorage]
@@ -227,13 +223,13 @@ impl Config {
         self
     }
 
-    /// Returns the Redis key for the list of active jobs associated with the queue.
-    /// The key is dynamically generated using the namespace of the queue.
+    /// Returns the name of the consumer group the workers of the queue read the job stream with.
+    /// The name is dynamically generated using the namespace of the queue.
     ///
     /// # Returns
-    /// A `String` representing the Redis key for the active jobs list.
-    pub fn active_jobs_list(&self) -> String {
-        ACTIVE_JOBS_LIST.replace("{queue}", &self.namespace)
+    /// A `String` representing the name of the consumer group.
+    pub fn consumer_group(&self) -> String {
+        CONSUMER_GROUP.replace("{queue}", &self.namespace)
     }
 
     /// Returns the Redis key for the set of consumers associated with the queue.
@@ -272,22 +268,22 @@ impl Config {
         FAILED_JOBS_SET.replace("{queue}", &self.namespace)
     }
 
-    /// Returns the Redis key for the set of inflight jobs associated with the queue.
+    /// Returns the Redis key for the hash storing job data associated with the queue.
     /// The key is dynamically generated using the namespace of the queue.
     ///
     /// # Returns
-    /// A `String` representing the Redis key for the inflight jobs set.
-    pub fn inflight_jobs_set(&self) -> String {
-        INFLIGHT_JOB_SET.replace("{queue}", &self.namespace)
+    /// A `String` representing the Redis key for the job data hash.
+    pub fn job_data_hash(&self) -> String {
+        JOB_DATA_HASH.replace("{queue}", &self.namespace)
     }
 
-    /// Returns the Redis key for the hash storing job data associated with the queue.
+    /// Returns the Redis key for the stream of jobs associated with the queue.
     /// The key is dynamically generated using the namespace of the queue.
     ///
     /// # Returns
-    /// A `String` representing the Redis key for the job data hash.
-    pub fn job_data_hash(&self) -> String {
-        JOB_DATA_HASH.replace("{queue}", &self.namespace)
+    /// A `String` representing the Redis key for the job stream.
+    pub fn job_stream(&self) -> String {
+        JOB_STREAM.replace("{queue}", &self.namespace)
     }
 
     /// Returns the Redis key for the set of scheduled jobs associated with the queue.
@@ -299,13 +295,13 @@ impl Config {
         SCHEDULED_JOBS_SET.replace("{queue}", &self.namespace)
     }
 
-    /// Returns the Redis key for the list of signals associated with the queue.
+    /// Returns the Redis key for the hash mapping jobs to their stream entries associated with the queue.
     /// The key is dynamically generated using the namespace of the queue.
     ///
     /// # Returns
-    /// A `String` representing the Redis key for the signal list.
-    pub fn signal_list(&self) -> String {
-        SIGNAL_LIST.replace("{queue}", &self.namespace)
+    /// A `String` representing the Redis key for the stream entries hash.
+    pub fn stream_entries_hash(&self) -> String {
+        STREAM_ENTRIES_HASH.replace("{queue}", &self.namespace)
     }
 
     /// Gets the reenqueue_orphaned_after duration.
@@ -319,9 +315,10 @@ impl Config {
     }
 
     /// Occasionally some workers die, or abandon jobs because of panics.
-    /// This is the time a task takes before its back to the queue
+    /// This is the time a task is left idle before another worker reclaims it.
     ///
-    /// Defaults to 5 minutes
+    /// Workers keep their tasks from going idle on every keep-alive, so this must be longer than
+    /// [`Config::set_keep_alive`]. Defaults to 5 minutes
     pub fn set_reenqueue_orphaned_after(mut self, after: Duration) -> Self {
         self.reenqueue_orphaned_after = after;
         self
@@ -388,6 +385,7 @@ impl<T: Serialize + DeserializeOwned, Conn> RedisStorage<T, Conn> {
             config,
             codec: PhantomData::<C>,
             scripts: RedisScript {
+                cancel_job: redis::Script::new(include_str!("../lua/cancel_job.lua")),
                 done_job: redis::Script::new(include_str!("../lua/done_job.lua")),
                 push_job: redis::Script::new(include_str!("../lua/push_job.lua")),
                 retry_job: redis::Script::new(include_str!("../lua/retry_job.lua")),
@@ -397,12 +395,6 @@ impl<T: Serialize + DeserializeOwned, Conn> RedisStorage<T, Conn> {
                 get_jobs: redis::Script::new(include_str!("../lua/get_jobs.lua")),
                 register_consumer: redis::Script::new(include_str!("../lua/register_consumer.lua")),
                 kill_job: redis::Script::new(include_str!("../lua/kill_job.lua")),
-                reenqueue_active: redis::Script::new(include_str!(
-                    "../lua/reenqueue_active_jobs.lua"
-                )),
-                reenqueue_orphaned: redis::Script::new(include_str!(
-                    "../lua/reenqueue_orphaned_jobs.lua"
-                )),
                 schedule_job: redis::Script::new(include_str!("../lua/schedule_job.lua")),
                 vacuum: redis::Script::new(include_str!("../lua/vacuum.lua")),
             },
@@ -450,9 +442,6 @@ where
         let stream: RequestStream<Request<T, RedisContext>> = Box::pin(rx);
         let worker = worker.clone();
         let heartbeat = async move {
-            let mut reenqueue_orphaned_stm =
-                apalis_core::interval::interval(config.poll_interval).fuse();
-
             let mut keep_alive_stm = apalis_core::interval::interval(config.keep_alive).fuse();
 
             let mut enqueue_scheduled_stm =
@@ -505,13 +494,6 @@ where
                             }
                         }
                     }
-                    _ = reenqueue_orphaned_stm.next() => {
-                        let dead_since = Utc::now()
-                            - chrono::Duration::from_std(config.reenqueue_orphaned_after).unwrap();
-                        if let Err(e) = self.reenqueue_orphaned((config.buffer_size * 10) as i32, dead_since).await {
-                            worker.emit(Event::Error(Box::new(RedisPollError::ReenqueueOrphanedError(e))));
-                        }
-                    }
                 };
             }
         };
@@ -526,29 +508,26 @@ where
 impl<T, Conn, Res, C> Ack<T, Res> for RedisStorage<T, Conn, C>
 where
     Res: Serialize + Sync + Send + 'static,
-    T: Sync + Send,
+    T: Serialize + DeserializeOwned + Send + 'static + Unpin + Sync,
     Conn: ConnectionLike + Send + Sync + 'static,
-    C: Codec<Compact = Vec<u8>> + Send,
+    C: Codec<Compact = Vec<u8>> + Send + 'static,
 {
     type Context = RedisContext;
     type AckError = RedisError;
-    async fn ack(&mut self, ctx: &Self::Context, res: &Response<Res>) -> Result<(), RedisError> {
-        let inflight_set = format!(
-            "{}:{}",
-            self.config.inflight_jobs_set(),
-            ctx.lock_by.clone().unwrap()
-        );
-
+    async fn ack(&mut self, _ctx: &Self::Context, res: &Response<Res>) -> Result<(), RedisError> {
         let now: i64 = Utc::now().timestamp();
         let task_id = res.task_id.to_string();
+        let attempts = res.attempt.current() + 1;
         match &res.inner {
             Ok(success_res) => {
                 let done_job = self.scripts.done_job.clone();
                 let done_jobs_set = &self.config.done_jobs_set();
                 done_job
-                    .key(inflight_set)
+                    .key(self.config.job_stream())
+                    .key(self.config.stream_entries_hash())
                     .key(done_jobs_set)
                     .key(self.config.job_data_hash())
+                    .arg(self.config.consumer_group())
                     .arg(task_id)
                     .arg(now)
                     .arg(C::encode(success_res).map_err(Into::into).unwrap())
@@ -556,29 +535,29 @@ where
                     .await
             }
             Err(e) => match e {
-                Error::Abort(e) => {
-                    let kill_job = self.scripts.kill_job.clone();
-                    let kill_jobs_set = &self.config.dead_jobs_set();
-                    kill_job
-                        .key(inflight_set)
-                        .key(kill_jobs_set)
-                        .key(self.config.job_data_hash())
-                        .arg(task_id)
-                        .arg(now)
-                        .arg(e.to_string())
-                        .invoke_async(&mut self.conn)
-                        .await
+                Error::Abort(_) => self.kill_with(&res.task_id, &e.to_string()).await,
+                _ if attempts >= self.config.max_retries => {
+                    self.kill_with(&res.task_id, &e.to_string()).await
                 }
                 _ => {
-                    // TODO: Increase the attempts
+                    let mut job = self.fetch_by_id(&res.task_id).await?.ok_or_else(|| {
+                        RedisError::from((ErrorKind::ResponseError, "Id not found"))
+                    })?;
+                    job.parts.attempt = Attempt::new_with_value(attempts);
+                    let job = C::encode(job)
+                        .map_err(|e| (ErrorKind::IoError, "Encode error", e.into().to_string()))?;
                     let retry_job = self.scripts.retry_job.clone();
-                    let retry_jobs_set = &self.config.scheduled_jobs_set();
                     retry_job
-                        .key(inflight_set)
-                        .key(retry_jobs_set)
+                        .key(self.config.job_stream())
+                        .key(self.config.stream_entries_hash())
+                        .key(self.config.scheduled_jobs_set())
+                        .key(self.config.failed_jobs_set())
                         .key(self.config.job_data_hash())
+                        .arg(self.config.consumer_group())
                         .arg(task_id)
                         .arg(now)
+                        .arg(now)
+                        .arg(job)
                         .arg(e.to_string())
                         .invoke_async(&mut self.conn)
                         .await
@@ -600,20 +579,26 @@ where
     ) -> Result<Vec<Request<T, RedisContext>>, RedisError> {
         let fetch_jobs = self.scripts.get_jobs.clone();
         let consumers_set = self.config.consumers_set();
-        let active_jobs_list = self.config.active_jobs_list();
+        let job_stream = self.config.job_stream();
         let job_data_hash = self.config.job_data_hash();
-        let inflight_set = format!("{}:{}", self.config.inflight_jobs_set(), worker_id);
-        let signal_list = self.config.signal_list();
+        let stream_entries_hash = self.config.stream_entries_hash();
         let namespace = &self.config.namespace;
+        let reclaim_after: u64 = self
+            .config
+            .reenqueue_orphaned_after
+            .as_millis()
+            .try_into()
+            .map_err(|e: TryFromIntError| (ErrorKind::IoError, "Duration error", e.to_string()))?;
 
         let result = fetch_jobs
             .key(&consumers_set)
-            .key(&active_jobs_list)
-            .key(&inflight_set)
+            .key(&job_stream)
             .key(&job_data_hash)
-            .key(&signal_list)
+            .key(&stream_entries_hash)
+            .arg(self.config.consumer_group())
+            .arg(worker_id.to_string())
             .arg(self.config.buffer_size) // No of jobs to fetch
-            .arg(&inflight_set)
+            .arg(reclaim_after)
             .invoke_async::<Vec<Value>>(&mut self.conn)
             .await;
 
@@ -660,17 +645,20 @@ fn deserialize_job(job: &Value) -> Result<&Vec<u8>, RedisError> {
 }
 
 impl<T, Conn: ConnectionLike, C> RedisStorage<T, Conn, C> {
+    /// Registers the worker, and keeps the jobs it is running from being reclaimed by others
     async fn keep_alive(&mut self, worker_id: &WorkerId) -> Result<(), RedisError> {
         let register_consumer = self.scripts.register_consumer.clone();
-        let inflight_set = format!("{}:{}", self.config.inflight_jobs_set(), worker_id);
         let consumers_set = self.config.consumers_set();
+        let job_stream = self.config.job_stream();
 
         let now: i64 = Utc::now().timestamp();
 
         register_consumer
             .key(consumers_set)
+            .key(job_stream)
             .arg(now)
-            .arg(inflight_set)
+            .arg(worker_id.to_string())
+            .arg(self.config.consumer_group())
             .invoke_async(&mut self.conn)
             .await
     }
@@ -693,18 +681,18 @@ where
         let conn = &mut self.conn;
         let push_job = self.scripts.push_job.clone();
         let job_data_hash = self.config.job_data_hash();
-        let active_jobs_list = self.config.active_jobs_list();
-        let signal_list = self.config.signal_list();
+        let job_stream = self.config.job_stream();
+        let stream_entries_hash = self.config.stream_entries_hash();
 
         let job = C::encode(&req)
             .map_err(|e| (ErrorKind::IoError, "Encode error", e.into().to_string()))?;
         push_job
             .key(job_data_hash)
-            .key(active_jobs_list)
-            .key(signal_list)
+            .key(job_stream)
+            .key(stream_entries_hash)
             .arg(req.parts.task_id.to_string())
             .arg(job)
-            .invoke_async(conn)
+            .invoke_async::<()>(conn)
             .await?;
         Ok(req.parts)
     }
@@ -725,7 +713,7 @@ where
             .arg(req.parts.task_id.to_string())
             .arg(job)
             .arg(on)
-            .invoke_async(&mut self.conn)
+            .invoke_async::<()>(&mut self.conn)
             .await?;
         Ok(req.parts)
     }
@@ -777,37 +765,26 @@ where
         job: Request<T, RedisContext>,
         wait: Duration,
     ) -> Result<(), RedisError> {
-        let schedule_job = self.scripts.schedule_job.clone();
-        let job_id = &job.parts.task_id;
-        let worker_id = &job.parts.context.lock_by.clone().unwrap();
+        let retry_job = self.scripts.retry_job.clone();
+        let job_id = job.parts.task_id.to_string();
         let job = C::encode(&job)
             .map_err(|e| (ErrorKind::IoError, "Encode error", e.into().to_string()))?;
-        let job_data_hash = self.config.job_data_hash();
-        let scheduled_jobs_set = self.config.scheduled_jobs_set();
         let on: i64 = Utc::now().timestamp();
         let wait: i64 = wait
             .as_secs()
             .try_into()
             .map_err(|e: TryFromIntError| (ErrorKind::IoError, "Duration error", e.to_string()))?;
-        let inflight_set = format!("{}:{}", self.config.inflight_jobs_set(), worker_id);
-        let failed_jobs_set = self.config.failed_jobs_set();
-        redis::cmd("SREM")
-            .arg(inflight_set)
-            .arg(job_id.to_string())
-            .query_async(&mut self.conn)
-            .await?;
-        redis::cmd("ZADD")
-            .arg(failed_jobs_set)
+        retry_job
+            .key(self.config.job_stream())
+            .key(self.config.stream_entries_hash())
+            .key(self.config.scheduled_jobs_set())
+            .key(self.config.failed_jobs_set())
+            .key(self.config.job_data_hash())
+            .arg(self.config.consumer_group())
+            .arg(job_id)
             .arg(on)
-            .arg(job_id.to_string())
-            .query_async(&mut self.conn)
-            .await?;
-        schedule_job
-            .key(job_data_hash)
-            .key(scheduled_jobs_set)
-            .arg(job_id.to_string())
-            .arg(job)
             .arg(on + wait)
+            .arg(job)
             .invoke_async(&mut self.conn)
             .await
     }
@@ -823,6 +800,28 @@ where
             .invoke_async(&mut self.conn)
             .await
     }
+
+    /// Cancel a job that has not finished yet, see [`Storage::cancel`]
+    ///
+    /// The job's entry is dropped from the stream, or from the pending entries of the worker
+    /// running it, and the job is moved to the dead jobs set. A running handler is not told, its
+    /// result is dropped when acked.
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, RedisError> {
+        let cancel_job = self.scripts.cancel_job.clone();
+        let now: i64 = Utc::now().timestamp();
+        let cancelled: i32 = cancel_job
+            .key(self.config.job_stream())
+            .key(self.config.stream_entries_hash())
+            .key(self.config.scheduled_jobs_set())
+            .key(self.config.dead_jobs_set())
+            .key(self.config.job_data_hash())
+            .arg(self.config.consumer_group())
+            .arg(job_id.to_string())
+            .arg(now)
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(cancelled == 1)
+    }
 }
 
 impl<T, Conn, C> RedisStorage<T, Conn, C>
@@ -830,86 +829,74 @@ where
     Conn: ConnectionLike + Send + Sync + 'static,
     C: Codec<Compact = Vec<u8>> + Send + 'static,
 {
-    /// Attempt to retry a job
-    pub async fn retry(&mut self, worker_id: &WorkerId, task_id: &TaskId) -> Result<i32, RedisError>
+    /// Attempt to retry a running job, killing it instead once it is out of retries
+    pub async fn retry(&mut self, task_id: &TaskId) -> Result<i32, RedisError>
     where
         T: Send + DeserializeOwned + Serialize + Unpin + Sync + 'static,
     {
         let retry_job = self.scripts.retry_job.clone();
-        let inflight_set = format!("{}:{}", self.config.inflight_jobs_set(), worker_id);
-        let scheduled_jobs_set = self.config.scheduled_jobs_set();
-        let job_data_hash = self.config.job_data_hash();
-        let failed_jobs_set = self.config.failed_jobs_set();
-        let job_fut = self.fetch_by_id(task_id);
         let now: i64 = Utc::now().timestamp();
-        let res = job_fut.await?;
-        let conn = &mut self.conn;
+        let res = self.fetch_by_id(task_id).await?;
         match res {
             Some(job) => {
                 let attempt = &job.parts.attempt;
                 if attempt.current() >= self.config.max_retries {
-                    redis::cmd("ZADD")
-                        .arg(failed_jobs_set)
-                        .arg(now)
-                        .arg(task_id.to_string())
-                        .query_async(conn)
-                        .await?;
-                    self.kill(worker_id, task_id).await?;
+                    self.kill(task_id).await?;
                     return Ok(1);
                 }
                 let job = C::encode(job)
                     .map_err(|e| (ErrorKind::IoError, "Encode error", e.into().to_string()))?;
 
-                let res: Result<i32, RedisError> = retry_job
-                    .key(inflight_set)
-                    .key(scheduled_jobs_set)
-                    .key(job_data_hash)
+                retry_job
+                    .key(self.config.job_stream())
+                    .key(self.config.stream_entries_hash())
+                    .key(self.config.scheduled_jobs_set())
+                    .key(self.config.failed_jobs_set())
+                    .key(self.config.job_data_hash())
+                    .arg(self.config.consumer_group())
                     .arg(task_id.to_string())
                     .arg(now)
+                    .arg(now)
                     .arg(job)
-                    .invoke_async(conn)
-                    .await;
-                match res {
-                    Ok(count) => Ok(count),
-                    Err(e) => Err(e),
-                }
+                    .invoke_async(&mut self.conn)
+                    .await
             }
             None => Err(RedisError::from((ErrorKind::ResponseError, "Id not found"))),
         }
     }
 
-    /// Attempt to kill a job
-    pub async fn kill(&mut self, worker_id: &WorkerId, task_id: &TaskId) -> Result<(), RedisError>
-    where
-        T: Send + DeserializeOwned + Serialize + Unpin + Sync + 'static,
-    {
+    /// Attempt to kill a running job
+    pub async fn kill(&mut self, task_id: &TaskId) -> Result<(), RedisError> {
+        self.kill_with(task_id, "AbortError").await
+    }
+
+    async fn kill_with(&mut self, task_id: &TaskId, result: &str) -> Result<(), RedisError> {
         let kill_job = self.scripts.kill_job.clone();
-        let current_worker_id = format!("{}:{}", self.config.inflight_jobs_set(), worker_id);
-        let job_data_hash = self.config.job_data_hash();
-        let dead_jobs_set = self.config.dead_jobs_set();
         let now: i64 = Utc::now().timestamp();
         kill_job
-            .key(current_worker_id)
-            .key(dead_jobs_set)
-            .key(job_data_hash)
+            .key(self.config.job_stream())
+            .key(self.config.stream_entries_hash())
+            .key(self.config.dead_jobs_set())
+            .key(self.config.job_data_hash())
+            .arg(self.config.consumer_group())
             .arg(task_id.to_string())
             .arg(now)
-            .arg("AbortError")
+            .arg(result)
             .invoke_async(&mut self.conn)
             .await
     }
 
-    /// Required to add scheduled jobs to the active set
+    /// Required to add scheduled jobs to the job stream
     pub async fn enqueue_scheduled(&mut self, count: usize) -> Result<usize, RedisError> {
         let enqueue_jobs = self.scripts.enqueue_scheduled.clone();
         let scheduled_jobs_set = self.config.scheduled_jobs_set();
-        let active_jobs_list = self.config.active_jobs_list();
-        let signal_list = self.config.signal_list();
+        let job_stream = self.config.job_stream();
+        let stream_entries_hash = self.config.stream_entries_hash();
         let now: i64 = Utc::now().timestamp();
         let res: Result<usize, _> = enqueue_jobs
             .key(scheduled_jobs_set)
-            .key(active_jobs_list)
-            .key(signal_list)
+            .key(job_stream)
+            .key(stream_entries_hash)
             .arg(now)
             .arg(count)
             .invoke_async(&mut self.conn)
@@ -919,53 +906,6 @@ where
             Err(e) => Err(e),
         }
     }
-
-    /// Re-enqueue some jobs that might be abandoned.
-    pub async fn reenqueue_active(&mut self, job_ids: Vec<&TaskId>) -> Result<(), RedisError> {
-        let reenqueue_active = self.scripts.reenqueue_active.clone();
-        let inflight_set = self.config.inflight_jobs_set().to_string();
-        let active_jobs_list = self.config.active_jobs_list();
-        let signal_list = self.config.signal_list();
-
-        reenqueue_active
-            .key(inflight_set)
-            .key(active_jobs_list)
-            .key(signal_list)
-            .arg(
-                job_ids
-                    .into_iter()
-                    .map(|j| j.to_string())
-                    .collect::<Vec<String>>(),
-            )
-            .invoke_async(&mut self.conn)
-            .await
-    }
-    /// Re-enqueue some jobs that might be orphaned after a number of seconds
-    pub async fn reenqueue_orphaned(
-        &mut self,
-        count: i32,
-        dead_since: DateTime<Utc>,
-    ) -> Result<usize, RedisError> {
-        let reenqueue_orphaned = self.scripts.reenqueue_orphaned.clone();
-        let consumers_set = self.config.consumers_set();
-        let active_jobs_list = self.config.active_jobs_list();
-        let signal_list = self.config.signal_list();
-
-        let dead_since = dead_since.timestamp();
-
-        let res: Result<usize, RedisError> = reenqueue_orphaned
-            .key(consumers_set)
-            .key(active_jobs_list)
-            .key(signal_list)
-            .arg(dead_since)
-            .arg(count)
-            .invoke_async(&mut self.conn)
-            .await;
-        match res {
-            Ok(count) => Ok(count),
-            Err(e) => Err(e),
-        }
-    }
 }
 
 #[cfg(test)]
@@ -1095,65 +1035,131 @@ mod tests {
         let job = consume_one(&mut storage, &worker.id()).await;
         let job_id = &job.parts.task_id;
 
-        storage
-            .kill(&worker.id(), &job_id)
-            .await
-            .expect("failed to kill job");
+        storage.kill(&job_id).await.expect("failed to kill job");
 
         let _job = get_job(&mut storage, &job_id).await;
     }
 
     #[tokio::test]
-    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_1sec() {
+    async fn test_cancel_job() {
+        let mut storage = setup().await;
+
+        push_email(&mut storage, example_email()).await;
+
+        let worker = register_worker(&mut storage).await;
+
+        let running = consume_one(&mut storage, worker.id()).await;
+        let running_id = &running.parts.task_id;
+        assert!(storage
+            .cancel(running_id)
+            .await
+            .expect("failed to cancel running job"));
+        assert!(!storage
+            .cancel(running_id)
+            .await
+            .expect("failed to cancel cancelled job"));
+
+        let res = 42usize;
+        storage
+            .ack(
+                &running.parts.context,
+                &Response::success(res, running_id.clone(), running.parts.attempt.clone()),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+        let done: Option<f64> = redis::cmd("ZSCORE")
+            .arg(storage.config.done_jobs_set())
+            .arg(running_id.to_string())
+            .query_async(&mut storage.conn)
+            .await
+            .expect("failed to read done jobs");
+        assert_eq!(done, None);
+    }
+
+    #[tokio::test]
+    async fn test_reclaim_job_left_idle() {
         let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_reenqueue_orphaned_after(Duration::from_millis(500));
 
         push_email(&mut storage, example_email()).await;
 
         let worker = register_worker_at(&mut storage).await;
+        let job = consume_one(&mut storage, worker.id()).await;
 
-        let job = consume_one(&mut storage, &worker.id()).await;
         sleep(Duration::from_millis(1000)).await;
-        let dead_since = Utc::now() - chrono::Duration::from_std(Duration::from_secs(1)).unwrap();
-        let res = storage
-            .reenqueue_orphaned(1, dead_since)
+        let other = Worker::new(WorkerId::new("other-worker"), Context::default());
+        storage
+            .keep_alive(other.id())
             .await
-            .expect("failed to reenqueue_orphaned");
-        // We expect 1 job to be re-enqueued
-        assert_eq!(res, 1);
-        let job = get_job(&mut storage, &job.parts.task_id).await;
-        let ctx = &job.parts.context;
-        // assert_eq!(*ctx.status(), State::Pending);
-        // assert!(ctx.done_at().is_none());
-        assert!(ctx.lock_by.is_none());
-        // assert!(ctx.lock_at().is_none());
-        // assert_eq!(*ctx.last_error(), Some("Job was abandoned".to_owned()));
-        // TODO: Redis should store context aside
-        // assert_eq!(job.parts.attempt.current(), 1);
+            .expect("failed to register worker");
+        let reclaimed = consume_one(&mut storage, other.id()).await;
+        assert_eq!(reclaimed.parts.task_id, job.parts.task_id);
+        assert_eq!(reclaimed.parts.context.lock_by, Some(other.id().clone()));
     }
 
     #[tokio::test]
-    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_5sec() {
+    async fn test_keep_alive_prevents_reclaim() {
         let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_reenqueue_orphaned_after(Duration::from_millis(500));
 
         push_email(&mut storage, example_email()).await;
 
         let worker = register_worker_at(&mut storage).await;
-        sleep(Duration::from_millis(1100)).await;
-        let job = consume_one(&mut storage, &worker.id()).await;
-        let dead_since = Utc::now() - chrono::Duration::from_std(Duration::from_secs(5)).unwrap();
-        let res = storage
-            .reenqueue_orphaned(1, dead_since)
+        let _job = consume_one(&mut storage, worker.id()).await;
+
+        sleep(Duration::from_millis(1000)).await;
+        storage
+            .keep_alive(worker.id())
+            .await
+            .expect("failed to refresh worker");
+        let other = Worker::new(WorkerId::new("other-worker"), Context::default());
+        storage
+            .keep_alive(other.id())
             .await
-            .expect("failed to reenqueue_orphaned");
-        // We expect 0 job to be re-enqueued
-        assert_eq!(res, 0);
-        let job = get_job(&mut storage, &job.parts.task_id).await;
-        let _ctx = &job.parts.context;
-        // assert_eq!(*ctx.status(), State::Running);
-        // TODO: update redis context
-        // assert_eq!(ctx.lock_by, Some(worker_id));
-        // assert!(ctx.lock_at().is_some());
-        // assert_eq!(*ctx.last_error(), None);
-        assert_eq!(job.parts.attempt.current(), 0);
+            .expect("failed to register worker");
+        let jobs = storage
+            .fetch_next(other.id())
+            .await
+            .expect("failed to fetch jobs");
+        assert!(jobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_is_retried_with_its_attempts() {
+        let mut storage = setup().await;
+        push_email(&mut storage, example_email()).await;
+
+        let worker = register_worker(&mut storage).await;
+
+        let job = consume_one(&mut storage, worker.id()).await;
+        let job_id = &job.parts.task_id;
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::<()>::failure(
+                    Error::Failed(std::sync::Arc::new("oops".into())),
+                    job_id.clone(),
+                    job.parts.attempt.clone(),
+                ),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        assert_eq!(
+            storage
+                .enqueue_scheduled(10)
+                .await
+                .expect("failed to enqueue"),
+            1
+        );
+        let job = consume_one(&mut storage, worker.id()).await;
+        assert_eq!(&job.parts.task_id, job_id);
+        assert_eq!(job.parts.attempt.current(), 1);
     }
 }
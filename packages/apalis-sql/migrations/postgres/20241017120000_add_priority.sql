@@ -0,0 +1,26 @@
+ALTER TABLE apalis.jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE OR replace FUNCTION apalis.get_jobs_by_priority(
+        worker_id TEXT,
+        v_job_type TEXT,
+        v_job_count integer DEFAULT 5 :: integer,
+        v_age_boost integer DEFAULT 3600 :: integer
+    ) returns setof apalis.jobs AS $$ BEGIN RETURN QUERY
+UPDATE apalis.jobs
+SET status = 'Running',
+    lock_by = worker_id,
+    lock_at = now()
+WHERE id IN (
+        SELECT id
+        FROM apalis.jobs
+        WHERE status = 'Pending'
+            AND run_at < now()
+            AND job_type = v_job_type
+        ORDER BY priority + FLOOR(EXTRACT(EPOCH FROM now() - run_at) / v_age_boost) DESC,
+            run_at ASC
+        limit v_job_count FOR
+        UPDATE skip LOCKED
+    )
+returning *;
+END;
+$$ LANGUAGE plpgsql volatile;
@@ -0,0 +1,3 @@
+ALTER TABLE Jobs ADD COLUMN deadline INTEGER;
+
+CREATE INDEX IF NOT EXISTS DIdx ON Jobs(deadline);
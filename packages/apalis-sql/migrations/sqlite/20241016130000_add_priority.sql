@@ -0,0 +1 @@
+ALTER TABLE Jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE Jobs ADD COLUMN dedup_key TEXT;
+CREATE INDEX IF NOT EXISTS KIdx ON Jobs(dedup_key);
@@ -0,0 +1 @@
+ALTER TABLE Jobs ADD COLUMN max_backoff_secs INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE Workers ADD COLUMN concurrency INTEGER;
+ALTER TABLE Workers ADD COLUMN in_flight INTEGER;
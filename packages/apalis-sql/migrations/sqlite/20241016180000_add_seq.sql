@@ -0,0 +1,4 @@
+ALTER TABLE Jobs ADD COLUMN seq INTEGER;
+UPDATE Jobs SET seq = rowid;
+
+CREATE INDEX IF NOT EXISTS QIdx ON Jobs(seq);
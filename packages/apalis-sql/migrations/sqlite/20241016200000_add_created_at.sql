@@ -0,0 +1 @@
+ALTER TABLE Jobs ADD COLUMN created_at INTEGER;
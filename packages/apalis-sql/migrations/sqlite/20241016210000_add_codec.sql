@@ -0,0 +1 @@
+ALTER TABLE Jobs ADD COLUMN codec INTEGER NOT NULL DEFAULT 0;
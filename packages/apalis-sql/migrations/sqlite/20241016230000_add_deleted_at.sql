@@ -0,0 +1 @@
+ALTER TABLE Jobs ADD COLUMN deleted_at INTEGER;
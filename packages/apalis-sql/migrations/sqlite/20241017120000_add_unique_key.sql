@@ -0,0 +1,4 @@
+ALTER TABLE Jobs ADD COLUMN unique_key TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS UKIdx ON Jobs(job_type, unique_key)
+    WHERE unique_key IS NOT NULL AND status NOT IN ('Done', 'Killed')
+    AND NOT (status = 'Failed' AND attempts >= max_attempts) AND deleted_at IS NULL;
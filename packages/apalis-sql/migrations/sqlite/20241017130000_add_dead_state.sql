@@ -0,0 +1,4 @@
+UPDATE Jobs SET status = 'Dead' WHERE status = 'Failed' AND attempts >= max_attempts;
+DROP INDEX IF EXISTS UKIdx;
+CREATE UNIQUE INDEX IF NOT EXISTS UKIdx ON Jobs(job_type, unique_key)
+    WHERE unique_key IS NOT NULL AND status NOT IN ('Done', 'Killed', 'Dead') AND deleted_at IS NULL;
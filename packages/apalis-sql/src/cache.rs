@@ -0,0 +1,74 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use apalis_core::backend::Stat;
+
+/// Memoizes job counts so frequent readers don't run a full count each time
+///
+/// A fresh value is served from memory until it is older than the ttl passed to [`CachedCounts::get`].
+/// While one caller refreshes a stale value, concurrent callers get the stale one instead of
+/// querying as well. Clones share the same cache.
+#[derive(Debug, Clone, Default)]
+pub struct CachedCounts {
+    state: Arc<Mutex<CacheState>>,
+    refreshes: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    value: Option<(Instant, Stat)>,
+    refreshing: bool,
+}
+
+impl CachedCounts {
+    /// Build an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the cached counts, calling `fetch` if they are missing or older than `ttl`
+    pub async fn get<F, Fut, E>(&self, ttl: Duration, fetch: F) -> Result<Stat, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Stat, E>>,
+    {
+        {
+            let mut state = self.state.lock().unwrap();
+            match &state.value {
+                Some((at, stat)) if at.elapsed() < ttl => return Ok(stat.clone()),
+                Some((_, stat)) if state.refreshing => return Ok(stat.clone()),
+                _ => state.refreshing = true,
+            }
+        }
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+        let guard = RefreshGuard(&self.state);
+        let res = fetch().await;
+        if let Ok(stat) = &res {
+            guard.0.lock().unwrap().value = Some((Instant::now(), stat.clone()));
+        }
+        res
+    }
+
+    /// The number of times the counts were fetched from the database
+    pub fn refreshes(&self) -> usize {
+        self.refreshes.load(Ordering::Relaxed)
+    }
+
+    /// Forget the cached counts so the next read hits the database
+    pub fn invalidate(&self) {
+        self.state.lock().unwrap().value = None;
+    }
+}
+
+/// Clears the refreshing flag even if the refresh is cancelled
+struct RefreshGuard<'a>(&'a Mutex<CacheState>);
+
+impl Drop for RefreshGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.refreshing = false;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Stops claiming jobs that keep failing so they can't starve the rest of the queue
+///
+/// After `threshold` consecutive failures for a key the breaker opens and the key is skipped
+/// for `cooldown`. Once the cooldown elapses the key is claimable again with a fresh count.
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    cooldown: Duration,
+    state: Arc<Mutex<HashMap<String, Breaker>>>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: usize,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Build a new breaker opening after `threshold` consecutive failures for `cooldown`
+    pub fn new(threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Arc::default(),
+        }
+    }
+
+    /// Gets the consecutive failures that open the breaker
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Gets how long an open breaker skips its key
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Record a failure for a key, opening the breaker once the threshold is reached
+    pub fn record_failure(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        let breaker = state.entry(key.to_owned()).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.threshold {
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Record a success for a key, closing its breaker
+    pub fn record_success(&self, key: &str) {
+        self.state.lock().unwrap().remove(key);
+    }
+
+    /// Returns true if jobs with this key should not be claimed
+    pub fn is_open(&self, key: &str) -> bool {
+        self.open_keys().iter().any(|k| k == key)
+    }
+
+    /// Returns all keys that should not be claimed, forgetting those whose cooldown elapsed
+    pub fn open_keys(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.retain(|_, breaker| breaker.open_until.map_or(true, |until| until > now));
+        state
+            .iter()
+            .filter(|(_, breaker)| breaker.open_until.is_some())
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
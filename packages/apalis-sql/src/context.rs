@@ -1,9 +1,12 @@
-use apalis_core::request::Request;
+use apalis_core::request::{Parts, Request};
 use apalis_core::service_fn::FromRequest;
+use apalis_core::storage::Storage;
 use apalis_core::worker::WorkerId;
 use apalis_core::{error::Error, request::State};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
 
 /// The context for a job is represented here
 /// Used to provide a context for a job with an sql backend
@@ -16,6 +19,22 @@ pub struct SqlContext {
     lock_at: Option<i64>,
     lock_by: Option<WorkerId>,
     done_at: Option<i64>,
+    deadline: Option<i64>,
+    priority: i32,
+    dedup_key: Option<String>,
+    max_backoff_secs: Option<i64>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    effect_token: Option<String>,
+    #[serde(default)]
+    created_at: Option<i64>,
+    #[serde(default)]
+    codec_tag: u8,
+    #[serde(default)]
+    counters: HashMap<String, i64>,
+    #[serde(default)]
+    unique_key: Option<String>,
 }
 
 impl Default for SqlContext {
@@ -35,6 +54,16 @@ impl SqlContext {
             max_attempts: 25,
             last_error: None,
             lock_by: None,
+            deadline: None,
+            priority: 0,
+            dedup_key: None,
+            max_backoff_secs: None,
+            headers: HashMap::new(),
+            effect_token: None,
+            created_at: None,
+            codec_tag: 0,
+            counters: HashMap::new(),
+            unique_key: None,
         }
     }
 
@@ -98,6 +127,130 @@ impl SqlContext {
         self.lock_by = lock_by;
     }
 
+    /// Get the time a job should be done by
+    pub fn deadline(&self) -> &Option<i64> {
+        &self.deadline
+    }
+
+    /// Set the time a job should be done by, as a unix timestamp in seconds
+    ///
+    /// Used by [`FetchOrder::EarliestDeadline`](crate::FetchOrder::EarliestDeadline)
+    pub fn set_deadline(&mut self, deadline: Option<i64>) {
+        self.deadline = deadline;
+    }
+
+    /// Get the priority of a job. Default 0
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Set the priority of a job, higher runs first
+    ///
+    /// Used by [`FetchOrder::Priority`](crate::FetchOrder::Priority)
+    pub fn set_priority(&mut self, priority: i32) {
+        self.priority = priority;
+    }
+
+    /// Get the business key identifying the work a job does
+    pub fn dedup_key(&self) -> &Option<String> {
+        &self.dedup_key
+    }
+
+    /// Set the business key identifying the work a job does, eg an order id
+    ///
+    /// Used by [`SqliteStorage::has_completed`](crate::sqlite::SqliteStorage::has_completed) to find earlier successful runs
+    pub fn set_dedup_key(&mut self, dedup_key: Option<String>) {
+        self.dedup_key = dedup_key;
+    }
+
+    /// Get the key that at most one live job of a namespace may hold
+    pub fn unique_key(&self) -> &Option<String> {
+        &self.unique_key
+    }
+
+    /// Set the key that at most one live job of a namespace may hold
+    ///
+    /// Pushing a job while a pending, running or retrying job holds its key pushes nothing
+    /// and returns the parts with the id of the job holding it.
+    /// Only the sqlite storage enforces this.
+    pub fn set_unique_key(&mut self, unique_key: Option<String>) {
+        self.unique_key = unique_key;
+    }
+
+    /// Get the longest a job may wait between attempts, in seconds
+    pub fn max_backoff_secs(&self) -> &Option<i64> {
+        &self.max_backoff_secs
+    }
+
+    /// Cap the delay before each retry of a job, in seconds
+    ///
+    /// Keeps a retry delay that grows with the attempts from pushing a flapping job far into the future
+    pub fn set_max_backoff_secs(&mut self, max_backoff_secs: Option<i64>) {
+        self.max_backoff_secs = max_backoff_secs;
+    }
+
+    /// Get the headers pushed along with the job
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Set the headers of a job
+    ///
+    /// Headers carry metadata such as where a job came from, which handlers can read without it being part of the job itself
+    pub fn set_headers(&mut self, headers: HashMap<String, String>) {
+        self.headers = headers;
+    }
+
+    /// Get the token of the external effect an earlier run of the job recorded
+    ///
+    /// Set when the job was claimed, so a retried or reclaimed job can skip an effect that already happened.
+    /// See [`SqliteStorage::record_effect`](crate::sqlite::SqliteStorage::record_effect)
+    pub fn effect_token(&self) -> &Option<String> {
+        &self.effect_token
+    }
+
+    /// Set the token of the effect performed by the job
+    pub fn set_effect_token(&mut self, effect_token: Option<String>) {
+        self.effect_token = effect_token;
+    }
+
+    /// Get when the job was pushed, in seconds
+    ///
+    /// `None` for jobs pushed before the column existed, or not yet pushed.
+    pub fn created_at(&self) -> &Option<i64> {
+        &self.created_at
+    }
+
+    /// Set when the job was created, in seconds
+    ///
+    /// Pushing a job stores this instead of the current time, see
+    /// [`SqliteStorage::push_with_created_at`](crate::sqlite::SqliteStorage::push_with_created_at)
+    pub fn set_created_at(&mut self, created_at: Option<i64>) {
+        self.created_at = created_at;
+    }
+
+    /// Get the tag of the codec the payload was encoded with, see [`SqliteConfig::set_codec_tag`](crate::sqlite::SqliteConfig::set_codec_tag)
+    pub fn codec_tag(&self) -> u8 {
+        self.codec_tag
+    }
+
+    /// Set the tag of the codec the payload was encoded with
+    pub fn set_codec_tag(&mut self, codec_tag: u8) {
+        self.codec_tag = codec_tag;
+    }
+
+    /// Get the application counters of the job, as of when it was fetched
+    ///
+    /// See [`SqliteStorage::bump_counter`](crate::sqlite::SqliteStorage::bump_counter)
+    pub fn counters(&self) -> &HashMap<String, i64> {
+        &self.counters
+    }
+
+    /// Set the application counters of the job
+    pub fn set_counters(&mut self, counters: HashMap<String, i64>) {
+        self.counters = counters;
+    }
+
     /// Get the time a job was locked
     pub fn last_error(&self) -> &Option<String> {
         &self.last_error
@@ -114,3 +267,81 @@ impl<Req> FromRequest<Request<Req, SqlContext>> for SqlContext {
         Ok(req.parts.context.clone())
     }
 }
+
+/// Builds a job with its own attempts, priority and start time, then pushes it to a sql storage
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use apalis_sql::{context::RequestBuilder, sqlite::{SqlitePool, SqliteStorage}};
+/// # async fn push(pool: SqlitePool) -> Result<(), apalis_sql::StorageError> {
+/// let mut storage = SqliteStorage::<String>::new(pool);
+/// RequestBuilder::new("hello@example.com".to_owned())
+///     .max_attempts(3)
+///     .priority(10)
+///     .delay(Duration::from_secs(60))
+///     .push(&mut storage)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestBuilder<T> {
+    request: Request<T, SqlContext>,
+    run_at: Option<i64>,
+}
+
+impl<T> RequestBuilder<T> {
+    /// Start building a request for `job`
+    pub fn new(job: T) -> Self {
+        Self {
+            request: Request::new(job),
+            run_at: None,
+        }
+    }
+
+    /// Give up on the job after `max_attempts` attempts instead of 25
+    pub fn max_attempts(mut self, max_attempts: i32) -> Self {
+        self.request.parts.context.set_max_attempts(max_attempts);
+        self
+    }
+
+    /// Set the priority of the job, see [`SqlContext::set_priority`]
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.request.parts.context.set_priority(priority);
+        self
+    }
+
+    /// Push nothing while a live job holds `key`, see [`SqlContext::set_unique_key`]
+    pub fn unique_key(mut self, key: impl Into<String>) -> Self {
+        self.request.parts.context.set_unique_key(Some(key.into()));
+        self
+    }
+
+    /// Run the job no earlier than `on`, a unix timestamp in seconds
+    pub fn run_at(mut self, on: i64) -> Self {
+        self.run_at = Some(on);
+        self
+    }
+
+    /// Run the job no earlier than `delay` from now
+    pub fn delay(self, delay: Duration) -> Self {
+        let on = Utc::now().timestamp() + i64::try_from(delay.as_secs()).unwrap_or(i64::MAX / 2);
+        self.run_at(on)
+    }
+
+    /// The request, for [`Storage::push_request`]. Its start time is lost, use [`RequestBuilder::push`] to keep it
+    pub fn build(self) -> Request<T, SqlContext> {
+        self.request
+    }
+
+    /// Push the job to `storage`, scheduling it if a start time was set
+    pub async fn push<S>(self, storage: &mut S) -> Result<Parts<SqlContext>, S::Error>
+    where
+        S: Storage<Job = T, Context = SqlContext>,
+    {
+        match self.run_at {
+            Some(on) => storage.schedule_request(self.request, on).await,
+            None => storage.push_request(self.request).await,
+        }
+    }
+}
@@ -56,12 +56,57 @@ impl<'r, T: Decode<'r, sqlx::Sqlite> + Type<sqlx::Sqlite>>
         let done_at: Option<i64> = row.try_get("done_at").unwrap_or_default();
         context.set_done_at(done_at);
 
+        // `lock_at` is stored in milliseconds, older rows may still hold seconds
         let lock_at: Option<i64> = row.try_get("lock_at").unwrap_or_default();
-        context.set_lock_at(lock_at);
+        context.set_lock_at(lock_at.map(|t| if t < 100_000_000_000 { t * 1000 } else { t }));
 
         let last_error = row.try_get("last_error").unwrap_or_default();
         context.set_last_error(last_error);
 
+        let deadline: Option<i64> = row.try_get("deadline").unwrap_or_default();
+        context.set_deadline(deadline);
+
+        let priority: i32 = row.try_get("priority").unwrap_or_default();
+        context.set_priority(priority);
+
+        let dedup_key: Option<String> = row.try_get("dedup_key").unwrap_or_default();
+        context.set_dedup_key(dedup_key);
+
+        let max_backoff_secs: Option<i64> = row.try_get("max_backoff_secs").unwrap_or_default();
+        context.set_max_backoff_secs(max_backoff_secs);
+
+        let headers: Option<String> = row.try_get("headers").unwrap_or_default();
+        if let Some(headers) = headers {
+            context.set_headers(serde_json::from_str(&headers).map_err(|e| {
+                sqlx::Error::ColumnDecode {
+                    index: "headers".to_string(),
+                    source: Box::new(e),
+                }
+            })?);
+        }
+
+        let effect_token: Option<String> = row.try_get("effect_token").unwrap_or_default();
+        context.set_effect_token(effect_token);
+
+        let created_at: Option<i64> = row.try_get("created_at").unwrap_or_default();
+        context.set_created_at(created_at);
+
+        let codec_tag: u8 = row.try_get("codec").unwrap_or_default();
+        context.set_codec_tag(codec_tag);
+
+        let unique_key: Option<String> = row.try_get("unique_key").unwrap_or_default();
+        context.set_unique_key(unique_key);
+
+        let counters: Option<String> = row.try_get("counters").unwrap_or_default();
+        if let Some(counters) = counters {
+            context.set_counters(serde_json::from_str(&counters).map_err(|e| {
+                sqlx::Error::ColumnDecode {
+                    index: "counters".to_string(),
+                    source: Box::new(e),
+                }
+            })?);
+        }
+
         let status: String = row.try_get("status")?;
         context.set_status(status.parse().map_err(|e| sqlx::Error::ColumnDecode {
             index: "status".to_string(),
@@ -126,6 +171,9 @@ impl<'r, T: Decode<'r, sqlx::Postgres> + Type<sqlx::Postgres>>
         let last_error = row.try_get("last_error").unwrap_or_default();
         context.set_last_error(last_error);
 
+        let priority: i32 = row.try_get("priority").unwrap_or_default();
+        context.set_priority(priority);
+
         let status: String = row.try_get("status")?;
         context.set_status(status.parse().map_err(|e| sqlx::Error::ColumnDecode {
             index: "job".to_string(),
@@ -189,6 +237,9 @@ impl<'r, T: Decode<'r, sqlx::MySql> + Type<sqlx::MySql>> sqlx::FromRow<'r, sqlx:
         let last_error = row.try_get("last_error").unwrap_or_default();
         context.set_last_error(last_error);
 
+        let priority: i32 = row.try_get("priority").unwrap_or_default();
+        context.set_priority(priority);
+
         let status: String = row.try_get("status")?;
         context.set_status(status.parse().map_err(|e| sqlx::Error::ColumnDecode {
             index: "job".to_string(),
@@ -10,14 +10,37 @@
 //! apalis offers Sqlite, Mysql and Postgres storages for its workers.
 //! See relevant modules for examples
 
-use std::{num::TryFromIntError, time::Duration};
-
-use apalis_core::{error::Error, request::State};
-
+use std::{
+    fmt,
+    num::TryFromIntError,
+    sync::{Arc, PoisonError},
+    time::Duration,
+};
+
+use apalis_core::{
+    backend::Stat,
+    error::{BoxDynError, Error},
+    request::{Request, State},
+    task::task_id::TaskId,
+    worker::WorkerId,
+};
+use context::SqlContext;
+use serde::{Deserialize, Serialize};
+
+/// Cache expensive reads
+pub mod cache;
+/// Skip jobs that keep failing
+pub mod circuit_breaker;
+/// Swappable time sources
+pub mod clock;
 /// The context of the sql job
 pub mod context;
 /// Util for fetching rows
 pub mod from_row;
+/// Validated queue names
+pub mod queue;
+/// Map jobs onto custom table layouts
+pub mod schema;
 
 /// Postgres storage for apalis. Uses `NOTIFY` and `SKIP LOCKED`
 #[cfg(feature = "postgres")]
@@ -45,7 +68,86 @@ pub struct Config {
     buffer_size: usize,
     poll_interval: Duration,
     reenqueue_orphaned_after: Duration,
+    reenqueue_orphaned_interval: Option<Duration>,
     namespace: String,
+    fetch_order: FetchOrder,
+}
+
+/// Decodes a payload written by a codec other than the storage's own, see [`SqliteConfig::add_legacy_codec`](sqlite::SqliteConfig::add_legacy_codec)
+pub type LegacyDecode = fn(String) -> Result<serde_json::Value, BoxDynError>;
+
+/// The order in which pending jobs are claimed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FetchOrder {
+    /// Whatever order the database returns them in
+    #[default]
+    Any,
+    /// The earliest due jobs first, those due at the same second in the order they were pushed
+    Fifo,
+    /// Jobs with the nearest [`deadline`](context::SqlContext::deadline) first, those without one last
+    EarliestDeadline,
+    /// Jobs with the highest [`priority`](context::SqlContext::priority) first
+    ///
+    /// A job gains one point of priority for every `age_boost` it has been due,
+    /// so old low priority jobs eventually outrank fresh ones instead of starving.
+    Priority {
+        /// How long a job waits to gain a point of priority, rounded down to whole seconds
+        age_boost: Duration,
+    },
+}
+
+/// How json payloads are laid out when they are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayloadFormat {
+    /// On a single line, the smallest
+    #[default]
+    Compact,
+    /// Indented over several lines, easier to read from a database shell
+    Pretty,
+}
+
+/// Computes how long a failed job waits before its next attempt
+///
+/// Receives the attempts made so far and the error the job failed with,
+/// so different failures can be retried at different paces.
+#[derive(Clone)]
+pub struct RetryDelay(Arc<RetryDelayFn>);
+
+type RetryDelayFn = dyn Fn(usize, &Error) -> Duration + Send + Sync;
+
+impl RetryDelay {
+    /// Build a new delay from a closure
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(usize, &Error) -> Duration + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// Build a delay following a [`Backoff`](apalis_core::backoff::Backoff), whatever the error
+    pub fn from_backoff<B>(backoff: B) -> Self
+    where
+        B: apalis_core::backoff::Backoff + Send + 'static,
+    {
+        let backoff = std::sync::Mutex::new(backoff);
+        Self::new(move |attempts, _| {
+            backoff
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .next_delay(attempts.try_into().unwrap_or(u32::MAX))
+        })
+    }
+
+    /// Get the delay for a failed attempt
+    pub fn delay(&self, attempts: usize, error: &Error) -> Duration {
+        (self.0)(attempts, error)
+    }
+}
+
+impl fmt::Debug for RetryDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RetryDelay").field(&"<fn>").finish()
+    }
 }
 
 /// A general sql error
@@ -57,6 +159,133 @@ pub enum SqlError {
     /// Handles int conversion errors
     #[error("TryFromIntError: {0}")]
     TryFromInt(#[from] TryFromIntError),
+    /// A table the storage needs does not exist
+    #[error("table `{0}` does not exist, run the storage's `setup` to create it")]
+    MissingTable(String),
+    /// Neither way of counting jobs by status works on this database
+    #[error("counting jobs failed with `COUNT(1) FILTER` ({filter}) and with `SUM(CASE ...)` ({fallback})")]
+    CountsUnsupported {
+        /// The error of the `COUNT(1) FILTER` query, which needs sqlite 3.30+
+        filter: sqlx::Error,
+        /// The error of the `SUM(CASE ...)` query
+        fallback: sqlx::Error,
+    },
+}
+
+/// The error `setup` returns when apalis-sql was built without its bundled migrations
+#[cfg(not(feature = "migrate"))]
+pub(crate) fn migrations_disabled() -> sqlx::Error {
+    sqlx::Error::Configuration(
+        "apalis-sql was built without the `migrate` feature, enable it to run `setup` or create the tables yourself".into(),
+    )
+}
+
+/// A [`Config`] that cannot be used, see [`Config::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    /// The buffer size is zero or above [`Config::MAX_BUFFER_SIZE`]
+    #[error("buffer size must be between 1 and {max}, got {0}", max = Config::MAX_BUFFER_SIZE)]
+    BufferSize(usize),
+}
+
+/// Schema and version details of a storage, useful for diagnostics and bug reports
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageInfo {
+    /// The kind of database backing the storage eg `sqlite`
+    pub backend: String,
+    /// The version of the latest applied migration, `None` if migrations were never run
+    pub migration_version: Option<i64>,
+    /// The journal mode of the database, if the backend has one
+    pub journal_mode: Option<String>,
+    /// The counts of jobs in different states for the storage's namespace
+    pub counts: Stat,
+}
+
+/// The error of a storage operation that can fail for a reason other than the database
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum StorageError {
+    /// The database failed
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    /// A job could not be pushed because its id is taken
+    ///
+    /// Ids are generated unique, so this only happens with ids picked by the caller,
+    /// eg with [`sqlite::SqliteStorage::push_with_id`], or restored from somewhere else.
+    #[error("a job with id {0} already exists")]
+    DuplicateId(TaskId),
+    /// A job could not be acknowledged because it is no longer held by the run acknowledging it
+    ///
+    /// Another worker or a later run of the same worker claimed it, eg after it was reclaimed as an orphan.
+    #[error("job {0} is held by another run")]
+    NotOwned(TaskId),
+}
+
+/// How big the jobs table has grown, see [`sqlite::SqliteStorage::storage_stats`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StorageStats {
+    /// The counts of jobs in each state, across all namespaces
+    pub counts: Stat,
+    /// The rows in the jobs table
+    pub rows: u64,
+    /// The approximate size of the jobs table on disk, in bytes
+    pub approx_bytes: u64,
+}
+
+/// How many jobs of a type finished over a window of time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThroughputStats {
+    /// Jobs that completed successfully
+    pub done: u64,
+    /// Jobs that failed or were killed
+    pub failed: u64,
+    /// Jobs completed per minute, on average over the window
+    pub done_per_minute: f64,
+    /// Jobs failed or killed per minute, on average over the window
+    pub failed_per_minute: f64,
+}
+
+/// How loaded a namespace is, the signal an autoscaler needs in one place
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PressureReport {
+    /// Jobs being run right now
+    pub in_flight: u64,
+    /// How long since the oldest due job was created, if any is waiting
+    pub oldest_pending_age: Option<Duration>,
+    /// Jobs taken back from workers that lost them
+    pub lock_losses: u64,
+    /// Jobs taken back per minute, since the previous report
+    pub lock_losses_per_minute: f64,
+}
+
+/// What a poll of the jobs table came back with
+#[derive(Debug)]
+pub enum Fetch<T> {
+    /// A job was claimed
+    Job(Box<Request<T, SqlContext>>),
+    /// Nothing is ready to run, or another worker claimed it first
+    Empty,
+    /// The worker is busy, the next poll is in this long
+    Backoff(Duration),
+}
+
+impl<T> Fetch<T> {
+    /// The claimed job, if any
+    pub fn into_job(self) -> Option<Request<T, SqlContext>> {
+        match self {
+            Fetch::Job(job) => Some(*job),
+            Fetch::Empty | Fetch::Backoff(_) => None,
+        }
+    }
+}
+
+/// A worker of a namespace coming or going, see [`sqlite::SqliteStorage::worker_events`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorkerEvent {
+    /// The worker started heartbeating
+    Registered(WorkerId),
+    /// The worker stopped heartbeating, its jobs are due to be reclaimed
+    Expired(WorkerId),
 }
 
 impl Default for Config {
@@ -66,17 +295,33 @@ impl Default for Config {
             buffer_size: 10,
             poll_interval: Duration::from_millis(100),
             reenqueue_orphaned_after: Duration::from_secs(300), // 5 minutes
+            reenqueue_orphaned_interval: None,
             namespace: String::from("apalis::sql"),
+            fetch_order: FetchOrder::default(),
         }
     }
 }
 
 impl Config {
+    /// The largest [`buffer_size`](Config::set_buffer_size) accepted by [`Config::validate`]
+    pub const MAX_BUFFER_SIZE: usize = u16::MAX as usize;
+
     /// Create a new config with a jobs namespace
     pub fn new(namespace: &str) -> Self {
         Config::default().set_namespace(namespace)
     }
 
+    /// Check the config can be used by a storage
+    ///
+    /// Setters do not validate their input, so check the whole config once it is built.
+    /// [`SqliteStorage::try_new_with_config`](sqlite::SqliteStorage::try_new_with_config) does so before using it.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(1..=Self::MAX_BUFFER_SIZE).contains(&self.buffer_size) {
+            return Err(ConfigError::BufferSize(self.buffer_size));
+        }
+        Ok(())
+    }
+
     /// Interval between database poll queries
     ///
     /// Defaults to 100ms
@@ -95,7 +340,7 @@ impl Config {
 
     /// Buffer size to use when querying for jobs
     ///
-    /// Defaults to 10
+    /// Defaults to 10, must be between 1 and [`Config::MAX_BUFFER_SIZE`]
     pub fn set_buffer_size(mut self, buffer_size: usize) -> Self {
         self.buffer_size = buffer_size;
         self
@@ -154,6 +399,26 @@ impl Config {
         &mut self.reenqueue_orphaned_after
     }
 
+    /// Gets how often workers look for orphaned jobs, the poll interval unless set.
+    pub fn reenqueue_orphaned_interval(&self) -> Duration {
+        self.reenqueue_orphaned_interval
+            .unwrap_or(self.poll_interval)
+    }
+
+    /// Gets the order in which pending jobs are claimed.
+    pub fn fetch_order(&self) -> FetchOrder {
+        self.fetch_order
+    }
+
+    /// Set the order in which pending jobs are claimed
+    ///
+    /// The postgres and mysql storages claim the earliest due jobs first
+    /// unless this is [`FetchOrder::Priority`].
+    pub fn set_fetch_order(mut self, order: FetchOrder) -> Self {
+        self.fetch_order = order;
+        self
+    }
+
     /// Occasionally some workers die, or abandon jobs because of panics.
     /// This is the time a task takes before its back to the queue
     ///
@@ -162,6 +427,34 @@ impl Config {
         self.reenqueue_orphaned_after = after;
         self
     }
+
+    /// How often each worker looks for orphaned jobs to put back into the queue
+    ///
+    /// Defaults to the poll interval. Orphans only appear after [`Config::set_reenqueue_orphaned_after`],
+    /// so a slower cadence saves queries when many workers poll quickly.
+    pub fn set_reenqueue_orphaned_interval(mut self, interval: Duration) -> Self {
+        self.reenqueue_orphaned_interval = Some(interval);
+        self
+    }
+}
+
+/// Turn an error message, or any payload describing a failure, into text that can always be stored
+///
+/// Invalid utf-8 and nul bytes are replaced with `U+FFFD`. A message longer than `max_len` bytes
+/// is cut on a char boundary and ends with `…`, the whole staying within `max_len`.
+pub fn truncate_error(error: &[u8], max_len: usize) -> String {
+    const ELLIPSIS: &str = "…";
+    let error = String::from_utf8_lossy(error).replace('\0', "\u{FFFD}");
+    if error.len() <= max_len {
+        return error;
+    }
+    let Some(mut end) = max_len.checked_sub(ELLIPSIS.len()) else {
+        return String::new();
+    };
+    while !error.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ELLIPSIS}", &error[..end])
 }
 
 /// Calculates the status from a result
@@ -30,7 +30,7 @@ use std::{marker::PhantomData, ops::Add, time::Duration};
 
 use crate::context::SqlContext;
 use crate::from_row::SqlRequest;
-use crate::{calculate_status, Config, SqlError};
+use crate::{calculate_status, Config, FetchOrder, SqlError};
 
 pub use sqlx::mysql::MySqlPool;
 
@@ -97,6 +97,12 @@ impl MysqlStorage<(), JsonCodec<Value>> {
         Self::migrations().run(pool).await?;
         Ok(())
     }
+
+    /// Always fails, the `migrate` feature is needed to run migrations
+    #[cfg(not(feature = "migrate"))]
+    pub async fn setup(_pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+        Err(crate::migrations_disabled())
+    }
 }
 
 impl<T> MysqlStorage<T>
@@ -157,12 +163,27 @@ where
                 let pool = pool.clone();
                 let job_type = self.config.namespace.clone();
                 let mut tx = pool.begin().await?;
-                let fetch_query = "SELECT id FROM jobs
-                WHERE status = 'Pending' AND run_at <= NOW() AND job_type = ? ORDER BY run_at ASC LIMIT ? FOR UPDATE SKIP LOCKED";
-                let task_ids: Vec<MySqlRow> = sqlx::query(fetch_query)
-                    .bind(job_type)
-                    .bind(buffer_size)
-                    .fetch_all(&mut *tx).await?;
+                let task_ids: Vec<MySqlRow> = match self.config.fetch_order() {
+                    FetchOrder::Priority { age_boost } => {
+                        let fetch_query = "SELECT id FROM jobs
+                        WHERE status = 'Pending' AND run_at <= NOW() AND job_type = ?
+                        ORDER BY priority + FLOOR(TIMESTAMPDIFF(SECOND, run_at, NOW()) / ?) DESC, run_at ASC
+                        LIMIT ? FOR UPDATE SKIP LOCKED";
+                        sqlx::query(fetch_query)
+                            .bind(job_type)
+                            .bind(age_boost.as_secs().max(1))
+                            .bind(buffer_size)
+                            .fetch_all(&mut *tx).await?
+                    }
+                    _ => {
+                        let fetch_query = "SELECT id FROM jobs
+                        WHERE status = 'Pending' AND run_at <= NOW() AND job_type = ? ORDER BY run_at ASC LIMIT ? FOR UPDATE SKIP LOCKED";
+                        sqlx::query(fetch_query)
+                            .bind(job_type)
+                            .bind(buffer_size)
+                            .fetch_all(&mut *tx).await?
+                    }
+                };
                 if task_ids.is_empty() {
                     tx.rollback().await?;
                     yield None
@@ -240,8 +261,7 @@ where
         job: Request<Self::Job, SqlContext>,
     ) -> Result<Parts<SqlContext>, sqlx::Error> {
         let (args, parts) = job.take_parts();
-        let query =
-            "INSERT INTO jobs VALUES (?, ?, ?, 'Pending', 0, ?, now(), NULL, NULL, NULL, NULL)";
+        let query = "INSERT INTO jobs (job, id, job_type, status, attempts, max_attempts, run_at, priority) VALUES (?, ?, ?, 'Pending', 0, ?, now(), ?)";
         let pool = self.pool.clone();
 
         let job = C::encode(args)
@@ -252,6 +272,7 @@ where
             .bind(parts.task_id.to_string())
             .bind(job_type.to_string())
             .bind(parts.context.max_attempts())
+            .bind(parts.context.priority())
             .execute(&pool)
             .await?;
         Ok(parts)
@@ -262,7 +283,7 @@ where
         req: Request<Self::Job, SqlContext>,
         on: i64,
     ) -> Result<Parts<Self::Context>, sqlx::Error> {
-        let query = "INSERT INTO jobs VALUES (?, ?, ?, 'Pending', 0, ?, ?, NULL, NULL, NULL, NULL)";
+        let query = "INSERT INTO jobs (job, id, job_type, status, attempts, max_attempts, run_at, priority) VALUES (?, ?, ?, 'Pending', 0, ?, ?, ?)";
         let pool = self.pool.clone();
 
         let args = C::encode(&req.args)
@@ -275,6 +296,7 @@ where
             .bind(job_type)
             .bind(req.parts.context.max_attempts())
             .bind(on)
+            .bind(req.parts.context.priority())
             .execute(&pool)
             .await?;
         Ok(req.parts)
@@ -377,6 +399,19 @@ where
         let record = sqlx::query(query).execute(&pool).await?;
         Ok(record.rows_affected().try_into().unwrap_or_default())
     }
+
+    /// Cancel a job that has not finished yet, see [`Storage::cancel`]
+    ///
+    /// A running handler is not told, the job is only left killed once it is acknowledged.
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, sqlx::Error> {
+        let query = "UPDATE jobs SET status = 'Killed', done_at = NOW() WHERE id = ?
+            AND (status IN ('Pending', 'Running') OR (status = 'Failed' AND attempts < max_attempts))";
+        let res = sqlx::query(query)
+            .bind(job_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
 }
 
 /// Errors that can occur while polling a MySQL database.
@@ -431,7 +466,7 @@ where
                 .await
             {
                 for (ctx, res) in ids {
-                    let query = "UPDATE jobs SET status = ?, done_at = now(), last_error = ? WHERE id = ? AND lock_by = ?";
+                    let query = "UPDATE jobs SET status = ?, done_at = now(), last_error = ? WHERE id = ? AND lock_by = ? AND status <> 'Killed'";
                     let query = sqlx::query(query);
                     let last_result =
                         C::encode(res.inner.as_ref().map_err(|e| e.to_string())).map_err(Box::new);
@@ -490,7 +525,7 @@ where
                         MysqlPollError::ReenqueueOrphanedError(e),
                     )));
                 }
-                apalis_core::sleep(config.poll_interval).await;
+                apalis_core::sleep(config.reenqueue_orphaned_interval()).await;
             }
         };
         Poller::new_with_layer(
@@ -605,8 +640,9 @@ impl<J: 'static + Serialize + DeserializeOwned + Unpin + Send + Sync> BackendExp
         Ok(Stat {
             pending: res.0.try_into()?,
             running: res.1.try_into()?,
-            dead: res.4.try_into()?,
-            failed: res.3.try_into()?,
+            dead: res.5.try_into()?,
+            retry: res.3.try_into()?,
+            failed: res.4.try_into()?,
             success: res.2.try_into()?,
         })
     }
@@ -801,6 +837,61 @@ mod tests {
         assert!(ctx.done_at().is_some());
     }
 
+    #[tokio::test]
+    async fn test_cancel_running_job_stays_killed() {
+        let (mut storage, poller) = TestWrapper::new_with_service(
+            setup::<Email>().await,
+            apalis_test_service_fn(|_: Request<Email, SqlContext>| async {
+                apalis_core::sleep(Duration::from_secs(3)).await;
+                Ok::<_, Error>(())
+            }),
+        );
+        tokio::spawn(poller);
+        let job_id = storage.push(example_email()).await.unwrap().task_id;
+        loop {
+            let job = storage.fetch_by_id(&job_id).await.unwrap().unwrap();
+            if *job.parts.context.status() == State::Running {
+                break;
+            }
+            apalis_core::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert!(storage.cancel(&job_id).await.unwrap());
+        let (_, res) = storage.execute_next().await;
+        assert_eq!(res, Ok("()".to_owned()));
+
+        // The ack of the cancelled run leaves the job killed
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Killed);
+        assert!(!storage.cancel(&job_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_priority_fetch_order() {
+        use crate::context::RequestBuilder;
+
+        let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_fetch_order(FetchOrder::Priority {
+                age_boost: Duration::from_secs(3600),
+            });
+
+        push_email(&mut storage, example_email()).await;
+        let parts = RequestBuilder::new(example_email())
+            .priority(10)
+            .push(&mut storage)
+            .await
+            .expect("failed to push job");
+
+        let worker = register_worker(&mut storage).await;
+
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, parts.task_id);
+        assert_eq!(job.parts.context.priority(), 10);
+    }
+
     #[tokio::test]
     async fn test_storage_heartbeat_reenqueuorphaned_pulse_last_seen_6min() {
         let mut storage = setup().await;
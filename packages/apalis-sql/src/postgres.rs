@@ -39,7 +39,7 @@
 //!  }
 //! ```
 use crate::context::SqlContext;
-use crate::{calculate_status, Config, SqlError};
+use crate::{calculate_status, Config, FetchOrder, SqlError};
 use apalis_core::backend::{BackendExpose, Stat, WorkerState};
 use apalis_core::codec::json::JsonCodec;
 use apalis_core::error::{BoxDynError, Error};
@@ -169,7 +169,7 @@ where
         let heartbeat = async move {
             let mut keep_alive_stm = apalis_core::interval::interval(config.keep_alive).fuse();
             let mut reenqueue_orphaned_stm =
-                apalis_core::interval::interval(config.poll_interval).fuse();
+                apalis_core::interval::interval(config.reenqueue_orphaned_interval()).fuse();
             let mut ack_stream = ack_notify.clone().ready_chunks(config.buffer_size).fuse();
 
             let mut poll_next_stm = apalis_core::interval::interval(config.poll_interval).fuse();
@@ -232,7 +232,7 @@ where
                                             (value->>4)::int as attempts 
                                         FROM json_array_elements($1::json)
                                     ) Q
-                                    WHERE apalis.jobs.id = Q.id;
+                                    WHERE apalis.jobs.id = Q.id AND apalis.jobs.status <> 'Killed';
                                     ";
                             let codec_res = C::encode(&ack_ids);
                             match codec_res {
@@ -294,6 +294,12 @@ impl PostgresStorage<()> {
         Self::migrations().run(pool).await?;
         Ok(())
     }
+
+    /// Always fails, the `migrate` feature is needed to run migrations
+    #[cfg(not(feature = "migrate"))]
+    pub async fn setup(_pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        Err(crate::migrations_disabled())
+    }
 }
 
 impl<T> PostgresStorage<T> {
@@ -427,17 +433,24 @@ where
     ) -> Result<Vec<Request<T, SqlContext>>, sqlx::Error> {
         let config = &self.config;
         let job_type = &config.namespace;
-        let fetch_query = "Select * from apalis.get_jobs($1, $2, $3);";
-        let jobs: Vec<SqlRequest<serde_json::Value>> = sqlx::query_as(fetch_query)
+        let fetch_query = match config.fetch_order() {
+            FetchOrder::Priority { .. } => {
+                "Select * from apalis.get_jobs_by_priority($1, $2, $3, $4);"
+            }
+            _ => "Select * from apalis.get_jobs($1, $2, $3);",
+        };
+        let mut query = sqlx::query_as(fetch_query)
             .bind(worker_id.to_string())
             .bind(job_type)
             // https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html
             .bind(
                 i32::try_from(config.buffer_size)
                     .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?,
-            )
-            .fetch_all(&self.pool)
-            .await?;
+            );
+        if let FetchOrder::Priority { age_boost } = config.fetch_order() {
+            query = query.bind(i32::try_from(age_boost.as_secs().max(1)).unwrap_or(i32::MAX));
+        }
+        let jobs: Vec<SqlRequest<serde_json::Value>> = query.fetch_all(&self.pool).await?;
         let jobs: Vec<_> = jobs
             .into_iter()
             .map(|job| {
@@ -476,7 +489,7 @@ where
         &mut self,
         req: Request<Self::Job, SqlContext>,
     ) -> Result<Parts<SqlContext>, sqlx::Error> {
-        let query = "INSERT INTO apalis.jobs VALUES ($1, $2, $3, 'Pending', 0, $4, NOW() , NULL, NULL, NULL, NULL)";
+        let query = "INSERT INTO apalis.jobs (job, id, job_type, status, attempts, max_attempts, run_at, priority) VALUES ($1, $2, $3, 'Pending', 0, $4, NOW(), $5)";
 
         let args = C::encode(&req.args)
             .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
@@ -486,6 +499,7 @@ where
             .bind(req.parts.task_id.to_string())
             .bind(&job_type)
             .bind(req.parts.context.max_attempts())
+            .bind(req.parts.context.priority())
             .execute(&self.pool)
             .await?;
         Ok(req.parts)
@@ -497,7 +511,7 @@ where
         on: Timestamp,
     ) -> Result<Parts<Self::Context>, sqlx::Error> {
         let query =
-            "INSERT INTO apalis.jobs VALUES ($1, $2, $3, 'Pending', 0, $4, $5, NULL, NULL, NULL, NULL)";
+            "INSERT INTO apalis.jobs (job, id, job_type, status, attempts, max_attempts, run_at, priority) VALUES ($1, $2, $3, 'Pending', 0, $4, $5, $6)";
         let task_id = req.parts.task_id.to_string();
         let parts = req.parts;
         let on = DateTime::from_timestamp(on, 0);
@@ -510,6 +524,7 @@ where
             .bind(job_type)
             .bind(parts.context.max_attempts())
             .bind(on)
+            .bind(parts.context.priority())
             .execute(&self.pool)
             .await?;
         Ok(parts)
@@ -604,6 +619,19 @@ where
         let record = sqlx::query(query).execute(&self.pool).await?;
         Ok(record.rows_affected().try_into().unwrap_or_default())
     }
+
+    /// Cancel a job that has not finished yet, see [`Storage::cancel`]
+    ///
+    /// A running handler is not told, the job is only left killed once it is acknowledged.
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, sqlx::Error> {
+        let query = "UPDATE apalis.jobs SET status = 'Killed', done_at = now() WHERE id = $1
+            AND (status IN ('Pending', 'Running') OR (status = 'Failed' AND attempts < max_attempts))";
+        let res = sqlx::query(query)
+            .bind(job_id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
 }
 
 impl<T, Res, C> Ack<T, Res> for PostgresStorage<T, C>
@@ -716,8 +744,9 @@ impl<J: 'static + Serialize + DeserializeOwned + Unpin + Send + Sync> BackendExp
         Ok(Stat {
             pending: res.0.try_into()?,
             running: res.1.try_into()?,
-            dead: res.4.try_into()?,
-            failed: res.3.try_into()?,
+            dead: res.5.try_into()?,
+            retry: res.3.try_into()?,
+            failed: res.4.try_into()?,
             success: res.2.try_into()?,
         })
     }
@@ -909,6 +938,35 @@ mod tests {
         assert!(ctx.done_at().is_some());
     }
 
+    #[tokio::test]
+    async fn test_cancel_running_job_stays_killed() {
+        let (mut storage, poller) = TestWrapper::new_with_service(
+            setup::<Email>().await,
+            apalis_test_service_fn(|_: Request<Email, SqlContext>| async {
+                apalis_core::sleep(Duration::from_secs(3)).await;
+                Ok::<_, Error>(())
+            }),
+        );
+        tokio::spawn(poller);
+        let job_id = storage.push(example_email()).await.unwrap().task_id;
+        loop {
+            let job = storage.fetch_by_id(&job_id).await.unwrap().unwrap();
+            if *job.parts.context.status() == State::Running {
+                break;
+            }
+            apalis_core::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert!(storage.cancel(&job_id).await.unwrap());
+        let (_, res) = storage.execute_next().await;
+        assert_eq!(res, Ok("()".to_owned()));
+
+        // The ack of the cancelled run leaves the job killed
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Killed);
+        assert!(!storage.cancel(&job_id).await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_heartbeat_renqueueorphaned_pulse_last_seen_6min() {
         let mut storage = setup().await;
@@ -965,4 +1023,30 @@ mod tests {
         assert_eq!(*ctx.last_error(), None);
         assert_eq!(job.parts.attempt.current(), 0);
     }
+
+    #[tokio::test]
+    async fn test_priority_fetch_order() {
+        use crate::context::RequestBuilder;
+
+        let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_fetch_order(FetchOrder::Priority {
+                age_boost: Duration::from_secs(3600),
+            });
+
+        push_email(&mut storage, example_email()).await;
+        let parts = RequestBuilder::new(example_email())
+            .priority(10)
+            .push(&mut storage)
+            .await
+            .expect("failed to push a job");
+
+        let worker = register_worker(&mut storage).await;
+
+        let job = consume_one(&mut storage, worker.id()).await;
+        assert_eq!(job.parts.task_id, parts.task_id);
+        assert_eq!(job.parts.context.priority(), 10);
+    }
 }
@@ -0,0 +1,156 @@
+use std::{fmt, ops::Deref, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of a queue, a namespace jobs are pushed to or a database holding them
+///
+/// Made of ascii letters, digits, `_` and `-`, at most [`QueueName::MAX_LEN`] long,
+/// so a typo or a stray character is caught when the name is built rather than when a query fails.
+/// Derefs to `str`, eg for [`Config::new`](crate::Config::new).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QueueName(String);
+
+/// Why a string is not a valid [`QueueName`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueueNameError {
+    /// The name is empty
+    #[error("queue name is empty")]
+    Empty,
+    /// The name is longer than [`QueueName::MAX_LEN`]
+    #[error("queue name is {0} bytes long, the limit is {max}", max = QueueName::MAX_LEN)]
+    TooLong(usize),
+    /// The name contains something other than ascii letters, digits, `_` and `-`
+    #[error("queue name `{name}` contains {found:?}, only ascii letters, digits, `_` and `-` are allowed")]
+    InvalidChar {
+        /// The rejected name
+        name: String,
+        /// The first character not allowed
+        found: char,
+    },
+}
+
+impl QueueName {
+    /// The longest name accepted, in bytes
+    pub const MAX_LEN: usize = 64;
+
+    /// Check `name` and wrap it
+    pub fn new(name: impl Into<String>) -> Result<Self, QueueNameError> {
+        let name = name.into();
+        if name.is_empty() {
+            return Err(QueueNameError::Empty);
+        }
+        if name.len() > Self::MAX_LEN {
+            return Err(QueueNameError::TooLong(name.len()));
+        }
+        if let Some(found) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+        {
+            return Err(QueueNameError::InvalidChar { name, found });
+        }
+        Ok(Self(name))
+    }
+
+    /// The name as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for QueueName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for QueueName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for QueueName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for QueueName {
+    type Err = QueueNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for QueueName {
+    type Error = QueueNameError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl TryFrom<String> for QueueName {
+    type Error = QueueNameError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<QueueName> for String {
+    fn from(name: QueueName) -> Self {
+        name.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_names() {
+        for name in ["emails", "tenant-b", "Reports_2024", "a", &"q".repeat(64)] {
+            assert_eq!(QueueName::try_from(name).unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert_eq!(QueueName::try_from(""), Err(QueueNameError::Empty));
+        assert_eq!(
+            QueueName::try_from("q".repeat(65).as_str()),
+            Err(QueueNameError::TooLong(65))
+        );
+        for (name, found) in [
+            ("email_service::Email", ':'),
+            ("two words", ' '),
+            ("emails\"; DROP", '"'),
+            ("café", 'é'),
+        ] {
+            assert_eq!(
+                QueueName::try_from(name),
+                Err(QueueNameError::InvalidChar {
+                    name: name.to_owned(),
+                    found
+                })
+            );
+        }
+        let err = QueueName::try_from("a.b").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "queue name `a.b` contains '.', only ascii letters, digits, `_` and `-` are allowed"
+        );
+    }
+
+    #[test]
+    fn deserializes_only_valid_names() {
+        let name: QueueName = serde_json::from_str("\"emails\"").unwrap();
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"emails\"");
+        assert!(serde_json::from_str::<QueueName>("\"no spaces\"").is_err());
+    }
+}
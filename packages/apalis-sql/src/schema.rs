@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::queue::QueueName;
+
+/// A column the framework reads or writes for every job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// The encoded job arguments
+    Job,
+    /// The task id
+    Id,
+    /// The namespace the job belongs to
+    JobType,
+    /// The current [`State`](apalis_core::request::State)
+    Status,
+    /// The attempts made so far
+    Attempts,
+    /// The maximum attempts allowed
+    MaxAttempts,
+    /// When the job should run, in seconds
+    RunAt,
+    /// The last error the job failed with
+    LastError,
+    /// When the job was locked, in milliseconds
+    LockAt,
+    /// The worker holding the job
+    LockBy,
+    /// When the job finished, in seconds
+    DoneAt,
+    /// When the job should be done by, in seconds
+    Deadline,
+    /// How urgent the job is, higher runs first
+    Priority,
+    /// The business key of the job
+    DedupKey,
+    /// The longest wait between attempts, in seconds
+    MaxBackoff,
+    /// Metadata pushed along with the job, as a json object
+    Headers,
+    /// The order jobs were pushed in, breaking ties between jobs due at the same second
+    Seq,
+    /// The token of the external effect the job recorded as performed
+    EffectToken,
+    /// When the job was pushed, in seconds
+    CreatedAt,
+    /// The tag of the codec the payload was encoded with
+    Codec,
+    /// The application counters of the job, a json object
+    Counters,
+    /// When the job was soft deleted, in seconds
+    DeletedAt,
+    /// When the recent retries of the job were due, in seconds, as a json array
+    RetriedAt,
+    /// The key at most one live job of the namespace may hold
+    UniqueKey,
+    /// The output of the job once it succeeded, as json
+    Result,
+}
+
+impl Column {
+    /// All the columns, in the order of the default layout
+    pub const ALL: [Column; 25] = [
+        Column::Job,
+        Column::Id,
+        Column::JobType,
+        Column::Status,
+        Column::Attempts,
+        Column::MaxAttempts,
+        Column::RunAt,
+        Column::LastError,
+        Column::LockAt,
+        Column::LockBy,
+        Column::DoneAt,
+        Column::Deadline,
+        Column::Priority,
+        Column::DedupKey,
+        Column::MaxBackoff,
+        Column::Headers,
+        Column::Seq,
+        Column::EffectToken,
+        Column::CreatedAt,
+        Column::Codec,
+        Column::Counters,
+        Column::DeletedAt,
+        Column::RetriedAt,
+        Column::UniqueKey,
+        Column::Result,
+    ];
+
+    /// The name of the column in the default layout
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Job => "job",
+            Column::Id => "id",
+            Column::JobType => "job_type",
+            Column::Status => "status",
+            Column::Attempts => "attempts",
+            Column::MaxAttempts => "max_attempts",
+            Column::RunAt => "run_at",
+            Column::LastError => "last_error",
+            Column::LockAt => "lock_at",
+            Column::LockBy => "lock_by",
+            Column::DoneAt => "done_at",
+            Column::Deadline => "deadline",
+            Column::Priority => "priority",
+            Column::DedupKey => "dedup_key",
+            Column::MaxBackoff => "max_backoff_secs",
+            Column::Headers => "headers",
+            Column::Seq => "seq",
+            Column::EffectToken => "effect_token",
+            Column::CreatedAt => "created_at",
+            Column::Codec => "codec",
+            Column::Counters => "counters",
+            Column::DeletedAt => "deleted_at",
+            Column::RetriedAt => "retried_at",
+            Column::UniqueKey => "unique_key",
+            Column::Result => "result",
+        }
+    }
+}
+
+/// Maps the framework's job fields onto an existing table
+///
+/// Queries are built from the table and column names returned here, so jobs can live in a schema
+/// that was not created by the bundled migrations. The default methods describe that layout.
+pub trait SchemaAdapter: fmt::Debug + Send + Sync {
+    /// The table holding the jobs
+    fn table(&self) -> &str {
+        "Jobs"
+    }
+
+    /// The table holding the workers, which job locks refer to
+    fn workers_table(&self) -> &str {
+        "Workers"
+    }
+
+    /// The name of a column in the table
+    fn column(&self, column: Column) -> &str {
+        column.name()
+    }
+
+    /// The columns written when a job is pushed, in bind order
+    fn insert_columns(&self) -> Vec<Column> {
+        vec![
+            Column::Job,
+            Column::Id,
+            Column::JobType,
+            Column::Status,
+            Column::Attempts,
+            Column::MaxAttempts,
+            Column::RunAt,
+            Column::Deadline,
+            Column::Priority,
+            Column::DedupKey,
+            Column::MaxBackoff,
+            Column::Headers,
+            Column::Seq,
+            Column::CreatedAt,
+            Column::Codec,
+            Column::UniqueKey,
+        ]
+    }
+
+    /// The select list used when fetching jobs
+    ///
+    /// Every column is aliased to its default name, which is what rows are decoded from.
+    fn select_columns(&self) -> String {
+        Column::ALL
+            .iter()
+            .map(|c| format!("{} AS {}", self.column(*c), c.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The layout created by the bundled migrations
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultSchema;
+
+impl SchemaAdapter for DefaultSchema {}
+
+/// The default layout, in a database attached to the connection under another name
+///
+/// Lets job types be sharded over several sqlite files sharing one pool,
+/// see [`SqliteStorage::connect_attached`](crate::sqlite::SqliteStorage::connect_attached).
+/// The attached database needs its own jobs and workers tables.
+#[derive(Debug, Clone)]
+pub struct AttachedSchema {
+    table: String,
+    workers_table: String,
+}
+
+impl AttachedSchema {
+    /// Use the tables of the database attached as `database`
+    pub fn new(database: &QueueName) -> Self {
+        Self {
+            table: format!("\"{database}\".Jobs"),
+            workers_table: format!("\"{database}\".Workers"),
+        }
+    }
+}
+
+impl SchemaAdapter for AttachedSchema {
+    fn table(&self) -> &str {
+        &self.table
+    }
+
+    fn workers_table(&self) -> &str {
+        &self.workers_table
+    }
+}
+
+/// Fill a query template with the names from a schema
+///
+/// `{table}` becomes the table, `{workers}` the workers table, `{columns}` the select list
+/// and `{<column>}` eg `{status}` the column.
+/// `{indexed_by}` becomes an `INDEXED BY` clause for `index_hint`, or nothing without one,
+/// and `{fetch_filter}` an `AND` clause for `fetch_filter`, or nothing without one.
+pub(crate) fn render(
+    schema: &dyn SchemaAdapter,
+    index_hint: Option<&str>,
+    fetch_filter: Option<&str>,
+    template: &str,
+) -> String {
+    let indexed_by = index_hint
+        .map(|index| format!("INDEXED BY \"{index}\""))
+        .unwrap_or_default();
+    let fetch_filter = fetch_filter
+        .map(|filter| format!("AND ({filter})"))
+        .unwrap_or_default();
+    let mut query = template
+        .replace("{table}", schema.table())
+        .replace("{workers}", schema.workers_table())
+        .replace("{indexed_by}", &indexed_by)
+        .replace("{fetch_filter}", &fetch_filter)
+        .replace("{columns}", &schema.select_columns());
+    for column in Column::ALL {
+        query = query.replace(&format!("{{{}}}", column.name()), schema.column(column));
+    }
+    query
+}
+
+/// Queries already rendered for a schema, so hot paths don't rebuild the sql on every call
+///
+/// Reusing the exact same text also lets sqlx reuse the statement it prepared on each connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RenderedQueries(Arc<Mutex<HashMap<&'static str, Arc<str>>>>);
+
+impl RenderedQueries {
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub(crate) fn get(
+        &self,
+        schema: &dyn SchemaAdapter,
+        index_hint: Option<&str>,
+        fetch_filter: Option<&str>,
+        template: &'static str,
+    ) -> Arc<str> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(template)
+            .or_insert_with(|| render(schema, index_hint, fetch_filter, template).into())
+            .clone()
+    }
+}
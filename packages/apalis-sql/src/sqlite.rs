@@ -1,30 +1,52 @@
+use crate::cache::CachedCounts;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock::{Clock, SystemClock};
 use crate::context::SqlContext;
-use crate::{calculate_status, Config, SqlError};
+use crate::queue::QueueName;
+use crate::schema::{Column, DefaultSchema, RenderedQueries, SchemaAdapter};
+use crate::{
+    calculate_status, truncate_error, Config, ConfigError, Fetch, FetchOrder, LegacyDecode,
+    PayloadFormat, PressureReport, RetryDelay, SqlError, StorageError, StorageInfo, StorageStats,
+    ThroughputStats, WorkerEvent,
+};
 use apalis_core::backend::{BackendExpose, Stat, WorkerState};
 use apalis_core::codec::json::JsonCodec;
 use apalis_core::error::Error;
-use apalis_core::layers::{Ack, AckLayer};
+use apalis_core::layers::{Ack, AckLayer, Layer, Service, Stack};
 use apalis_core::poller::controller::Controller;
 use apalis_core::poller::stream::BackendStream;
 use apalis_core::poller::Poller;
 use apalis_core::request::{Parts, Request, RequestStream, State};
 use apalis_core::response::Response;
+use apalis_core::sink::JobEvent;
 use apalis_core::storage::Storage;
+use apalis_core::task::attempt::Attempt;
+use apalis_core::task::cancellation::CancellationToken;
 use apalis_core::task::namespace::Namespace;
 use apalis_core::task::task_id::TaskId;
 use apalis_core::worker::{Context, Event, Worker, WorkerId};
 use apalis_core::{backend::Backend, codec::Codec};
 use async_stream::try_stream;
 use chrono::{DateTime, Utc};
-use futures::{FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
-use log::error;
+use futures::channel::mpsc;
+use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use log::{debug, error};
 use serde::{de::DeserializeOwned, Serialize};
-use sqlx::{Pool, Row, Sqlite};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection, Transaction};
 use std::any::type_name;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::sync::Arc;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::{fmt, io};
-use std::{marker::PhantomData, time::Duration};
+use std::{
+    marker::PhantomData,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::from_row::SqlRequest;
 
@@ -36,10 +58,29 @@ pub struct SqliteStorage<T, C = JsonCodec<String>> {
     pool: Pool<Sqlite>,
     job_type: PhantomData<T>,
     controller: Controller,
-    config: Config,
+    config: SqliteConfig,
     codec: PhantomData<C>,
+    counts: CachedCounts,
+    lock_losses: Arc<AtomicU64>,
+    returning: Arc<AtomicU8>,
+    cancellations: Cancellations,
+    started: Started,
 }
 
+// The tokens of the jobs running in this process, shared by clones of a storage
+type Cancellations = Arc<Mutex<HashMap<TaskId, CancellationToken>>>;
+
+// The jobs handed to a handler in this process whose outcome is not stored yet, shared by clones of a storage
+type Started = Arc<Mutex<HashSet<TaskId>>>;
+
+/// The longest [`SqliteStorage::worker_events`] waits between reads while the workers table keeps failing
+pub const WORKER_EVENTS_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// What `SqliteStorage::supports_returning` found out, shared by clones of a storage
+const RETURNING_UNKNOWN: u8 = 0;
+const RETURNING_SUPPORTED: u8 = 1;
+const RETURNING_UNSUPPORTED: u8 = 2;
+
 impl<T, C> fmt::Debug for SqliteStorage<T, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("MysqlStorage")
@@ -48,6 +89,11 @@ impl<T, C> fmt::Debug for SqliteStorage<T, C> {
             .field("controller", &self.controller)
             .field("config", &self.config)
             .field("codec", &std::any::type_name::<C>())
+            .field("counts", &self.counts)
+            .field("lock_losses", &self.lock_losses)
+            .field("returning", &self.returning)
+            .field("cancellations", &self.cancellations)
+            .field("started", &self.started)
             .finish()
     }
 }
@@ -60,10 +106,432 @@ impl<T> Clone for SqliteStorage<T> {
             controller: self.controller.clone(),
             config: self.config.clone(),
             codec: self.codec,
+            counts: self.counts.clone(),
+            lock_losses: self.lock_losses.clone(),
+            returning: self.returning.clone(),
+            cancellations: self.cancellations.clone(),
+            started: self.started.clone(),
+        }
+    }
+}
+
+/// Config for [`SqliteStorage`]: the [`Config`] every sql storage shares, plus settings only this storage has
+///
+/// A plain [`Config`] converts into one with every sqlite setting left at its default.
+#[derive(Debug, Clone)]
+pub struct SqliteConfig {
+    base: Config,
+    retry_delay: Option<RetryDelay>,
+    circuit_breaker: Option<CircuitBreaker>,
+    schema: Arc<dyn SchemaAdapter>,
+    queries: RenderedQueries,
+    validate_raw: bool,
+    log_queries: bool,
+    migration: Option<fn(serde_json::Value) -> serde_json::Value>,
+    codec_tag: u8,
+    legacy_codecs: HashMap<u8, LegacyDecode>,
+    dead_letter_retention: Option<Duration>,
+    soft_delete: bool,
+    max_retries_per_window: Option<(u32, Duration)>,
+    max_error_len: usize,
+    payload_format: PayloadFormat,
+    fetch_index_hint: Option<String>,
+    fetch_filter: Option<String>,
+    worker_concurrency: Option<usize>,
+    lock_renew_interval: Option<Duration>,
+    event_sink: Option<Arc<dyn apalis_core::sink::Sink>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Config::default().into()
+    }
+}
+
+impl From<Config> for SqliteConfig {
+    fn from(base: Config) -> Self {
+        Self {
+            base,
+            retry_delay: None,
+            circuit_breaker: None,
+            schema: Arc::new(DefaultSchema),
+            queries: RenderedQueries::default(),
+            validate_raw: false,
+            log_queries: false,
+            migration: None,
+            codec_tag: 0,
+            legacy_codecs: HashMap::new(),
+            dead_letter_retention: None,
+            soft_delete: false,
+            max_retries_per_window: None,
+            max_error_len: 4096,
+            payload_format: PayloadFormat::default(),
+            fetch_index_hint: None,
+            fetch_filter: None,
+            worker_concurrency: None,
+            lock_renew_interval: None,
+            event_sink: None,
+            clock: Arc::new(SystemClock),
         }
     }
 }
 
+impl SqliteConfig {
+    /// Create a new config with a jobs namespace
+    pub fn new(namespace: &str) -> Self {
+        Config::new(namespace).into()
+    }
+
+    /// Gets the settings shared with the other sql storages.
+    pub fn base(&self) -> &Config {
+        &self.base
+    }
+
+    /// Check the config can be used by the storage, see [`Config::validate`]
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.base.validate()
+    }
+
+    /// Interval between database poll queries, see [`Config::set_poll_interval`]
+    pub fn set_poll_interval(mut self, interval: Duration) -> Self {
+        self.base = self.base.set_poll_interval(interval);
+        self
+    }
+
+    /// Interval between worker keep-alive database updates, see [`Config::set_keep_alive`]
+    pub fn set_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.base = self.base.set_keep_alive(keep_alive);
+        self
+    }
+
+    /// Buffer size to use when querying for jobs, see [`Config::set_buffer_size`]
+    pub fn set_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.base = self.base.set_buffer_size(buffer_size);
+        self
+    }
+
+    /// Set the namespace to consume and push jobs to, see [`Config::set_namespace`]
+    pub fn set_namespace(mut self, namespace: &str) -> Self {
+        self.base = self.base.set_namespace(namespace);
+        self
+    }
+
+    /// Time a job's worker must be unseen for before the job is reclaimed, see [`Config::set_reenqueue_orphaned_after`]
+    pub fn set_reenqueue_orphaned_after(mut self, after: Duration) -> Self {
+        self.base = self.base.set_reenqueue_orphaned_after(after);
+        self
+    }
+
+    /// How often each worker looks for orphaned jobs, see [`Config::set_reenqueue_orphaned_interval`]
+    pub fn set_reenqueue_orphaned_interval(mut self, interval: Duration) -> Self {
+        self.base = self.base.set_reenqueue_orphaned_interval(interval);
+        self
+    }
+
+    /// Set the order in which pending jobs are claimed, see [`Config::set_fetch_order`]
+    pub fn set_fetch_order(mut self, order: FetchOrder) -> Self {
+        self.base = self.base.set_fetch_order(order);
+        self
+    }
+
+    /// Gets the delay applied to failed jobs, if any.
+    pub fn retry_delay(&self) -> Option<&RetryDelay> {
+        self.retry_delay.as_ref()
+    }
+
+    /// Delay the next attempt of a failed job depending on the error it failed with
+    ///
+    /// Defaults to retrying immediately
+    pub fn set_retry_delay<F>(mut self, delay: F) -> Self
+    where
+        F: Fn(usize, &Error) -> Duration + Send + Sync + 'static,
+    {
+        self.retry_delay = Some(RetryDelay::new(delay));
+        self
+    }
+
+    /// Delay the next attempt of a failed job following `backoff`, whatever the error
+    ///
+    /// See [`SqliteConfig::set_retry_delay`] for delays that depend on the error.
+    pub fn set_retry_backoff<B>(mut self, backoff: B) -> Self
+    where
+        B: apalis_core::backoff::Backoff + Send + 'static,
+    {
+        self.retry_delay = Some(RetryDelay::from_backoff(backoff));
+        self
+    }
+
+    /// Gets the circuit breaker used to skip repeatedly failing jobs, if any.
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    /// Stop claiming a job for `cooldown` after it failed `threshold` times in a row
+    ///
+    /// This keeps a poison pill from hogging a worker in a tight retry loop while other jobs wait.
+    /// Disabled by default
+    pub fn set_circuit_breaker(mut self, threshold: usize, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some(CircuitBreaker::new(threshold, cooldown));
+        self
+    }
+
+    /// Gets the sink job outcomes are reported to, if any.
+    pub fn event_sink(&self) -> Option<&Arc<dyn apalis_core::sink::Sink>> {
+        self.event_sink.as_ref()
+    }
+
+    /// Report whether failed jobs are rescheduled or dead lettered to `sink`
+    ///
+    /// Pair it with a [`SinkLayer`](apalis_core::sink::SinkLayer) on the worker sharing the same sink
+    /// to follow a job through its whole lifecycle.
+    pub fn set_event_sink<S: apalis_core::sink::Sink + 'static>(mut self, sink: S) -> Self {
+        self.event_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Gets the current time from the configured clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Read the time from a custom clock
+    ///
+    /// Defaults to [`SystemClock`]. Use a [`MockClock`](crate::clock::MockClock) to control time in tests.
+    pub fn set_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Gets how json payloads are laid out when they are stored.
+    pub fn payload_format(&self) -> PayloadFormat {
+        self.payload_format
+    }
+
+    /// Set how json payloads are laid out when they are stored
+    ///
+    /// [`PayloadFormat::Pretty`] helps when debugging but takes more space, keep the compact default in production.
+    /// Both are decoded the same way.
+    pub fn set_payload_format(mut self, format: PayloadFormat) -> Self {
+        self.payload_format = format;
+        self
+    }
+
+    /// Gets whether raw payloads are decoded before they are pushed.
+    pub fn validate_raw(&self) -> bool {
+        self.validate_raw
+    }
+
+    /// Decode raw payloads when they are pushed, rejecting those that don't match the job type
+    ///
+    /// Disabled by default, so a bad payload only fails once it is consumed
+    pub fn set_validate_raw(mut self, validate: bool) -> Self {
+        self.validate_raw = validate;
+        self
+    }
+
+    /// Gets whether the storage logs its queries.
+    pub fn log_queries(&self) -> bool {
+        self.log_queries
+    }
+
+    /// Log the operation and duration of the storage's queries at `debug` level
+    ///
+    /// Covers pushing, fetching, acknowledging and the worker heartbeat, each log line starting with the namespace.
+    /// Unlike the statement logging of sqlx it is scoped to this storage. Disabled by default.
+    pub fn set_log_queries(mut self, log_queries: bool) -> Self {
+        self.log_queries = log_queries;
+        self
+    }
+
+    /// Gets the migration applied to payloads before they are decoded.
+    pub fn migration(&self) -> Option<fn(serde_json::Value) -> serde_json::Value> {
+        self.migration
+    }
+
+    /// Upgrade old payloads before decoding them, usually with the job's [`Migrate::migrate`](apalis_core::codec::json::Migrate::migrate)
+    ///
+    /// Payloads are assumed to be json.
+    pub fn set_migration(mut self, migrate: fn(serde_json::Value) -> serde_json::Value) -> Self {
+        self.migration = Some(migrate);
+        self
+    }
+
+    /// Gets the tag stored with new payloads.
+    pub fn codec_tag(&self) -> u8 {
+        self.codec_tag
+    }
+
+    /// Tag new payloads with `tag`, telling which codec encoded them
+    ///
+    /// Payloads with this tag are decoded with the storage's codec, others with the codec added for their tag
+    /// by [`SqliteConfig::add_legacy_codec`]. Change it along with the codec, so rows written before the switch keep decoding.
+    /// Defaults to 0, the tag of every row written before tags were stored.
+    pub fn set_codec_tag(mut self, tag: u8) -> Self {
+        self.codec_tag = tag;
+        self
+    }
+
+    /// Gets the decoder of payloads tagged with `tag`, unless it is the current tag.
+    pub fn legacy_codec(&self, tag: u8) -> Option<LegacyDecode> {
+        self.legacy_codecs.get(&tag).copied()
+    }
+
+    /// Decode payloads tagged with `tag` using the codec `D`, eg the previous codec during a rollout
+    ///
+    /// They are decoded into json first, then go through [`SqliteConfig::set_migration`] like any other payload.
+    pub fn add_legacy_codec<D>(mut self, tag: u8) -> Self
+    where
+        D: Codec<Compact = String>,
+    {
+        self.legacy_codecs.insert(tag, |raw| {
+            D::decode::<serde_json::Value>(raw).map_err(Into::into)
+        });
+        self
+    }
+
+    /// Gets the schema adapter used to build queries.
+    pub fn schema(&self) -> &dyn SchemaAdapter {
+        self.schema.as_ref()
+    }
+
+    /// Store jobs in a custom table layout
+    ///
+    /// Defaults to [`DefaultSchema`], the layout created by the migrations.
+    pub fn set_schema<S: SchemaAdapter + 'static>(mut self, schema: S) -> Self {
+        self.schema = Arc::new(schema);
+        self.queries = RenderedQueries::default();
+        self
+    }
+
+    pub(crate) fn query(&self, template: &'static str) -> Arc<str> {
+        self.queries.get(
+            self.schema(),
+            self.fetch_index_hint(),
+            self.fetch_filter(),
+            template,
+        )
+    }
+
+    /// Gets the concurrency workers report on their heartbeat, if any.
+    pub fn worker_concurrency(&self) -> Option<usize> {
+        self.worker_concurrency
+    }
+
+    /// The number of jobs workers of this storage run at once, eg the limit of their concurrency layer
+    ///
+    /// Workers report it on every heartbeat alongside the jobs they are running, so dashboards can spot saturated workers.
+    pub fn set_worker_concurrency(mut self, concurrency: usize) -> Self {
+        self.worker_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Gets how often running jobs renew their lock, if they do.
+    pub fn lock_renew_interval(&self) -> Option<Duration> {
+        self.lock_renew_interval
+    }
+
+    /// Renew the lock of every running job each `interval` until its handler returns
+    ///
+    /// A job is reclaimed once its worker has not been seen for [`Config::set_reenqueue_orphaned_after`],
+    /// renewing also marks the worker as seen so jobs outliving a missed heartbeat are not run twice.
+    /// Keep `interval` well below the orphan timeout. Disabled by default.
+    pub fn set_lock_renew_interval(mut self, interval: Duration) -> Self {
+        self.lock_renew_interval = Some(interval);
+        self
+    }
+
+    /// Gets the index the fetch query is told to use, if any.
+    pub fn fetch_index_hint(&self) -> Option<&str> {
+        self.fetch_index_hint.as_deref()
+    }
+
+    /// Make the query looking for jobs to claim use `index`, through an `INDEXED BY` clause
+    ///
+    /// Can help on very large tables where the planner sometimes picks a slower index. There is no hint by default.
+    /// Hints go stale: if a migration drops or renames the index every fetch fails, and a new better index is ignored.
+    pub fn set_fetch_index_hint(mut self, index: impl Into<String>) -> Self {
+        self.fetch_index_hint = Some(index.into());
+        self.queries = RenderedQueries::default();
+        self
+    }
+
+    /// Gets the condition jobs must meet to be claimed, if any.
+    pub fn fetch_filter(&self) -> Option<&str> {
+        self.fetch_filter.as_deref()
+    }
+
+    /// Only claim jobs matching `filter`, a sql expression over the job's row
+    ///
+    /// For workers handling a subset of a shared job type, eg `json_extract(job, '$.to') LIKE '%@vip.com'`,
+    /// so other jobs are left pending for other workers instead of being locked and discarded.
+    /// The filter is pasted into the fetch queries as is, nothing is escaped or checked: it must be trusted input,
+    /// eg a constant of the application, never built from values a user or a job payload controls.
+    pub fn set_fetch_filter(mut self, filter: impl Into<String>) -> Self {
+        self.fetch_filter = Some(filter.into());
+        self.queries = RenderedQueries::default();
+        self
+    }
+
+    /// Gets how long dead letters are kept, if they are purged at all.
+    pub fn dead_letter_retention(&self) -> Option<Duration> {
+        self.dead_letter_retention
+    }
+
+    /// Purge dead letters, jobs that were killed or ran out of attempts, once they are older than `retention`
+    ///
+    /// Workers check alongside orphaned jobs, see [`Config::set_reenqueue_orphaned_interval`]. This is separate from [`Storage::vacuum`],
+    /// which only removes completed jobs. Dead letters are kept forever by default.
+    pub fn set_dead_letter_retention(mut self, retention: Duration) -> Self {
+        self.dead_letter_retention = Some(retention);
+        self
+    }
+
+    /// Gets whether deleting a job keeps its row as a tombstone.
+    pub fn soft_delete(&self) -> bool {
+        self.soft_delete
+    }
+
+    /// Keep deleted jobs as tombstones, stamped with when they were deleted, instead of removing them
+    ///
+    /// Tombstones are left out of fetching, counting and listing, and are only removed by
+    /// [`SqliteStorage::purge_deleted`], eg once an audit retention period is over.
+    /// Disabled by default.
+    pub fn set_soft_delete(mut self, soft_delete: bool) -> Self {
+        self.soft_delete = soft_delete;
+        self
+    }
+
+    /// Gets how many retries a job gets within a window of time, if capped.
+    pub fn max_retries_per_window(&self) -> Option<(u32, Duration)> {
+        self.max_retries_per_window
+    }
+
+    /// Retry a job at most `count` times within any `window`, deferring further retries until the window allows them
+    ///
+    /// Unlike the max attempts of a job, this caps how fast it retries rather than how often,
+    /// so a job failing in a loop can't take over the workers. Applies to rescheduled jobs and
+    /// to failures acknowledged with attempts left. A `count` of zero is treated as one.
+    pub fn set_max_retries_per_window(mut self, count: u32, window: Duration) -> Self {
+        self.max_retries_per_window = Some((count, window));
+        self
+    }
+
+    /// Gets the longest error message stored with a job, in bytes.
+    pub fn max_error_len(&self) -> usize {
+        self.max_error_len
+    }
+
+    /// Cut the error messages stored with failed jobs to `len` bytes, see [`truncate_error`]
+    ///
+    /// Keeps a handler returning a huge error, eg a whole response body, from bloating the table.
+    /// Defaults to 4 KiB.
+    pub fn set_max_error_len(mut self, len: usize) -> Self {
+        self.max_error_len = len;
+        self
+    }
+}
+
 impl SqliteStorage<()> {
     /// Perform migrations for storage
     #[cfg(feature = "migrate")]
@@ -82,11 +550,79 @@ impl SqliteStorage<()> {
         Ok(())
     }
 
+    /// Always fails, the `migrate` feature is needed to run migrations
+    #[cfg(not(feature = "migrate"))]
+    pub async fn setup(_pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+        Err(crate::migrations_disabled())
+    }
+
     /// Get sqlite migrations without running them
     #[cfg(feature = "migrate")]
     pub fn migrations() -> sqlx::migrate::Migrator {
         sqlx::migrate!("migrations/sqlite")
     }
+
+    /// Connect to a named in-memory database shared by all connections in this process
+    ///
+    /// Uses the `file:{name}?mode=memory&cache=shared` URI, so separate pools (and thus separate
+    /// storages and workers) opened with the same `name` see the same database.
+    ///
+    /// The database only lives as long as a connection to it is open. The returned pool keeps one
+    /// connection around, but once every pool using `name` is closed or dropped the data vanishes.
+    pub async fn connect_memory_shared(name: &str) -> Result<SqlitePool, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(&format!(
+            "sqlite:file:{name}?mode=memory&cache=shared"
+        ))?;
+        SqlitePoolOptions::new()
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+    }
+
+    /// Connect to the database at `url` with other database files attached to every connection
+    ///
+    /// `attached` maps a database name, usually the namespace stored in it, to the path of its file, which is created if missing.
+    /// Point the config of a job type at one of them with [`AttachedSchema`](crate::schema::AttachedSchema)
+    /// to keep that type in its own file, so writes to different types stop contending for one file lock.
+    /// Each attached file must be set up on its own first, eg with [`SqliteStorage::setup`] on a pool opened on it.
+    /// Files are attached with the flags of the main database, so `url` should not be an in-memory database.
+    pub async fn connect_attached(
+        url: &str,
+        attached: &[(QueueName, &str)],
+    ) -> Result<SqlitePool, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let attached: Vec<(String, String)> = attached
+            .iter()
+            .map(|(name, path)| (name.to_string(), path.to_string()))
+            .collect();
+        SqlitePoolOptions::new()
+            .after_connect(move |conn, _| {
+                let attached = attached.clone();
+                Box::pin(async move {
+                    for (name, path) in attached {
+                        sqlx::query(&format!("ATTACH DATABASE ?1 AS \"{name}\""))
+                            .bind(path)
+                            .execute(&mut *conn)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .connect_with(options)
+            .await
+    }
+
+    /// Build a pool that only connects when it is first used
+    ///
+    /// Lets an app start before its database is reachable, eg when both come up together in a container
+    /// orchestrator. Each query retries the connection, so failures surface from the first query instead.
+    /// The database file is created if missing.
+    pub fn connect_lazy(url: &str) -> Result<SqlitePool, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        Ok(SqlitePoolOptions::new().connect_lazy_with(options))
+    }
 }
 
 impl<T: Serialize + DeserializeOwned> SqliteStorage<T> {
@@ -96,734 +632,6235 @@ impl<T: Serialize + DeserializeOwned> SqliteStorage<T> {
             pool,
             job_type: PhantomData,
             controller: Controller::new(),
-            config: Config::new(type_name::<T>()),
+            config: SqliteConfig::new(type_name::<T>()),
             codec: PhantomData,
+            counts: CachedCounts::new(),
+            lock_losses: Arc::default(),
+            returning: Arc::default(),
+            cancellations: Arc::default(),
+            started: Arc::default(),
         }
     }
 
     /// Create a new instance with a custom config
-    pub fn new_with_config(pool: SqlitePool, config: Config) -> Self {
-        Self {
+    ///
+    /// # Panics
+    ///
+    /// If the config is invalid, see [`SqliteStorage::try_new_with_config`].
+    pub fn new_with_config(pool: SqlitePool, config: impl Into<SqliteConfig>) -> Self {
+        match Self::try_new_with_config(pool, config) {
+            Ok(storage) => storage,
+            Err(e) => panic!("invalid sqlite storage config: {e}"),
+        }
+    }
+
+    /// Create a new instance with a custom config, checking it with [`SqliteConfig::validate`] first
+    pub fn try_new_with_config(
+        pool: SqlitePool,
+        config: impl Into<SqliteConfig>,
+    ) -> Result<Self, ConfigError> {
+        let config = config.into();
+        config.validate()?;
+        Ok(Self {
             pool,
             job_type: PhantomData,
             controller: Controller::new(),
             config,
             codec: PhantomData,
-        }
+            counts: CachedCounts::new(),
+            lock_losses: Arc::default(),
+            returning: Arc::default(),
+            cancellations: Arc::default(),
+            started: Arc::default(),
+        })
     }
     /// Keeps a storage notified that the worker is still alive manually
+    ///
+    /// `last_seen` is a unix timestamp in milliseconds
     pub async fn keep_alive_at<Service>(
         &mut self,
         worker_id: &WorkerId,
         last_seen: i64,
     ) -> Result<(), sqlx::Error> {
-        let worker_type = self.config.namespace.clone();
+        let worker_type = self.config.base.namespace.clone();
         let storage_name = std::any::type_name::<Self>();
-        let query = "INSERT INTO Workers (id, worker_type, storage_name, layers, last_seen)
+        let query = self.config.query(
+            "INSERT INTO {workers} (id, worker_type, storage_name, layers, last_seen)
                 VALUES ($1, $2, $3, $4, $5)
                 ON CONFLICT (id) DO
-                   UPDATE SET last_seen = EXCLUDED.last_seen";
-        sqlx::query(query)
+                   UPDATE SET last_seen = EXCLUDED.last_seen",
+        );
+        let query = sqlx::query(&query)
             .bind(worker_id.to_string())
             .bind(worker_type)
             .bind(storage_name)
             .bind(std::any::type_name::<Service>())
-            .bind(last_seen)
+            .bind(last_seen);
+        logged(&self.config, "keep alive", query.execute(&self.pool)).await?;
+        Ok(())
+    }
+
+    /// Record the load of a worker registered with [`SqliteStorage::keep_alive_at`]
+    ///
+    /// Polling workers report this on every heartbeat, `in_flight` being the jobs they are running.
+    /// Read back through [`BackendExpose::list_workers`].
+    pub async fn report_load(
+        &mut self,
+        worker_id: &WorkerId,
+        concurrency: Option<usize>,
+        in_flight: usize,
+    ) -> Result<(), sqlx::Error> {
+        let to_i64 = |n: usize| {
+            i64::try_from(n)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))
+        };
+        let query = self
+            .config
+            .query("UPDATE {workers} SET concurrency = ?2, in_flight = ?3 WHERE id = ?1");
+        sqlx::query(&query)
+            .bind(worker_id.to_string())
+            .bind(concurrency.map(to_i64).transpose()?)
+            .bind(to_i64(in_flight)?)
             .execute(&self.pool)
             .await?;
         Ok(())
     }
 
+    /// Consume jobs as `worker_id` and forward them into `sink`
+    ///
+    /// A job is only claimed once the sink is ready for it, so a full sink pauses fetching.
+    /// Acknowledging or rescheduling the forwarded jobs is left to the receiver.
+    /// The worker is kept alive while draining. Returns once the sink closes or fails.
+    /// A job claimed just as the sink went away is put back to `Pending` rather than left to the orphan recovery.
+    pub async fn drain_to<S>(
+        &mut self,
+        worker_id: &WorkerId,
+        mut sink: S,
+    ) -> Result<(), sqlx::Error>
+    where
+        T: Send + Unpin,
+        S: Sink<Request<T, SqlContext>> + Unpin,
+    {
+        let worker = Worker::new(worker_id.clone(), Context::default());
+        worker.start();
+        let storage = SqliteStorage::<T>::new_with_config(self.pool.clone(), self.config.clone());
+        let stream = storage.stream_jobs(&worker, self.config.base.poll_interval, 1);
+        futures::pin_mut!(stream);
+        let mut last_seen: Option<std::time::Instant> = None;
+        loop {
+            if futures::future::poll_fn(|cx| sink.poll_ready_unpin(cx))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+            if last_seen.map_or(true, |at| at.elapsed() >= self.config.base.keep_alive) {
+                self.keep_alive_at::<S>(worker_id, self.config.now().timestamp_millis())
+                    .await?;
+                last_seen = Some(std::time::Instant::now());
+            }
+            let job = match stream.next().await {
+                Some(Ok(Fetch::Job(job))) => *job,
+                Some(Ok(Fetch::Empty | Fetch::Backoff(_))) => continue,
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            };
+            let job_id = job.parts.task_id.clone();
+            if sink.start_send_unpin(job).is_err() {
+                // The receiver hung up between asking for a job and getting it
+                unclaim(&self.pool, &self.config, &job_id, worker_id).await?;
+                return Ok(());
+            }
+            if sink.flush().await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
     /// Expose the pool for other functionality, eg custom migrations
     pub fn pool(&self) -> &Pool<Sqlite> {
         &self.pool
     }
 
     /// Get the config used by the storage
-    pub fn get_config(&self) -> &Config {
+    pub fn get_config(&self) -> &SqliteConfig {
         &self.config
     }
 }
 
 impl<T, C> SqliteStorage<T, C> {
-    /// Expose the code used
-    pub fn codec(&self) -> &PhantomData<C> {
-        &self.codec
+    /// Whether the database supports `RETURNING`, which sqlite has since 3.35
+    ///
+    /// The version is read once and remembered by the storage and its clones.
+    /// Methods that would write and read back a row in one statement do it in a transaction instead
+    /// on older versions, or if the version can't be read.
+    pub async fn supports_returning(&self) -> bool {
+        match self.returning.load(Ordering::Relaxed) {
+            RETURNING_SUPPORTED => return true,
+            RETURNING_UNSUPPORTED => return false,
+            _ => {}
+        }
+        let version: Result<String, _> = sqlx::query_scalar("SELECT sqlite_version()")
+            .fetch_one(&self.pool)
+            .await;
+        let Ok(version) = version else {
+            return false;
+        };
+        let mut parts = version.split('.').map(|n| n.parse::<u32>().unwrap_or(0));
+        let supported = (parts.next().unwrap_or(0), parts.next().unwrap_or(0)) >= (3, 35);
+        let state = match supported {
+            true => RETURNING_SUPPORTED,
+            false => RETURNING_UNSUPPORTED,
+        };
+        let _ = self.returning.compare_exchange(
+            RETURNING_UNKNOWN,
+            state,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+        supported
     }
-}
-
-async fn fetch_next(
-    pool: &Pool<Sqlite>,
-    worker_id: &WorkerId,
-    id: String,
-    config: &Config,
-) -> Result<Option<SqlRequest<String>>, sqlx::Error> {
-    let now: i64 = Utc::now().timestamp();
-    let update_query = "UPDATE Jobs SET status = 'Running', lock_by = ?2, lock_at = ?3, attempts = attempts + 1 WHERE id = ?1 AND job_type = ?4 AND status = 'Pending' AND lock_by IS NULL; Select * from Jobs where id = ?1 AND lock_by = ?2 AND job_type = ?4";
-    let job: Option<SqlRequest<String>> = sqlx::query_as(update_query)
-        .bind(id.to_string())
-        .bind(worker_id.to_string())
-        .bind(now)
-        .bind(config.namespace.clone())
-        .fetch_optional(pool)
-        .await?;
 
-    Ok(job)
-}
-
-impl<T, C> SqliteStorage<T, C>
-where
-    T: DeserializeOwned + Send + Unpin,
-    C: Codec<Compact = String>,
-{
-    fn stream_jobs(
+    /// List jobs of this namespace that failed or were killed after `since`, with their last error
+    ///
+    /// `since` is a unix timestamp in seconds, compared against `done_at`.
+    /// The most recent failures are returned first.
+    pub async fn recent_failures(
         &self,
-        worker: &Worker<Context>,
-        interval: Duration,
-        buffer_size: usize,
-    ) -> impl Stream<Item = Result<Option<Request<T, SqlContext>>, sqlx::Error>> {
-        let pool = self.pool.clone();
-        let worker = worker.clone();
-        let config = self.config.clone();
-        let namespace = Namespace(self.config.namespace.clone());
-        try_stream! {
-            loop {
-                apalis_core::sleep(interval).await;
-                if !worker.is_ready() {
-                    continue;
-                }
-                let worker_id = worker.id();
-                let tx = pool.clone();
-                let mut tx = tx.acquire().await?;
-                let job_type = &config.namespace;
-                let fetch_query = "SELECT id FROM Jobs
-                    WHERE (status = 'Pending' OR (status = 'Failed' AND attempts < max_attempts)) AND run_at < ?1 AND job_type = ?2 LIMIT ?3";
-                let now: i64 = Utc::now().timestamp();
-                let ids: Vec<(String,)> = sqlx::query_as(fetch_query)
-                    .bind(now)
-                    .bind(job_type)
-                    .bind(i64::try_from(buffer_size).map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?)
-                    .fetch_all(&mut *tx)
-                    .await?;
-                for id in ids {
-                    let res = fetch_next(&pool, worker_id, id.0, &config).await?;
-                    yield match res {
-                        None => None::<Request<T, SqlContext>>,
-                        Some(job) => {
-                            let (req, parts) = job.req.take_parts();
-                            let args = C::decode(req)
-                                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-                            let mut req = Request::new_with_parts(args, parts);
-                            req.parts.namespace = Some(namespace.clone());
-                            Some(req)
-                        }
-                    }
-                };
-            }
-        }
+        since: i64,
+        limit: i64,
+    ) -> Result<Vec<(TaskId, String)>, sqlx::Error> {
+        let query = self.config.query("SELECT {id}, {last_error} FROM {table}
+            WHERE {job_type} = ?1 AND {status} IN ('Failed', 'Killed', 'Dead') AND {done_at} > ?2 AND {last_error} IS NOT NULL AND {deleted_at} IS NULL
+            ORDER BY {done_at} DESC LIMIT ?3");
+        let rows: Vec<(String, String)> = sqlx::query_as(&query)
+            .bind(&self.config.base.namespace)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|(id, error)| {
+                let id = TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })?;
+                Ok((id, error))
+            })
+            .collect()
     }
-}
-
-impl<T, C> Storage for SqliteStorage<T, C>
-where
-    T: Serialize + DeserializeOwned + Send + 'static + Unpin + Sync,
-    C: Codec<Compact = String> + Send,
-{
-    type Job = T;
-
-    type Error = sqlx::Error;
-
-    type Context = SqlContext;
 
-    async fn push_request(
-        &mut self,
-        job: Request<Self::Job, SqlContext>,
-    ) -> Result<Parts<SqlContext>, Self::Error> {
-        let query = "INSERT INTO Jobs VALUES (?1, ?2, ?3, 'Pending', 0, ?4, strftime('%s','now'), NULL, NULL, NULL, NULL)";
-        let (task, parts) = job.take_parts();
-        let raw = C::encode(&task)
+    /// List jobs of this namespace with only a few top level fields of their payload
+    ///
+    /// Meant for list views, which would otherwise decode every payload in full through [`BackendExpose::list_jobs`].
+    /// Each job comes back as an object holding just `fields`, a missing field is `null`.
+    /// Pages and ordering match `list_jobs`. This assumes jobs are stored as json.
+    pub async fn list_projected(
+        &self,
+        status: &State,
+        page: i32,
+        fields: &[&str],
+    ) -> Result<Vec<(TaskId, serde_json::Value)>, sqlx::Error> {
+        let query = self.config.query("SELECT {id},
+            (SELECT json_group_object(f.value, json_extract({job}, '$.\"' || f.value || '\"')) FROM json_each(?4) f)
+            FROM {table} WHERE {status} = ?1 AND {job_type} = ?2 AND {deleted_at} IS NULL
+            ORDER BY {done_at} DESC, {run_at} DESC LIMIT 10 OFFSET ?3");
+        let fields = serde_json::to_string(fields)
             .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-        let job_type = self.config.namespace.clone();
-        sqlx::query(query)
-            .bind(raw)
-            .bind(parts.task_id.to_string())
-            .bind(job_type.to_string())
-            .bind(parts.context.max_attempts())
-            .execute(&self.pool)
+        let rows: Vec<(String, String)> = sqlx::query_as(&query)
+            .bind(status.to_string())
+            .bind(&self.config.base.namespace)
+            .bind((page - 1) * 10)
+            .bind(fields)
+            .fetch_all(&self.pool)
             .await?;
-        Ok(parts)
+        rows.into_iter()
+            .map(|(id, projection)| {
+                let id = TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })?;
+                let projection =
+                    serde_json::from_str(&projection).map_err(|e| sqlx::Error::ColumnDecode {
+                        index: "job".to_string(),
+                        source: Box::new(e),
+                    })?;
+                Ok((id, projection))
+            })
+            .collect()
     }
 
-    async fn schedule_request(
-        &mut self,
-        req: Request<Self::Job, SqlContext>,
-        on: i64,
-    ) -> Result<Parts<SqlContext>, Self::Error> {
-        let query =
-            "INSERT INTO Jobs VALUES (?1, ?2, ?3, 'Pending', 0, ?4, ?5, NULL, NULL, NULL, NULL)";
-        let id = &req.parts.task_id;
-        let job = C::encode(&req.args)
-            .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-        let job_type = self.config.namespace.clone();
-        sqlx::query(query)
-            .bind(job)
-            .bind(id.to_string())
-            .bind(job_type)
-            .bind(req.parts.context.max_attempts())
-            .bind(on)
-            .execute(&self.pool)
+    /// Find the output of an earlier successful run of a job with this [`dedup_key`](SqlContext::dedup_key)
+    ///
+    /// With at least once delivery a job may be replayed after it already did its work.
+    /// A handler can check this first and return the prior output instead of doing the work again.
+    /// Returns the most recent output if several jobs completed with the same key.
+    pub async fn has_completed(
+        &self,
+        dedup_key: &str,
+    ) -> Result<Option<serde_json::Value>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {result} FROM {table}
+            WHERE {dedup_key} = ?1 AND {job_type} = ?2 AND {status} = 'Done' AND {deleted_at} IS NULL
+            ORDER BY {done_at} DESC LIMIT 1",
+        );
+        let output: Option<Option<String>> = sqlx::query_scalar(&query)
+            .bind(dedup_key)
+            .bind(&self.config.base.namespace)
+            .fetch_optional(&self.pool)
             .await?;
-        Ok(req.parts)
+        output
+            .flatten()
+            .map(|output| {
+                serde_json::from_str(&output).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "result".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()
     }
 
-    async fn fetch_by_id(
-        &mut self,
-        job_id: &TaskId,
-    ) -> Result<Option<Request<Self::Job, SqlContext>>, Self::Error> {
-        let fetch_query = "SELECT * FROM Jobs WHERE id = ?1";
-        let res: Option<SqlRequest<String>> = sqlx::query_as(fetch_query)
+    /// Get the status of a job without fetching or decoding it
+    ///
+    /// Cheap enough to poll in a tight loop, eg while waiting on a job's result.
+    /// The sql is rendered once per storage and the prepared statement is reused on each connection.
+    /// On an in-memory database the `status_polling` bench measures about 24µs per call,
+    /// against 29µs when the query is rendered and prepared each time.
+    pub async fn status(&self, job_id: &TaskId) -> Result<Option<State>, sqlx::Error> {
+        let query = self
+            .config
+            .query("SELECT {status} FROM {table} WHERE {id} = ?1 AND {deleted_at} IS NULL");
+        let status: Option<String> = sqlx::query_scalar(&query)
             .bind(job_id.to_string())
             .fetch_optional(&self.pool)
             .await?;
-        match res {
-            None => Ok(None),
-            Some(job) => Ok(Some({
-                let (req, parts) = job.req.take_parts();
-                let args = C::decode(req)
-                    .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-
-                let mut req: Request<T, SqlContext> = Request::new_with_parts(args, parts);
-                req.parts.namespace = Some(Namespace(self.config.namespace.clone()));
-                req
-            })),
-        }
-    }
-
-    async fn len(&mut self) -> Result<i64, Self::Error> {
-        let query = "Select Count(*) as count from Jobs where status='Pending'";
-        let record = sqlx::query(query).fetch_one(&self.pool).await?;
-        record.try_get("count")
+        status
+            .map(|s| {
+                s.parse().map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "status".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()
     }
 
-    async fn reschedule(
-        &mut self,
-        job: Request<T, SqlContext>,
-        wait: Duration,
-    ) -> Result<(), Self::Error> {
-        let task_id = job.parts.task_id;
+    /// Get what a job returned, once it succeeded
+    ///
+    /// The output is stored as json when the job is acknowledged, so `R` is whatever the handler returned
+    /// or a type it deserializes into. `None` until the job is done, or if there is no such job.
+    pub async fn fetch_result<R: DeserializeOwned>(
+        &self,
+        job_id: &TaskId,
+    ) -> Result<Option<R>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {result} FROM {table} WHERE {id} = ?1 AND {status} = 'Done' AND {deleted_at} IS NULL",
+        );
+        let output: Option<Option<String>> = sqlx::query_scalar(&query)
+            .bind(job_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        output
+            .flatten()
+            .map(|output| {
+                serde_json::from_str(&output).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "result".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()
+    }
 
-        let wait: i64 = wait
-            .as_secs()
-            .try_into()
+    /// Park the waiting jobs whose type none of `known_types` handles
+    ///
+    /// Workers only fetch jobs of their own namespace, so when several job types share a database
+    /// a job pushed with an unknown type would otherwise wait forever.
+    /// `fallback` is called once per such job with its id, type and raw payload, eg to log or archive it,
+    /// then the job is killed with an `unroutable` error so it is not handed over again.
+    /// Returns how many jobs were parked.
+    pub async fn park_unroutable<F>(
+        &self,
+        known_types: &[&str],
+        mut fallback: F,
+    ) -> Result<u64, sqlx::Error>
+    where
+        F: FnMut(&TaskId, &str, &str),
+    {
+        let mut tx = self.pool.begin().await?;
+        let select = self.config.query(
+            "SELECT {id}, {job_type}, {job} FROM {table}
+            WHERE {status} IN ('Pending', 'Retry') AND {deleted_at} IS NULL AND {job_type} NOT IN (SELECT value FROM json_each(?1))",
+        );
+        let known_types = serde_json::to_string(known_types)
             .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-
-        let mut tx = self.pool.acquire().await?;
-        let query =
-                "UPDATE Jobs SET status = 'Failed', done_at = NULL, lock_by = NULL, lock_at = NULL, run_at = ?2 WHERE id = ?1";
-        let now: i64 = Utc::now().timestamp();
-        let wait_until = now + wait;
-
-        sqlx::query(query)
-            .bind(task_id.to_string())
-            .bind(wait_until)
-            .execute(&mut *tx)
+        let jobs: Vec<(String, String, String)> = sqlx::query_as(&select)
+            .bind(known_types)
+            .fetch_all(&mut *tx)
             .await?;
-        Ok(())
+        let park = self.config.query(
+            "UPDATE {table} SET {status} = 'Killed', {done_at} = ?2, {last_error} = 'unroutable' WHERE {id} = ?1",
+        );
+        let now = self.config.now().timestamp();
+        for (id, job_type, job) in &jobs {
+            let task_id = TaskId::from_str(id).map_err(|e| sqlx::Error::ColumnDecode {
+                index: "id".to_string(),
+                source: Box::new(e),
+            })?;
+            sqlx::query(&park)
+                .bind(id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+            fallback(&task_id, job_type, job);
+        }
+        tx.commit().await?;
+        Ok(jobs.len() as u64)
     }
 
-    async fn update(&mut self, job: Request<Self::Job, SqlContext>) -> Result<(), Self::Error> {
-        let ctx = job.parts.context;
-        let status = ctx.status().to_string();
-        let attempts = job.parts.attempt;
-        let done_at = *ctx.done_at();
-        let lock_by = ctx.lock_by().clone();
-        let lock_at = *ctx.lock_at();
-        let last_error = ctx.last_error().clone();
-        let job_id = job.parts.task_id;
-        let mut tx = self.pool.acquire().await?;
-        let query =
-                "UPDATE Jobs SET status = ?1, attempts = ?2, done_at = ?3, lock_by = ?4, lock_at = ?5, last_error = ?6 WHERE id = ?7";
-        sqlx::query(query)
-            .bind(status.to_owned())
-            .bind::<i64>(
-                attempts
-                    .current()
-                    .try_into()
-                    .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
-            )
-            .bind(done_at)
-            .bind(lock_by.map(|w| w.name().to_string()))
-            .bind(lock_at)
-            .bind(last_error)
-            .bind(job_id.to_string())
-            .execute(&mut *tx)
+    /// Get how many jobs of each type finished over the last `window`, with their rates per minute
+    ///
+    /// Covers every job type in the table, not only this namespace. Rates come from `done_at`, so at second precision.
+    pub async fn stats_by_type(
+        &self,
+        window: Duration,
+    ) -> Result<HashMap<String, ThroughputStats>, SqlError> {
+        let since = self.config.now()
+            - chrono::Duration::from_std(window)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let query = self.config.query(
+            "SELECT {job_type},
+                SUM(CASE WHEN {status} = 'Done' THEN 1 ELSE 0 END),
+                SUM(CASE WHEN {status} IN ('Failed', 'Killed', 'Dead') THEN 1 ELSE 0 END)
+            FROM {table} WHERE {done_at} >= ?1 AND {deleted_at} IS NULL GROUP BY {job_type}",
+        );
+        let rows: Vec<(String, i64, i64)> = sqlx::query_as(&query)
+            .bind(since.timestamp())
+            .fetch_all(&self.pool)
             .await?;
-        Ok(())
+        let minutes = window.as_secs_f64() / 60.0;
+        rows.into_iter()
+            .map(|(job_type, done, failed)| {
+                let done = u64::try_from(done)?;
+                let failed = u64::try_from(failed)?;
+                let stats = ThroughputStats {
+                    done,
+                    failed,
+                    done_per_minute: done as f64 / minutes,
+                    failed_per_minute: failed as f64 / minutes,
+                };
+                Ok((job_type, stats))
+            })
+            .collect()
     }
 
-    async fn is_empty(&mut self) -> Result<bool, Self::Error> {
-        self.len().map_ok(|c| c == 0).await
+    /// Count the workers of this namespace that were seen within `within`
+    ///
+    /// Cheaper than [`BackendExpose::list_workers`] when only the number of live workers matters, eg for autoscaling.
+    pub async fn active_worker_count(&self, within: Duration) -> Result<i64, sqlx::Error> {
+        let seen_since = self.config.now()
+            - chrono::Duration::from_std(within)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        // Older rows stored `last_seen` in seconds
+        let query = self.config.query(
+            "SELECT COUNT(*) FROM {workers} WHERE worker_type = ?1
+            AND (CASE WHEN last_seen < 100000000000 THEN last_seen * 1000 ELSE last_seen END) >= ?2",
+        );
+        sqlx::query_scalar(&query)
+            .bind(&self.config.base.namespace)
+            .bind(seen_since.timestamp_millis())
+            .fetch_one(&self.pool)
+            .await
     }
 
-    async fn vacuum(&mut self) -> Result<usize, sqlx::Error> {
-        let query = "Delete from Jobs where status='Done'";
-        let record = sqlx::query(query).execute(&self.pool).await?;
-        Ok(record.rows_affected().try_into().unwrap_or_default())
+    /// Watch the workers of this namespace come and go
+    ///
+    /// The workers table is read every [`Config::keep_alive`], the interval workers heartbeat at.
+    /// A worker is alive while it was seen within [`Config::reenqueue_orphaned_after`], the window after which
+    /// its jobs are reclaimed, so `Expired` tells which workers lost their jobs.
+    /// The first read reports every worker alive at that time as `Registered`.
+    /// A failed read is emitted as an error and retried, waiting twice as long after each
+    /// consecutive failure, up to [`WORKER_EVENTS_MAX_BACKOFF`].
+    pub fn worker_events(&self) -> impl Stream<Item = Result<WorkerEvent, sqlx::Error>> + '_ {
+        let query = self.config.query(
+            "SELECT id FROM {workers} WHERE worker_type = ?1
+            AND (CASE WHEN last_seen < 100000000000 THEN last_seen * 1000 ELSE last_seen END) >= ?2",
+        );
+        async_stream::stream! {
+            let mut alive: Vec<String> = Vec::new();
+            let mut backoff = self.config.base.keep_alive;
+            loop {
+                let now_alive: Result<Vec<String>, _> = sqlx::query_scalar(&query)
+                    .bind(&self.config.base.namespace)
+                    .bind(orphaned_since(&self.config).timestamp_millis())
+                    .fetch_all(&self.pool)
+                    .await;
+                let now_alive = match now_alive {
+                    Ok(now_alive) => now_alive,
+                    Err(e) => {
+                        yield Err(e);
+                        apalis_core::sleep(backoff).await;
+                        backoff = backoff.saturating_mul(2).min(WORKER_EVENTS_MAX_BACKOFF);
+                        continue;
+                    }
+                };
+                backoff = self.config.base.keep_alive;
+                for id in alive.iter().filter(|id| !now_alive.contains(id)) {
+                    yield Ok(WorkerEvent::Expired(WorkerId::new(id)));
+                }
+                for id in now_alive.iter().filter(|id| !alive.contains(id)) {
+                    yield Ok(WorkerEvent::Registered(WorkerId::new(id)));
+                }
+                alive = now_alive;
+                apalis_core::sleep(self.config.base.keep_alive).await;
+            }
+        }
     }
-}
 
-impl<T> SqliteStorage<T> {
-    /// Puts the job instantly back into the queue
-    /// Another Worker may consume
-    pub async fn retry(
-        &mut self,
+    /// Mark `worker_id` as gone, putting back the jobs it claimed but never handed to a handler
+    ///
+    /// The worker's row is kept since finished jobs still refer to it, but it is no longer counted as active.
+    /// Polling workers do this on shutdown, once their running jobs are acknowledged.
+    ///
+    /// A job handed to a handler in this process is not put back, its handler may have finished
+    /// even though its outcome could not be stored. Those still running under `worker_id` are returned,
+    /// and are left to [`SqliteStorage::reenqueue_orphaned`] like the jobs of a crashed worker.
+    pub async fn deregister_worker(
+        &self,
         worker_id: &WorkerId,
-        job_id: &TaskId,
-    ) -> Result<(), sqlx::Error> {
-        let mut tx = self.pool.acquire().await?;
-        let query =
-                "UPDATE Jobs SET status = 'Pending', done_at = NULL, lock_by = NULL WHERE id = ?1 AND lock_by = ?2";
-        sqlx::query(query)
-            .bind(job_id.to_string())
+    ) -> Result<Vec<TaskId>, sqlx::Error> {
+        let started = self.started_ids()?;
+        let query = self.config.query("UPDATE {table} SET {status} = 'Pending', {lock_by} = NULL, {lock_at} = NULL WHERE {status} = 'Running' AND {lock_by} = ?1 AND {job_type} = ?2 AND {id} NOT IN (SELECT value FROM json_each(?3))");
+        let requeued = sqlx::query(&query)
             .bind(worker_id.to_string())
-            .execute(&mut *tx)
+            .bind(&self.config.base.namespace)
+            .bind(&started)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        self.lock_losses.fetch_add(requeued, Ordering::Relaxed);
+        let query = self.config.query("SELECT {id} FROM {table} WHERE {status} = 'Running' AND {lock_by} = ?1 AND {job_type} = ?2 AND {id} IN (SELECT value FROM json_each(?3))");
+        let unacked: Vec<String> = sqlx::query_scalar(&query)
+            .bind(worker_id.to_string())
+            .bind(&self.config.base.namespace)
+            .bind(&started)
+            .fetch_all(&self.pool)
             .await?;
-        Ok(())
-    }
-
-    /// Kill a job
-    pub async fn kill(&mut self, worker_id: &WorkerId, job_id: &TaskId) -> Result<(), sqlx::Error> {
-        let mut tx = self.pool.begin().await?;
-        let query =
-                "UPDATE Jobs SET status = 'Killed', done_at = strftime('%s','now') WHERE id = ?1 AND lock_by = ?2";
-        sqlx::query(query)
-            .bind(job_id.to_string())
+        let query = self
+            .config
+            .query("UPDATE {workers} SET last_seen = 0 WHERE id = ?1");
+        sqlx::query(&query)
             .bind(worker_id.to_string())
-            .execute(&mut *tx)
+            .execute(&self.pool)
             .await?;
-        tx.commit().await?;
-        Ok(())
+        unacked
+            .iter()
+            .map(|id| {
+                TaskId::from_str(id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .collect()
     }
 
-    /// Add jobs that failed back to the queue if there are still remaining attemps
-    pub async fn reenqueue_failed(&mut self) -> Result<(), sqlx::Error> {
-        let job_type = self.config.namespace.clone();
-        let mut tx = self.pool.acquire().await?;
-        let query = r#"Update Jobs
-                            SET status = "Pending", done_at = NULL, lock_by = NULL, lock_at = NULL
-                            WHERE id in
-                                (SELECT Jobs.id from Jobs
-                                    WHERE status= "Failed" AND Jobs.attempts < Jobs.max_attempts
-                                     ORDER BY lock_at ASC LIMIT ?2);"#;
-        sqlx::query(query)
-            .bind(job_type)
-            .bind::<u32>(
-                self.config
-                    .buffer_size
-                    .try_into()
-                    .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
-            )
-            .execute(&mut *tx)
+    /// Stop tracking `task_id` as handed to a handler, once its outcome is stored or another worker owns it
+    fn forget_started(&self, task_id: &TaskId) {
+        self.started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(task_id);
+    }
+
+    /// The jobs handed to a handler in this process and not acknowledged yet, as a json array
+    fn started_ids(&self) -> Result<String, sqlx::Error> {
+        let started = self.started.lock().unwrap_or_else(PoisonError::into_inner);
+        serde_json::to_string(&started.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    /// Wait until the queue is drained for `worker_id`
+    ///
+    /// Resolves once no job of this namespace is ready to run and none is still running on `worker_id`.
+    /// Jobs scheduled for later don't hold it back. The database is checked every [`Config::poll_interval`].
+    pub async fn on_drained(&self, worker_id: &WorkerId) -> Result<(), sqlx::Error> {
+        let query = self.config.query(
+            "SELECT COUNT(*) FROM {table} WHERE {job_type} = ?1 AND {deleted_at} IS NULL AND (
+                (({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {run_at} <= ?2)
+                OR ({status} = 'Running' AND {lock_by} = ?3)
+            )",
+        );
+        loop {
+            let remaining: i64 = sqlx::query_scalar(&query)
+                .bind(&self.config.base.namespace)
+                .bind(self.config.now().timestamp())
+                .bind(worker_id.to_string())
+                .fetch_one(&self.pool)
+                .await?;
+            if remaining == 0 {
+                return Ok(());
+            }
+            apalis_core::sleep(self.config.base.poll_interval).await;
+        }
+    }
+
+    /// Get the status of many jobs in a single query
+    ///
+    /// Ids that don't match a job are left out of the map.
+    pub async fn status_map(&self, ids: &[TaskId]) -> Result<HashMap<TaskId, State>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {id}, {status} FROM {table} WHERE {id} IN (SELECT value FROM json_each(?1)) AND {deleted_at} IS NULL",
+        );
+        let ids = serde_json::to_string(&ids.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        let rows: Vec<(String, String)> = sqlx::query_as(&query)
+            .bind(ids)
+            .fetch_all(&self.pool)
             .await?;
-        Ok(())
+        rows.into_iter()
+            .map(|(id, status)| {
+                let id = TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })?;
+                let status = status.parse().map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "status".to_string(),
+                    source: Box::new(e),
+                })?;
+                Ok((id, status))
+            })
+            .collect()
     }
 
-    /// Add jobs that workers have disappeared to the queue
-    pub async fn reenqueue_orphaned(
-        &self,
-        count: i32,
-        dead_since: DateTime<Utc>,
-    ) -> Result<(), sqlx::Error> {
-        let job_type = self.config.namespace.clone();
-        let mut tx = self.pool.acquire().await?;
-        let query = r#"Update Jobs
-                            SET status = "Pending", done_at = NULL, lock_by = NULL, lock_at = NULL, last_error ="Job was abandoned"
-                            WHERE id in
-                                (SELECT Jobs.id from Jobs INNER join Workers ON lock_by = Workers.id
-                                    WHERE status= "Running" AND workers.last_seen < ?1
-                                    AND Workers.worker_type = ?2 ORDER BY lock_at ASC LIMIT ?3);"#;
-
-        sqlx::query(query)
-            .bind(dead_since.timestamp())
-            .bind(job_type)
-            .bind(count)
-            .execute(&mut *tx)
+    /// Describe the database backing this storage
+    ///
+    /// Reports the applied migration version, the journal mode and the job counts for this namespace.
+    /// This only reads from the database.
+    pub async fn describe(&self) -> Result<StorageInfo, SqlError> {
+        let migration_version: Option<i64> =
+            match sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1")
+                .fetch_one(&self.pool)
+                .await
+            {
+                Ok(version) => version,
+                // The migrations table only exists once `setup` has been run
+                Err(sqlx::Error::Database(_)) => None,
+                Err(e) => return Err(e.into()),
+            };
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&self.pool)
             .await?;
-        Ok(())
+        Ok(StorageInfo {
+            backend: "sqlite".to_owned(),
+            migration_version,
+            journal_mode: Some(journal_mode),
+            counts: self.counts().await?,
+        })
     }
-}
 
-/// Errors that can occur while polling an SQLite database.
-#[derive(thiserror::Error, Debug)]
-pub enum SqlitePollError {
-    /// Error during a keep-alive heartbeat.
-    #[error("Encountered an error during KeepAlive heartbeat: `{0}`")]
-    KeepAliveError(sqlx::Error),
+    /// Check the tables this storage uses exist
+    ///
+    /// Fails with [`SqlError::MissingTable`] when they don't, typically because [`SqliteStorage::setup`] was not run.
+    /// Call it at startup to fail early instead of on the first push or fetch.
+    pub async fn check_schema(&self) -> Result<(), SqlError> {
+        for template in [
+            "SELECT 1 FROM {table} LIMIT 0",
+            "SELECT 1 FROM {workers} LIMIT 0",
+        ] {
+            let query = self.config.query(template);
+            match sqlx::query(&query).execute(&self.pool).await {
+                Ok(_) => {}
+                Err(sqlx::Error::Database(e)) if e.message().starts_with("no such table") => {
+                    let table = e.message().trim_start_matches("no such table: ");
+                    return Err(SqlError::MissingTable(table.to_owned()));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
 
-    /// Error during re-enqueuing orphaned tasks.
-    #[error("Encountered an error during ReenqueueOrphaned heartbeat: `{0}`")]
-    ReenqueueOrphanedError(sqlx::Error),
-}
+    /// Get the job counts for this namespace, reusing a previous result younger than `ttl`
+    ///
+    /// Counting scans the whole table, so dashboards refreshing often should prefer this over [`SqliteStorage::describe`].
+    /// `pending` doubles as a cached [`Storage::len`]. The cache is shared by clones of the storage.
+    pub async fn counts_cached(&self, ttl: Duration) -> Result<Stat, SqlError> {
+        self.counts.get(ttl, || self.counts()).await
+    }
 
-impl<T: Serialize + DeserializeOwned + Sync + Send + Unpin + 'static, Res>
-    Backend<Request<T, SqlContext>, Res> for SqliteStorage<T>
-{
-    type Stream = BackendStream<RequestStream<Request<T, SqlContext>>>;
-    type Layer = AckLayer<SqliteStorage<T>, T, SqlContext, Res>;
+    /// Gets the cache used by [`SqliteStorage::counts_cached`]
+    pub fn counts_cache(&self) -> &CachedCounts {
+        &self.counts
+    }
 
-    fn poll<Svc>(mut self, worker: &Worker<Context>) -> Poller<Self::Stream, Self::Layer> {
-        let layer = AckLayer::new(self.clone());
-        let config = self.config.clone();
-        let controller = self.controller.clone();
-        let stream = self
-            .stream_jobs(worker, config.poll_interval, config.buffer_size)
-            .map_err(|e| Error::SourceError(Arc::new(Box::new(e))));
-        let stream = BackendStream::new(stream.boxed(), controller);
-        let requeue_storage = self.clone();
-        let w = worker.clone();
-        let heartbeat = async move {
+    /// Watch the job counts for this namespace, checking every `interval`
+    ///
+    /// The current counts are emitted first, then only when they change.
+    /// A failed check is emitted as an error and retried on the next interval.
+    pub fn watch_counts(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<Stat, SqlError>> + '_ {
+        async_stream::stream! {
+            let mut last = None;
             loop {
-                let now: i64 = Utc::now().timestamp();
-                if let Err(e) = self.keep_alive_at::<Self::Layer>(w.id(), now).await {
-                    w.emit(Event::Error(Box::new(SqlitePollError::KeepAliveError(e))));
+                match self.counts().await {
+                    Ok(stat) if last.as_ref() != Some(&stat) => {
+                        last = Some(stat.clone());
+                        yield Ok(stat);
+                    }
+                    Ok(_) => {}
+                    Err(e) => yield Err(e),
                 }
-                apalis_core::sleep(Duration::from_secs(30)).await;
+                apalis_core::sleep(interval).await;
             }
         }
-        .boxed();
-        let w = worker.clone();
-        let reenqueue_beat = async move {
+    }
+
+    /// Get how loaded this namespace is, for autoscalers
+    ///
+    /// `lock_losses` counts the jobs this storage and its clones took back from workers that stopped
+    /// heartbeating or restarted. The per minute rate is left at zero, see [`SqliteStorage::watch_pressure`].
+    pub async fn pressure(&self) -> Result<PressureReport, SqlError> {
+        let query = self
+            .config
+            .query("SELECT COUNT(*) FROM {table} WHERE {job_type} = ?1 AND {status} = 'Running' AND {deleted_at} IS NULL");
+        let in_flight: i64 = sqlx::query_scalar(&query)
+            .bind(&self.config.base.namespace)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(PressureReport {
+            in_flight: in_flight.try_into()?,
+            oldest_pending_age: self.oldest_pending_age().await?,
+            lock_losses: self.lock_losses.load(Ordering::Relaxed),
+            lock_losses_per_minute: 0.0,
+        })
+    }
+
+    /// Get how long the oldest job of this namespace that is ready to run has been waiting
+    ///
+    /// The age runs from the job's [`SqlContext::created_at`], or from its `run_at` for rows stored without one.
+    /// Only jobs that are due count, so a scheduled job is left out until its `run_at` has passed.
+    /// Returns `None` when nothing is waiting.
+    pub async fn oldest_pending_age(&self) -> Result<Option<Duration>, SqlError> {
+        let query = self.config.query(
+            "SELECT MIN(COALESCE({created_at}, {run_at})) FROM {table} WHERE {job_type} = ?1 AND {status} IN ('Pending', 'Retry') AND {run_at} <= ?2 AND {deleted_at} IS NULL",
+        );
+        let now = self.config.now().timestamp();
+        let oldest: Option<i64> = sqlx::query_scalar(&query)
+            .bind(&self.config.base.namespace)
+            .bind(now)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(oldest.map(|since| Duration::from_secs((now - since).try_into().unwrap_or_default())))
+    }
+
+    /// How long until a job of this namespace can be fetched
+    ///
+    /// `Some(Duration::ZERO)` when one can be fetched now, `None` when there is nothing left to run.
+    /// Follows the fetch query, [`SqliteConfig::fetch_filter`] included, so an idle worker can sleep
+    /// this long instead of waking up every [`Config::poll_interval`].
+    /// Jobs pushed in the meantime are not accounted for, so cap the sleep when new jobs should be picked up quickly.
+    pub async fn time_until_next(&self) -> Result<Option<Duration>, SqlError> {
+        let query = self.config.query(
+            "SELECT MIN({run_at}) FROM {table} WHERE {job_type} = ?1 AND {deleted_at} IS NULL {fetch_filter}
+            AND ({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts}))
+            AND ({lock_by} IS NULL OR {status} = 'Retry')",
+        );
+        let next: Option<i64> = sqlx::query_scalar(&query)
+            .bind(&self.config.base.namespace)
+            .fetch_one(&self.pool)
+            .await?;
+        // Fetching takes jobs with a `run_at` strictly before the current second
+        Ok(next.map(|run_at| {
+            let wait_ms = (run_at + 1) * 1000 - self.config.now().timestamp_millis();
+            Duration::from_millis(wait_ms.try_into().unwrap_or_default())
+        }))
+    }
+
+    /// Report how loaded this namespace is every `interval`
+    ///
+    /// Each report rates the lock losses since the previous one.
+    /// A failed check is emitted as an error and retried on the next interval.
+    pub fn watch_pressure(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<PressureReport, SqlError>> + '_ {
+        async_stream::stream! {
+            let mut last: Option<(DateTime<Utc>, u64)> = None;
             loop {
-                let dead_since = Utc::now()
-                    - chrono::Duration::from_std(config.reenqueue_orphaned_after).unwrap();
-                if let Err(e) = requeue_storage
-                    .reenqueue_orphaned(
-                        config
-                            .buffer_size
-                            .try_into()
-                            .expect("could not convert usize to i32"),
-                        dead_since,
-                    )
-                    .await
-                {
-                    w.emit(Event::Error(Box::new(
-                        SqlitePollError::ReenqueueOrphanedError(e),
-                    )));
+                match self.pressure().await {
+                    Ok(mut report) => {
+                        let now = self.config.now();
+                        if let Some((at, losses)) = last {
+                            let minutes = (now - at).num_milliseconds() as f64 / 60_000.0;
+                            if minutes > 0.0 {
+                                report.lock_losses_per_minute =
+                                    report.lock_losses.saturating_sub(losses) as f64 / minutes;
+                            }
+                        }
+                        last = Some((now, report.lock_losses));
+                        yield Ok(report);
+                    }
+                    Err(e) => yield Err(e),
                 }
-                apalis_core::sleep(config.poll_interval).await;
+                apalis_core::sleep(interval).await;
             }
-        };
-        Poller::new_with_layer(
-            stream,
-            async {
-                futures::join!(heartbeat, reenqueue_beat);
-            },
-            layer,
-        )
+        }
     }
-}
 
-impl<T: Sync + Send, Res: Serialize + Sync> Ack<T, Res> for SqliteStorage<T> {
-    type Context = SqlContext;
-    type AckError = sqlx::Error;
-    async fn ack(&mut self, ctx: &Self::Context, res: &Response<Res>) -> Result<(), sqlx::Error> {
-        let pool = self.pool.clone();
-        let query =
-                "UPDATE Jobs SET status = ?4, done_at = strftime('%s','now'), last_error = ?3 WHERE id = ?1 AND lock_by = ?2";
-        let result = serde_json::to_string(&res.inner.as_ref().map_err(|r| r.to_string()))
-            .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
-        sqlx::query(query)
-            .bind(res.task_id.to_string())
-            .bind(
-                ctx.lock_by()
-                    .as_ref()
-                    .expect("Task is not locked")
-                    .to_string(),
-            )
-            .bind(result)
-            .bind(calculate_status(&res.inner).to_string())
-            .execute(&pool)
+    async fn counts(&self) -> Result<Stat, SqlError> {
+        let query = self.config.query(
+            "SELECT {status}, COUNT(*) FROM {table} WHERE {job_type} = ?1 AND {deleted_at} IS NULL GROUP BY {status}",
+        );
+        let counts: Vec<(String, i64)> = sqlx::query_as(&query)
+            .bind(&self.config.base.namespace)
+            .fetch_all(&self.pool)
+            .await?;
+        stat_from_counts(counts)
+    }
+
+    /// Get the size of the jobs table, across all namespaces
+    ///
+    /// Helps tell when finished jobs are piling up and should be cleaned up or archived.
+    /// The size comes from the `dbstat` table when sqlite was built with it,
+    /// otherwise it is the size of the whole database file.
+    pub async fn storage_stats(&self) -> Result<StorageStats, SqlError> {
+        let query = self
+            .config
+            .query("SELECT {status}, COUNT(*) FROM {table} GROUP BY {status}");
+        let counts: Vec<(String, i64)> = sqlx::query_as(&query).fetch_all(&self.pool).await?;
+        let rows = counts
+            .iter()
+            .map(|(_, count)| count)
+            .sum::<i64>()
+            .try_into()?;
+        let table_bytes: Option<i64> =
+            match sqlx::query_scalar("SELECT SUM(pgsize) FROM dbstat WHERE name = ?1")
+                .bind(self.config.schema().table())
+                .fetch_one(&self.pool)
+                .await
+            {
+                Ok(bytes) => bytes,
+                // No such table, sqlite was built without dbstat
+                Err(sqlx::Error::Database(_)) => None,
+                Err(e) => return Err(e.into()),
+            };
+        let approx_bytes =
+            match table_bytes {
+                Some(bytes) => bytes,
+                None => sqlx::query_scalar(
+                    "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                )
+                .fetch_one(&self.pool)
+                .await?,
+            };
+        Ok(StorageStats {
+            counts: stat_from_counts(counts)?,
+            rows,
+            approx_bytes: approx_bytes.try_into()?,
+        })
+    }
+
+    /// Write a consistent copy of the database to `path` while workers keep running
+    ///
+    /// Uses `VACUUM INTO`, so the copy is also compacted. It holds a read transaction for as long as
+    /// the copy takes: writers are not blocked in WAL mode, but in the default rollback journal mode
+    /// pushes and acks wait until it is done.
+    /// Fails with [`io::ErrorKind::AlreadyExists`] rather than overwrite an existing file.
+    /// A `sqlite::memory:` database cannot be backed up this way, sqlite writes the copy to memory as well.
+    pub async fn backup_to(&self, path: &Path) -> Result<(), sqlx::Error> {
+        if path.exists() {
+            return Err(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("backup path {} already exists", path.display()),
+            )));
+        }
+        let path = path.to_str().ok_or_else(|| {
+            sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("backup path {} is not valid utf-8", path.display()),
+            ))
+        })?;
+        sqlx::query("VACUUM INTO ?1")
+            .bind(path)
+            .execute(&self.pool)
             .await?;
         Ok(())
     }
 }
 
-impl<J: 'static + Serialize + DeserializeOwned + Unpin + Send + Sync> BackendExpose<J>
-    for SqliteStorage<J, JsonCodec<String>>
+impl<T, C> SqliteStorage<T, C> {
+    /// Expose the code used
+    pub fn codec(&self) -> &PhantomData<C> {
+        &self.codec
+    }
+
+    /// Encode and decode jobs with `D` instead
+    ///
+    /// Rows already in the table keep their encoding, see [`SqliteConfig::set_codec_tag`] to switch codecs without draining the queue.
+    pub fn with_codec<D>(self) -> SqliteStorage<T, D> {
+        SqliteStorage {
+            pool: self.pool,
+            job_type: PhantomData,
+            controller: self.controller,
+            config: self.config,
+            codec: PhantomData,
+            counts: self.counts,
+            lock_losses: self.lock_losses,
+            returning: self.returning,
+            cancellations: self.cancellations,
+            started: self.started,
+        }
+    }
+}
+
+/// How [`count_by_status`] counts
+#[derive(Debug, Clone, Copy)]
+enum CountBy {
+    /// `COUNT(1) FILTER (WHERE ...)`, sqlite 3.30+
+    Filter,
+    /// `SUM(CASE WHEN ... THEN 1 ELSE 0 END)`, any sqlite
+    SumCase,
+}
+
+/// Count the jobs of the namespace as pending, running, done, retry, failed and killed
+async fn count_by_status(
+    pool: &Pool<Sqlite>,
+    config: &SqliteConfig,
+    by: CountBy,
+) -> Result<(i64, i64, i64, i64, i64, i64), sqlx::Error> {
+    let query = config.query(match by {
+        CountBy::Filter => {
+            "SELECT
+                            COUNT(1) FILTER (WHERE {status} = 'Pending') AS pending,
+                            COUNT(1) FILTER (WHERE {status} = 'Running') AS running,
+                            COUNT(1) FILTER (WHERE {status} = 'Done') AS done,
+                            COUNT(1) FILTER (WHERE {status} = 'Retry') AS retry,
+                            COUNT(1) FILTER (WHERE {status} = 'Failed') AS failed,
+                            COUNT(1) FILTER (WHERE {status} IN ('Killed', 'Dead')) AS killed
+                        FROM {table} WHERE {job_type} = ? AND {deleted_at} IS NULL"
+        }
+        // COALESCE as SUM over no rows is NULL
+        CountBy::SumCase => {
+            "SELECT
+                            COALESCE(SUM(CASE WHEN {status} = 'Pending' THEN 1 ELSE 0 END), 0) AS pending,
+                            COALESCE(SUM(CASE WHEN {status} = 'Running' THEN 1 ELSE 0 END), 0) AS running,
+                            COALESCE(SUM(CASE WHEN {status} = 'Done' THEN 1 ELSE 0 END), 0) AS done,
+                            COALESCE(SUM(CASE WHEN {status} = 'Retry' THEN 1 ELSE 0 END), 0) AS retry,
+                            COALESCE(SUM(CASE WHEN {status} = 'Failed' THEN 1 ELSE 0 END), 0) AS failed,
+                            COALESCE(SUM(CASE WHEN {status} IN ('Killed', 'Dead') THEN 1 ELSE 0 END), 0) AS killed
+                        FROM {table} WHERE {job_type} = ? AND {deleted_at} IS NULL"
+        }
+    });
+    sqlx::query_as(&query)
+        .bind(config.base.namespace())
+        .fetch_one(pool)
+        .await
+}
+
+/// Sum up `(status, count)` rows
+fn stat_from_counts(counts: Vec<(String, i64)>) -> Result<Stat, SqlError> {
+    let mut stat = Stat::default();
+    for (status, count) in counts {
+        let count = count.try_into()?;
+        match status.parse() {
+            Ok(State::Pending) => stat.pending = count,
+            Ok(State::Running) => stat.running = count,
+            Ok(State::Done) => stat.success = count,
+            Ok(State::Retry) => stat.retry = count,
+            Ok(State::Failed) => stat.failed = count,
+            Ok(State::Killed | State::Dead) => stat.dead += count,
+            _ => {}
+        }
+    }
+    Ok(stat)
+}
+
+/// Await `query`, logging how long `operation` took if [`SqliteConfig::log_queries`] is on
+async fn logged<F, R>(config: &SqliteConfig, operation: &str, query: F) -> Result<R, sqlx::Error>
+where
+    F: Future<Output = Result<R, sqlx::Error>>,
 {
-    type Request = Request<J, Parts<SqlContext>>;
-    type Error = SqlError;
-    async fn stats(&self) -> Result<Stat, Self::Error> {
-        let fetch_query = "SELECT
-                            COUNT(1) FILTER (WHERE status = 'Pending') AS pending,
-                            COUNT(1) FILTER (WHERE status = 'Running') AS running,
-                            COUNT(1) FILTER (WHERE status = 'Done') AS done,
-                            COUNT(1) FILTER (WHERE status = 'Failed') AS failed,
-                            COUNT(1) FILTER (WHERE status = 'Killed') AS killed
-                        FROM Jobs WHERE job_type = ?";
+    if !config.log_queries() {
+        return query.await;
+    }
+    let start = Instant::now();
+    let res = query.await;
+    let outcome = if res.is_ok() { "done" } else { "failed" };
+    debug!(
+        "{}: {operation} {outcome} in {:?}",
+        config.base.namespace,
+        start.elapsed()
+    );
+    res
+}
 
-        let res: (i64, i64, i64, i64, i64, i64) = sqlx::query_as(fetch_query)
-            .bind(self.get_config().namespace())
-            .fetch_one(self.pool())
-            .await?;
+/// The heartbeat a worker must have been seen after to keep its jobs
+///
+/// A [`Config::reenqueue_orphaned_after`] too long to represent, eg `Duration::MAX`, never orphans anyone.
+fn orphaned_since(config: &SqliteConfig) -> DateTime<Utc> {
+    chrono::Duration::from_std(config.base.reenqueue_orphaned_after)
+        .ok()
+        .and_then(|after| config.now().checked_sub_signed(after))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
 
-        Ok(Stat {
-            pending: res.0.try_into()?,
-            running: res.1.try_into()?,
-            dead: res.4.try_into()?,
-            failed: res.3.try_into()?,
-            success: res.2.try_into()?,
-        })
+/// Cap a retry delay to the job's [`max_backoff_secs`](SqlContext::max_backoff_secs)
+fn clamp_backoff(ctx: &SqlContext, wait: Duration) -> Duration {
+    match ctx.max_backoff_secs() {
+        Some(max) => wait.min(Duration::from_secs((*max).max(0) as u64)),
+        None => wait,
     }
+}
 
-    async fn list_jobs(
-        &self,
-        status: &State,
-        page: i32,
-    ) -> Result<Vec<Self::Request>, Self::Error> {
-        let status = status.to_string();
-        let fetch_query = "SELECT * FROM Jobs WHERE status = ? AND job_type = ? ORDER BY done_at DESC, run_at DESC LIMIT 10 OFFSET ?";
-        let res: Vec<SqlRequest<String>> = sqlx::query_as(fetch_query)
-            .bind(status)
-            .bind(self.get_config().namespace())
-            .bind(((page - 1) * 10).to_string())
-            .fetch_all(self.pool())
+/// Encode a job's payload in the configured format
+fn encode_job<T, C>(config: &SqliteConfig, job: &T) -> Result<String, sqlx::Error>
+where
+    T: Serialize,
+    C: Codec<Compact = String>,
+{
+    let raw = C::encode(job)
+        .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    match config.payload_format() {
+        PayloadFormat::Compact => Ok(raw),
+        PayloadFormat::Pretty => serde_json::from_str::<serde_json::Value>(&raw)
+            .and_then(|value| serde_json::to_string_pretty(&value))
+            .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e))),
+    }
+}
+
+/// Decode a job's payload with the codec it was tagged with, first upgrading it with the configured migration
+fn decode_job<T, C>(config: &SqliteConfig, raw: String, codec_tag: u8) -> Result<T, sqlx::Error>
+where
+    T: DeserializeOwned,
+    C: Codec<Compact = String>,
+{
+    let invalid = |e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e));
+    if codec_tag != config.codec_tag() {
+        let decode = config.legacy_codec(codec_tag).ok_or_else(|| {
+            invalid(format!("no codec added for payloads tagged {codec_tag}").into())
+        })?;
+        let value = decode(raw).map_err(invalid)?;
+        let value = match config.migration() {
+            Some(migrate) => migrate(value),
+            None => value,
+        };
+        return serde_json::from_value(value).map_err(|e| invalid(e.into()));
+    }
+    let raw = match config.migration() {
+        Some(migrate) => {
+            let value = serde_json::from_str(&raw)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            serde_json::to_string(&migrate(value))
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?
+        }
+        None => raw,
+    };
+    C::decode(raw).map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+async fn fetch_next(
+    pool: &Pool<Sqlite>,
+    worker_id: &WorkerId,
+    id: String,
+    config: &SqliteConfig,
+) -> Result<Option<SqlRequest<String>>, sqlx::Error> {
+    let now: i64 = config.now().timestamp_millis();
+    // Two separate statements, a multi statement query may only run its first one
+    let mut tx = pool.begin().await?;
+    let update_query = config.query("UPDATE {table} SET {status} = 'Running', {lock_by} = ?2, {lock_at} = ?3, {attempts} = {attempts} + 1 WHERE {id} = ?1 AND {job_type} = ?4 AND (({status} = 'Pending' AND {lock_by} IS NULL) OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {deleted_at} IS NULL");
+    let update = sqlx::query(&update_query)
+        .bind(&id)
+        .bind(worker_id.to_string())
+        .bind(now)
+        .bind(&config.base.namespace);
+    logged(config, "lock", update.execute(&mut *tx)).await?;
+    let select_query = config.query(
+        "SELECT {columns} FROM {table} WHERE {id} = ?1 AND {lock_by} = ?2 AND {job_type} = ?3",
+    );
+    let select = sqlx::query_as(&select_query)
+        .bind(&id)
+        .bind(worker_id.to_string())
+        .bind(&config.base.namespace);
+    let job: Option<SqlRequest<String>> =
+        logged(config, "fetch locked", select.fetch_optional(&mut *tx)).await?;
+    tx.commit().await?;
+
+    Ok(job)
+}
+
+impl<T, C> SqliteStorage<T, C>
+where
+    T: DeserializeOwned + Send + Unpin,
+    C: Codec<Compact = String>,
+{
+    /// Stream the ids of up to `limit` jobs ready to be claimed, in the order workers would claim them
+    ///
+    /// Nothing is locked, so an external scheduler can pick which jobs to hand out with [`SqliteStorage::claim`].
+    pub fn runnable_ids(&self, limit: usize) -> impl Stream<Item = Result<TaskId, sqlx::Error>> {
+        let pool = self.pool.clone();
+        let config = self.config.clone();
+        try_stream! {
+            for id in fetch_runnable_ids(&pool, &config, limit).await? {
+                yield TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })?;
+            }
+        }
+    }
+
+    /// Claim a specific job for `worker_id`, as a worker polling the storage would
+    ///
+    /// Returns `None` if the job is not ready to be claimed, eg because another worker got it first.
+    /// The worker must have been registered with [`SqliteStorage::keep_alive_at`].
+    pub async fn claim(
+        &mut self,
+        worker_id: &WorkerId,
+        job_id: &TaskId,
+    ) -> Result<Option<Request<T, SqlContext>>, sqlx::Error> {
+        // Unlike `fetch_next`, a job this worker already holds is not handed out again
+        let job: Option<SqlRequest<String>> = if self.supports_returning().await {
+            let query = self.config.query("UPDATE {table} SET {status} = 'Running', {lock_by} = ?2, {lock_at} = ?3, {attempts} = {attempts} + 1 WHERE {id} = ?1 AND {job_type} = ?4 AND (({status} = 'Pending' AND {lock_by} IS NULL) OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {deleted_at} IS NULL RETURNING {columns}");
+            let query = sqlx::query_as(&query)
+                .bind(job_id.to_string())
+                .bind(worker_id.to_string())
+                .bind(self.config.now().timestamp_millis())
+                .bind(&self.config.base.namespace);
+            logged(&self.config, "claim", query.fetch_optional(&self.pool)).await?
+        } else {
+            let mut tx = self.pool.begin().await?;
+            let query = self.config.query("UPDATE {table} SET {status} = 'Running', {lock_by} = ?2, {lock_at} = ?3, {attempts} = {attempts} + 1 WHERE {id} = ?1 AND {job_type} = ?4 AND (({status} = 'Pending' AND {lock_by} IS NULL) OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {deleted_at} IS NULL");
+            let query = sqlx::query(&query)
+                .bind(job_id.to_string())
+                .bind(worker_id.to_string())
+                .bind(self.config.now().timestamp_millis())
+                .bind(&self.config.base.namespace);
+            let claimed = logged(&self.config, "claim", query.execute(&mut *tx)).await?;
+            let job = match claimed.rows_affected() {
+                0 => None,
+                _ => {
+                    let query = self
+                        .config
+                        .query("SELECT {columns} FROM {table} WHERE {id} = ?1");
+                    sqlx::query_as(&query)
+                        .bind(job_id.to_string())
+                        .fetch_optional(&mut *tx)
+                        .await?
+                }
+            };
+            tx.commit().await?;
+            job
+        };
+        let Some(job) = job else {
+            return Ok(None);
+        };
+        let (req, parts) = job.req.take_parts();
+        let args = decode_job::<T, C>(&self.config, req, parts.context.codec_tag())?;
+        let mut req = Request::new_with_parts(args, parts);
+        req.parts.namespace = Some(Namespace(self.config.base.namespace.clone()));
+        Ok(Some(req))
+    }
+
+    /// Claim the next runnable job for `worker_id` and open a transaction to process it in
+    ///
+    /// The claim itself is committed right away, the transaction is for the handler's own writes.
+    /// Finish with [`SqliteStorage::ack_in_tx`] and commit, so the job is only done if its writes are.
+    /// Dropping the transaction instead rolls those back and leaves the job running under `worker_id`,
+    /// to be recovered by [`SqliteStorage::reenqueue_orphaned`] once the worker stops its heartbeat.
+    pub async fn claim_next_tx(
+        &mut self,
+        worker_id: &WorkerId,
+    ) -> Result<Option<(Request<T, SqlContext>, Transaction<'static, Sqlite>)>, sqlx::Error> {
+        let ids =
+            fetch_runnable_ids(&self.pool, &self.config, self.config.base.buffer_size).await?;
+        for id in ids {
+            let job_id = TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                index: "id".to_string(),
+                source: Box::new(e),
+            })?;
+            if let Some(req) = self.claim(worker_id, &job_id).await? {
+                let tx = self.pool.begin().await?;
+                return Ok(Some((req, tx)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Acknowledge a job claimed with [`SqliteStorage::claim_next_tx`] as part of `tx`
+    ///
+    /// Nothing is visible until `tx` is committed, which is why the configured event sink
+    /// and circuit breaker are not told about the outcome here.
+    pub async fn ack_in_tx<Res: Serialize>(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ctx: &SqlContext,
+        res: &Response<Res>,
+    ) -> Result<(), StorageError> {
+        write_ack(tx, &self.config, ctx, res).await?;
+        Ok(())
+    }
+
+    fn stream_jobs(
+        &self,
+        worker: &Worker<Context>,
+        interval: Duration,
+        buffer_size: usize,
+    ) -> impl Stream<Item = Result<Fetch<T>, sqlx::Error>> {
+        let pool = self.pool.clone();
+        let worker = worker.clone();
+        let config = self.config.clone();
+        let namespace = Namespace(self.config.base.namespace.clone());
+        try_stream! {
+            loop {
+                apalis_core::sleep(interval).await;
+                if !worker.is_ready() {
+                    yield Fetch::Backoff(interval);
+                    continue;
+                }
+                let worker_id = worker.id();
+                let ids = fetch_runnable_ids(&pool, &config, buffer_size).await?;
+                if ids.is_empty() {
+                    yield Fetch::Empty;
+                }
+                for id in ids {
+                    let res = fetch_next(&pool, worker_id, id, &config).await?;
+                    yield match res {
+                        None => Fetch::Empty,
+                        Some(job) => {
+                            let (req, parts) = job.req.take_parts();
+                            let args = decode_job::<T, C>(&config, req, parts.context.codec_tag())?;
+                            let mut req = Request::new_with_parts(args, parts);
+                            req.parts.namespace = Some(namespace.clone());
+                            Fetch::Job(Box::new(req))
+                        }
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Get the ids of up to `limit` jobs ready to be claimed, in the configured fetch order
+async fn fetch_runnable_ids(
+    pool: &Pool<Sqlite>,
+    config: &SqliteConfig,
+    limit: usize,
+) -> Result<Vec<String>, sqlx::Error> {
+    let fetch_query = config.query(match config.base.fetch_order() {
+        FetchOrder::Any => "SELECT {id} FROM {table} {indexed_by}
+        WHERE ({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {run_at} < ?1 AND {job_type} = ?2 AND {deleted_at} IS NULL {fetch_filter}
+        AND {id} NOT IN (SELECT value FROM json_each(?4)) LIMIT ?3",
+        FetchOrder::Fifo => "SELECT {id} FROM {table} {indexed_by}
+        WHERE ({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {run_at} < ?1 AND {job_type} = ?2 AND {deleted_at} IS NULL {fetch_filter}
+        AND {id} NOT IN (SELECT value FROM json_each(?4)) ORDER BY {run_at} ASC, {seq} ASC LIMIT ?3",
+        FetchOrder::EarliestDeadline => "SELECT {id} FROM {table} {indexed_by}
+        WHERE ({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {run_at} < ?1 AND {job_type} = ?2 AND {deleted_at} IS NULL {fetch_filter}
+        AND {id} NOT IN (SELECT value FROM json_each(?4)) ORDER BY {deadline} ASC NULLS LAST, {seq} ASC LIMIT ?3",
+        FetchOrder::Priority { .. } => "SELECT {id} FROM {table} {indexed_by}
+        WHERE ({status} = 'Pending' OR ({status} IN ('Retry', 'Failed') AND {attempts} < {max_attempts})) AND {run_at} < ?1 AND {job_type} = ?2 AND {deleted_at} IS NULL {fetch_filter}
+        AND {id} NOT IN (SELECT value FROM json_each(?4)) ORDER BY {priority} + (?1 - {run_at}) / ?5 DESC, {run_at} ASC, {seq} ASC LIMIT ?3",
+    });
+    let skipped = config
+        .circuit_breaker()
+        .map(|breaker| breaker.open_keys())
+        .unwrap_or_default();
+    let mut query = sqlx::query_as(&fetch_query)
+        .bind(config.now().timestamp())
+        .bind(&config.base.namespace)
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .bind(
+            serde_json::to_string(&skipped)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
+        );
+    if let FetchOrder::Priority { age_boost } = config.base.fetch_order() {
+        query = query.bind(i64::try_from(age_boost.as_secs().max(1)).unwrap_or(i64::MAX));
+    }
+    let ids: Vec<(String,)> = logged(config, "fetch runnable", query.fetch_all(pool)).await?;
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
+
+/// Store the outcome of a job, returning the state it was moved to
+///
+/// Returns `None` without writing anything if the job is done or this run already stored its outcome,
+/// as when a job is delivered twice. A job claimed again since, by another worker or in a later run
+/// of the same worker, is left to that run. The claim is told apart by its attempt, which only grows.
+async fn write_ack<Res: Serialize>(
+    conn: &mut SqliteConnection,
+    config: &SqliteConfig,
+    ctx: &SqlContext,
+    res: &Response<Res>,
+) -> Result<Option<State>, StorageError> {
+    let not_owned = || StorageError::NotOwned(res.task_id.clone());
+    let worker_id = ctx.lock_by().as_ref().ok_or_else(not_owned)?.to_string();
+    let attempt = i64::try_from(res.attempt.current()).unwrap_or(i64::MAX);
+    let query = config.query("UPDATE {table} SET {status} = ?4, {done_at} = ?6, {last_error} = ?3, {run_at} = COALESCE(?5, {run_at}), {result} = ?8 WHERE {id} = ?1 AND {lock_by} = ?2 AND {status} = 'Running' AND {attempts} <= ?7");
+    let result = res
+        .inner
+        .as_ref()
+        .map_err(|e| truncate_error(e.to_string().as_bytes(), config.max_error_len()));
+    let result = serde_json::to_string(&result)
+        .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    // The output of a success is kept for `SqliteStorage::fetch_result`
+    let output = res
+        .inner
+        .as_ref()
+        .ok()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    let run_at = match (&res.inner, config.retry_delay()) {
+        (Err(e), Some(delay)) => {
+            let wait = clamp_backoff(ctx, delay.delay(res.attempt.current(), e));
+            Some(config.now().timestamp() + wait.as_secs() as i64)
+        }
+        _ => None,
+    };
+    // A failure with a pending retry delay awaits its next attempt, one out of attempts is dead
+    let status = match calculate_status(&res.inner) {
+        State::Failed if res.attempt.current() >= ctx.max_attempts() as usize => State::Dead,
+        State::Failed if run_at.is_some() => State::Retry,
+        status => status,
+    };
+    // The guarded write goes first, so the transaction takes the write lock before reading anything.
+    // Reading first would leave concurrent acks each holding a read lock that neither can upgrade.
+    let query = sqlx::query(&query)
+        .bind(res.task_id.to_string())
+        .bind(&worker_id)
+        .bind(result)
+        .bind(status.to_string())
+        .bind(run_at)
+        .bind(config.now().timestamp())
+        .bind(attempt)
+        .bind(output);
+    if logged(config, "ack", query.execute(&mut *conn))
+        .await?
+        .rows_affected()
+        == 0
+    {
+        let query =
+            config.query("SELECT {status}, {lock_by}, {attempts} FROM {table} WHERE {id} = ?1");
+        let current: Option<(String, Option<String>, i64)> = sqlx::query_as(&query)
+            .bind(res.task_id.to_string())
+            .fetch_optional(&mut *conn)
             .await?;
-        Ok(res
-            .into_iter()
-            .map(|j| {
-                let (req, ctx) = j.req.take_parts();
-                let req = JsonCodec::<String>::decode(req).unwrap();
-                Request::new_with_ctx(req, ctx)
-            })
-            .collect())
+        let (status, lock_by, attempts) = current.ok_or(sqlx::Error::RowNotFound)?;
+        let same_run = lock_by.as_deref() == Some(worker_id.as_str()) && attempts <= attempt;
+        return match status.parse::<State>() {
+            Ok(State::Done) => Ok(None),
+            _ if same_run => Ok(None),
+            _ => Err(not_owned()),
+        };
+    }
+    if matches!(status, State::Retry | State::Failed) && config.max_retries_per_window().is_some() {
+        let due = run_at.unwrap_or_else(|| config.now().timestamp());
+        let run_at = throttle_retry(&mut *conn, config, &res.task_id, due).await?;
+        let query = config.query("UPDATE {table} SET {run_at} = ?2 WHERE {id} = ?1");
+        sqlx::query(&query)
+            .bind(res.task_id.to_string())
+            .bind(run_at)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(Some(status))
+}
+
+/// Defer a retry of `job_id` due at `run_at` once it used up its retries for the window, see [`SqliteConfig::set_max_retries_per_window`]
+///
+/// Returns when the retry may run, recording it against the job.
+async fn throttle_retry(
+    conn: &mut SqliteConnection,
+    config: &SqliteConfig,
+    job_id: &TaskId,
+    run_at: i64,
+) -> Result<i64, sqlx::Error> {
+    let Some((count, window)) = config.max_retries_per_window() else {
+        return Ok(run_at);
+    };
+    let window = i64::try_from(window.as_secs()).unwrap_or(i64::MAX);
+    let query = config.query("SELECT {retried_at} FROM {table} WHERE {id} = ?1");
+    let retried_at: Option<String> = sqlx::query_scalar(&query)
+        .bind(job_id.to_string())
+        .fetch_optional(&mut *conn)
+        .await?
+        .flatten();
+    let mut retried_at: Vec<i64> = match retried_at {
+        Some(retried_at) => {
+            serde_json::from_str(&retried_at).map_err(|e| sqlx::Error::ColumnDecode {
+                index: "retried_at".to_string(),
+                source: Box::new(e),
+            })?
+        }
+        None => Vec::new(),
+    };
+    retried_at.sort_unstable();
+    let count = count.max(1) as usize;
+    // The oldest of the last `count` retries has to leave the window before the next one runs
+    let run_at = match retried_at.len().checked_sub(count) {
+        Some(oldest) if retried_at[oldest] > run_at.saturating_sub(window) => {
+            retried_at[oldest].saturating_add(window)
+        }
+        _ => run_at,
+    };
+    retried_at.retain(|&at| at > run_at.saturating_sub(window));
+    retried_at.push(run_at);
+    let query = config.query("UPDATE {table} SET {retried_at} = ?2 WHERE {id} = ?1");
+    sqlx::query(&query)
+        .bind(job_id.to_string())
+        .bind(
+            serde_json::to_string(&retried_at)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
+        )
+        .execute(conn)
+        .await?;
+    Ok(run_at)
+}
+
+/// Whether a sqlite `UNIQUE constraint failed: table.column, ...` message names `column`
+fn violates_column(message: &str, column: &str) -> bool {
+    message
+        .rsplit(": ")
+        .next()
+        .into_iter()
+        .flat_map(|columns| columns.split(", "))
+        .any(|failed| failed.rsplit('.').next() == Some(column))
+}
+
+/// Put back a job `worker_id` claimed but never handed to anyone, undoing the attempt
+async fn unclaim(
+    pool: &Pool<Sqlite>,
+    config: &SqliteConfig,
+    job_id: &TaskId,
+    worker_id: &WorkerId,
+) -> Result<(), sqlx::Error> {
+    let query = config.query("UPDATE {table} SET {status} = 'Pending', {lock_by} = NULL, {lock_at} = NULL, {attempts} = MAX({attempts} - 1, 0) WHERE {id} = ?1 AND {lock_by} = ?2 AND {status} = 'Running'");
+    sqlx::query(&query)
+        .bind(job_id.to_string())
+        .bind(worker_id.to_string())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Move a job to `Retry`, releasing its lock, so it runs again once `wait` has passed
+///
+/// A job out of attempts is marked `Dead` instead, it is not run again.
+/// The retry is deferred further if the job retried too often, see [`SqliteConfig::set_max_retries_per_window`].
+/// Returns the status the job was left in.
+async fn reschedule_job(
+    conn: &mut SqliteConnection,
+    config: &SqliteConfig,
+    job_id: &TaskId,
+    wait: Duration,
+) -> Result<State, sqlx::Error> {
+    let wait: i64 = wait
+        .as_secs()
+        .try_into()
+        .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    let query = config.query("SELECT {attempts} >= {max_attempts} FROM {table} WHERE {id} = ?1");
+    let exhausted: bool = sqlx::query_scalar(&query)
+        .bind(job_id.to_string())
+        .fetch_optional(&mut *conn)
+        .await?
+        .unwrap_or_default();
+    if exhausted {
+        let query = config.query("UPDATE {table} SET {status} = 'Dead', {done_at} = ?2, {lock_by} = NULL, {lock_at} = NULL WHERE {id} = ?1");
+        sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(config.now().timestamp())
+            .execute(conn)
+            .await?;
+        return Ok(State::Dead);
+    }
+    let query = config.query("UPDATE {table} SET {status} = 'Retry', {done_at} = NULL, {lock_by} = NULL, {lock_at} = NULL, {run_at} = ?2 WHERE {id} = ?1");
+    let run_at =
+        throttle_retry(&mut *conn, config, job_id, config.now().timestamp() + wait).await?;
+    sqlx::query(&query)
+        .bind(job_id.to_string())
+        .bind(run_at)
+        .execute(conn)
+        .await?;
+    Ok(State::Retry)
+}
+
+async fn requeue_running_for_worker(
+    pool: &Pool<Sqlite>,
+    config: &SqliteConfig,
+    worker_id: &WorkerId,
+) -> Result<u64, sqlx::Error> {
+    let query = config.query("UPDATE {table} SET {status} = 'Pending', {lock_by} = NULL, {lock_at} = NULL WHERE {status} = 'Running' AND {lock_by} = ?1 AND {job_type} = ?2");
+    let res = sqlx::query(&query)
+        .bind(worker_id.to_string())
+        .bind(&config.base.namespace)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected())
+}
+
+/// Insert a job, returning whether it was inserted
+///
+/// A job with a [`unique_key`](SqlContext::unique_key) held by a live job of its namespace is not inserted.
+async fn insert_job(
+    executor: impl sqlx::SqliteExecutor<'_>,
+    config: &SqliteConfig,
+    job: String,
+    job_type: &str,
+    parts: &Parts<SqlContext>,
+    run_at: i64,
+) -> Result<bool, StorageError> {
+    let headers = match parts.context.headers() {
+        headers if headers.is_empty() => None,
+        headers => Some(
+            serde_json::to_string(headers)
+                .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
+        ),
+    };
+    let schema = config.schema();
+    let columns = schema.insert_columns();
+    // The sequence is assigned by the insert itself, which sqlite serializes with other writes
+    let mut binds = 0;
+    let values = columns
+        .iter()
+        .map(|c| match c {
+            Column::Seq => format!(
+                "(SELECT COALESCE(MAX({seq}), 0) + 1 FROM {table})",
+                seq = schema.column(Column::Seq),
+                table = schema.table()
+            ),
+            _ => {
+                binds += 1;
+                format!("?{binds}")
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut query = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        schema.table(),
+        columns
+            .iter()
+            .map(|c| schema.column(*c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        values.join(", ")
+    );
+    if parts.context.unique_key().is_some() {
+        // The target repeats the predicate of the partial index, which sqlite needs to match it
+        query.push(' ');
+        query.push_str(&config.query(
+            "ON CONFLICT ({job_type}, {unique_key}) WHERE {unique_key} IS NOT NULL
+            AND {status} NOT IN ('Done', 'Killed', 'Dead')
+            AND {deleted_at} IS NULL DO NOTHING",
+        ));
+    }
+    let mut query = sqlx::query(&query);
+    for column in columns {
+        query = match column {
+            Column::Job => query.bind(job.clone()),
+            Column::Id => query.bind(parts.task_id.to_string()),
+            Column::JobType => query.bind(job_type.to_owned()),
+            Column::Status => query.bind(State::Pending.to_string()),
+            Column::Attempts => query.bind(0),
+            Column::MaxAttempts => query.bind(parts.context.max_attempts()),
+            Column::RunAt => query.bind(run_at),
+            Column::LastError
+            | Column::LockBy
+            | Column::EffectToken
+            | Column::Counters
+            | Column::RetriedAt
+            | Column::Result => query.bind(None::<String>),
+            Column::LockAt | Column::DoneAt | Column::DeletedAt => query.bind(None::<i64>),
+            Column::Deadline => query.bind(*parts.context.deadline()),
+            Column::Priority => query.bind(parts.context.priority()),
+            Column::DedupKey => query.bind(parts.context.dedup_key().clone()),
+            Column::UniqueKey => query.bind(parts.context.unique_key().clone()),
+            Column::MaxBackoff => query.bind(*parts.context.max_backoff_secs()),
+            Column::Headers => query.bind(headers.clone()),
+            Column::Seq => query,
+            Column::Codec => query.bind(config.codec_tag()),
+            Column::CreatedAt => query.bind(
+                parts
+                    .context
+                    .created_at()
+                    .unwrap_or_else(|| config.now().timestamp()),
+            ),
+        };
+    }
+    // Only the id is unique, another constraint failing is left as is
+    match logged(config, "push", query.execute(executor)).await {
+        Err(sqlx::Error::Database(e))
+            if e.is_unique_violation()
+                && violates_column(e.message(), schema.column(Column::Id)) =>
+        {
+            Err(StorageError::DuplicateId(parts.task_id.clone()))
+        }
+        res => Ok(res?.rows_affected() > 0),
+    }
+}
+
+/// Insert a job, returning its id or the id of the live job already holding its [`unique_key`](SqlContext::unique_key)
+async fn insert_unique(
+    pool: &Pool<Sqlite>,
+    config: &SqliteConfig,
+    job: String,
+    parts: &Parts<SqlContext>,
+    run_at: i64,
+) -> Result<TaskId, StorageError> {
+    let query = config.query(
+        "SELECT {id} FROM {table} WHERE {job_type} = ?1 AND {unique_key} = ?2
+        AND {status} NOT IN ('Done', 'Killed', 'Dead')
+        AND {deleted_at} IS NULL",
+    );
+    loop {
+        if insert_job(
+            pool,
+            config,
+            job.clone(),
+            &config.base.namespace,
+            parts,
+            run_at,
+        )
+        .await?
+        {
+            return Ok(parts.task_id.clone());
+        }
+        let holder: Option<String> = sqlx::query_scalar(&query)
+            .bind(&config.base.namespace)
+            .bind(parts.context.unique_key())
+            .fetch_optional(pool)
+            .await?;
+        // The job holding the key may have finished since, then the key is free again
+        if let Some(id) = holder {
+            return Ok(
+                TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "id".to_string(),
+                    source: Box::new(e),
+                })?,
+            );
+        }
+    }
+}
+
+impl<T, C> Storage for SqliteStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + 'static + Unpin + Sync,
+    C: Codec<Compact = String> + Send,
+{
+    type Job = T;
+
+    type Error = StorageError;
+
+    type Context = SqlContext;
+
+    async fn push_request(
+        &mut self,
+        job: Request<Self::Job, SqlContext>,
+    ) -> Result<Parts<SqlContext>, Self::Error> {
+        let (task, mut parts) = job.take_parts();
+        let raw = encode_job::<T, C>(&self.config, &task)?;
+        parts.task_id = insert_unique(
+            &self.pool,
+            &self.config,
+            raw,
+            &parts,
+            self.config.now().timestamp(),
+        )
+        .await?;
+        Ok(parts)
+    }
+
+    async fn schedule_request(
+        &mut self,
+        req: Request<Self::Job, SqlContext>,
+        on: i64,
+    ) -> Result<Parts<SqlContext>, Self::Error> {
+        let job = encode_job::<T, C>(&self.config, &req.args)?;
+        let mut parts = req.parts;
+        parts.task_id = insert_unique(&self.pool, &self.config, job, &parts, on).await?;
+        Ok(parts)
+    }
+
+    async fn fetch_by_id(
+        &mut self,
+        job_id: &TaskId,
+    ) -> Result<Option<Request<Self::Job, SqlContext>>, Self::Error> {
+        let fetch_query = self
+            .config
+            .query("SELECT {columns} FROM {table} WHERE {id} = ?1 AND {deleted_at} IS NULL");
+        let query = sqlx::query_as(&fetch_query).bind(job_id.to_string());
+        let res: Option<SqlRequest<String>> = logged(
+            &self.config,
+            "fetch by id",
+            query.fetch_optional(&self.pool),
+        )
+        .await?;
+        match res {
+            None => Ok(None),
+            Some(job) => Ok(Some({
+                let (req, parts) = job.req.take_parts();
+                let args = decode_job::<T, C>(&self.config, req, parts.context.codec_tag())?;
+
+                let mut req: Request<T, SqlContext> = Request::new_with_parts(args, parts);
+                req.parts.namespace = Some(Namespace(self.config.base.namespace.clone()));
+                req
+            })),
+        }
+    }
+
+    async fn len(&mut self) -> Result<i64, Self::Error> {
+        let query = self
+            .config
+            .query("SELECT COUNT(*) AS count FROM {table} WHERE {status} = 'Pending' AND {deleted_at} IS NULL");
+        let record = logged(
+            &self.config,
+            "len",
+            sqlx::query(&query).fetch_one(&self.pool),
+        )
+        .await?;
+        Ok(record.try_get("count")?)
+    }
+
+    async fn reschedule(
+        &mut self,
+        job: Request<T, SqlContext>,
+        wait: Duration,
+    ) -> Result<(), Self::Error> {
+        let mut conn = self.pool.acquire().await?;
+        reschedule_job(&mut conn, &self.config, &job.parts.task_id, wait).await?;
+        Ok(())
+    }
+
+    async fn update(&mut self, job: Request<Self::Job, SqlContext>) -> Result<(), Self::Error> {
+        let ctx = job.parts.context;
+        let status = ctx.status().to_string();
+        let attempts = job.parts.attempt;
+        let done_at = *ctx.done_at();
+        let lock_by = ctx.lock_by().clone();
+        let lock_at = *ctx.lock_at();
+        let last_error = ctx
+            .last_error()
+            .as_ref()
+            .map(|e| truncate_error(e.as_bytes(), self.config.max_error_len()));
+        let job_id = job.parts.task_id;
+        let mut tx = self.pool.acquire().await?;
+        let query = self.config.query("UPDATE {table} SET {status} = ?1, {attempts} = ?2, {done_at} = ?3, {lock_by} = ?4, {lock_at} = ?5, {last_error} = ?6 WHERE {id} = ?7");
+        sqlx::query(&query)
+            .bind(status.to_owned())
+            .bind::<i64>(
+                attempts
+                    .current()
+                    .try_into()
+                    .map_err(|e| sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?,
+            )
+            .bind(done_at)
+            .bind(lock_by.map(|w| w.name().to_string()))
+            .bind(lock_at)
+            .bind(last_error)
+            .bind(job_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_empty(&mut self) -> Result<bool, Self::Error> {
+        // EXISTS stops at the first pending row instead of counting them all
+        let query = self.config.query(
+            "SELECT NOT EXISTS (SELECT 1 FROM {table} WHERE {status} = 'Pending' AND {deleted_at} IS NULL) AS empty",
+        );
+        let record = logged(
+            &self.config,
+            "is_empty",
+            sqlx::query(&query).fetch_one(&self.pool),
+        )
+        .await?;
+        Ok(record.try_get("empty")?)
+    }
+
+    async fn vacuum(&mut self) -> Result<usize, Self::Error> {
+        let query = self
+            .config
+            .query("DELETE FROM {table} WHERE {status} = 'Done' AND {deleted_at} IS NULL");
+        let record = logged(
+            &self.config,
+            "vacuum",
+            sqlx::query(&query).execute(&self.pool),
+        )
+        .await?;
+        Ok(record.rows_affected().try_into().unwrap_or_default())
+    }
+
+    /// Cancel a job that has not finished yet, see [`Storage::cancel`]
+    ///
+    /// Handlers running in this process are told right away, others once their worker next renews
+    /// the job's lock, every [`SqliteConfig::lock_renew_interval`], or checks on it, every [`Config::keep_alive`].
+    async fn cancel(&mut self, job_id: &TaskId) -> Result<bool, Self::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {status} = 'Killed', {done_at} = ?2 WHERE {id} = ?1
+            AND ({status} IN ('Pending', 'Retry', 'Running') OR ({status} = 'Failed' AND {attempts} < {max_attempts}))
+            AND {deleted_at} IS NULL",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(self.config.now().timestamp())
+            .execute(&self.pool)
+            .await?;
+        if let Some(token) = self
+            .cancellations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(job_id)
+        {
+            token.cancel();
+        }
+        Ok(res.rows_affected() > 0)
     }
+}
+
+impl<T, C> SqliteStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + 'static + Unpin + Sync,
+    C: Codec<Compact = String> + Send,
+{
+    /// Reschedule a job with a delay computed from the error it failed with
+    ///
+    /// `delay` receives the attempts made so far and the error.
+    pub async fn reschedule_with<F>(
+        &mut self,
+        job: Request<T, SqlContext>,
+        error: &Error,
+        delay: F,
+    ) -> Result<(), StorageError>
+    where
+        F: FnOnce(usize, &Error) -> Duration,
+    {
+        let wait = clamp_backoff(
+            &job.parts.context,
+            delay(job.parts.attempt.current(), error),
+        );
+        self.reschedule(job, wait).await
+    }
+
+    /// Push a job whose arguments are already encoded, skipping serialization
+    ///
+    /// Useful for producers written in other languages or for replaying an export.
+    /// `job_type` is the namespace of the workers that should consume the job.
+    /// The payload is only decoded when consumed, unless [`SqliteConfig::set_validate_raw`] is enabled.
+    pub async fn push_raw(&mut self, raw: String, job_type: &str) -> Result<TaskId, StorageError> {
+        if self.config.validate_raw() {
+            decode_job::<T, C>(&self.config, raw.clone(), self.config.codec_tag())?;
+        }
+        let parts = Parts::<SqlContext>::default();
+        insert_job(
+            &self.pool,
+            &self.config,
+            raw,
+            job_type,
+            &parts,
+            self.config.now().timestamp(),
+        )
+        .await?;
+        Ok(parts.task_id)
+    }
+
+    /// Overwrite the payload of a job that has not finished, eg to checkpoint its progress before it is retried
+    ///
+    /// Status and attempts are left untouched.
+    /// Fails with [`sqlx::Error::RowNotFound`] if there is no such job or it is done, killed or dead.
+    pub async fn update_payload(&mut self, job_id: &TaskId, job: &T) -> Result<(), sqlx::Error> {
+        let raw = encode_job::<T, C>(&self.config, job)?;
+        let query = self.config.query(
+            "UPDATE {table} SET {job} = ?2 WHERE {id} = ?1 AND {status} NOT IN ('Done', 'Killed', 'Dead')",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(raw)
+            .execute(&self.pool)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    /// Push a follow up job spawned by the job running with `parent`
+    ///
+    /// The child inherits the [`priority`](SqlContext::priority) of its parent so a workflow keeps its urgency
+    /// from step to step, unless `priority` overrides it.
+    pub async fn push_after(
+        &mut self,
+        parent: &SqlContext,
+        job: T,
+        priority: Option<i32>,
+    ) -> Result<Parts<SqlContext>, StorageError> {
+        let mut req = Request::<T, SqlContext>::new(job);
+        req.parts
+            .context
+            .set_priority(priority.unwrap_or(parent.priority()));
+        self.push_request(req).await
+    }
+
+    /// Push a job unless one with the same `key` is waiting, in which case that one is replaced
+    ///
+    /// The waiting job gets the new arguments and becomes due now, so a job pushed repeatedly in a burst
+    /// runs once with the latest arguments. A job that already started is left alone and a new one is pushed.
+    /// The key is stored as the job's [`dedup_key`](SqlContext::dedup_key). Returns the id of the job that will run.
+    pub async fn push_or_replace(&mut self, job: T, key: &str) -> Result<TaskId, StorageError> {
+        let raw = encode_job::<T, C>(&self.config, &job)?;
+        let now = self.config.now().timestamp();
+        let returning = self.supports_returning().await;
+        let mut tx = self.pool.begin().await?;
+        let replaced: Option<String> = if returning {
+            let query = self.config.query(
+                "UPDATE {table} SET {job} = ?3, {run_at} = ?4 WHERE {id} =
+                (SELECT {id} FROM {table} WHERE {dedup_key} = ?1 AND {job_type} = ?2
+                AND {status} = 'Pending' AND {lock_by} IS NULL AND {deleted_at} IS NULL ORDER BY {run_at} DESC LIMIT 1)
+                RETURNING {id}",
+            );
+            sqlx::query_scalar(&query)
+                .bind(key)
+                .bind(&self.config.base.namespace)
+                .bind(&raw)
+                .bind(now)
+                .fetch_optional(&mut *tx)
+                .await?
+        } else {
+            let query = self.config.query(
+                "SELECT {id} FROM {table} WHERE {dedup_key} = ?1 AND {job_type} = ?2
+                AND {status} = 'Pending' AND {lock_by} IS NULL AND {deleted_at} IS NULL ORDER BY {run_at} DESC LIMIT 1",
+            );
+            let waiting: Option<String> = sqlx::query_scalar(&query)
+                .bind(key)
+                .bind(&self.config.base.namespace)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let query = self
+                .config
+                .query("UPDATE {table} SET {job} = ?2, {run_at} = ?3 WHERE {id} = ?1");
+            if let Some(id) = &waiting {
+                sqlx::query(&query)
+                    .bind(id)
+                    .bind(&raw)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            waiting
+        };
+        let task_id = match replaced {
+            Some(id) => TaskId::from_str(&id).map_err(|e| sqlx::Error::ColumnDecode {
+                index: "id".to_string(),
+                source: Box::new(e),
+            })?,
+            None => {
+                let mut parts = Parts::<SqlContext>::default();
+                parts.context.set_dedup_key(Some(key.to_owned()));
+                insert_job(
+                    &mut *tx,
+                    &self.config,
+                    raw,
+                    &self.config.base.namespace,
+                    &parts,
+                    now,
+                )
+                .await?;
+                parts.task_id
+            }
+        };
+        tx.commit().await?;
+        Ok(task_id)
+    }
+
+    /// Push a job with headers, metadata that handlers read from [`SqlContext::headers`]
+    pub async fn push_with_headers(
+        &mut self,
+        job: T,
+        headers: HashMap<String, String>,
+    ) -> Result<Parts<SqlContext>, StorageError> {
+        let mut req = Request::<T, SqlContext>::new(job);
+        req.parts.context.set_headers(headers);
+        self.push_request(req).await
+    }
+
+    /// Push a job recording `created_at`, in seconds, as its creation time instead of now
+    ///
+    /// For backfills, so the job carries the time of the historical event it stands for.
+    /// The job is still due now and queued behind the jobs already waiting, as the fetch order goes by `run_at`.
+    /// [`SqliteStorage::oldest_pending_age`] counts its age from `created_at` though.
+    /// A `created_at` in the future is rejected.
+    pub async fn push_with_created_at(
+        &mut self,
+        job: T,
+        created_at: i64,
+    ) -> Result<Parts<SqlContext>, StorageError> {
+        if created_at > self.config.now().timestamp() {
+            return Err(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "created_at is in the future",
+            ))
+            .into());
+        }
+        let mut req = Request::<T, SqlContext>::new(job);
+        req.parts.context.set_created_at(Some(created_at));
+        self.push_request(req).await
+    }
+
+    /// Push a job under an id picked by the caller, eg one derived from the order it processes
+    ///
+    /// Pushing the same id twice fails with [`StorageError::DuplicateId`] and leaves the first job as is,
+    /// so a failed producer can push again without queueing the work twice.
+    /// The id is then known upfront, for [`Storage::fetch_by_id`] or to correlate logs.
+    pub async fn push_with_id(
+        &mut self,
+        id: TaskId,
+        job: T,
+    ) -> Result<Parts<SqlContext>, StorageError> {
+        let mut req = Request::<T, SqlContext>::new(job);
+        req.parts.task_id = id;
+        self.push_request(req).await
+    }
+
+    /// List the waiting jobs of this namespace due to run before `at`, soonest first
+    ///
+    /// The jobs are only read, not claimed, so they remain available to workers.
+    pub async fn fetch_due_before(
+        &self,
+        at: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Request<T, SqlContext>>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {columns} FROM {table}
+            WHERE {job_type} = ?1 AND {status} IN ('Pending', 'Retry') AND {run_at} < ?2 AND {deleted_at} IS NULL
+            ORDER BY {run_at} ASC LIMIT ?3",
+        );
+        let jobs: Vec<SqlRequest<String>> = sqlx::query_as(&query)
+            .bind(&self.config.base.namespace)
+            .bind(at.timestamp())
+            .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+            .fetch_all(&self.pool)
+            .await?;
+        jobs.into_iter()
+            .map(|job| {
+                let (req, parts) = job.req.take_parts();
+                let args = decode_job::<T, C>(&self.config, req, parts.context.codec_tag())?;
+                let mut req: Request<T, SqlContext> = Request::new_with_parts(args, parts);
+                req.parts.namespace = Some(Namespace(self.config.base.namespace.clone()));
+                Ok(req)
+            })
+            .collect()
+    }
+
+    /// Follow the jobs pushed to this namespace, in the order they were enqueued
+    ///
+    /// Rows are only read, never locked or updated, so this suits observers such as audit or analytics pipelines.
+    /// The cursor is the sqlite `rowid` of the last job seen: `None` starts after the newest job,
+    /// `Some(0)` replays the namespace from the start. New rows are checked for every [`Config::poll_interval`].
+    pub fn enqueue_feed(
+        &self,
+        from: Option<i64>,
+    ) -> impl Stream<Item = Result<Request<T, SqlContext>, sqlx::Error>> + '_ {
+        try_stream! {
+            let mut cursor = match from {
+                Some(cursor) => cursor,
+                None => {
+                    let query = self.config.query("SELECT COALESCE(MAX(rowid), 0) FROM {table}");
+                    sqlx::query_scalar(&query).fetch_one(&self.pool).await?
+                }
+            };
+            let query = self.config.query(
+                "SELECT rowid AS feed_cursor, {columns} FROM {table}
+                WHERE {job_type} = ?1 AND rowid > ?2 AND {deleted_at} IS NULL ORDER BY rowid ASC LIMIT ?3",
+            );
+            loop {
+                let rows = sqlx::query(&query)
+                    .bind(&self.config.base.namespace)
+                    .bind(cursor)
+                    .bind(i64::try_from(self.config.base.buffer_size).unwrap_or(i64::MAX))
+                    .fetch_all(&self.pool)
+                    .await?;
+                if rows.is_empty() {
+                    apalis_core::sleep(self.config.base.poll_interval).await;
+                    continue;
+                }
+                for row in rows {
+                    cursor = row.try_get("feed_cursor")?;
+                    let job = <SqlRequest<String> as sqlx::FromRow<_>>::from_row(&row)?;
+                    let (req, parts) = job.req.take_parts();
+                    let args = decode_job::<T, C>(&self.config, req, parts.context.codec_tag())?;
+                    let mut req: Request<T, SqlContext> = Request::new_with_parts(args, parts);
+                    req.parts.namespace = Some(Namespace(self.config.base.namespace.clone()));
+                    yield req;
+                }
+            }
+        }
+    }
+}
+
+impl<T> SqliteStorage<T> {
+    /// Puts the job instantly back into the queue
+    /// Another Worker may consume
+    pub async fn retry(
+        &mut self,
+        worker_id: &WorkerId,
+        job_id: &TaskId,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.acquire().await?;
+        let query = self.config.query("UPDATE {table} SET {status} = 'Pending', {done_at} = NULL, {lock_by} = NULL WHERE {id} = ?1 AND {lock_by} = ?2");
+        sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(worker_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Hold a pending job back until `until`, eg while someone previews it before it runs
+    ///
+    /// `until` is a unix timestamp in seconds. The job is not assigned to any worker,
+    /// so the hold can be lifted early with [`SqliteStorage::release`]. Jobs that are not pending are left untouched.
+    pub async fn hold(&mut self, job_id: &TaskId, until: i64) -> Result<(), sqlx::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {run_at} = ?2 WHERE {id} = ?1 AND {status} = 'Pending' AND {lock_by} IS NULL",
+        );
+        sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(until)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Release a job held by [`SqliteStorage::hold`], making it available right away
+    pub async fn release(&mut self, job_id: &TaskId) -> Result<(), sqlx::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {run_at} = ?2 WHERE {id} = ?1 AND {status} = 'Pending' AND {lock_by} IS NULL",
+        );
+        sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(self.config.now().timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Extend the lock `worker_id` holds on a running job
+    ///
+    /// Also marks the worker as seen, which is what keeps its jobs from being reclaimed as orphans.
+    /// Fails with [`sqlx::Error::RowNotFound`] if the worker no longer holds the job.
+    pub async fn renew_lock(
+        &self,
+        job_id: &TaskId,
+        worker_id: &WorkerId,
+    ) -> Result<(), sqlx::Error> {
+        let now = self.config.now().timestamp_millis();
+        let mut tx = self.pool.begin().await?;
+        let query = self.config.query(
+            "UPDATE {table} SET {lock_at} = ?3 WHERE {id} = ?1 AND {lock_by} = ?2 AND {status} = 'Running'",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(worker_id.to_string())
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        let query = self
+            .config
+            .query("UPDATE {workers} SET last_seen = ?2 WHERE id = ?1");
+        sqlx::query(&query)
+            .bind(worker_id.to_string())
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record that the external effect of a job, eg a card charge, was performed under `token`
+    ///
+    /// Call it right after the effect succeeded, ideally with the idempotency key the external system was given.
+    /// A retried or reclaimed run then finds the token in [`SqlContext::effect_token`] and skips the effect.
+    /// Returns `false` if the job already had an effect recorded, eg by a run that was thought dead,
+    /// in which case the recorded token is kept.
+    pub async fn record_effect(&self, job_id: &TaskId, token: &str) -> Result<bool, sqlx::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {effect_token} = ?2 WHERE {id} = ?1 AND {effect_token} IS NULL",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        if res.rows_affected() == 1 {
+            return Ok(true);
+        }
+        match self.fetch_effect_token(job_id).await? {
+            Some(_) => Ok(false),
+            None => Err(sqlx::Error::RowNotFound),
+        }
+    }
+
+    /// Add `by` to the counter `key` of a job, returning its new value
+    ///
+    /// Counters start at 0 and are kept apart from the attempts, across retries and reschedules,
+    /// eg to count deliveries to one provider. They are read back from [`SqlContext::counters`].
+    /// The update is a single statement, so concurrent bumps are not lost.
+    /// Fails with [`sqlx::Error::RowNotFound`] if the job does not exist.
+    pub async fn bump_counter(
+        &mut self,
+        job_id: &TaskId,
+        key: &str,
+        by: i64,
+    ) -> Result<i64, sqlx::Error> {
+        if key.contains('"') {
+            return Err(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("counter key {key:?} must not contain a double quote"),
+            )));
+        }
+        let path = format!("$.\"{key}\"");
+        if self.supports_returning().await {
+            let query = self.config.query(
+                "UPDATE {table} SET {counters} = json_set(COALESCE({counters}, '{}'), ?2, COALESCE(json_extract({counters}, ?2), 0) + ?3)
+                WHERE {id} = ?1 RETURNING json_extract({counters}, ?2)",
+            );
+            return sqlx::query_scalar(&query)
+                .bind(job_id.to_string())
+                .bind(&path)
+                .bind(by)
+                .fetch_optional(&self.pool)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound);
+        }
+        let mut tx = self.pool.begin().await?;
+        let query = self.config.query(
+            "UPDATE {table} SET {counters} = json_set(COALESCE({counters}, '{}'), ?2, COALESCE(json_extract({counters}, ?2), 0) + ?3)
+            WHERE {id} = ?1",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(&path)
+            .bind(by)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        let query = self
+            .config
+            .query("SELECT json_extract({counters}, ?2) FROM {table} WHERE {id} = ?1");
+        let value = sqlx::query_scalar(&query)
+            .bind(job_id.to_string())
+            .bind(&path)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(value)
+    }
+
+    async fn fetch_effect_token(&self, job_id: &TaskId) -> Result<Option<String>, sqlx::Error> {
+        let query = self
+            .config
+            .query("SELECT {effect_token} FROM {table} WHERE {id} = ?1");
+        let token: Option<Option<String>> = sqlx::query_scalar(&query)
+            .bind(job_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(token.flatten())
+    }
+
+    /// Move a job to `new_namespace`, or into this storage's namespace with `None`
+    ///
+    /// Reclassifies a misrouted job while keeping its id, attempts and history.
+    /// Only pending or failed jobs move, a running job is left with its worker.
+    /// Fails with [`sqlx::Error::RowNotFound`] if there is no such job or it can't be moved.
+    pub async fn transfer_job(
+        &mut self,
+        job_id: &TaskId,
+        new_namespace: Option<&QueueName>,
+    ) -> Result<(), sqlx::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {job_type} = ?2
+            WHERE {id} = ?1 AND {status} IN ('Pending', 'Retry', 'Failed', 'Dead')",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(new_namespace.map_or(self.config.base.namespace.as_str(), QueueName::as_str))
+            .execute(&self.pool)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    /// Kill a job
+    pub async fn kill(&mut self, worker_id: &WorkerId, job_id: &TaskId) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let query = self.config.query("UPDATE {table} SET {status} = 'Killed', {done_at} = ?3 WHERE {id} = ?1 AND {lock_by} = ?2");
+        sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(worker_id.to_string())
+            .bind(self.config.now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Whether `worker_id` still holds a running job
+    async fn holds(&self, job_id: &TaskId, worker_id: &WorkerId) -> Result<bool, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT EXISTS (SELECT 1 FROM {table} WHERE {id} = ?1 AND {lock_by} = ?2 AND {status} = 'Running')",
+        );
+        sqlx::query_scalar(&query)
+            .bind(job_id.to_string())
+            .bind(worker_id.to_string())
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Add jobs that failed back to the queue if there are still remaining attemps
+    pub async fn reenqueue_failed(&mut self) -> Result<(), sqlx::Error> {
+        let job_type = self.config.base.namespace.clone();
+        let mut tx = self.pool.acquire().await?;
+        let query = self.config.query(r#"Update {table}
+                            SET {status} = "Pending", {done_at} = NULL, {lock_by} = NULL, {lock_at} = NULL
+                            WHERE {id} in
+                                (SELECT {table}.{id} from {table}
+                                    WHERE {status}= "Failed" AND {table}.{attempts} < {table}.{max_attempts}
+                                     ORDER BY {lock_at} ASC LIMIT ?2);"#,
+        );
+        sqlx::query(&query)
+            .bind(job_type)
+            .bind(u32::try_from(self.config.base.buffer_size).unwrap_or(u32::MAX))
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the dead letters of this namespace that ended before `before`, with their raw payload
+    ///
+    /// Dead letters are jobs that were killed or ran out of attempts.
+    /// Meant for archiving them elsewhere ahead of [`SqliteStorage::purge_dead_letters`], which removes the same jobs.
+    pub async fn export_dead_letters(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Request<String, SqlContext>>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {columns} FROM {table}
+            WHERE {job_type} = ?1 AND {done_at} < ?2 AND {deleted_at} IS NULL
+            AND {status} IN ('Killed', 'Dead')
+            ORDER BY {done_at} ASC",
+        );
+        let jobs: Vec<SqlRequest<String>> = sqlx::query_as(&query)
+            .bind(&self.config.base.namespace)
+            .bind(before.timestamp())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(jobs.into_iter().map(|job| job.req).collect())
+    }
+
+    /// List the dead letters of this namespace, the most recently ended first, with their raw payload
+    ///
+    /// Dead letters are jobs that were killed or ran out of attempts, see [`SqliteStorage::retry_dead`] to run one again.
+    pub async fn list_dead(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Request<String, SqlContext>>, sqlx::Error> {
+        let query = self.config.query(
+            "SELECT {columns} FROM {table}
+            WHERE {job_type} = ?1 AND {status} IN ('Killed', 'Dead') AND {deleted_at} IS NULL
+            ORDER BY {done_at} DESC LIMIT ?2 OFFSET ?3",
+        );
+        let jobs: Vec<SqlRequest<String>> = sqlx::query_as(&query)
+            .bind(&self.config.base.namespace)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(jobs.into_iter().map(|job| job.req).collect())
+    }
+
+    /// Put a dead letter back into the queue with fresh attempts, returning whether there was one
+    ///
+    /// The job keeps its id and last error. Fails with a database error if the job has a unique key
+    /// that another live job now holds.
+    pub async fn retry_dead(&self, job_id: &TaskId) -> Result<bool, sqlx::Error> {
+        let query = self.config.query(
+            "UPDATE {table} SET {status} = 'Pending', {attempts} = 0, {run_at} = ?2,
+            {done_at} = NULL, {lock_by} = NULL, {lock_at} = NULL
+            WHERE {id} = ?1 AND {status} IN ('Killed', 'Dead') AND {deleted_at} IS NULL",
+        );
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(self.config.now().timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Delete the dead letters of this namespace that ended before `before`
+    ///
+    /// Returns how many jobs were deleted.
+    pub async fn purge_dead_letters(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let query = self.config.query(
+            "DELETE FROM {table}
+            WHERE {job_type} = ?1 AND {done_at} < ?2 AND {deleted_at} IS NULL
+            AND {status} IN ('Killed', 'Dead')",
+        );
+        let res = sqlx::query(&query)
+            .bind(&self.config.base.namespace)
+            .bind(before.timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Delete a job, returning whether it existed
+    ///
+    /// With [`SqliteConfig::set_soft_delete`] the row is kept as a tombstone until [`SqliteStorage::purge_deleted`],
+    /// otherwise it is removed right away. Either way the job is no longer fetched, counted or listed.
+    /// A running job is deleted too, its outcome is then discarded.
+    pub async fn delete(&mut self, job_id: &TaskId) -> Result<bool, sqlx::Error> {
+        let query = if self.config.soft_delete() {
+            self.config.query(
+                "UPDATE {table} SET {deleted_at} = ?2 WHERE {id} = ?1 AND {deleted_at} IS NULL",
+            )
+        } else {
+            self.config
+                .query("DELETE FROM {table} WHERE {id} = ?1 AND {deleted_at} IS NULL")
+        };
+        let res = sqlx::query(&query)
+            .bind(job_id.to_string())
+            .bind(self.config.now().timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Remove the tombstones of jobs soft deleted before `before`, across all namespaces
+    ///
+    /// See [`SqliteConfig::set_soft_delete`]. Returns how many rows were removed.
+    pub async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let query = self
+            .config
+            .query("DELETE FROM {table} WHERE {deleted_at} < ?1");
+        let res = sqlx::query(&query)
+            .bind(before.timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Put the jobs still marked as running by `worker_id` back into the queue
+    ///
+    /// Polling does this when a worker starts, so a worker restarted with a stable id
+    /// (eg [`WorkerId::from_host_and_name`]) picks its previous jobs up again right away.
+    /// Without it, those jobs would never be treated as orphans since the worker keeps its heartbeat alive.
+    /// Returns the number of jobs reclaimed.
+    pub async fn requeue_running_for_worker(
+        &self,
+        worker_id: &WorkerId,
+    ) -> Result<u64, sqlx::Error> {
+        let requeued = requeue_running_for_worker(&self.pool, &self.config, worker_id).await?;
+        self.lock_losses.fetch_add(requeued, Ordering::Relaxed);
+        Ok(requeued)
+    }
+
+    /// Add jobs that workers have disappeared to the queue
+    ///
+    /// Workers are compared at millisecond precision, so sub-second `dead_since` windows are honoured.
+    /// Rows written with second granularity are still read correctly.
+    pub async fn reenqueue_orphaned(
+        &self,
+        count: i32,
+        dead_since: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let job_type = self.config.base.namespace.clone();
+        let mut tx = self.pool.acquire().await?;
+        let query = self.config.query(r#"Update {table}
+                            SET {status} = "Pending", {done_at} = NULL, {lock_by} = NULL, {lock_at} = NULL, {last_error} ="Job was abandoned"
+                            WHERE {id} in
+                                (SELECT {table}.{id} from {table} INNER join {workers} ON {lock_by} = {workers}.id
+                                    WHERE {status}= "Running"
+                                    AND (CASE WHEN {workers}.last_seen < 100000000000 THEN {workers}.last_seen * 1000 ELSE {workers}.last_seen END) < ?1
+                                    AND {workers}.worker_type = ?2 ORDER BY {lock_at} ASC LIMIT ?3);"#,
+        );
+
+        let query = sqlx::query(&query)
+            .bind(dead_since.timestamp_millis())
+            .bind(job_type)
+            .bind(count);
+        let res = logged(&self.config, "reenqueue orphaned", query.execute(&mut *tx)).await?;
+        self.lock_losses
+            .fetch_add(res.rows_affected(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Errors that can occur while polling an SQLite database.
+#[derive(thiserror::Error, Debug)]
+pub enum SqlitePollError {
+    /// Error during a keep-alive heartbeat.
+    #[error("Encountered an error during KeepAlive heartbeat: `{0}`")]
+    KeepAliveError(sqlx::Error),
+
+    /// Error during re-enqueuing orphaned tasks.
+    #[error("Encountered an error during ReenqueueOrphaned heartbeat: `{0}`")]
+    ReenqueueOrphanedError(sqlx::Error),
+
+    /// Error during purging of expired dead letters.
+    #[error("Encountered an error during PurgeDeadLetters heartbeat: `{0}`")]
+    PurgeDeadLettersError(sqlx::Error),
+
+    /// A job handed to a handler was still running at shutdown, as its outcome could not be stored.
+    #[error("Job `{0}` was not acknowledged before the worker shut down")]
+    Unacknowledged(TaskId),
+}
+
+impl<T: Serialize + DeserializeOwned + Sync + Send + Unpin + 'static, Res>
+    Backend<Request<T, SqlContext>, Res> for SqliteStorage<T>
+{
+    type Stream = BackendStream<RequestStream<Request<T, SqlContext>>>;
+    type Layer = Stack<LockRenewLayer<T>, AckLayer<SqliteStorage<T>, T, SqlContext, Res>>;
+
+    fn poll<Svc>(mut self, worker: &Worker<Context>) -> Poller<Self::Stream, Self::Layer> {
+        let layer = Stack::new(
+            LockRenewLayer::new(self.clone()),
+            AckLayer::new(self.clone()),
+        );
+        let config = self.config.clone();
+        let controller = self.controller.clone();
+        // Runs before anything new is claimed, so only jobs from a previous run are reclaimed
+        let reclaim = {
+            let storage = self.clone();
+            let worker_id = worker.id().clone();
+            futures::stream::once(
+                async move { storage.requeue_running_for_worker(&worker_id).await },
+            )
+            .try_filter_map(|_| futures::future::ready(Ok(None)))
+        };
+        // Only claimed jobs reach the worker, empty polls and backoffs stay in the backend
+        let stream = reclaim
+            .chain(self.stream_jobs(worker, config.base.poll_interval, config.base.buffer_size))
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job().map(Some))))
+            .map_err(|e| Error::SourceError(Arc::new(Box::new(e))));
+        let stream = BackendStream::new(stream.boxed(), controller);
+        let requeue_storage = self.clone();
+        let shutdown = {
+            let storage = self.clone();
+            let w = worker.clone();
+            async move {
+                match storage.deregister_worker(w.id()).await {
+                    Ok(unacked) => {
+                        for task_id in unacked {
+                            w.emit(Event::Error(Box::new(SqlitePollError::Unacknowledged(
+                                task_id,
+                            ))));
+                        }
+                    }
+                    Err(e) => {
+                        w.emit(Event::Error(Box::new(SqlitePollError::KeepAliveError(e))));
+                    }
+                }
+            }
+        };
+        let w = worker.clone();
+        let heartbeat = async move {
+            loop {
+                let now: i64 = self.config.now().timestamp_millis();
+                let concurrency = self.config.worker_concurrency();
+                if let Err(e) = self.keep_alive_at::<Self::Layer>(w.id(), now).await {
+                    w.emit(Event::Error(Box::new(SqlitePollError::KeepAliveError(e))));
+                } else if let Err(e) = self.report_load(w.id(), concurrency, w.task_count()).await {
+                    w.emit(Event::Error(Box::new(SqlitePollError::KeepAliveError(e))));
+                }
+                apalis_core::sleep(self.config.base.keep_alive).await;
+            }
+        }
+        .boxed();
+        let w = worker.clone();
+        let reenqueue_beat = async move {
+            loop {
+                if let Err(e) = requeue_storage
+                    .reenqueue_orphaned(
+                        i32::try_from(config.base.buffer_size).unwrap_or(i32::MAX),
+                        orphaned_since(&config),
+                    )
+                    .await
+                {
+                    w.emit(Event::Error(Box::new(
+                        SqlitePollError::ReenqueueOrphanedError(e),
+                    )));
+                }
+                // A retention too long to represent, eg `Duration::MAX`, keeps dead letters forever
+                let expired = config.dead_letter_retention().and_then(|retention| {
+                    config
+                        .now()
+                        .checked_sub_signed(chrono::Duration::from_std(retention).ok()?)
+                });
+                if let Some(expired) = expired {
+                    if let Err(e) = requeue_storage.purge_dead_letters(expired).await {
+                        w.emit(Event::Error(Box::new(
+                            SqlitePollError::PurgeDeadLettersError(e),
+                        )));
+                    }
+                }
+                apalis_core::sleep(config.base.reenqueue_orphaned_interval()).await;
+            }
+        };
+        Poller::new_with_layer(
+            stream,
+            async {
+                futures::join!(heartbeat, reenqueue_beat);
+            },
+            layer,
+        )
+        .with_shutdown(shutdown)
+    }
+}
+
+/// Renews the lock of a job while its handler runs, see [`SqliteConfig::set_lock_renew_interval`]
+///
+/// Part of the layer of [`SqliteStorage`] workers. Also hands each job a [`CancellationToken`],
+/// cancelled once the job is [cancelled](SqliteStorage::cancel) or its run no longer holds it.
+#[derive(Debug)]
+pub struct LockRenewLayer<T> {
+    storage: SqliteStorage<T>,
+}
+
+impl<T> LockRenewLayer<T> {
+    /// Renew locks through `storage`
+    pub fn new(storage: SqliteStorage<T>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<T> Clone for LockRenewLayer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<T, S> Layer<S> for LockRenewLayer<T> {
+    type Service = LockRenewService<T, S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        LockRenewService {
+            storage: self.storage.clone(),
+            service,
+        }
+    }
+}
+
+/// The service built by a [`LockRenewLayer`]
+#[derive(Debug)]
+pub struct LockRenewService<T, S> {
+    storage: SqliteStorage<T>,
+    service: S,
+}
+
+impl<T, S: Clone> Clone for LockRenewService<T, S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+impl<T, S> Service<Request<T, SqlContext>> for LockRenewService<T, S>
+where
+    T: Send + Sync + 'static,
+    S: Service<Request<T, SqlContext>>,
+    S::Future: Send + 'static,
+    S::Response: Send,
+    S::Error: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<T, SqlContext>) -> Self::Future {
+        let renew_interval = self.storage.config.lock_renew_interval();
+        let lock_by = req.parts.context.lock_by().clone();
+        let task_id = req.parts.task_id.clone();
+        let token = CancellationToken::new();
+        req.parts.data.insert(token.clone());
+        self.storage
+            .cancellations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(task_id.clone(), token.clone());
+        self.storage
+            .started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(task_id.clone());
+        let fut = self.service.call(req);
+        let storage = self.storage.clone();
+        async move {
+            let watch = async {
+                let Some(worker_id) = lock_by else {
+                    return futures::future::pending().await;
+                };
+                loop {
+                    apalis_core::sleep(renew_interval.unwrap_or(storage.config.base.keep_alive))
+                        .await;
+                    let held = match renew_interval {
+                        Some(_) => match storage.renew_lock(&task_id, &worker_id).await {
+                            Err(sqlx::Error::RowNotFound) => Ok(false),
+                            res => res.map(|()| true),
+                        },
+                        None => storage.holds(&task_id, &worker_id).await,
+                    };
+                    match held {
+                        Ok(true) => {}
+                        // Cancelled, or reclaimed by another run, either way this run should stop
+                        Ok(false) => {
+                            token.cancel();
+                            return futures::future::pending().await;
+                        }
+                        Err(e) => error!("Failed to renew the lock of job {task_id}: {e}"),
+                    }
+                }
+            };
+            let res = match futures::future::select(fut.boxed(), watch.boxed()).await {
+                futures::future::Either::Left((res, _)) => res,
+                futures::future::Either::Right(((), fut)) => fut.await,
+            };
+            storage
+                .cancellations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&task_id);
+            res
+        }
+        .boxed()
+    }
+}
+
+/// Settles a job handed out by [`SqliteStorage::consume_guarded`] when dropped
+///
+/// A guard marked with [`JobGuard::mark_success`] acknowledges the job as done,
+/// any other guard reschedules it to run again right away, eg when the handler returned early or panicked.
+/// The lock of the job is renewed for as long as the guard is held.
+#[derive(Debug)]
+pub struct JobGuard {
+    task_id: TaskId,
+    context: SqlContext,
+    attempt: Attempt,
+    success: bool,
+    settle: mpsc::UnboundedSender<GuardMsg>,
+}
+
+impl JobGuard {
+    /// The job this guard settles
+    pub fn task_id(&self) -> &TaskId {
+        &self.task_id
+    }
+
+    /// Acknowledge the job as done when the guard is dropped
+    pub fn mark_success(&mut self) {
+        self.success = true;
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        let _ = self.settle.unbounded_send(GuardMsg::Settle {
+            task_id: self.task_id.clone(),
+            context: Box::new(self.context.clone()),
+            attempt: self.attempt.clone(),
+            success: self.success,
+        });
+    }
+}
+
+/// A job handed out by [`SqliteStorage::consume_guarded`] and the guard settling it
+pub type GuardedJob<T> = (Request<T, SqlContext>, JobGuard);
+
+/// What the driver of [`SqliteStorage::consume_guarded`] is told about the jobs it handed out
+#[derive(Debug)]
+enum GuardMsg {
+    Held(TaskId, WorkerId),
+    Settle {
+        task_id: TaskId,
+        context: Box<SqlContext>,
+        attempt: Attempt,
+        success: bool,
+    },
+    Renew,
+    Closed,
+}
+
+impl<T: Serialize + DeserializeOwned + Sync + Send + Unpin + 'static> SqliteStorage<T> {
+    /// Consume jobs as `worker`, each bundled with a [`JobGuard`] that settles it when dropped
+    ///
+    /// An alternative to [`Backend::poll`] for code that runs jobs itself rather than through a service.
+    /// Returns the stream of jobs and a future that acknowledges, reschedules and renews the locks of the guarded jobs.
+    /// The future must be driven for guards to have any effect, and resolves once the stream and every guard are gone.
+    /// Locks are renewed every [`SqliteConfig::lock_renew_interval`], or [`Config::keep_alive`] if unset.
+    pub fn consume_guarded(
+        &self,
+        worker: &Worker<Context>,
+    ) -> (
+        impl Stream<Item = Result<GuardedJob<T>, sqlx::Error>>,
+        impl Future<Output = ()>,
+    ) {
+        let (tx, rx) = mpsc::unbounded();
+        let settle = tx.clone();
+        let stream = self
+            .stream_jobs(
+                worker,
+                self.config.base.poll_interval,
+                self.config.base.buffer_size,
+            )
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .map_ok(move |job| {
+                if let Some(worker_id) = job.parts.context.lock_by() {
+                    let _ = tx.unbounded_send(GuardMsg::Held(
+                        job.parts.task_id.clone(),
+                        worker_id.clone(),
+                    ));
+                }
+                let guard = JobGuard {
+                    task_id: job.parts.task_id.clone(),
+                    context: job.parts.context.clone(),
+                    attempt: job.parts.attempt.clone(),
+                    success: false,
+                    settle: settle.clone(),
+                };
+                (job, guard)
+            });
+        let mut storage = self.clone();
+        let interval = self
+            .config
+            .lock_renew_interval()
+            .unwrap_or(self.config.base.keep_alive);
+        let driver = async move {
+            let ticks = futures::stream::unfold((), move |()| async move {
+                apalis_core::sleep(interval).await;
+                Some((GuardMsg::Renew, ()))
+            });
+            let msgs = futures::stream::select(
+                rx.chain(futures::stream::once(async { GuardMsg::Closed })),
+                ticks,
+            );
+            futures::pin_mut!(msgs);
+            let mut held: HashMap<TaskId, WorkerId> = HashMap::new();
+            while let Some(msg) = msgs.next().await {
+                match msg {
+                    GuardMsg::Held(task_id, worker_id) => {
+                        held.insert(task_id, worker_id);
+                    }
+                    GuardMsg::Settle {
+                        task_id,
+                        context,
+                        attempt,
+                        success,
+                    } => {
+                        held.remove(&task_id);
+                        let res = if success {
+                            let res = Response::success((), task_id.clone(), attempt);
+                            Ack::<T, ()>::ack(&mut storage, &context, &res).await
+                        } else {
+                            let rescheduled = match storage.pool.acquire().await {
+                                Ok(mut conn) => {
+                                    reschedule_job(
+                                        &mut conn,
+                                        &storage.config,
+                                        &task_id,
+                                        Duration::ZERO,
+                                    )
+                                    .await
+                                }
+                                Err(e) => Err(e),
+                            };
+                            match (rescheduled, storage.config.event_sink()) {
+                                (Ok(State::Dead), Some(sink)) => {
+                                    sink.send(JobEvent::DeadLettered(task_id.clone()));
+                                    Ok(())
+                                }
+                                (Ok(_), Some(sink)) => {
+                                    sink.send(JobEvent::Rescheduled(task_id.clone()));
+                                    Ok(())
+                                }
+                                (res, _) => res.map(|_| ()).map_err(StorageError::from),
+                            }
+                        };
+                        if let Err(e) = res {
+                            error!("Failed to settle job {task_id}: {e}");
+                        }
+                    }
+                    GuardMsg::Renew => {
+                        for (task_id, worker_id) in &held {
+                            if let Err(e) = storage.renew_lock(task_id, worker_id).await {
+                                error!("Failed to renew the lock of job {task_id}: {e}");
+                            }
+                        }
+                    }
+                    GuardMsg::Closed => break,
+                }
+            }
+        };
+        (stream, driver)
+    }
+}
+
+impl<T: Sync + Send, Res: Serialize + Sync> Ack<T, Res> for SqliteStorage<T> {
+    type Context = SqlContext;
+    type AckError = StorageError;
+    async fn ack(&mut self, ctx: &Self::Context, res: &Response<Res>) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        let status = match write_ack(&mut tx, &self.config, ctx, res).await {
+            Err(StorageError::NotOwned(task_id)) => {
+                self.forget_started(&task_id);
+                return Err(StorageError::NotOwned(task_id));
+            }
+            res => res?,
+        };
+        tx.commit().await?;
+        self.forget_started(&res.task_id);
+        // Delivered twice, the first ack already reported the outcome
+        let Some(status) = status else {
+            return Ok(());
+        };
+        if let Some(sink) = self.config.event_sink() {
+            let task_id = res.task_id.clone();
+            match status {
+                State::Killed | State::Dead => sink.send(JobEvent::DeadLettered(task_id)),
+                State::Failed | State::Retry => sink.send(JobEvent::Rescheduled(task_id)),
+                _ => {}
+            }
+        }
+        if let Some(breaker) = self.config.circuit_breaker() {
+            let key = res.task_id.to_string();
+            match &res.inner {
+                Ok(_) => breaker.record_success(&key),
+                Err(_) => breaker.record_failure(&key),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// id, layers, last_seen, concurrency and in_flight of a worker
+type WorkerRow = (String, String, i64, Option<i64>, Option<i64>);
+
+impl<J: 'static + Serialize + DeserializeOwned + Unpin + Send + Sync> BackendExpose<J>
+    for SqliteStorage<J, JsonCodec<String>>
+{
+    type Request = Request<J, Parts<SqlContext>>;
+    type Error = SqlError;
+    async fn stats(&self) -> Result<Stat, Self::Error> {
+        // `FILTER` needs sqlite 3.30+, older builds reject it as a syntax error
+        let res = match count_by_status(&self.pool, &self.config, CountBy::Filter).await {
+            Err(sqlx::Error::Database(e)) if e.message().contains("syntax error") => {
+                count_by_status(&self.pool, &self.config, CountBy::SumCase)
+                    .await
+                    .map_err(|fallback| SqlError::CountsUnsupported {
+                        filter: sqlx::Error::Database(e),
+                        fallback,
+                    })?
+            }
+            res => res?,
+        };
+
+        Ok(Stat {
+            pending: res.0.try_into()?,
+            running: res.1.try_into()?,
+            dead: res.5.try_into()?,
+            retry: res.3.try_into()?,
+            failed: res.4.try_into()?,
+            success: res.2.try_into()?,
+        })
+    }
+
+    async fn list_jobs(
+        &self,
+        status: &State,
+        page: i32,
+    ) -> Result<Vec<Self::Request>, Self::Error> {
+        let status = status.to_string();
+        let fetch_query = self.config.query("SELECT {columns} FROM {table} WHERE {status} = ? AND {job_type} = ? AND {deleted_at} IS NULL ORDER BY {done_at} DESC, {run_at} DESC LIMIT 10 OFFSET ?");
+        let res: Vec<SqlRequest<String>> = sqlx::query_as(&fetch_query)
+            .bind(status)
+            .bind(self.get_config().base().namespace())
+            .bind(((page - 1) * 10).to_string())
+            .fetch_all(self.pool())
+            .await?;
+        Ok(res
+            .into_iter()
+            .map(|j| {
+                let (req, ctx) = j.req.take_parts();
+                let req =
+                    decode_job::<J, JsonCodec<String>>(&self.config, req, ctx.context.codec_tag())
+                        .unwrap();
+                Request::new_with_ctx(req, ctx)
+            })
+            .collect())
+    }
+
+    async fn now(&self) -> Result<SystemTime, Self::Error> {
+        let millis: i64 =
+            sqlx::query_scalar("SELECT CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)")
+                .fetch_one(self.pool())
+                .await?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis.try_into()?))
+    }
+
+    async fn list_workers(&self) -> Result<Vec<Worker<WorkerState>>, Self::Error> {
+        let fetch_query = self.config.query(
+            "SELECT id, layers, last_seen, concurrency, in_flight FROM {workers} WHERE worker_type = ? ORDER BY last_seen DESC LIMIT 20 OFFSET ?",
+        );
+        let res: Vec<WorkerRow> = sqlx::query_as(&fetch_query)
+            .bind(self.get_config().base().namespace())
+            .bind(0)
+            .fetch_all(self.pool())
+            .await?;
+        res.into_iter()
+            .map(|w| {
+                let concurrency = w.3.map(usize::try_from).transpose()?;
+                let in_flight = w.4.map(usize::try_from).transpose()?;
+                let state = WorkerState::new::<Self>(w.1).with_load(concurrency, in_flight);
+                Ok(Worker::new(WorkerId::new(w.0), state))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::sql_storage_tests;
+
+    use super::*;
+    use apalis_core::error::BoxDynError;
+    use apalis_core::request::State;
+    use apalis_core::test_utils::DummyService;
+    use chrono::Utc;
+    use email_service::example_good_email;
+    use email_service::Email;
+    use futures::StreamExt;
+
+    use apalis_core::generic_storage_test;
+    use apalis_core::test_utils::apalis_test_service_fn;
+    use apalis_core::test_utils::TestWrapper;
+
+    generic_storage_test!(setup);
+    sql_storage_tests!(setup::<Email>, SqliteStorage<Email>, Email);
+
+    /// migrate DB and return a storage instance.
+    async fn setup<T: Serialize + DeserializeOwned>() -> SqliteStorage<T> {
+        // Because connections cannot be shared across async runtime
+        // (different runtimes are created for each test),
+        // we don't share the storage and tests must be run sequentially.
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        SqliteStorage::setup(&pool)
+            .await
+            .expect("failed to migrate DB");
+        let storage = SqliteStorage::<T>::new(pool);
+
+        storage
+    }
+
+    #[tokio::test]
+    async fn test_inmemory_sqlite_worker() {
+        let mut sqlite = setup().await;
+        sqlite
+            .push(Email {
+                subject: "Test Subject".to_string(),
+                to: "example@sqlite".to_string(),
+                text: "Some Text".to_string(),
+            })
+            .await
+            .expect("Unable to push job");
+        let len = sqlite.len().await.expect("Could not fetch the jobs count");
+        assert_eq!(len, 1);
+    }
+
+    async fn consume_one(
+        storage: &mut SqliteStorage<Email>,
+        worker: &Worker<Context>,
+    ) -> Request<Email, SqlContext> {
+        let mut stream = storage
+            .stream_jobs(worker, std::time::Duration::from_secs(10), 1)
+            .boxed();
+        stream
+            .next()
+            .await
+            .expect("stream is empty")
+            .expect("failed to poll job")
+            .into_job()
+            .expect("no job is pending")
+    }
+
+    async fn register_worker_at(
+        storage: &mut SqliteStorage<Email>,
+        last_seen: i64,
+    ) -> Worker<Context> {
+        let worker_id = WorkerId::new("test-worker");
+
+        storage
+            .keep_alive_at::<DummyService>(&worker_id, last_seen)
+            .await
+            .expect("failed to register worker");
+        let wrk = Worker::new(worker_id, Context::default());
+        wrk.start();
+        wrk
+    }
+
+    async fn register_worker(storage: &mut SqliteStorage<Email>) -> Worker<Context> {
+        register_worker_at(storage, Utc::now().timestamp_millis()).await
+    }
+
+    async fn push_email(storage: &mut SqliteStorage<Email>, email: Email) {
+        storage.push(email).await.expect("failed to push a job");
+    }
+
+    async fn get_job(
+        storage: &mut SqliteStorage<Email>,
+        job_id: &TaskId,
+    ) -> Request<Email, SqlContext> {
+        storage
+            .fetch_by_id(job_id)
+            .await
+            .expect("failed to fetch job by id")
+            .expect("no job found by id")
+    }
+
+    #[tokio::test]
+    async fn test_consume_last_pushed_job() {
+        let mut storage = setup().await;
+        let worker = register_worker(&mut storage).await;
+
+        push_email(&mut storage, example_good_email()).await;
+        let len = storage.len().await.expect("Could not fetch the jobs count");
+        assert_eq!(len, 1);
+
+        let job = consume_one(&mut storage, &worker).await;
+        let ctx = job.parts.context;
+        assert_eq!(*ctx.status(), State::Running);
+        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
+        assert!(ctx.lock_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_job() {
+        let mut storage = setup().await;
+        let worker = register_worker(&mut storage).await;
+
+        push_email(&mut storage, example_good_email()).await;
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = &job.parts.task_id;
+        let ctx = &job.parts.context;
+        let res = 1usize;
+        storage
+            .ack(
+                ctx,
+                &Response::success(res, job_id.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        let job = get_job(&mut storage, job_id).await;
+        let ctx = job.parts.context;
+        assert_eq!(*ctx.status(), State::Done);
+        assert!(ctx.done_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_acks_from_several_workers() {
+        let mut storage = setup().await;
+        for round in 0..5 {
+            let mut jobs = Vec::new();
+            for i in 0..8 {
+                let job_id = storage
+                    .push(example_good_email())
+                    .await
+                    .expect("failed to push a job")
+                    .task_id;
+                let worker_id = WorkerId::new(format!("worker-{round}-{i}"));
+                storage
+                    .keep_alive_at::<DummyService>(&worker_id, Utc::now().timestamp_millis())
+                    .await
+                    .expect("failed to register worker");
+                let job = storage
+                    .claim(&worker_id, &job_id)
+                    .await
+                    .expect("failed to claim job")
+                    .expect("job is not ready");
+                jobs.push(job);
+            }
+            let acks = jobs.iter().map(|job| {
+                let mut storage = storage.clone();
+                async move {
+                    storage
+                        .ack(
+                            &job.parts.context,
+                            &Response::success(
+                                1usize,
+                                job.parts.task_id.clone(),
+                                job.parts.attempt.clone(),
+                            ),
+                        )
+                        .await
+                }
+            });
+            for res in futures::future::join_all(acks).await {
+                res.expect("failed to acknowledge the job");
+            }
+            for job in &jobs {
+                let job = get_job(&mut storage, &job.parts.task_id).await;
+                assert_eq!(*job.parts.context.status(), State::Done);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kill_job() {
+        let mut storage = setup().await;
+
+        push_email(&mut storage, example_good_email()).await;
+
+        let worker = register_worker(&mut storage).await;
+
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = &job.parts.task_id;
+
+        storage
+            .kill(&worker.id(), job_id)
+            .await
+            .expect("failed to kill job");
+
+        let job = get_job(&mut storage, job_id).await;
+        let ctx = job.parts.context;
+        assert_eq!(*ctx.status(), State::Killed);
+        assert!(ctx.done_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_is_empty_tracks_pending_jobs() {
+        let mut storage = setup().await;
+        assert!(storage.is_empty().await.unwrap());
+
+        push_email(&mut storage, example_good_email()).await;
+        assert!(!storage.is_empty().await.unwrap());
+
+        let worker = register_worker(&mut storage).await;
+        consume_one(&mut storage, &worker).await;
+        assert!(storage.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_update_persists_context() {
+        let mut storage = setup().await;
+        push_email(&mut storage, example_good_email()).await;
+        let worker = register_worker(&mut storage).await;
+        let mut job = consume_one(&mut storage, &worker).await;
+        let job_id = job.parts.task_id.clone();
+
+        job.parts.attempt = Attempt::new_with_value(3);
+        job.parts.context.set_status(State::Failed);
+        job.parts.context.set_lock_by(None);
+        job.parts.context.set_lock_at(None);
+        job.parts.context.set_done_at(Some(42));
+        job.parts
+            .context
+            .set_last_error(Some("smtp timeout".to_owned()));
+        storage.update(job).await.expect("failed to update job");
+
+        let job = get_job(&mut storage, &job_id).await;
+        let ctx = job.parts.context;
+        assert_eq!(*ctx.status(), State::Failed);
+        assert_eq!(job.parts.attempt.current(), 3);
+        assert_eq!(*ctx.lock_by(), None);
+        assert_eq!(*ctx.lock_at(), None);
+        assert_eq!(*ctx.done_at(), Some(42));
+        assert_eq!(ctx.last_error().as_deref(), Some("smtp timeout"));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_6min() {
+        let mut storage = setup().await;
+
+        push_email(&mut storage, example_good_email()).await;
+
+        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+
+        let five_minutes_ago = Utc::now() - Duration::from_secs(5 * 60);
+        let worker = register_worker_at(&mut storage, six_minutes_ago.timestamp_millis()).await;
+
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = &job.parts.task_id;
+        storage
+            .reenqueue_orphaned(1, five_minutes_ago)
+            .await
+            .expect("failed to heartbeat");
+        let job = get_job(&mut storage, job_id).await;
+        let ctx = &job.parts.context;
+        assert_eq!(*ctx.status(), State::Pending);
+        assert!(ctx.done_at().is_none());
+        assert!(ctx.lock_by().is_none());
+        assert!(ctx.lock_at().is_none());
+        assert_eq!(*ctx.last_error(), Some("Job was abandoned".to_owned()));
+        assert_eq!(job.parts.attempt.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_4min() {
+        let mut storage = setup().await;
+
+        push_email(&mut storage, example_good_email()).await;
+
+        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+        let four_minutes_ago = Utc::now() - Duration::from_secs(4 * 60);
+        let worker = register_worker_at(&mut storage, four_minutes_ago.timestamp_millis()).await;
+
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = &job.parts.task_id;
+        storage
+            .reenqueue_orphaned(1, six_minutes_ago)
+            .await
+            .expect("failed to heartbeat");
+
+        let job = get_job(&mut storage, job_id).await;
+        let ctx = &job.parts.context;
+        assert_eq!(*ctx.status(), State::Running);
+        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
+        assert!(ctx.lock_at().is_some());
+        assert_eq!(*ctx.last_error(), None);
+        assert_eq!(job.parts.attempt.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_renqueueorphaned_sub_second_timeout() {
+        let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_reenqueue_orphaned_after(Duration::from_millis(500));
+
+        push_email(&mut storage, example_good_email()).await;
+        push_email(&mut storage, example_good_email()).await;
+
+        let now = Utc::now();
+        let worker = register_worker_at(&mut storage, now.timestamp_millis() - 200).await;
+        let fresh = consume_one(&mut storage, &worker).await;
+
+        let dead_since = now
+            - chrono::Duration::from_std(storage.config.base.reenqueue_orphaned_after()).unwrap();
+        storage
+            .reenqueue_orphaned(1, dead_since)
+            .await
+            .expect("failed to heartbeat");
+        let job = get_job(&mut storage, &fresh.parts.task_id).await;
+        assert_eq!(*job.parts.context.status(), State::Running);
+
+        // The same worker now missed its heartbeat by more than 500ms
+        register_worker_at(&mut storage, now.timestamp_millis() - 600).await;
+        storage
+            .reenqueue_orphaned(1, dead_since)
+            .await
+            .expect("failed to heartbeat");
+        let job = get_job(&mut storage, &fresh.parts.task_id).await;
+        let ctx = &job.parts.context;
+        assert_eq!(*ctx.status(), State::Pending);
+        assert!(ctx.lock_by().is_none());
+        assert_eq!(*ctx.last_error(), Some("Job was abandoned".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_renqueueorphaned_reads_second_granularity() {
+        let mut storage = setup().await;
+
+        push_email(&mut storage, example_good_email()).await;
+
+        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+        let five_minutes_ago = Utc::now() - Duration::from_secs(5 * 60);
+        // Rows written by older versions hold `last_seen` in seconds
+        let worker = register_worker_at(&mut storage, six_minutes_ago.timestamp()).await;
+
+        let job = consume_one(&mut storage, &worker).await;
+        storage
+            .reenqueue_orphaned(1, five_minutes_ago)
+            .await
+            .expect("failed to heartbeat");
+        let job = get_job(&mut storage, &job.parts.task_id).await;
+        assert_eq!(*job.parts.context.status(), State::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_shared_memory_storages_see_same_jobs() {
+        let producer_pool = SqliteStorage::connect_memory_shared("apalis_shared_memory_test")
+            .await
+            .expect("failed to connect");
+        SqliteStorage::setup(&producer_pool)
+            .await
+            .expect("failed to migrate DB");
+        let consumer_pool = SqliteStorage::connect_memory_shared("apalis_shared_memory_test")
+            .await
+            .expect("failed to connect");
+
+        let mut producer = SqliteStorage::<Email>::new(producer_pool);
+        let mut consumer = SqliteStorage::<Email>::new(consumer_pool);
+
+        push_email(&mut producer, example_good_email()).await;
+        assert_eq!(consumer.len().await.unwrap(), 1);
+
+        let worker = register_worker(&mut consumer).await;
+        let job = consume_one(&mut consumer, &worker).await;
+        let job = get_job(&mut producer, &job.parts.task_id).await;
+        assert_eq!(*job.parts.context.status(), State::Running);
+        assert_eq!(*job.parts.context.lock_by(), Some(worker.id().clone()));
+    }
+
+    fn rate_limit_aware_delay(_attempts: usize, error: &Error) -> Duration {
+        if error.to_string().contains("rate limited") {
+            Duration::from_secs(60)
+        } else {
+            Duration::from_secs(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_delay_depends_on_error() {
+        let mut storage = setup().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_retry_delay(rate_limit_aware_delay);
+        let worker = register_worker(&mut storage).await;
+
+        push_email(&mut storage, example_good_email()).await;
+        let limited = consume_one(&mut storage, &worker).await;
+        push_email(&mut storage, example_good_email()).await;
+        let generic = consume_one(&mut storage, &worker).await;
+
+        let rate_limited = Error::Failed(Arc::new("rate limited".into()));
+        let transient = Error::Failed(Arc::new("connection reset".into()));
+        storage
+            .ack(
+                &limited.parts.context,
+                &Response::<()>::failure(
+                    rate_limited,
+                    limited.parts.task_id.clone(),
+                    limited.parts.attempt.clone(),
+                ),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+        storage
+            .ack(
+                &generic.parts.context,
+                &Response::<()>::failure(
+                    transient.clone(),
+                    generic.parts.task_id.clone(),
+                    generic.parts.attempt.clone(),
+                ),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        let limited = get_job(&mut storage, &limited.parts.task_id).await;
+        let generic_job = get_job(&mut storage, &generic.parts.task_id).await;
+        assert_eq!(*limited.parts.context.status(), State::Retry);
+        assert!(limited.parts.context.run_at() > generic_job.parts.context.run_at());
+        let limited_wait = *limited.parts.context.run_at() - Utc::now();
+        assert!(limited_wait > chrono::Duration::seconds(50));
+
+        storage
+            .reschedule_with(generic_job, &transient, rate_limit_aware_delay)
+            .await
+            .expect("failed to reschedule");
+        let generic_job = get_job(&mut storage, &generic.parts.task_id).await;
+        let generic_wait = *generic_job.parts.context.run_at() - Utc::now();
+        assert!(generic_wait <= chrono::Duration::seconds(1));
+    }
+
+    #[tokio::test]
+    async fn test_describe_reports_migration_version() {
+        let mut storage = setup().await;
+        push_email(&mut storage, example_good_email()).await;
+
+        let info = storage.describe().await.expect("failed to describe");
+        let latest = SqliteStorage::migrations().iter().map(|m| m.version).max();
+        assert_eq!(info.backend, "sqlite");
+        assert_eq!(info.migration_version, latest);
+        assert!(info.journal_mode.is_some());
+        assert_eq!(info.counts.pending, 1);
+    }
+
+    #[tokio::test]
+    async fn test_recent_failures_include_errors() {
+        let mut storage = setup().await;
+        let worker = register_worker(&mut storage).await;
+        let since = Utc::now().timestamp() - 1;
+
+        let mut jobs = Vec::new();
+        for _ in 0..3 {
+            push_email(&mut storage, example_good_email()).await;
+            jobs.push(consume_one(&mut storage, &worker).await);
+        }
+        let done = jobs.pop().unwrap();
+        let mut failed = Vec::new();
+        for (job, reason) in jobs.into_iter().zip(["smtp timeout", "mailbox full"]) {
+            let error = Error::Failed(Arc::new(reason.into()));
+            storage
+                .ack(
+                    &job.parts.context,
+                    &Response::<()>::failure(
+                        error,
+                        job.parts.task_id.clone(),
+                        job.parts.attempt.clone(),
+                    ),
+                )
+                .await
+                .expect("failed to acknowledge the job");
+            failed.push((job.parts.task_id, reason));
+        }
+        storage
+            .ack(
+                &done.parts.context,
+                &Response::success((), done.parts.task_id.clone(), done.parts.attempt.clone()),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        let failures = storage
+            .recent_failures(since, 10)
+            .await
+            .expect("failed to list failures");
+        assert_eq!(failures.len(), 2);
+        for (id, reason) in failed {
+            let (_, error) = failures
+                .iter()
+                .find(|(failed_id, _)| *failed_id == id)
+                .expect("failed job is missing");
+            assert!(error.contains(reason));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_lets_other_jobs_through() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_buffer_size(1)
+            .set_circuit_breaker(2, Duration::from_secs(60));
+        let (mut t, poller) = TestWrapper::new_with_service(
+            storage,
+            apalis_core::service_fn::service_fn(email_service::send_email),
+        );
+        tokio::spawn(poller);
+
+        let poison = t
+            .push(email_service::example_retry_able_email())
+            .await
+            .unwrap();
+        let good = t.push(example_good_email()).await.unwrap();
+
+        let mut poison_runs = 0;
+        loop {
+            let (job_id, res) = t.execute_next().await;
+            if job_id == good.task_id {
+                assert_eq!(res, Ok("()".to_owned()));
+                break;
+            }
+            assert_eq!(job_id, poison.task_id);
+            poison_runs += 1;
+            assert!(poison_runs <= 2, "poison pill starved the queue");
+        }
+        assert!(t
+            .get_config()
+            .circuit_breaker()
+            .unwrap()
+            .is_open(&poison.task_id.to_string()));
+    }
+
+    #[derive(Debug)]
+    struct RenamedColumns;
+
+    impl crate::schema::SchemaAdapter for RenamedColumns {
+        fn table(&self) -> &str {
+            "Tasks"
+        }
+
+        fn column(&self, column: Column) -> &str {
+            match column {
+                Column::Job => "payload",
+                Column::Id => "task_id",
+                Column::JobType => "queue",
+                Column::Status => "state",
+                Column::Attempts => "tries",
+                Column::MaxAttempts => "max_tries",
+                Column::RunAt => "scheduled_for",
+                Column::LastError => "error",
+                Column::LockAt => "locked_at",
+                Column::LockBy => "locked_by",
+                Column::DoneAt => "finished_at",
+                Column::Deadline => "due",
+                Column::Priority => "prio",
+                Column::DedupKey => "business_key",
+                Column::MaxBackoff => "backoff_cap",
+                Column::Headers => "meta",
+                Column::Seq => "position",
+                Column::EffectToken => "effect",
+                Column::CreatedAt => "created",
+                Column::Codec => "encoding",
+                Column::Counters => "tallies",
+                Column::DeletedAt => "removed_at",
+                Column::RetriedAt => "retry_times",
+                Column::UniqueKey => "lone_key",
+                Column::Result => "output",
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_schema_round_trip() {
+        let mut storage = setup::<Email>().await;
+        sqlx::query(
+            "CREATE TABLE Tasks (
+                payload TEXT NOT NULL,
+                task_id TEXT NOT NULL UNIQUE,
+                queue TEXT NOT NULL,
+                state TEXT NOT NULL,
+                tries INTEGER NOT NULL,
+                max_tries INTEGER NOT NULL,
+                scheduled_for INTEGER NOT NULL,
+                error TEXT,
+                locked_at INTEGER,
+                locked_by TEXT,
+                finished_at INTEGER,
+                due INTEGER,
+                prio INTEGER NOT NULL,
+                business_key TEXT,
+                backoff_cap INTEGER,
+                meta TEXT,
+                position INTEGER,
+                effect TEXT,
+                created INTEGER,
+                encoding INTEGER NOT NULL DEFAULT 0,
+                tallies TEXT,
+                removed_at INTEGER,
+                retry_times TEXT,
+                lone_key TEXT,
+                output TEXT
+            )",
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+        storage.config = storage.config.clone().set_schema(RenamedColumns);
+
+        push_email(&mut storage, example_good_email()).await;
+        assert_eq!(storage.len().await.unwrap(), 1);
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.args.to, example_good_email().to);
+
+        let job = get_job(&mut storage, &job.parts.task_id).await;
+        let ctx = job.parts.context;
+        assert_eq!(*ctx.status(), State::Running);
+        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
+        assert_eq!(job.parts.attempt.current(), 1);
+
+        let untouched: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM Jobs")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(untouched, 0);
+    }
+
+    #[tokio::test]
+    async fn test_counts_cached_within_ttl() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+
+        let ttl = Duration::from_secs(60);
+        let counts = storage.counts_cached(ttl).await.unwrap();
+        assert_eq!(counts.pending, 1);
+        assert_eq!(storage.counts_cache().refreshes(), 1);
+
+        push_email(&mut storage, example_good_email()).await;
+        let counts = storage.clone().counts_cached(ttl).await.unwrap();
+        assert_eq!(counts.pending, 1, "served from the cache");
+        assert_eq!(storage.counts_cache().refreshes(), 1);
+
+        let counts = storage.counts_cached(Duration::ZERO).await.unwrap();
+        assert_eq!(counts.pending, 2);
+        assert_eq!(storage.counts_cache().refreshes(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_status_polling() {
+        let mut storage = setup::<Email>().await;
+        let parts = storage.push(example_good_email()).await.unwrap();
+        assert_eq!(
+            storage.status(&parts.task_id).await.unwrap(),
+            Some(State::Pending)
+        );
+        assert_eq!(storage.status(&TaskId::new()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_push_raw_consumed_as_typed_job() {
+        let mut storage = setup::<Email>().await;
+        let raw = r#"{"to":"raw@example.com","subject":"Raw","text":"From elsewhere"}"#;
+        let namespace = storage.get_config().base().namespace().to_owned();
+        let task_id = storage.push_raw(raw.to_owned(), &namespace).await.unwrap();
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, task_id);
+        assert_eq!(job.args.to, "raw@example.com");
+        assert_eq!(job.args.subject, "Raw");
+    }
+
+    #[tokio::test]
+    async fn test_push_raw_validates_when_enabled() {
+        let mut storage = setup::<Email>().await;
+        let namespace = storage.get_config().base().namespace().to_owned();
+        storage
+            .push_raw("not json".to_owned(), &namespace)
+            .await
+            .expect("payload is not checked by default");
+
+        storage.config = storage.config.clone().set_validate_raw(true);
+        assert!(storage
+            .push_raw("not json".to_owned(), &namespace)
+            .await
+            .is_err());
+        assert_eq!(storage.len().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_earliest_deadline_consumed_first() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_fetch_order(FetchOrder::EarliestDeadline);
+        let now = Utc::now().timestamp();
+        let mut nearest = None;
+        for (subject, deadline) in [
+            ("later", Some(now + 300)),
+            ("none", None),
+            ("soonest", Some(now + 100)),
+            ("soon", Some(now + 200)),
+        ] {
+            let mut email = example_good_email();
+            email.subject = subject.to_owned();
+            let mut req = Request::<_, SqlContext>::new(email);
+            req.parts.context.set_deadline(deadline);
+            let parts = storage.push_request(req).await.unwrap();
+            if subject == "soonest" {
+                nearest = Some(parts.task_id);
+            }
+        }
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(Some(job.parts.task_id), nearest);
+        assert_eq!(job.args.subject, "soonest");
+        assert_eq!(*job.parts.context.deadline(), Some(now + 100));
+    }
+
+    #[tokio::test]
+    async fn test_restarted_worker_reclaims_its_jobs() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = job.parts.task_id;
+        assert_eq!(
+            *get_job(&mut storage, &job_id).await.parts.context.status(),
+            State::Running
+        );
+
+        // The process dies and comes back with the same id
+        let (mut t, poller) = TestWrapper::new_with_service(
+            storage,
+            apalis_core::service_fn::service_fn(email_service::send_email),
+        );
+        tokio::spawn(poller);
+        let (reclaimed_id, res) = t.execute_next().await;
+        assert_eq!(reclaimed_id, job_id);
+        assert_eq!(res, Ok("()".to_owned()));
+        let job = t.fetch_by_id(&job_id).await.unwrap().unwrap();
+        assert_eq!(job.parts.attempt.current(), 2);
+        assert_eq!(*job.parts.context.lock_by(), Some(worker.id().clone()));
+    }
+
+    #[tokio::test]
+    async fn test_retry_state_until_attempts_exhausted() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_retry_delay(|_, _| Duration::ZERO);
+        let worker = register_worker(&mut storage).await;
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_max_attempts(2);
+        let job_id = storage.push_request(req).await.unwrap().task_id;
+
+        let job = consume_one(&mut storage, &worker).await;
+        storage
+            .reschedule(job, Duration::ZERO)
+            .await
+            .expect("failed to reschedule");
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Retry);
+        assert_eq!(
+            storage.counts_cached(Duration::ZERO).await.unwrap().retry,
+            1
+        );
+
+        // Picked up again once `run_at` passes
+        apalis_core::sleep(Duration::from_secs(1)).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, job_id);
+        assert_eq!(job.parts.attempt.current(), 2);
+
+        let error = Error::Failed(Arc::new("still broken".into()));
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::<()>::failure(error, job_id.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Dead);
+    }
+
+    #[tokio::test]
+    async fn test_drain_to_respects_backpressure() {
+        let mut storage = setup::<Email>().await;
+        for _ in 0..5 {
+            push_email(&mut storage, example_good_email()).await;
+        }
+
+        // Holds at most 2 jobs: one slot plus one per sender
+        let (tx, mut rx) = futures::channel::mpsc::channel(1);
+        let mut drainer = storage.clone();
+        let worker_id = WorkerId::new("drainer");
+        tokio::spawn(async move { drainer.drain_to(&worker_id, tx).await });
+
+        // `run_at` has second precision, so jobs only become due on the next second
+        apalis_core::sleep(Duration::from_millis(2500)).await;
+        let counts = storage.counts_cached(Duration::ZERO).await.unwrap();
+        assert_eq!(counts.running, 2);
+        assert_eq!(counts.pending, 3);
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let job = rx.next().await.expect("drain stopped early");
+            ids.push(job.parts.task_id);
+        }
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(storage.len().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_to_puts_back_job_when_receiver_hangs_up() {
+        /// Ready for a job, but gone by the time it is sent
+        struct HungUp;
+
+        impl Sink<Request<Email, SqlContext>> for HungUp {
+            type Error = ();
+
+            fn poll_ready(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Result<(), ()>> {
+                std::task::Poll::Ready(Ok(()))
+            }
+
+            fn start_send(
+                self: std::pin::Pin<&mut Self>,
+                _: Request<Email, SqlContext>,
+            ) -> Result<(), ()> {
+                Err(())
+            }
+
+            fn poll_flush(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Result<(), ()>> {
+                std::task::Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(
+                self: std::pin::Pin<&mut Self>,
+                _: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Result<(), ()>> {
+                std::task::Poll::Ready(Ok(()))
+            }
+        }
+
+        let mut storage = setup::<Email>().await;
+        let parts = storage
+            .schedule(example_good_email(), Utc::now().timestamp() - 5)
+            .await
+            .unwrap();
+        let worker_id = WorkerId::new("hung-up");
+        storage.drain_to(&worker_id, HungUp).await.unwrap();
+
+        let job = get_job(&mut storage, &parts.task_id).await;
+        assert_eq!(*job.parts.context.status(), State::Pending);
+        assert_eq!(job.parts.context.lock_by(), &None);
+        assert_eq!(job.parts.attempt.current(), 0);
+
+        // Still there for the next receiver
+        let (tx, mut rx) = futures::channel::mpsc::channel(1);
+        let mut drainer = storage.clone();
+        tokio::spawn(async move { drainer.drain_to(&WorkerId::new("next"), tx).await });
+        let job = rx.next().await.unwrap();
+        assert_eq!(job.parts.task_id, parts.task_id);
+    }
+
+    #[tokio::test]
+    async fn test_connect_lazy_defers_connection() {
+        let dir = std::env::temp_dir().join(format!("apalis-lazy-{}", TaskId::new()));
+        let url = format!("sqlite://{}", dir.join("jobs.db").display());
+
+        // The directory holding the database does not exist yet
+        let pool = SqliteStorage::connect_lazy(&url).expect("lazy pool should not connect");
+        assert!(SqliteStorage::setup(&pool).await.is_err());
+
+        std::fs::create_dir_all(&dir).unwrap();
+        SqliteStorage::setup(&pool)
+            .await
+            .expect("connects on first use");
+        let mut storage = SqliteStorage::<Email>::new(pool);
+        push_email(&mut storage, example_good_email()).await;
+        assert_eq!(storage.len().await.unwrap(), 1);
+
+        storage.pool().close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mock_clock_triggers_orphan_recovery() {
+        use crate::clock::{Clock, MockClock};
+
+        let clock = MockClock::default();
+        let mut storage = setup::<Email>().await;
+        storage.config = storage.config.clone().set_clock(clock.clone());
+        push_email(&mut storage, example_good_email()).await;
+
+        // Jobs are due strictly after their `run_at`
+        clock.advance(Duration::from_secs(1));
+        let dead_worker = WorkerId::new("dead-worker");
+        storage
+            .keep_alive_at::<DummyService>(&dead_worker, clock.now().timestamp_millis())
+            .await
+            .unwrap();
+        let wrk = Worker::new(dead_worker, Context::default());
+        wrk.start();
+        let job = consume_one(&mut storage, &wrk).await;
+
+        // Well past `reenqueue_orphaned_after`, without waiting for it
+        clock.advance(Duration::from_secs(600));
+        let (mut t, poller) = TestWrapper::new_with_service(
+            storage,
+            apalis_core::service_fn::service_fn(email_service::send_email),
+        );
+        tokio::spawn(poller);
+        let (job_id, res) = t.execute_next().await;
+        assert_eq!(job_id, job.parts.task_id);
+        assert_eq!(res, Ok("()".to_owned()));
+        let job = t.fetch_by_id(&job_id).await.unwrap().unwrap();
+        assert_eq!(*job.parts.context.done_at(), Some(clock.now().timestamp()));
+    }
+
+    #[tokio::test]
+    async fn test_aged_low_priority_job_outranks_fresh_ones() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_fetch_order(FetchOrder::Priority {
+                age_boost: Duration::from_secs(3600),
+            });
+        let now = Utc::now().timestamp();
+        let push = |subject: &'static str, priority: i32, due: i64| {
+            let mut email = example_good_email();
+            email.subject = subject.to_owned();
+            let mut req = Request::<_, SqlContext>::new(email);
+            req.parts.context.set_priority(priority);
+            let mut storage = storage.clone();
+            async move { storage.schedule_request(req, due).await.unwrap() }
+        };
+        // Gains 24 points over a day, overtaking the medium jobs
+        push("ancient", 0, now - 86_400).await;
+        // Gains a single point, not enough
+        push("waiting", 0, now - 3_600).await;
+        push("medium", 5, now - 1).await;
+        push("medium", 5, now - 1).await;
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.args.subject, "ancient");
+        assert_eq!(job.parts.context.priority(), 0);
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.args.subject, "medium");
+    }
+
+    #[tokio::test]
+    async fn test_list_projected_returns_only_requested_fields() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage
+            .push(example_good_email())
+            .await
+            .expect("failed to push a job")
+            .task_id;
+
+        let jobs = storage
+            .list_projected(&State::Pending, 1, &["subject", "to"])
+            .await
+            .unwrap();
+        assert_eq!(jobs.len(), 1);
+        let (id, projection) = &jobs[0];
+        assert_eq!(id, &job_id);
+        let email = example_good_email();
+        assert_eq!(
+            projection,
+            &serde_json::json!({ "subject": email.subject, "to": email.to })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_finds_prior_result() {
+        let mut storage = setup::<Email>().await;
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_dedup_key(Some("order-42".to_owned()));
+        storage.push_request(req).await.unwrap();
+        assert_eq!(storage.has_completed("order-42").await.unwrap(), None);
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.context.dedup_key().as_deref(), Some("order-42"));
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::success(
+                    "receipt-7".to_owned(),
+                    job.parts.task_id.clone(),
+                    job.parts.attempt.clone(),
+                ),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        assert_eq!(
+            storage.has_completed("order-42").await.unwrap(),
+            Some(serde_json::json!("receipt-7"))
+        );
+        assert_eq!(storage.has_completed("order-43").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_watch_counts_emits_on_change() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+        let watcher = storage.clone();
+        let mut counts = watcher.watch_counts(Duration::from_millis(50)).boxed();
+        let first = counts.next().await.unwrap().unwrap();
+        assert_eq!((first.pending, first.success), (1, 0));
+
+        // Nothing changed, so nothing is emitted
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), counts.next())
+                .await
+                .is_err()
+        );
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::success((), job.parts.task_id.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .expect("failed to acknowledge the job");
+
+        let mut next = counts.next().await.unwrap().unwrap();
+        // The job may be seen running on its way to done
+        if next.running == 1 {
+            next = counts.next().await.unwrap().unwrap();
+        }
+        assert_eq!((next.pending, next.running, next.success), (0, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_status_map_skips_unknown_ids() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+        let worker = register_worker(&mut storage).await;
+        let running = consume_one(&mut storage, &worker).await.parts.task_id;
+        let pending = storage.push(example_good_email()).await.unwrap().task_id;
+        let unknown = TaskId::new();
+
+        let statuses = storage
+            .status_map(&[pending.clone(), unknown.clone(), running.clone()])
+            .await
+            .unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses.get(&pending), Some(&State::Pending));
+        assert_eq!(statuses.get(&running), Some(&State::Running));
+        assert!(!statuses.contains_key(&unknown));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_due_before_cutoff() {
+        let mut storage = setup::<Email>().await;
+        let now = Utc::now().timestamp();
+        let mut ids = Vec::new();
+        for offset in [300, 60, 3_600] {
+            let parts = storage
+                .schedule(example_good_email(), now + offset)
+                .await
+                .unwrap();
+            ids.push(parts.task_id);
+        }
+
+        let cutoff = Utc::now() + chrono::Duration::seconds(600);
+        let due = storage.fetch_due_before(cutoff, 10).await.unwrap();
+        let due: Vec<_> = due.into_iter().map(|job| job.parts.task_id).collect();
+        assert_eq!(due, vec![ids[1].clone(), ids[0].clone()]);
+
+        let due = storage.fetch_due_before(cutoff, 1).await.unwrap();
+        assert_eq!(due.len(), 1);
+        // Reading does not claim the jobs
+        assert_eq!(storage.len().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_job_type_is_parked_once() {
+        let mut storage = setup::<Email>().await;
+        let routed = storage.push(example_good_email()).await.unwrap().task_id;
+        let unknown = storage
+            .push_raw(r#"{"to":"x"}"#.to_owned(), "legacy::Invoice")
+            .await
+            .unwrap();
+        let known = [storage.get_config().base().namespace().as_str()];
+
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            storage
+                .park_unroutable(&known, |id, job_type, _| {
+                    seen.push((id.clone(), job_type.to_owned()))
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(seen, vec![(unknown.clone(), "legacy::Invoice".to_owned())]);
+        assert_eq!(storage.status(&unknown).await.unwrap(), Some(State::Killed));
+        assert_eq!(storage.status(&routed).await.unwrap(), Some(State::Pending));
+    }
+
+    #[derive(Debug, Serialize, serde::Deserialize)]
+    struct Invoice {
+        amount: u64,
+        currency: String,
+    }
+
+    impl apalis_core::codec::json::Migrate for Invoice {
+        fn migrate(mut value: serde_json::Value) -> serde_json::Value {
+            // Invoices used to be in euros only
+            if value.get("currency").is_none() {
+                value["currency"] = "EUR".into();
+            }
+            value
+        }
+    }
+
+    #[tokio::test]
+    async fn test_old_payload_is_migrated_on_read() {
+        use apalis_core::codec::json::Migrate;
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        SqliteStorage::setup(&pool).await.unwrap();
+        let config = SqliteConfig::new(type_name::<Invoice>()).set_migration(Invoice::migrate);
+        let mut storage = SqliteStorage::<Invoice>::new_with_config(pool, config);
+        let old = storage
+            .push_raw(r#"{"amount":42}"#.to_owned(), type_name::<Invoice>())
+            .await
+            .unwrap();
+
+        let job = storage.fetch_by_id(&old).await.unwrap().unwrap();
+        assert_eq!(job.args.amount, 42);
+        assert_eq!(job.args.currency, "EUR");
+    }
+
+    #[tokio::test]
+    async fn test_expired_dead_letters_are_purged() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+        push_email(&mut storage, example_good_email()).await;
+        let worker = register_worker(&mut storage).await;
+        let old = consume_one(&mut storage, &worker).await.parts.task_id;
+        let recent = consume_one(&mut storage, &worker).await.parts.task_id;
+        storage.kill(worker.id(), &old).await.unwrap();
+        storage.kill(worker.id(), &recent).await.unwrap();
+        // Age the first dead letter by ten days
+        sqlx::query("UPDATE Jobs SET done_at = done_at - 864000 WHERE id = ?1")
+            .bind(old.to_string())
+            .execute(storage.pool())
+            .await
+            .unwrap();
+
+        let expired = Utc::now() - chrono::Duration::days(7);
+        let archived = storage.export_dead_letters(expired).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].parts.task_id, old);
+        let email: Email = serde_json::from_str(&archived[0].args).unwrap();
+        assert_eq!(email.to, example_good_email().to);
+
+        assert_eq!(storage.purge_dead_letters(expired).await.unwrap(), 1);
+        assert_eq!(storage.status(&old).await.unwrap(), None);
+        assert_eq!(storage.status(&recent).await.unwrap(), Some(State::Killed));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_result_of_done_job() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        let sent = storage.push(example_good_email()).await.unwrap().task_id;
+        let failed = storage.push(example_good_email()).await.unwrap().task_id;
+
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(
+            storage.fetch_result::<Vec<String>>(&sent).await.unwrap(),
+            None
+        );
+        let output = vec!["queued".to_owned(), "sent".to_owned()];
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::success(output.clone(), sent.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_result::<Vec<String>>(&sent).await.unwrap(),
+            Some(output)
+        );
+
+        let job = consume_one(&mut storage, &worker).await;
+        let error = Error::Failed(Arc::new("still broken".into()));
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::<Vec<String>>::failure(error, failed.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.fetch_result::<Vec<String>>(&failed).await.unwrap(),
+            None
+        );
+        assert!(storage.fetch_result::<String>(&sent).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_job_is_dead_until_retried() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_max_attempts(1);
+        let job_id = storage.push_request(req).await.unwrap().task_id;
+
+        let job = consume_one(&mut storage, &worker).await;
+        let error = Error::Failed(Arc::new("still broken".into()));
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::<()>::failure(error, job_id.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(storage.status(&job_id).await.unwrap(), Some(State::Dead));
+        assert!(storage.is_empty().await.unwrap());
+        assert!(matches!(
+            storage.update_payload(&job_id, &example_good_email()).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        let dead = storage.list_dead(10, 0).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].parts.task_id, job_id);
+        assert_eq!(storage.stats().await.unwrap().dead, 1);
+
+        assert!(storage.retry_dead(&job_id).await.unwrap());
+        assert!(!storage.retry_dead(&job_id).await.unwrap());
+        assert!(storage.list_dead(10, 0).await.unwrap().is_empty());
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, job_id);
+        assert_eq!(job.parts.attempt.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reschedule_on_final_attempt_is_not_retried() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_max_attempts(1);
+        let job_id = storage.push_request(req).await.unwrap().task_id;
+
+        let job = consume_one(&mut storage, &worker).await;
+        storage.reschedule(job, Duration::ZERO).await.unwrap();
+        assert_eq!(storage.status(&job_id).await.unwrap(), Some(State::Dead));
+
+        // Nor is a retry left out of attempts by an older version
+        sqlx::query("UPDATE Jobs SET status = 'Retry' WHERE id = ?1")
+            .bind(job_id.to_string())
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(storage.time_until_next().await.unwrap(), None);
+        assert!(storage.claim(worker.id(), &job_id).await.unwrap().is_none());
+        let mut stream = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(1500), stream.next())
+                .await
+                .is_err()
+        );
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(job.parts.attempt.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_held_job_is_not_consumed_until_released() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        storage
+            .hold(&job_id, Utc::now().timestamp() + 3600)
+            .await
+            .unwrap();
+
+        let worker = register_worker(&mut storage).await;
+        let mut stream = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(1500), stream.next())
+                .await
+                .is_err()
+        );
+
+        storage.release(&job_id).await.unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(3), stream.next())
+            .await
+            .expect("released job should be consumed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.parts.task_id, job_id);
+    }
+
+    #[tokio::test]
+    async fn test_on_drained_after_all_jobs_are_done() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_poll_interval(Duration::from_millis(100));
+        for _ in 0..3 {
+            push_email(&mut storage, example_good_email()).await;
+        }
+        let worker = register_worker(&mut storage).await;
+
+        let mut consumer = storage.clone();
+        let jobs = storage.stream_jobs(&worker, Duration::from_millis(100), 1);
+        tokio::spawn(async move {
+            let mut jobs = jobs.boxed();
+            while let Some(Ok(job)) = jobs.next().await {
+                let Fetch::Job(job) = job else { continue };
+                consumer
+                    .ack(
+                        &job.parts.context,
+                        &Response::success(
+                            (),
+                            job.parts.task_id.clone(),
+                            job.parts.attempt.clone(),
+                        ),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+
+        tokio::time::timeout(Duration::from_secs(10), storage.on_drained(worker.id()))
+            .await
+            .expect("queue should drain")
+            .unwrap();
+        let stats = storage.stats().await.unwrap();
+        assert_eq!((stats.pending, stats.running, stats.success), (0, 0, 3));
+    }
+
+    #[tokio::test]
+    async fn test_active_worker_count_skips_stale_workers() {
+        let mut storage = setup::<Email>().await;
+        let now = Utc::now().timestamp_millis();
+        for (name, last_seen) in [
+            ("recent-1", now),
+            ("recent-2", now - 5_000),
+            ("stale", now - 600_000),
+        ] {
+            storage
+                .keep_alive_at::<DummyService>(&WorkerId::new(name), last_seen)
+                .await
+                .unwrap();
+        }
+
+        let active = storage
+            .active_worker_count(Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(active, 2);
+    }
+
+    #[tokio::test]
+    async fn test_request_builder_persists_job_options() {
+        use crate::context::RequestBuilder;
+
+        let mut storage = setup().await;
+        let delayed = RequestBuilder::new(example_good_email())
+            .max_attempts(3)
+            .priority(5)
+            .delay(Duration::from_secs(60))
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        let job = get_job(&mut storage, &delayed).await;
+        assert_eq!(job.parts.context.max_attempts(), 3);
+        assert_eq!(job.parts.context.priority(), 5);
+        assert!(job.parts.context.run_at().timestamp() >= Utc::now().timestamp() + 59);
+
+        // Only the job without a delay is due
+        let due = RequestBuilder::new(example_good_email())
+            .max_attempts(1)
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, due);
+        assert_eq!(job.parts.context.max_attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unique_key_holds_while_job_is_live() {
+        use crate::context::RequestBuilder;
+
+        let mut storage = setup().await;
+        let first = RequestBuilder::new(example_good_email())
+            .unique_key("order-7")
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        let again = RequestBuilder::new(example_good_email())
+            .unique_key("order-7")
+            .delay(Duration::from_secs(60))
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        assert_eq!(again, first);
+        assert_eq!(storage.len().await.unwrap(), 1);
+
+        // Running jobs still hold their key, finished ones release it
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, first);
+        let again = RequestBuilder::new(example_good_email())
+            .unique_key("order-7")
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        assert_eq!(again, first);
+
+        storage.kill(worker.id(), &first).await.unwrap();
+        let next = RequestBuilder::new(example_good_email())
+            .unique_key("order-7")
+            .push(&mut storage)
+            .await
+            .unwrap()
+            .task_id;
+        assert_ne!(next, first);
+        let job = get_job(&mut storage, &next).await;
+        assert_eq!(job.parts.context.unique_key().as_deref(), Some("order-7"));
+    }
+
+    #[tokio::test]
+    async fn test_child_inherits_parent_priority() {
+        let mut storage = setup::<Email>().await;
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_priority(9);
+        let parent_id = storage.push_request(req).await.unwrap().task_id;
+        let parent = get_job(&mut storage, &parent_id).await.parts.context;
+
+        let inherited = storage
+            .push_after(&parent, example_good_email(), None)
+            .await
+            .unwrap()
+            .task_id;
+        let overridden = storage
+            .push_after(&parent, example_good_email(), Some(1))
+            .await
+            .unwrap()
+            .task_id;
+
+        let child = get_job(&mut storage, &inherited).await;
+        assert_eq!(child.parts.context.priority(), 9);
+        let child = get_job(&mut storage, &overridden).await;
+        assert_eq!(child.parts.context.priority(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_payload_formats_round_trip() {
+        let mut storage = setup::<Email>().await;
+        let compact = storage.push(example_good_email()).await.unwrap().task_id;
+        storage.config = storage
+            .config
+            .clone()
+            .set_payload_format(PayloadFormat::Pretty);
+        let pretty = storage.push(example_good_email()).await.unwrap().task_id;
+
+        for (id, multiline) in [(&compact, false), (&pretty, true)] {
+            let raw: String = sqlx::query_scalar("SELECT job FROM Jobs WHERE id = ?1")
+                .bind(id.to_string())
+                .fetch_one(storage.pool())
+                .await
+                .unwrap();
+            assert_eq!(raw.contains('\n'), multiline);
+            let job = get_job(&mut storage, id).await;
+            assert_eq!(job.args.subject, example_good_email().subject);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backend_now_matches_system_time() {
+        let storage = setup::<Email>().await;
+        let now = storage.now().await.unwrap();
+        let skew = now
+            .duration_since(SystemTime::now())
+            .or_else(|e| Ok::<_, ()>(e.duration()))
+            .unwrap();
+        assert!(skew < Duration::from_secs(1), "skew of {skew:?}");
+    }
+
+    #[tokio::test]
+    async fn test_runnable_ids_match_consume_order() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_fetch_order(FetchOrder::Priority {
+                age_boost: Duration::from_secs(3600),
+            });
+        let due = Utc::now().timestamp() - 10;
+        for priority in [1, 7, 4] {
+            let mut req = Request::<_, SqlContext>::new(example_good_email());
+            req.parts.context.set_priority(priority);
+            storage.schedule_request(req, due).await.unwrap();
+        }
+
+        let ids: Vec<TaskId> = storage.runnable_ids(10).try_collect().await.unwrap();
+        let mut priorities = Vec::new();
+        for id in &ids {
+            priorities.push(get_job(&mut storage, id).await.parts.context.priority());
+        }
+        assert_eq!(priorities, vec![7, 4, 1]);
+
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(job.parts.task_id, ids[0]);
+
+        let claimed = storage.claim(worker.id(), &ids[2]).await.unwrap().unwrap();
+        assert_eq!(claimed.parts.task_id, ids[2]);
+        assert!(storage.claim(worker.id(), &ids[2]).await.unwrap().is_none());
+        let ids: Vec<TaskId> = storage.runnable_ids(10).try_collect().await.unwrap();
+        assert_eq!(ids.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_index_hint_returns_same_jobs() {
+        let mut storage = setup::<Email>().await;
+        let due = Utc::now().timestamp() - 10;
+        for _ in 0..3 {
+            storage.schedule(example_good_email(), due).await.unwrap();
+        }
+        let unhinted: Vec<TaskId> = storage.runnable_ids(10).try_collect().await.unwrap();
+        assert_eq!(unhinted.len(), 3);
+
+        let mut hinted = storage.clone();
+        hinted.config = hinted.config.clone().set_fetch_index_hint("JTIdx");
+        let mut ids: Vec<TaskId> = hinted.runnable_ids(10).try_collect().await.unwrap();
+        let mut expected = unhinted.clone();
+        ids.sort_by_key(ToString::to_string);
+        expected.sort_by_key(ToString::to_string);
+        assert_eq!(ids, expected);
+
+        // The hint really reaches the query
+        let mut stale = storage.clone();
+        stale.config = stale.config.clone().set_fetch_index_hint("DroppedIdx");
+        let res: Result<Vec<TaskId>, _> = stale.runnable_ids(10).try_collect().await;
+        assert!(res.unwrap_err().to_string().contains("no such index"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_next_returns_locked_row() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let worker = register_worker(&mut storage).await;
+
+        let job = fetch_next(
+            storage.pool(),
+            worker.id(),
+            job_id.to_string(),
+            storage.get_config(),
+        )
+        .await
+        .unwrap()
+        .expect("the locked job should be returned");
+        let ctx = &job.req.parts.context;
+        assert_eq!(*ctx.status(), State::Running);
+        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
+        assert!(ctx.lock_at().is_some());
+        assert_eq!(job.req.parts.attempt.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats_by_type_rates() {
+        let mut storage = setup::<Email>().await;
+        for _ in 0..4 {
+            push_email(&mut storage, example_good_email()).await;
+        }
+        let worker = register_worker(&mut storage).await;
+        let jobs: Vec<_> = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 4)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .take(4)
+            .try_collect()
+            .await
+            .unwrap();
+        for (job, done) in jobs.into_iter().zip([true, true, true, false]) {
+            let res = match done {
+                true => Response::success((), job.parts.task_id.clone(), job.parts.attempt.clone()),
+                false => Response::failure(
+                    Error::Abort(Arc::new("gave up".into())),
+                    job.parts.task_id.clone(),
+                    job.parts.attempt.clone(),
+                ),
+            };
+            storage.ack(&job.parts.context, &res).await.unwrap();
+        }
+        // An old completion falls outside the window
+        sqlx::query("UPDATE Jobs SET done_at = done_at - 3600 WHERE id = (SELECT id FROM Jobs WHERE status = 'Done' LIMIT 1)")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+
+        let stats = storage
+            .stats_by_type(Duration::from_secs(120))
+            .await
+            .unwrap();
+        let stats = &stats[storage.get_config().base().namespace()];
+        assert_eq!((stats.done, stats.failed), (2, 1));
+        assert_eq!(stats.done_per_minute, 1.0);
+        assert_eq!(stats.failed_per_minute, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_updated_payload_seen_on_retry() {
+        let mut storage = setup::<Email>().await;
+        push_email(&mut storage, example_good_email()).await;
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        let job_id = job.parts.task_id.clone();
+
+        let mut checkpoint = example_good_email();
+        checkpoint.subject = "Resume from page 3".to_owned();
+        storage.update_payload(&job_id, &checkpoint).await.unwrap();
+        storage.reschedule(job, Duration::ZERO).await.unwrap();
+
+        let mut stream = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        let job = stream.next().await.unwrap().unwrap();
+        assert_eq!(job.parts.task_id, job_id);
+        assert_eq!(job.args.subject, "Resume from page 3");
+        assert_eq!(job.parts.attempt.current(), 2);
+
+        storage.kill(worker.id(), &job_id).await.unwrap();
+        assert!(matches!(
+            storage.update_payload(&job_id, &checkpoint).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_delay_clamped_to_job_ceiling() {
+        let mut storage = setup::<Email>().await;
+        // Exponential in hours, a day away by the fifth attempt
+        storage.config = storage
+            .config
+            .clone()
+            .set_retry_delay(|attempts, _| Duration::from_secs(3600 << attempts));
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_max_backoff_secs(Some(60));
+        storage.push_request(req).await.unwrap();
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        assert_eq!(*job.parts.context.max_backoff_secs(), Some(60));
+        for _ in 0..4 {
+            job.parts.attempt.increment();
+        }
+
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::<()>::failure(
+                    Error::Failed(Arc::new("flapping".into())),
+                    job.parts.task_id.clone(),
+                    job.parts.attempt.clone(),
+                ),
+            )
+            .await
+            .unwrap();
+        let job = get_job(&mut storage, &job.parts.task_id).await;
+        let wait = *job.parts.context.run_at() - Utc::now();
+        assert!(wait <= chrono::Duration::seconds(60), "waits {wait}");
+        assert!(wait > chrono::Duration::seconds(50), "waits {wait}");
+    }
+
+    #[tokio::test]
+    async fn test_list_workers_reports_load() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        storage.report_load(worker.id(), Some(8), 3).await.unwrap();
+
+        let workers = storage.list_workers().await.unwrap();
+        assert_eq!(workers.len(), 1);
+        assert_eq!(workers[0].id(), worker.id());
+        assert_eq!(workers[0].concurrency, Some(8));
+        assert_eq!(workers[0].in_flight, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_feed_follows_new_jobs() {
+        let mut storage = setup::<Email>().await;
+        storage.push(example_good_email()).await.unwrap();
+        let feed = storage.enqueue_feed(None);
+        futures::pin_mut!(feed);
+        // Nothing is replayed, this also pins the cursor before the pushes below
+        let first = futures::future::select(
+            feed.next(),
+            Box::pin(apalis_core::sleep(Duration::from_millis(50))),
+        );
+        assert!(matches!(first.await, futures::future::Either::Right(_)));
+
+        let mut producer = storage.clone();
+        for subject in ["first", "second", "third"] {
+            let mut email = example_good_email();
+            email.subject = subject.to_owned();
+            producer.push(email).await.unwrap();
+        }
+        let seen: Vec<String> = feed
+            .take(3)
+            .map_ok(|job| job.args.subject)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(seen, ["first", "second", "third"]);
+        assert_eq!(producer.len().await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_job_types_sharded_into_attached_files() {
+        use crate::schema::AttachedSchema;
+
+        let dir = std::env::temp_dir().join(format!("apalis-shards-{}", TaskId::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let emails_path = dir.join("emails.db").display().to_string();
+        let reports_path = dir.join("reports.db").display().to_string();
+        let mut shards = Vec::new();
+        for path in [&emails_path, &reports_path] {
+            let shard = SqlitePool::connect(&format!("sqlite:{path}?mode=rwc"))
+                .await
+                .unwrap();
+            SqliteStorage::setup(&shard).await.unwrap();
+            shards.push(shard);
+        }
+        let emails_queue = QueueName::try_from("emails").unwrap();
+        let reports_queue = QueueName::try_from("reports").unwrap();
+        let pool = SqliteStorage::connect_attached(
+            &format!("sqlite:{}", dir.join("main.db").display()),
+            &[
+                (emails_queue.clone(), &emails_path),
+                (reports_queue.clone(), &reports_path),
+            ],
+        )
+        .await
+        .unwrap();
+        let mut emails = SqliteStorage::<Email>::new_with_config(
+            pool.clone(),
+            SqliteConfig::new(&emails_queue).set_schema(AttachedSchema::new(&emails_queue)),
+        );
+        let mut reports = SqliteStorage::<u64>::new_with_config(
+            pool,
+            SqliteConfig::new(&reports_queue).set_schema(AttachedSchema::new(&reports_queue)),
+        );
+        emails.push(example_good_email()).await.unwrap();
+        reports.push(2024).await.unwrap();
+
+        for (shard, job_type) in shards.iter().zip(["emails", "reports"]) {
+            let job_types: Vec<String> = sqlx::query_scalar("SELECT job_type FROM Jobs")
+                .fetch_all(shard)
+                .await
+                .unwrap();
+            assert_eq!(job_types, [job_type]);
+        }
+
+        // Workers register in the shard too, as its jobs are locked against them
+        let worker = register_worker(&mut emails).await;
+        let job = emails
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.parts.context.lock_by().as_ref(), Some(worker.id()));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_handler_reads_pushed_headers() {
+        use apalis_core::layers::Service;
+        use apalis_core::service_fn::service_fn;
+
+        let mut storage = setup::<Email>().await;
+        let headers = HashMap::from([
+            ("source".to_owned(), "webhook".to_owned()),
+            ("idempotency-key".to_owned(), "order-42".to_owned()),
+        ]);
+        storage
+            .push_with_headers(example_good_email(), headers)
+            .await
+            .unwrap();
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+
+        let mut handler = service_fn(|_: Email, ctx: SqlContext| async move {
+            Ok::<_, Error>(ctx.headers().get("source").cloned())
+        });
+        let source = handler.call(job).await.unwrap();
+        assert_eq!(source.as_deref(), Some("webhook"));
+    }
+
+    #[tokio::test]
+    async fn test_pressure_tracks_processing() {
+        let mut storage = setup::<Email>().await;
+        let mut ids = Vec::new();
+        for waited in [120, 60] {
+            let parts = storage
+                .push_with_created_at(example_good_email(), Utc::now().timestamp() - waited)
+                .await
+                .unwrap();
+            ids.push(parts.task_id);
+        }
+        let age = |report: &PressureReport| report.oldest_pending_age.unwrap().as_secs();
+
+        let report = storage.pressure().await.unwrap();
+        assert_eq!(report.in_flight, 0);
+        assert!((120..=122).contains(&age(&report)), "{report:?}");
+
+        // The worker stopped heartbeating ten minutes ago
+        let worker = register_worker_at(
+            &mut storage,
+            (Utc::now() - chrono::Duration::minutes(10)).timestamp_millis(),
+        )
+        .await;
+        let job = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        let report = storage.pressure().await.unwrap();
+        assert_eq!(report.in_flight, 1);
+        let waiting = if job.parts.task_id == ids[0] { 60 } else { 120 };
+        assert!(
+            (waiting..=waiting + 2).contains(&age(&report)),
+            "{report:?}"
+        );
+
+        storage
+            .reenqueue_orphaned(10, Utc::now() - chrono::Duration::minutes(1))
+            .await
+            .unwrap();
+        let watch = storage.watch_pressure(Duration::from_millis(50));
+        futures::pin_mut!(watch);
+        let report = watch.next().await.unwrap().unwrap();
+        assert_eq!(report.in_flight, 0);
+        assert_eq!(report.lock_losses, 1);
+        assert!((120..=122).contains(&age(&report)), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn test_storage_stats_counts_all_namespaces() {
+        let mut storage = setup::<Email>().await;
+        let mut other = SqliteStorage::<Email>::new_with_config(
+            storage.pool().clone(),
+            Config::new("other-emails"),
+        );
+        for _ in 0..3 {
+            storage.push(example_good_email()).await.unwrap();
+        }
+        for _ in 0..2 {
+            other.push(example_good_email()).await.unwrap();
+        }
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
+        storage
+            .ack(
+                &job.parts.context,
+                &Response::success((), job.parts.task_id.clone(), job.parts.attempt.clone()),
+            )
+            .await
+            .unwrap();
+
+        let stats = storage.storage_stats().await.unwrap();
+        assert_eq!(stats.counts.pending, 4);
+        assert_eq!(stats.counts.success, 1);
+        assert_eq!(stats.rows, 5);
+        assert!(stats.approx_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_job_to_another_namespace() {
+        let mut storage = setup::<Email>().await;
+        let mut tenant = SqliteStorage::<Email>::new_with_config(
+            storage.pool().clone(),
+            Config::new("tenant-b"),
+        );
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        storage
+            .transfer_job(&job_id, Some(&QueueName::try_from("tenant-b").unwrap()))
+            .await
+            .unwrap();
+
+        let worker = register_worker(&mut storage).await;
+        let mut jobs = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), jobs.next())
+                .await
+                .is_err()
+        );
+        drop(jobs);
+
+        let tenant_worker = register_worker(&mut tenant).await;
+        let job = tenant
+            .stream_jobs(&tenant_worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.parts.task_id, job_id);
+
+        // Running jobs stay where they are
+        assert!(matches!(
+            tenant.transfer_job(&job_id, None).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lock_renewed_while_handler_runs() {
+        use apalis_core::layers::ServiceBuilder;
+
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_lock_renew_interval(Duration::from_millis(100));
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let worker = register_worker(&mut storage).await;
+        let job = storage
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        // Claiming may take a while on a busy machine, the window starts from a fresh heartbeat
+        storage
+            .keep_alive_at::<DummyService>(worker.id(), Utc::now().timestamp_millis())
+            .await
+            .unwrap();
+        let mut service = ServiceBuilder::new()
+            .layer(AckLayer::<_, Email, SqlContext, ()>::new(storage.clone()))
+            .layer(LockRenewLayer::new(storage.clone()))
+            .service(apalis_test_service_fn(
+                |_: Request<Email, SqlContext>| async {
+                    tokio::time::sleep(Duration::from_millis(3000)).await;
+                    Ok::<_, Error>(())
+                },
+            ));
+
+        // Jobs of workers unseen for a second are reclaimed, the handler takes three times that
+        let reclaim = async {
+            for _ in 0..28 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                storage
+                    .reenqueue_orphaned(10, Utc::now() - chrono::Duration::seconds(1))
+                    .await
+                    .unwrap();
+            }
+        };
+        let (res, _) = tokio::join!(service.call(job), reclaim);
+        res.unwrap();
+
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Done);
+        assert_eq!(job.parts.context.lock_by().as_ref(), Some(worker.id()));
+    }
+
+    #[tokio::test]
+    async fn test_deregister_keeps_jobs_handed_to_handler() {
+        use apalis_core::layers::ServiceBuilder;
+
+        let mut storage = setup::<Email>().await;
+        let worker_id = WorkerId::new("shutting-down");
+        storage
+            .keep_alive_at::<DummyService>(&worker_id, Utc::now().timestamp_millis())
+            .await
+            .unwrap();
+        let handled_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let claimed_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let handled = storage
+            .claim(&worker_id, &handled_id)
+            .await
+            .unwrap()
+            .unwrap();
+        storage
+            .claim(&worker_id, &claimed_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The handler finishes but its outcome is never stored, as when the ack fails
+        let mut service = ServiceBuilder::new()
+            .layer(LockRenewLayer::new(storage.clone()))
+            .service(apalis_test_service_fn(
+                |_: Request<Email, SqlContext>| async { Ok::<_, Error>(()) },
+            ));
+        service.call(handled).await.unwrap();
+
+        let unacked = storage.deregister_worker(&worker_id).await.unwrap();
+        assert_eq!(unacked, vec![handled_id.clone()]);
+        let handled = get_job(&mut storage, &handled_id).await;
+        assert_eq!(*handled.parts.context.status(), State::Running);
+        let claimed = get_job(&mut storage, &claimed_id).await;
+        assert_eq!(*claimed.parts.context.status(), State::Pending);
+        assert_eq!(claimed.parts.context.lock_by(), &None);
+    }
+
+    async fn run_until_cancelled(
+        storage: &mut SqliteStorage<Email>,
+        worker: &Worker<Context>,
+        cancel: impl Future<Output = ()>,
+    ) {
+        use apalis_core::layers::ServiceBuilder;
+
+        let job = storage
+            .stream_jobs(worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        let mut service = ServiceBuilder::new()
+            .layer(AckLayer::<_, Email, SqlContext, ()>::new(storage.clone()))
+            .layer(LockRenewLayer::new(storage.clone()))
+            .service(apalis_test_service_fn(
+                |req: Request<Email, SqlContext>| async move {
+                    let token: CancellationToken =
+                        apalis_core::service_fn::FromRequest::from_request(&req).unwrap();
+                    tokio::time::timeout(Duration::from_secs(10), token.cancelled())
+                        .await
+                        .expect("the handler was not cancelled");
+                    Ok::<_, Error>(())
+                },
+            ));
+        let (res, _) = tokio::join!(service.call(job), cancel);
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_running_handler() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let worker = register_worker(&mut storage).await;
+        let mut canceller = storage.clone();
+        run_until_cancelled(&mut storage, &worker, async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert!(canceller.cancel(&job_id).await.unwrap());
+        })
+        .await;
+
+        // The ack of the cancelled run leaves the job killed
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Killed);
+        assert!(!storage.cancel(&job_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_reaches_handler_of_other_process() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_keep_alive(Duration::from_millis(100));
+        // Another storage on the pool shares no state with this one, as if it ran elsewhere
+        let mut other = SqliteStorage::<Email>::new_with_config(
+            storage.pool().clone(),
+            storage.get_config().clone(),
+        );
+        let running = storage.push(example_good_email()).await.unwrap().task_id;
+        let worker = register_worker(&mut storage).await;
+        run_until_cancelled(&mut storage, &worker, async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert!(other.cancel(&running).await.unwrap());
+        })
+        .await;
+        let job = get_job(&mut storage, &running).await;
+        assert_eq!(*job.parts.context.status(), State::Killed);
+
+        // A waiting job is killed before it ever runs
+        let waiting = storage.push(example_good_email()).await.unwrap().task_id;
+        assert!(other.cancel(&waiting).await.unwrap());
+        assert!(storage.is_empty().await.unwrap());
+        let job = get_job(&mut storage, &waiting).await;
+        assert_eq!(*job.parts.context.status(), State::Killed);
+    }
+
+    #[tokio::test]
+    async fn test_push_or_replace_keeps_latest() {
+        let mut storage = setup::<Email>().await;
+        let mut ids = Vec::new();
+        for subject in ["v1", "v2", "v3"] {
+            let mut email = example_good_email();
+            email.subject = subject.to_owned();
+            ids.push(storage.push_or_replace(email, "doc-7").await.unwrap());
+        }
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        assert_eq!(storage.len().await.unwrap(), 1);
+        let job = get_job(&mut storage, &ids[0]).await;
+        assert_eq!(job.args.subject, "v3");
+
+        // Once started the job is no longer replaced
+        let worker = register_worker(&mut storage).await;
+        consume_one(&mut storage, &worker).await;
+        let mut email = example_good_email();
+        email.subject = "v4".to_owned();
+        let next = storage.push_or_replace(email, "doc-7").await.unwrap();
+        assert_ne!(next, ids[0]);
+        assert_eq!(get_job(&mut storage, &ids[0]).await.args.subject, "v3");
+    }
+
+    #[tokio::test]
+    async fn test_check_schema_points_to_setup() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let storage = SqliteStorage::<Email>::new(pool);
+        let err = storage.check_schema().await.unwrap_err();
+        assert!(matches!(&err, SqlError::MissingTable(table) if table == "Jobs"));
+        assert!(err.to_string().contains("setup"), "{err}");
+
+        SqliteStorage::setup(storage.pool()).await.unwrap();
+        storage.check_schema().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_claim_next_tx_commit_or_drop() {
+        let mut storage = setup::<Email>().await;
+        sqlx::query("CREATE TABLE Sent (subject TEXT NOT NULL)")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        let mut ids = HashMap::new();
+        for subject in ["committed", "dropped"] {
+            let mut email = example_good_email();
+            email.subject = subject.to_owned();
+            let parts = storage
+                .schedule(email, Utc::now().timestamp() - 1)
+                .await
+                .unwrap();
+            ids.insert(subject, parts.task_id);
+        }
+        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+        let worker = register_worker_at(&mut storage, six_minutes_ago.timestamp_millis()).await;
+
+        for _ in 0..2 {
+            let (job, mut tx) = storage.claim_next_tx(worker.id()).await.unwrap().unwrap();
+            assert_eq!(*job.parts.context.status(), State::Running);
+            sqlx::query("INSERT INTO Sent (subject) VALUES (?1)")
+                .bind(&job.args.subject)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+            let res = Response::success((), job.parts.task_id.clone(), job.parts.attempt.clone());
+            storage
+                .ack_in_tx(&mut tx, &job.parts.context, &res)
+                .await
+                .unwrap();
+            if job.args.subject == "committed" {
+                tx.commit().await.unwrap();
+            }
+        }
+        assert!(storage.claim_next_tx(worker.id()).await.unwrap().is_none());
+
+        let sent: Vec<(String,)> = sqlx::query_as("SELECT subject FROM Sent")
+            .fetch_all(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(sent, [("committed".to_owned(),)]);
+        assert_eq!(
+            storage.status(&ids["committed"]).await.unwrap(),
+            Some(State::Done)
+        );
+        let job = get_job(&mut storage, &ids["dropped"]).await;
+        assert_eq!(*job.parts.context.status(), State::Running);
+        assert_eq!(job.parts.context.lock_by().as_ref(), Some(worker.id()));
+
+        storage
+            .reenqueue_orphaned(10, Utc::now() - Duration::from_secs(5 * 60))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.status(&ids["dropped"]).await.unwrap(),
+            Some(State::Pending)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fifo_keeps_push_order_within_a_second() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage.config.clone().set_fetch_order(FetchOrder::Fifo);
+        let due = Utc::now().timestamp() - 1;
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            let mut email = example_good_email();
+            email.subject = format!("email {i}");
+            ids.push(storage.schedule(email, due).await.unwrap().task_id);
+        }
+        let worker = register_worker(&mut storage).await;
+
+        let runnable: Vec<TaskId> = storage.runnable_ids(5).try_collect().await.unwrap();
+        assert_eq!(runnable, ids);
+        let mut stream = storage
+            .stream_jobs(&worker, Duration::from_millis(10), 5)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        for id in ids {
+            let job = stream.next().await.unwrap().unwrap();
+            assert_eq!(job.parts.task_id, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oldest_pending_age_follows_oldest_due_job() {
+        let mut storage = setup::<Email>().await;
+        assert_eq!(storage.oldest_pending_age().await.unwrap(), None);
+
+        let now = Utc::now().timestamp();
+        storage
+            .schedule(example_good_email(), now + 3_600)
+            .await
+            .unwrap();
+        assert_eq!(storage.oldest_pending_age().await.unwrap(), None);
+
+        storage
+            .push_with_created_at(example_good_email(), now - 60)
+            .await
+            .unwrap();
+        let oldest = storage
+            .push_with_created_at(example_good_email(), now - 600)
+            .await
+            .unwrap()
+            .task_id;
+        let age = storage.oldest_pending_age().await.unwrap().unwrap();
+        assert!(age >= Duration::from_secs(600) && age < Duration::from_secs(610));
+
+        // Claiming the oldest job leaves the next one as the oldest
+        let worker = register_worker(&mut storage).await;
+        storage.claim(worker.id(), &oldest).await.unwrap().unwrap();
+        let age = storage.oldest_pending_age().await.unwrap().unwrap();
+        assert!(age >= Duration::from_secs(60) && age < Duration::from_secs(70));
+
+        // Rows stored without a creation time count from when they were due
+        let legacy = storage
+            .schedule(example_good_email(), now - 300)
+            .await
+            .unwrap()
+            .task_id;
+        let query = storage
+            .config
+            .query("UPDATE {table} SET {created_at} = NULL WHERE {id} = ?1");
+        sqlx::query(&query)
+            .bind(legacy.to_string())
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+        let age = storage.oldest_pending_age().await.unwrap().unwrap();
+        assert!(age >= Duration::from_secs(300) && age < Duration::from_secs(310));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_filter_leaves_other_jobs_pending() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        SqliteStorage::setup(&pool).await.unwrap();
+        let config = SqliteConfig::new(type_name::<Email>())
+            .set_fetch_filter("json_extract(job, '$.to') LIKE '%@vip.com'");
+        let mut storage = SqliteStorage::<Email>::try_new_with_config(pool, config).unwrap();
+        let mut ids = HashMap::new();
+        for to in ["a@vip.com", "b@example.com", "c@vip.com", "d@example.com"] {
+            let mut email = example_good_email();
+            email.to = to.to_owned();
+            let parts = storage
+                .schedule(email, Utc::now().timestamp() - 1)
+                .await
+                .unwrap();
+            ids.insert(to, parts.task_id);
+        }
+        let worker = register_worker(&mut storage).await;
+
+        let claimed: Vec<_> = storage
+            .stream_jobs(&worker, Duration::from_millis(10), 10)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .take(2)
+            .map_ok(|job| job.args.to)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 2);
+        assert!(claimed.iter().all(|to| to.ends_with("@vip.com")));
+        let runnable: Vec<TaskId> = storage.runnable_ids(10).try_collect().await.unwrap();
+        assert!(runnable.is_empty());
+        for to in ["b@example.com", "d@example.com"] {
+            assert_eq!(
+                storage.status(&ids[to]).await.unwrap(),
+                Some(State::Pending)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_before_heartbeat_stops() {
+        use apalis_core::builder::{WorkerBuilder, WorkerFactoryFn};
+
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_keep_alive(Duration::from_millis(100))
+            .set_reenqueue_orphaned_after(Duration::from_millis(500));
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+
+        async fn task(_job: Email) {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+        }
+        let worker = WorkerBuilder::new("draining")
+            .backend(storage.clone())
+            .build_fn(task)
+            .run();
+        let handle = worker.get_handle();
+        let stop = async {
+            while storage.status(&job_id).await.unwrap() != Some(State::Running) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            handle.stop();
+        };
+        tokio::join!(worker, stop);
+
+        // The job outlived the orphan window while draining, yet it ran once and was acked by its worker
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Done);
+        assert_eq!(job.parts.attempt.current(), 1);
+        assert_eq!(
+            job.parts.context.lock_by().as_ref().map(|w| w.name()),
+            Some("draining")
+        );
+        assert_eq!(
+            storage
+                .active_worker_count(Duration::from_secs(60))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reclaimed_job_skips_recorded_effect() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage
+            .schedule(example_good_email(), Utc::now().timestamp() - 1)
+            .await
+            .unwrap()
+            .task_id;
+        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+        let worker = register_worker_at(&mut storage, six_minutes_ago.timestamp_millis()).await;
+
+        let charges = AtomicU64::new(0);
+        let handler_storage = storage.clone();
+        let charge = |job: Request<Email, SqlContext>| {
+            let (storage, charges) = (&handler_storage, &charges);
+            async move {
+                if job.parts.context.effect_token().is_some() {
+                    return false;
+                }
+                charges.fetch_add(1, Ordering::Relaxed);
+                storage
+                    .record_effect(&job.parts.task_id, "charge-1")
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // The first run charges then dies before acking, the reclaimed run sees the charge
+        let job = storage.claim(worker.id(), &job_id).await.unwrap().unwrap();
+        assert!(charge(job).await);
+        storage
+            .reenqueue_orphaned(10, Utc::now() - Duration::from_secs(5 * 60))
+            .await
+            .unwrap();
+        let job = storage.claim(worker.id(), &job_id).await.unwrap().unwrap();
+        assert_eq!(job.parts.attempt.current(), 2);
+        assert_eq!(
+            job.parts.context.effect_token().as_deref(),
+            Some("charge-1")
+        );
+        assert!(!charge(job).await);
+        assert_eq!(charges.load(Ordering::Relaxed), 1);
+
+        // A late second record keeps the first token
+        assert!(!storage.record_effect(&job_id, "charge-2").await.unwrap());
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(
+            job.parts.context.effect_token().as_deref(),
+            Some("charge-1")
+        );
+        assert!(matches!(
+            storage
+                .record_effect(&TaskId::new(), "charge-3")
+                .await
+                .unwrap_err(),
+            sqlx::Error::RowNotFound
+        ));
+    }
+
+    #[derive(Debug)]
+    struct CaptureLogs(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for CaptureLogs {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.level() <= log::Level::Debug
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGS: CaptureLogs = CaptureLogs(std::sync::Mutex::new(Vec::new()));
+
+    #[tokio::test]
+    async fn test_log_queries_only_when_enabled() {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&LOGS).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        SqliteStorage::setup(&pool).await.unwrap();
+        for (namespace, enabled) in [("logged-queries", true), ("quiet-queries", false)] {
+            let config = SqliteConfig::new(namespace).set_log_queries(enabled);
+            let mut storage = SqliteStorage::<Email>::new_with_config(pool.clone(), config);
+            storage.push(example_good_email()).await.unwrap();
+        }
+
+        let logged: Vec<String> = LOGS
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains("-queries:"))
+            .cloned()
+            .collect();
+        assert_eq!(logged.len(), 1, "{logged:?}");
+        assert!(logged[0].starts_with("logged-queries: push done in "));
+    }
+
+    #[tokio::test]
+    async fn test_push_with_created_at_is_stored_verbatim() {
+        let mut storage = setup::<Email>().await;
+        let pushed = storage.push(example_good_email()).await.unwrap().task_id;
+        let event_time = Utc::now().timestamp() - 30 * 86_400;
+        let backfilled = storage
+            .push_with_created_at(example_good_email(), event_time)
+            .await
+            .unwrap()
+            .task_id;
+
+        let job = get_job(&mut storage, &backfilled).await;
+        assert_eq!(*job.parts.context.created_at(), Some(event_time));
+        let job = get_job(&mut storage, &pushed).await;
+        let created_at = job.parts.context.created_at().unwrap();
+        assert!((Utc::now().timestamp() - created_at).abs() <= 1);
+
+        // Waiting since the event it stands for
+        let age = storage.oldest_pending_age().await.unwrap().unwrap();
+        assert!(age >= Duration::from_secs(30 * 86_400));
+        assert!(age < Duration::from_secs(30 * 86_400 + 5));
+        assert!(storage
+            .push_with_created_at(example_good_email(), Utc::now().timestamp() + 3_600)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_worker_events_follow_heartbeats() {
+        use crate::clock::{Clock, MockClock};
+
+        let clock = MockClock::default();
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_clock(clock.clone())
+            .set_keep_alive(Duration::from_millis(10));
+        let watcher = storage.clone();
+        let mut events = watcher.worker_events().boxed();
+
+        let worker = WorkerId::new("coming-and-going");
+        storage
+            .keep_alive_at::<DummyService>(&worker, clock.now().timestamp_millis())
+            .await
+            .unwrap();
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            WorkerEvent::Registered(worker.clone())
+        );
 
-    async fn list_workers(&self) -> Result<Vec<Worker<WorkerState>>, Self::Error> {
-        let fetch_query =
-            "SELECT id, layers, last_seen FROM Workers WHERE worker_type = ? ORDER BY last_seen DESC LIMIT 20 OFFSET ?";
-        let res: Vec<(String, String, i64)> = sqlx::query_as(fetch_query)
-            .bind(self.get_config().namespace())
-            .bind(0)
-            .fetch_all(self.pool())
-            .await?;
-        Ok(res
-            .into_iter()
-            .map(|w| Worker::new(WorkerId::new(w.0), WorkerState::new::<Self>(w.1)))
-            .collect())
+        // Unseen for longer than the orphan window
+        clock.advance(storage.config.base.reenqueue_orphaned_after + Duration::from_secs(1));
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            WorkerEvent::Expired(worker.clone())
+        );
+
+        storage
+            .keep_alive_at::<DummyService>(&worker, clock.now().timestamp_millis())
+            .await
+            .unwrap();
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            WorkerEvent::Registered(worker)
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[tokio::test]
+    async fn test_worker_events_survive_failed_reads() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_keep_alive(Duration::from_millis(10))
+            .set_reenqueue_orphaned_after(Duration::MAX);
+        let gone = storage.config.query("ALTER TABLE {workers} RENAME TO gone");
+        sqlx::query(&gone).execute(&storage.pool).await.unwrap();
+        let watcher = storage.clone();
+        let mut events = watcher.worker_events().boxed();
+        assert!(events.next().await.unwrap().is_err());
 
-    use crate::sql_storage_tests;
+        let back = storage.config.query("ALTER TABLE gone RENAME TO {workers}");
+        sqlx::query(&back).execute(&storage.pool).await.unwrap();
+        let worker = WorkerId::new("after-outage");
+        storage
+            .keep_alive_at::<DummyService>(&worker, Utc::now().timestamp_millis())
+            .await
+            .unwrap();
+        loop {
+            match events.next().await.unwrap() {
+                Ok(event) => {
+                    assert_eq!(event, WorkerEvent::Registered(worker));
+                    break;
+                }
+                // Reads racing the rename back still fail
+                Err(_) => continue,
+            }
+        }
+    }
 
-    use super::*;
-    use apalis_core::request::State;
-    use apalis_core::test_utils::DummyService;
-    use chrono::Utc;
-    use email_service::example_good_email;
-    use email_service::Email;
-    use futures::StreamExt;
+    #[tokio::test]
+    async fn test_backup_to_copies_every_job() {
+        // An in-memory database would be backed up in memory too
+        let dir = std::env::temp_dir().join(format!("apalis-backup-{}", TaskId::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = SqlitePool::connect(&format!(
+            "sqlite:{}?mode=rwc",
+            dir.join("queue.db").display()
+        ))
+        .await
+        .unwrap();
+        SqliteStorage::setup(&pool).await.unwrap();
+        let mut storage = SqliteStorage::<Email>::new(pool);
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let parts = storage.push(example_good_email()).await.unwrap();
+            ids.push(parts.task_id);
+        }
+        let path = dir.join("backup.db");
 
-    use apalis_core::generic_storage_test;
-    use apalis_core::test_utils::apalis_test_service_fn;
-    use apalis_core::test_utils::TestWrapper;
+        storage.backup_to(&path).await.unwrap();
+        assert!(matches!(
+            storage.backup_to(&path).await,
+            Err(sqlx::Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists
+        ));
 
-    generic_storage_test!(setup);
-    sql_storage_tests!(setup::<Email>, SqliteStorage<Email>, Email);
+        let pool = SqlitePool::connect(&format!("sqlite:{}", path.display()))
+            .await
+            .unwrap();
+        let mut backup =
+            SqliteStorage::<Email>::new_with_config(pool, storage.get_config().clone());
+        assert_eq!(backup.len().await.unwrap(), 3);
+        for id in &ids {
+            let job = backup.fetch_by_id(id).await.unwrap().unwrap();
+            assert_eq!(job.args.to, example_good_email().to);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-    /// migrate DB and return a storage instance.
-    async fn setup<T: Serialize + DeserializeOwned>() -> SqliteStorage<T> {
-        // Because connections cannot be shared across async runtime
-        // (different runtimes are created for each test),
-        // we don't share the storage and tests must be run sequentially.
-        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
-        SqliteStorage::setup(&pool)
+    #[tokio::test]
+    async fn test_time_until_next_follows_earliest_job() {
+        let mut storage = setup::<Email>().await;
+        assert_eq!(storage.time_until_next().await.unwrap(), None);
+
+        storage
+            .schedule(example_good_email(), Utc::now().timestamp() + 10)
             .await
-            .expect("failed to migrate DB");
-        let storage = SqliteStorage::<T>::new(pool);
+            .unwrap();
+        let wait = storage.time_until_next().await.unwrap().unwrap();
+        assert!(
+            wait > Duration::from_secs(9) && wait <= Duration::from_secs(11),
+            "{wait:?}"
+        );
 
         storage
+            .schedule(example_good_email(), Utc::now().timestamp() - 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.time_until_next().await.unwrap(),
+            Some(Duration::ZERO)
+        );
     }
 
     #[tokio::test]
-    async fn test_inmemory_sqlite_worker() {
-        let mut sqlite = setup().await;
-        sqlite
-            .push(Email {
-                subject: "Test Subject".to_string(),
-                to: "example@sqlite".to_string(),
-                text: "Some Text".to_string(),
-            })
+    async fn test_rows_of_two_codecs_decode_side_by_side() {
+        /// Json behind a version marker, standing in for a new encoding
+        struct MarkedJson;
+
+        impl Codec for MarkedJson {
+            type Compact = String;
+            type Error = BoxDynError;
+
+            fn encode<I: Serialize>(input: I) -> Result<String, BoxDynError> {
+                Ok(format!("v2:{}", serde_json::to_string(&input)?))
+            }
+
+            fn decode<O: DeserializeOwned>(input: String) -> Result<O, BoxDynError> {
+                let json = input.strip_prefix("v2:").ok_or("missing v2 marker")?;
+                Ok(serde_json::from_str(json)?)
+            }
+        }
+
+        let mut old = setup::<Email>().await;
+        let before = old.push(example_good_email()).await.unwrap().task_id;
+        let mut new = old.clone().with_codec::<MarkedJson>();
+        new.config = new
+            .config
+            .clone()
+            .set_codec_tag(1)
+            .add_legacy_codec::<JsonCodec<String>>(0);
+        let after = new.push(example_good_email()).await.unwrap().task_id;
+
+        let rows: Vec<(u8, String)> = sqlx::query_as("SELECT codec, job FROM Jobs ORDER BY seq")
+            .fetch_all(old.pool())
             .await
-            .expect("Unable to push job");
-        let len = sqlite.len().await.expect("Could not fetch the jobs count");
-        assert_eq!(len, 1);
+            .unwrap();
+        assert_eq!(rows[0].0, 0);
+        assert_eq!(rows[1].0, 1);
+        assert!(rows[1].1.starts_with("v2:"));
+
+        for id in [&before, &after] {
+            let job = new.fetch_by_id(id).await.unwrap().unwrap();
+            assert_eq!(job.args.to, example_good_email().to);
+        }
+
+        // Workers still on the old codec only read the new rows once told how
+        assert!(old.fetch_by_id(&after).await.is_err());
+        old.config = old.config.clone().add_legacy_codec::<MarkedJson>(1);
+        let job = old.fetch_by_id(&after).await.unwrap().unwrap();
+        assert_eq!(job.parts.context.codec_tag(), 1);
     }
 
-    async fn consume_one(
-        storage: &mut SqliteStorage<Email>,
-        worker: &Worker<Context>,
-    ) -> Request<Email, SqlContext> {
-        let mut stream = storage
-            .stream_jobs(worker, std::time::Duration::from_secs(10), 1)
-            .boxed();
-        stream
-            .next()
-            .await
-            .expect("stream is empty")
-            .expect("failed to poll job")
-            .expect("no job is pending")
+    #[tokio::test]
+    async fn test_bump_counter_accumulates_across_reschedules() {
+        let mut storage = setup::<Email>().await;
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        assert_eq!(
+            storage
+                .bump_counter(&job_id, "provider.x", 1)
+                .await
+                .unwrap(),
+            1
+        );
+        for _ in 0..2 {
+            let job = get_job(&mut storage, &job_id).await;
+            storage
+                .bump_counter(&job_id, "provider.x", 1)
+                .await
+                .unwrap();
+            storage.reschedule(job, Duration::ZERO).await.unwrap();
+        }
+        assert_eq!(
+            storage.bump_counter(&job_id, "other", -2).await.unwrap(),
+            -2
+        );
+
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(job.parts.context.counters()["provider.x"], 3);
+        assert_eq!(job.parts.context.counters()["other"], -2);
+        assert_eq!(*job.parts.context.status(), State::Retry);
+
+        assert!(matches!(
+            storage.bump_counter(&TaskId::new(), "provider.x", 1).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
     }
 
-    async fn register_worker_at(
-        storage: &mut SqliteStorage<Email>,
-        last_seen: i64,
-    ) -> Worker<Context> {
-        let worker_id = WorkerId::new("test-worker");
+    #[tokio::test]
+    async fn test_counts_fallback_matches_filter() {
+        let mut storage = setup::<Email>().await;
+        assert_eq!(
+            count_by_status(storage.pool(), storage.get_config(), CountBy::SumCase)
+                .await
+                .unwrap(),
+            (0, 0, 0, 0, 0, 0)
+        );
 
+        for _ in 0..3 {
+            push_email(&mut storage, example_good_email()).await;
+        }
+        let worker = register_worker(&mut storage).await;
+        let job = consume_one(&mut storage, &worker).await;
         storage
-            .keep_alive_at::<DummyService>(&worker_id, last_seen)
+            .kill(&worker.id(), &job.parts.task_id)
             .await
-            .expect("failed to register worker");
-        let wrk = Worker::new(worker_id, Context::default());
-        wrk.start();
-        wrk
+            .unwrap();
+
+        let filter = count_by_status(storage.pool(), storage.get_config(), CountBy::Filter)
+            .await
+            .unwrap();
+        let sum_case = count_by_status(storage.pool(), storage.get_config(), CountBy::SumCase)
+            .await
+            .unwrap();
+        assert_eq!(sum_case, (2, 0, 0, 0, 0, 1));
+        assert_eq!(filter, sum_case);
     }
 
-    async fn register_worker(storage: &mut SqliteStorage<Email>) -> Worker<Context> {
-        register_worker_at(storage, Utc::now().timestamp()).await
+    #[tokio::test]
+    async fn test_empty_poll_yields_fetch_empty() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        let mut stream = storage
+            .stream_jobs(&worker, Duration::from_millis(10), 1)
+            .boxed();
+        assert!(matches!(stream.next().await, Some(Ok(Fetch::Empty))));
+
+        // Not started, so not ready for jobs
+        let busy = Worker::new(WorkerId::new("busy"), Context::default());
+        let mut stream = storage
+            .stream_jobs(&busy, Duration::from_millis(10), 1)
+            .boxed();
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(Fetch::Backoff(interval))) if interval == Duration::from_millis(10)
+        ));
     }
 
-    async fn push_email(storage: &mut SqliteStorage<Email>, email: Email) {
-        storage.push(email).await.expect("failed to push a job");
+    #[tokio::test]
+    async fn test_duplicate_id_is_a_typed_error() {
+        let mut storage = setup::<Email>().await;
+        let first = Request::new_with_ctx(example_good_email(), SqlContext::new());
+        let mut second = Request::new_with_ctx(example_good_email(), SqlContext::new());
+        second.parts.task_id = first.parts.task_id.clone();
+        let task_id = storage.push_request(first).await.unwrap().task_id;
+
+        let err = storage.push_request(second).await.unwrap_err();
+        assert!(matches!(err, StorageError::DuplicateId(id) if id == task_id));
+        assert_eq!(storage.len().await.unwrap(), 1);
+
+        assert!(!violates_column("UNIQUE constraint failed: Jobs.idx", "id"));
+        assert!(violates_column(
+            "UNIQUE constraint failed: Jobs.job_type, Jobs.id",
+            "id"
+        ));
     }
 
-    async fn get_job(
-        storage: &mut SqliteStorage<Email>,
-        job_id: &TaskId,
-    ) -> Request<Email, SqlContext> {
-        storage
-            .fetch_by_id(job_id)
+    #[tokio::test]
+    async fn test_push_with_id_is_get_or_create() {
+        let mut storage = setup::<Email>().await;
+        let order_id = TaskId::from_str("01ARZ3NDEKTSV4RRFFQ69G5FAV").unwrap();
+        let parts = storage
+            .push_with_id(order_id.clone(), example_good_email())
             .await
-            .expect("failed to fetch job by id")
-            .expect("no job found by id")
+            .unwrap();
+        assert_eq!(parts.task_id, order_id);
+
+        let job = storage.fetch_by_id(&order_id).await.unwrap().unwrap();
+        assert_eq!(job.args.to, example_good_email().to);
+
+        let mut other = example_good_email();
+        other.to = "someone.else@example.com".to_owned();
+        let err = storage
+            .push_with_id(order_id.clone(), other)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::DuplicateId(id) if id == order_id));
+        let job = storage.fetch_by_id(&order_id).await.unwrap().unwrap();
+        assert_eq!(job.args.to, example_good_email().to);
+        assert_eq!(storage.len().await.unwrap(), 1);
     }
 
     #[tokio::test]
-    async fn test_consume_last_pushed_job() {
-        let mut storage = setup().await;
+    async fn test_soft_deleted_job_is_hidden_until_purged() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage.config.clone().set_soft_delete(true);
+        let deleted = storage
+            .schedule(example_good_email(), Utc::now().timestamp() - 5)
+            .await
+            .unwrap()
+            .task_id;
+        let kept = storage
+            .schedule(example_good_email(), Utc::now().timestamp() - 5)
+            .await
+            .unwrap()
+            .task_id;
+
+        assert!(storage.delete(&deleted).await.unwrap());
+        assert!(!storage.delete(&deleted).await.unwrap());
+        assert!(storage.fetch_by_id(&deleted).await.unwrap().is_none());
+        assert_eq!(storage.status(&deleted).await.unwrap(), None);
+        assert_eq!(storage.len().await.unwrap(), 1);
+        assert_eq!(storage.stats().await.unwrap().pending, 1);
         let worker = register_worker(&mut storage).await;
+        assert_eq!(consume_one(&mut storage, &worker).await.parts.task_id, kept);
+
+        // The tombstone is only purged once past the retention
+        assert_eq!(
+            storage
+                .purge_deleted(Utc::now() - chrono::Duration::days(1))
+                .await
+                .unwrap(),
+            0
+        );
+        sqlx::query("UPDATE Jobs SET deleted_at = ?1 WHERE id = ?2")
+            .bind((Utc::now() - chrono::Duration::days(2)).timestamp())
+            .bind(deleted.to_string())
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .purge_deleted(Utc::now() - chrono::Duration::days(1))
+                .await
+                .unwrap(),
+            1
+        );
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM Jobs")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
 
+    #[tokio::test]
+    async fn test_dropped_guard_reschedules_unless_marked_success() {
+        let mut storage = setup::<Email>().await;
         push_email(&mut storage, example_good_email()).await;
-        let len = storage.len().await.expect("Could not fetch the jobs count");
-        assert_eq!(len, 1);
+        push_email(&mut storage, example_good_email()).await;
+        let worker = register_worker(&mut storage).await;
+        let (stream, driver) = storage.consume_guarded(&worker);
+        let driver = tokio::spawn(driver);
+        let mut stream = Box::pin(stream);
 
-        let job = consume_one(&mut storage, &worker).await;
-        let ctx = job.parts.context;
-        assert_eq!(*ctx.status(), State::Running);
-        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
-        assert!(ctx.lock_at().is_some());
+        let (failed, guard) = stream.next().await.unwrap().unwrap();
+        drop(guard);
+        let (done, mut guard) = stream.next().await.unwrap().unwrap();
+        guard.mark_success();
+        drop(guard);
+        drop(stream);
+        driver.await.unwrap();
+
+        let failed = get_job(&mut storage, &failed.parts.task_id).await;
+        assert_eq!(*failed.parts.context.status(), State::Retry);
+        assert_eq!(*failed.parts.context.lock_by(), None);
+        let done = get_job(&mut storage, &done.parts.task_id).await;
+        assert_eq!(*done.parts.context.status(), State::Done);
     }
 
     #[tokio::test]
-    async fn test_acknowledge_job() {
-        let mut storage = setup().await;
+    async fn test_retries_throttled_per_window() {
+        use crate::clock::{Clock, MockClock};
+
+        let clock = MockClock::default();
+        let mut storage = setup::<Email>().await;
+        storage.config = storage
+            .config
+            .clone()
+            .set_clock(clock.clone())
+            .set_max_retries_per_window(2, Duration::from_secs(60));
         let worker = register_worker(&mut storage).await;
+        let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+        let start = clock.now().timestamp();
 
-        push_email(&mut storage, example_good_email()).await;
-        let job = consume_one(&mut storage, &worker).await;
-        let job_id = &job.parts.task_id;
-        let ctx = &job.parts.context;
-        let res = 1usize;
+        // Two retries fit in the window
+        for _ in 0..2 {
+            let job = get_job(&mut storage, &job_id).await;
+            storage.reschedule(job, Duration::ZERO).await.unwrap();
+            let job = get_job(&mut storage, &job_id).await;
+            assert_eq!(job.parts.context.run_at().timestamp(), start);
+        }
+
+        // The third, here a failure with attempts left, waits for the first to leave the window
+        clock.advance(Duration::from_secs(1));
+        let job = storage.claim(worker.id(), &job_id).await.unwrap().unwrap();
+        let error = Error::Failed(Arc::new("still broken".into()));
         storage
             .ack(
-                ctx,
-                &Response::success(res, job_id.clone(), job.parts.attempt.clone()),
+                &job.parts.context,
+                &Response::<()>::failure(error, job_id.clone(), job.parts.attempt.clone()),
             )
             .await
-            .expect("failed to acknowledge the job");
+            .unwrap();
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Failed);
+        assert_eq!(job.parts.context.run_at().timestamp(), start + 60);
 
-        let job = get_job(&mut storage, job_id).await;
-        let ctx = job.parts.context;
-        assert_eq!(*ctx.status(), State::Done);
-        assert!(ctx.done_at().is_some());
+        // Once it did, retries run as asked again
+        clock.advance(Duration::from_secs(60));
+        storage.reschedule(job, Duration::ZERO).await.unwrap();
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(job.parts.context.run_at().timestamp(), start + 61);
     }
 
     #[tokio::test]
-    async fn test_kill_job() {
-        let mut storage = setup().await;
+    async fn test_returning_fallback_matches_returning() {
+        let mut storage = setup::<Email>().await;
+        assert!(storage.supports_returning().await);
+        let mut fallback = SqliteStorage::<Email>::new(storage.pool().clone());
+        fallback
+            .returning
+            .store(RETURNING_UNSUPPORTED, Ordering::Relaxed);
+        assert!(!fallback.supports_returning().await);
+        let worker = register_worker(&mut storage).await;
 
-        push_email(&mut storage, example_good_email()).await;
+        let mut outcomes = Vec::new();
+        for storage in [&mut storage, &mut fallback] {
+            let job_id = storage.push(example_good_email()).await.unwrap().task_id;
+            let job = storage.claim(worker.id(), &job_id).await.unwrap().unwrap();
+            let claimed_again = storage.claim(worker.id(), &job_id).await.unwrap();
+            let counters = (
+                storage.bump_counter(&job_id, "sent", 2).await.unwrap(),
+                storage.bump_counter(&job_id, "sent", 3).await.unwrap(),
+                storage.bump_counter(&TaskId::new(), "sent", 1).await,
+            );
+            let key = format!("digest-{}", outcomes.len());
+            let first = storage
+                .push_or_replace(example_good_email(), &key)
+                .await
+                .unwrap();
+            let replaced = storage
+                .push_or_replace(example_good_email(), &key)
+                .await
+                .unwrap();
+            outcomes.push((
+                job.parts.task_id == job_id,
+                job.parts.attempt.current(),
+                job.parts.context.status().clone(),
+                job.parts.context.lock_by().clone(),
+                claimed_again.is_none(),
+                counters.0,
+                counters.1,
+                matches!(counters.2, Err(sqlx::Error::RowNotFound)),
+                first == replaced,
+            ));
+        }
+        assert_eq!(outcomes[0], outcomes[1]);
+        assert_eq!(
+            outcomes[0],
+            (
+                true,
+                1,
+                State::Running,
+                Some(worker.id().clone()),
+                true,
+                2,
+                5,
+                true,
+                true
+            )
+        );
+    }
 
+    #[tokio::test]
+    async fn test_duplicate_ack_of_done_job_is_a_noop() {
+        let mut storage = setup::<Email>().await;
         let worker = register_worker(&mut storage).await;
+        push_email(&mut storage, example_good_email()).await;
+        let job = consume_one(&mut storage, &worker).await;
+        let res = Response::success((), job.parts.task_id.clone(), job.parts.attempt.clone());
+
+        storage.ack(&job.parts.context, &res).await.unwrap();
+        let done_at = *get_job(&mut storage, &job.parts.task_id)
+            .await
+            .parts
+            .context
+            .done_at();
+        // A failure reported late by a second delivery doesn't undo the success either
+        let late = Response::<()>::failure(
+            Error::Failed(Arc::new("timed out".into())),
+            job.parts.task_id.clone(),
+            job.parts.attempt.clone(),
+        );
+        storage.ack(&job.parts.context, &res).await.unwrap();
+        storage.ack(&job.parts.context, &late).await.unwrap();
+
+        let job = get_job(&mut storage, &job.parts.task_id).await;
+        assert_eq!(*job.parts.context.status(), State::Done);
+        assert_eq!(*job.parts.context.done_at(), done_at);
+        assert_eq!(
+            *job.parts.context.last_error(),
+            Some("{\"Ok\":null}".to_owned())
+        );
+    }
 
+    #[tokio::test]
+    async fn test_ack_of_job_held_by_another_worker_is_not_owned() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
+        push_email(&mut storage, example_good_email()).await;
         let job = consume_one(&mut storage, &worker).await;
-        let job_id = &job.parts.task_id;
+        let job_id = job.parts.task_id.clone();
 
+        // The job is reclaimed from the slow worker and claimed by another
         storage
-            .kill(&worker.id(), job_id)
+            .reenqueue_orphaned(10, Utc::now() + Duration::from_secs(1))
             .await
-            .expect("failed to kill job");
+            .unwrap();
+        let other = WorkerId::new("other-worker");
+        storage
+            .keep_alive_at::<DummyService>(&other, Utc::now().timestamp_millis())
+            .await
+            .unwrap();
+        storage.claim(&other, &job_id).await.unwrap().unwrap();
 
-        let job = get_job(&mut storage, job_id).await;
-        let ctx = job.parts.context;
-        assert_eq!(*ctx.status(), State::Killed);
-        assert!(ctx.done_at().is_some());
+        let res = Response::success((), job_id.clone(), job.parts.attempt.clone());
+        let err = storage.ack(&job.parts.context, &res).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotOwned(id) if id == job_id));
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Running);
+        assert_eq!(*job.parts.context.lock_by(), Some(other));
     }
 
     #[tokio::test]
-    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_6min() {
-        let mut storage = setup().await;
-
+    async fn test_stale_ack_leaves_rerun_alone() {
+        let mut storage = setup::<Email>().await;
+        let worker = register_worker(&mut storage).await;
         push_email(&mut storage, example_good_email()).await;
+        let first = consume_one(&mut storage, &worker).await;
+        let job_id = first.parts.task_id.clone();
 
-        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
+        // The same worker restarts and runs the job again before the first run reports back
+        storage
+            .requeue_running_for_worker(worker.id())
+            .await
+            .unwrap();
+        let rerun = storage.claim(worker.id(), &job_id).await.unwrap().unwrap();
+        assert_eq!(rerun.parts.attempt.current(), 2);
 
-        let five_minutes_ago = Utc::now() - Duration::from_secs(5 * 60);
-        let worker = register_worker_at(&mut storage, six_minutes_ago.timestamp()).await;
+        let stale = Response::<()>::failure(
+            Error::Failed(Arc::new("stale".into())),
+            job_id.clone(),
+            first.parts.attempt.clone(),
+        );
+        let err = storage.ack(&first.parts.context, &stale).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotOwned(_)));
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Running);
+        assert_eq!(*job.parts.context.last_error(), None);
 
+        let res = Response::success((), job_id.clone(), rerun.parts.attempt.clone());
+        storage.ack(&rerun.parts.context, &res).await.unwrap();
+        let job = get_job(&mut storage, &job_id).await;
+        assert_eq!(*job.parts.context.status(), State::Done);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_error_is_truncated() {
+        let mut storage = setup::<Email>().await;
+        storage.config = storage.config.clone().set_max_error_len(64);
+        let worker = register_worker(&mut storage).await;
+        push_email(&mut storage, example_good_email()).await;
         let job = consume_one(&mut storage, &worker).await;
-        let job_id = &job.parts.task_id;
-        storage
-            .reenqueue_orphaned(1, five_minutes_ago)
-            .await
-            .expect("failed to heartbeat");
-        let job = get_job(&mut storage, job_id).await;
-        let ctx = &job.parts.context;
-        assert_eq!(*ctx.status(), State::Pending);
-        assert!(ctx.done_at().is_none());
-        assert!(ctx.lock_by().is_none());
-        assert!(ctx.lock_at().is_none());
-        assert_eq!(*ctx.last_error(), Some("Job was abandoned".to_owned()));
-        assert_eq!(job.parts.attempt.current(), 1);
+
+        let huge = format!("smtp said: {}\0{}", "é".repeat(10_000), "x".repeat(10_000));
+        let res = Response::<()>::failure(
+            Error::Failed(Arc::new(huge.into())),
+            job.parts.task_id.clone(),
+            job.parts.attempt.clone(),
+        );
+        storage.ack(&job.parts.context, &res).await.unwrap();
+
+        let job = get_job(&mut storage, &job.parts.task_id).await;
+        let stored: Result<(), String> =
+            serde_json::from_str(job.parts.context.last_error().as_ref().unwrap()).unwrap();
+        let stored = stored.unwrap_err();
+        assert!(stored.len() <= 64, "{} bytes stored", stored.len());
+        assert!(stored.starts_with("FailedError: smtp said: é"));
+        assert!(stored.ends_with("é…"));
+
+        assert_eq!(
+            truncate_error(b"bad \xff\xfe bytes\0", 64),
+            "bad \u{FFFD}\u{FFFD} bytes\u{FFFD}"
+        );
+        assert_eq!(truncate_error(b"short", 2), "");
     }
 
     #[tokio::test]
-    async fn test_heartbeat_renqueueorphaned_pulse_last_seen_4min() {
-        let mut storage = setup().await;
+    async fn test_buffer_size_checked_on_construction() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let build = |buffer_size| {
+            SqliteStorage::<Email>::try_new_with_config(
+                pool.clone(),
+                Config::new("buffer").set_buffer_size(buffer_size),
+            )
+            .map(|_| ())
+        };
+        assert_eq!(build(0), Err(ConfigError::BufferSize(0)));
+        assert_eq!(build(usize::MAX), Err(ConfigError::BufferSize(usize::MAX)));
+        assert_eq!(build(1), Ok(()));
+        assert_eq!(build(Config::MAX_BUFFER_SIZE), Ok(()));
+    }
 
-        push_email(&mut storage, example_good_email()).await;
+    #[derive(Debug, Default)]
+    struct CollectEvents(std::sync::Mutex<Vec<JobEvent>>);
 
-        let six_minutes_ago = Utc::now() - Duration::from_secs(6 * 60);
-        let four_minutes_ago = Utc::now() - Duration::from_secs(4 * 60);
-        let worker = register_worker_at(&mut storage, four_minutes_ago.timestamp()).await;
+    impl apalis_core::sink::Sink for CollectEvents {
+        fn send(&self, event: JobEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
 
-        let job = consume_one(&mut storage, &worker).await;
-        let job_id = &job.parts.task_id;
-        storage
-            .reenqueue_orphaned(1, six_minutes_ago)
-            .await
-            .expect("failed to heartbeat");
+    #[tokio::test]
+    async fn test_event_sink_follows_job_lifecycle() {
+        use apalis_core::layers::{Service, ServiceBuilder};
+        use apalis_core::sink::SinkLayer;
 
-        let job = get_job(&mut storage, job_id).await;
-        let ctx = &job.parts.context;
-        assert_eq!(*ctx.status(), State::Running);
-        assert_eq!(*ctx.lock_by(), Some(worker.id().clone()));
-        assert!(ctx.lock_at().is_some());
-        assert_eq!(*ctx.last_error(), None);
-        assert_eq!(job.parts.attempt.current(), 1);
+        let mut storage = setup::<Email>().await;
+        let events = Arc::new(CollectEvents::default());
+        storage.config = storage.config.clone().set_event_sink(events.clone());
+        let mut req = Request::<_, SqlContext>::new(example_good_email());
+        req.parts.context.set_max_attempts(2);
+        let task_id = storage.push_request(req).await.unwrap().task_id;
+        let worker = register_worker(&mut storage).await;
+        let mut service = ServiceBuilder::new()
+            .layer(AckLayer::<_, Email, SqlContext, ()>::new(storage.clone()))
+            .layer(SinkLayer::new(events.clone()))
+            .service(apalis_test_service_fn(
+                |_: Request<Email, SqlContext>| async {
+                    Err::<(), _>(Error::Failed(Arc::new("smtp is down".into())))
+                },
+            ));
+
+        let consumer = storage.clone();
+        let mut jobs = consumer
+            .stream_jobs(&worker, Duration::from_millis(100), 1)
+            .try_filter_map(|fetch| futures::future::ready(Ok(fetch.into_job())))
+            .boxed();
+        for _ in 0..2 {
+            let job = jobs.next().await.unwrap().unwrap();
+            assert!(service.call(job).await.is_err());
+            storage.reenqueue_failed().await.unwrap();
+        }
+
+        let failed = JobEvent::Failed {
+            task_id: task_id.clone(),
+            error: "FailedError: smtp is down".to_owned(),
+        };
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                JobEvent::Claimed(task_id.clone()),
+                failed.clone(),
+                JobEvent::Rescheduled(task_id.clone()),
+                JobEvent::Claimed(task_id.clone()),
+                failed,
+                JobEvent::DeadLettered(task_id),
+            ]
+        );
     }
 }
@@ -0,0 +1,48 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! apalis backend using Amazon SQS
+//!
+//! A received message is leased to the worker for the queue's visibility timeout,
+//! which the backend keeps extending while the job runs. Successful jobs are deleted,
+//! failed ones become visible again for another attempt, and jobs that are aborted or
+//! run out of attempts are forwarded to the configured dead-letter queue.
+//!
+//! The [`Client`] is built by the application, usually through `aws-config`,
+//! so credentials, region and the HTTP client stay under its control.
+//! ```rust,no_run
+//! use apalis::prelude::*;
+//! use apalis_sqs::{Client, Config, SqsStorage};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, Serialize)]
+//! struct Email {
+//!     to: String,
+//! }
+//!
+//! async fn send_email(job: Email) -> Result<(), Error> {
+//!     Ok(())
+//! }
+//!
+//! async fn run(client: Client) {
+//!     let config = Config::new("https://sqs.eu-west-1.amazonaws.com/123456789012/emails")
+//!         .set_dead_letter_queue_url("https://sqs.eu-west-1.amazonaws.com/123456789012/emails-dlq");
+//!     let storage: SqsStorage<Email> = SqsStorage::new(client, config);
+//!     let worker = WorkerBuilder::new("tasty-mango")
+//!         .backend(storage)
+//!         .build_fn(send_email);
+//!
+//!     worker.run().await;
+//! }
+//! ```
+
+mod storage;
+pub use aws_sdk_sqs::Client;
+pub use storage::Config;
+pub use storage::SqsContext;
+pub use storage::SqsError;
+pub use storage::SqsStorage;
@@ -0,0 +1,603 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    marker::PhantomData,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use apalis_core::{
+    backend::Backend,
+    codec::{json::JsonCodec, Codec},
+    error::{BoxDynError, Error},
+    layers::{Ack, AckLayer},
+    mq::MessageQueue,
+    poller::{controller::Controller, stream::BackendStream, Poller},
+    request::{Parts, Request, RequestStream},
+    response::Response,
+    service_fn::FromRequest,
+    task::attempt::Attempt,
+    worker::{Context, Worker},
+};
+use aws_sdk_sqs::{
+    types::{Message, MessageSystemAttributeName, QueueAttributeName},
+    Client,
+};
+use futures::{FutureExt, StreamExt};
+use log::error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The longest delay SQS accepts on a message
+const MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// The longest SQS holds a receive open waiting for messages
+const MAX_WAIT_TIME: Duration = Duration::from_secs(20);
+
+/// The most messages SQS hands out per receive
+const MAX_BATCH: usize = 10;
+
+/// The context for a job received from SQS
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct SqsContext {
+    #[serde(skip)]
+    message_id: Option<String>,
+    #[serde(skip)]
+    receipt_handle: Option<String>,
+    /// The raw message, kept to forward it to the dead-letter queue
+    #[serde(skip)]
+    body: Option<String>,
+}
+
+impl SqsContext {
+    /// The id SQS assigned to the message
+    pub fn message_id(&self) -> Option<&str> {
+        self.message_id.as_deref()
+    }
+
+    /// The handle of the current receive, needed to delete the message or change its visibility
+    pub fn receipt_handle(&self) -> Option<&str> {
+        self.receipt_handle.as_deref()
+    }
+}
+
+impl<Req> FromRequest<Request<Req, SqsContext>> for SqsContext {
+    fn from_request(req: &Request<Req, SqsContext>) -> Result<Self, Error> {
+        Ok(req.parts.context.clone())
+    }
+}
+
+/// Errors that can occur while talking to SQS
+#[derive(thiserror::Error, Debug)]
+pub enum SqsError {
+    /// The request to SQS failed
+    #[error("SQS request failed: {0}")]
+    Sqs(#[from] Box<aws_sdk_sqs::Error>),
+
+    /// The job could not be encoded or decoded
+    #[error("Could not encode or decode the job: {0}")]
+    Codec(BoxDynError),
+
+    /// SQS caps message delays at 15 minutes
+    #[error("A delay of {0:?} is longer than the 15 minutes SQS allows")]
+    DelayTooLong(Duration),
+
+    /// A received message lacked a field the backend relies on
+    #[error("The message has no {0}")]
+    MissingField(&'static str),
+}
+
+fn sqs_error<E: Into<aws_sdk_sqs::Error>>(err: E) -> SqsError {
+    SqsError::Sqs(Box::new(err.into()))
+}
+
+/// Config for a [SqsStorage]
+#[derive(Clone, Debug)]
+pub struct Config {
+    queue_url: String,
+    dead_letter_queue_url: Option<String>,
+    wait_time: Duration,
+    buffer_size: usize,
+    visibility_timeout: Duration,
+    keep_alive: Duration,
+    max_attempts: usize,
+}
+
+impl Config {
+    /// Create a config for the queue at `queue_url`
+    pub fn new(queue_url: impl Into<String>) -> Self {
+        Self {
+            queue_url: queue_url.into(),
+            dead_letter_queue_url: None,
+            wait_time: MAX_WAIT_TIME,
+            buffer_size: MAX_BATCH,
+            visibility_timeout: Duration::from_secs(60),
+            keep_alive: Duration::from_secs(30),
+            max_attempts: 25,
+        }
+    }
+
+    /// Get the url of the queue
+    pub fn get_queue_url(&self) -> &str {
+        &self.queue_url
+    }
+
+    /// Get the url of the dead-letter queue
+    pub fn get_dead_letter_queue_url(&self) -> Option<&str> {
+        self.dead_letter_queue_url.as_deref()
+    }
+
+    /// Get how long a receive waits for messages
+    pub fn get_wait_time(&self) -> &Duration {
+        &self.wait_time
+    }
+
+    /// Get the number of messages to receive at once
+    pub fn get_buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Get how long a received message is leased to the worker
+    pub fn get_visibility_timeout(&self) -> &Duration {
+        &self.visibility_timeout
+    }
+
+    /// Get how often the leases of running jobs are extended
+    pub fn get_keep_alive(&self) -> &Duration {
+        &self.keep_alive
+    }
+
+    /// Get the max attempts
+    pub fn get_max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Forward jobs that are aborted or run out of attempts to the queue at `url`
+    ///
+    /// Without it those jobs are deleted. A redrive policy on the queue itself still applies
+    /// to messages whose receive count exceeds its own limit.
+    pub fn set_dead_letter_queue_url(mut self, url: impl Into<String>) -> Self {
+        self.dead_letter_queue_url = Some(url.into());
+        self
+    }
+
+    /// Set how long a receive waits for messages, capped at the 20 seconds SQS allows
+    pub fn set_wait_time(mut self, wait_time: Duration) -> Self {
+        self.wait_time = wait_time.min(MAX_WAIT_TIME);
+        self
+    }
+
+    /// Set the number of messages to receive at once, between 1 and the 10 SQS allows
+    pub fn set_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.clamp(1, MAX_BATCH);
+        self
+    }
+
+    /// Set how long a received message is hidden from other workers
+    ///
+    /// Keep it comfortably above the keep-alive so a lease is extended before it runs out.
+    pub fn set_visibility_timeout(mut self, visibility_timeout: Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// Set how often the leases of running jobs are extended
+    pub fn set_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set the max attempts
+    pub fn set_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+/// Represents a [Backend] that uses an SQS queue
+pub struct SqsStorage<T, C = JsonCodec<String>> {
+    client: Client,
+    config: Config,
+    controller: Controller,
+    /// Receipt handles of the messages being worked on, keyed by message id
+    leases: Arc<Mutex<HashMap<String, String>>>,
+    job_type: PhantomData<T>,
+    codec: PhantomData<C>,
+}
+
+impl<T, C> fmt::Debug for SqsStorage<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqsStorage")
+            .field("client", &self.client)
+            .field("config", &self.config)
+            .field("job_type", &std::any::type_name::<T>())
+            .field("codec", &std::any::type_name::<C>())
+            .finish()
+    }
+}
+
+impl<T, C> Clone for SqsStorage<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            config: self.config.clone(),
+            controller: self.controller.clone(),
+            leases: self.leases.clone(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<T, C> SqsStorage<T, C> {
+    /// Create a storage for the queue in `config`
+    pub fn new(client: Client, config: Config) -> Self {
+        Self {
+            client,
+            config,
+            controller: Controller::new(),
+            leases: Arc::default(),
+            job_type: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// Get the config used by the storage
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Keep the message hidden from other workers for `by` from now
+    pub async fn extend_lease(&self, ctx: &SqsContext, by: Duration) -> Result<(), SqsError> {
+        let receipt_handle = ctx
+            .receipt_handle()
+            .ok_or(SqsError::MissingField("receipt handle"))?;
+        self.change_visibility(receipt_handle, by).await
+    }
+
+    async fn change_visibility(&self, receipt_handle: &str, by: Duration) -> Result<(), SqsError> {
+        self.client
+            .change_message_visibility()
+            .queue_url(&self.config.queue_url)
+            .receipt_handle(receipt_handle)
+            .visibility_timeout(seconds(by))
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, receipt_handle: &str) -> Result<(), SqsError> {
+        self.client
+            .delete_message()
+            .queue_url(&self.config.queue_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        Ok(())
+    }
+
+    /// Extends the lease of every message being worked on
+    async fn keep_alive(&self) {
+        let leases: Vec<String> = self
+            .leases
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect();
+        for receipt_handle in leases {
+            if let Err(e) = self
+                .change_visibility(&receipt_handle, self.config.visibility_timeout)
+                .await
+            {
+                error!("Could not extend the lease of an SQS message: {e}");
+            }
+        }
+    }
+}
+
+impl<T, C> SqsStorage<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec<Compact = String>,
+{
+    /// Push a job to the queue
+    pub async fn push(&mut self, job: T) -> Result<Parts<SqsContext>, SqsError> {
+        self.send(Request::new(job), Duration::ZERO).await
+    }
+
+    /// Push a job that becomes visible at `on`, a unix timestamp in seconds
+    ///
+    /// SQS delays messages by at most 15 minutes, so `on` must not be further away than that.
+    pub async fn schedule(&mut self, job: T, on: i64) -> Result<Parts<SqsContext>, SqsError> {
+        let delay = delay_until(on, now())?;
+        self.send(Request::new(job), delay).await
+    }
+
+    async fn send(
+        &self,
+        req: Request<T, SqsContext>,
+        delay: Duration,
+    ) -> Result<Parts<SqsContext>, SqsError> {
+        if delay > MAX_DELAY {
+            return Err(SqsError::DelayTooLong(delay));
+        }
+        let body = C::encode(&req).map_err(|e| SqsError::Codec(e.into()))?;
+        self.client
+            .send_message()
+            .queue_url(&self.config.queue_url)
+            .message_body(body)
+            .delay_seconds(seconds(delay))
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        Ok(req.parts)
+    }
+
+    async fn receive(&self, max: usize) -> Result<Vec<Request<T, SqsContext>>, SqsError> {
+        let output = self
+            .client
+            .receive_message()
+            .queue_url(&self.config.queue_url)
+            .max_number_of_messages(max as i32)
+            .wait_time_seconds(seconds(self.config.wait_time))
+            .visibility_timeout(seconds(self.config.visibility_timeout))
+            .message_system_attribute_names(MessageSystemAttributeName::ApproximateReceiveCount)
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        output
+            .messages()
+            .iter()
+            .map(decode_message::<T, C>)
+            .collect()
+    }
+}
+
+/// Decodes a received message, taking the attempt from its receive count
+fn decode_message<T, C>(message: &Message) -> Result<Request<T, SqsContext>, SqsError>
+where
+    T: DeserializeOwned,
+    C: Codec<Compact = String>,
+{
+    let body = message.body().ok_or(SqsError::MissingField("body"))?;
+    let mut req: Request<T, SqsContext> =
+        C::decode(body.to_owned()).map_err(|e| SqsError::Codec(e.into()))?;
+    let receive_count = message
+        .attributes()
+        .and_then(|attributes| attributes.get(&MessageSystemAttributeName::ApproximateReceiveCount))
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1);
+    req.parts.attempt = Attempt::new_with_value(receive_count);
+    req.parts.context = SqsContext {
+        message_id: message.message_id().map(ToOwned::to_owned),
+        receipt_handle: Some(
+            message
+                .receipt_handle()
+                .ok_or(SqsError::MissingField("receipt handle"))?
+                .to_owned(),
+        ),
+        body: Some(body.to_owned()),
+    };
+    Ok(req)
+}
+
+/// The delay until `on`, both unix timestamps in seconds
+fn delay_until(on: i64, now: i64) -> Result<Duration, SqsError> {
+    let delay = Duration::from_secs(on.saturating_sub(now).max(0) as u64);
+    if delay > MAX_DELAY {
+        return Err(SqsError::DelayTooLong(delay));
+    }
+    Ok(delay)
+}
+
+fn seconds(duration: Duration) -> i32 {
+    duration.as_secs().try_into().unwrap_or(i32::MAX)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl<T, C, Res> Backend<Request<T, SqsContext>, Res> for SqsStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    C: Codec<Compact = String> + Send + Sync + 'static,
+    Res: Send + Sync,
+{
+    type Stream = BackendStream<RequestStream<Request<T, SqsContext>>>;
+
+    type Layer = AckLayer<Self, T, SqsContext, Res>;
+
+    fn poll<Svc>(self, _worker: &Worker<Context>) -> Poller<Self::Stream, Self::Layer> {
+        let storage = self.clone();
+        let stream = futures::stream::unfold(VecDeque::new(), move |mut received| {
+            let storage = storage.clone();
+            async move {
+                if received.is_empty() {
+                    match storage.receive(storage.config.buffer_size).await {
+                        Ok(jobs) => received.extend(jobs),
+                        Err(e) => {
+                            return Some((Err(Error::SourceError(Arc::new(Box::new(e)))), received))
+                        }
+                    }
+                }
+                let Some(req) = received.pop_front() else {
+                    return Some((Ok(None), received));
+                };
+                if let (Some(id), Some(receipt_handle)) = (
+                    req.parts.context.message_id(),
+                    req.parts.context.receipt_handle(),
+                ) {
+                    storage
+                        .leases
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(id.to_owned(), receipt_handle.to_owned());
+                }
+                Some((Ok(Some(req)), received))
+            }
+        })
+        .boxed();
+        let keep_alive = {
+            let storage = self.clone();
+            async move {
+                let mut ticks = apalis_core::interval::interval(storage.config.keep_alive);
+                while ticks.next().await.is_some() {
+                    storage.keep_alive().await;
+                }
+            }
+            .boxed()
+        };
+        Poller::new_with_layer(
+            BackendStream::new(stream, self.controller.clone()),
+            keep_alive,
+            AckLayer::new(self),
+        )
+    }
+}
+
+impl<T, C, Res> Ack<T, Res> for SqsStorage<T, C>
+where
+    T: Send + Sync,
+    C: Send + Sync,
+    Res: Send + Sync,
+{
+    type Context = SqsContext;
+
+    type AckError = SqsError;
+
+    async fn ack(&mut self, ctx: &SqsContext, res: &Response<Res>) -> Result<(), SqsError> {
+        let receipt_handle = ctx
+            .receipt_handle()
+            .ok_or(SqsError::MissingField("receipt handle"))?;
+        if let Some(id) = ctx.message_id() {
+            self.leases
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(id);
+        }
+        let dead = match &res.inner {
+            Ok(_) => false,
+            Err(Error::Abort(_)) => true,
+            Err(_) => res.attempt.current() >= self.config.max_attempts,
+        };
+        if res.is_failure() && !dead {
+            // Visible again right away, for the next attempt
+            return self.change_visibility(receipt_handle, Duration::ZERO).await;
+        }
+        if let (true, Some(dead_letter_queue_url), Some(body)) =
+            (dead, &self.config.dead_letter_queue_url, &ctx.body)
+        {
+            self.client
+                .send_message()
+                .queue_url(dead_letter_queue_url)
+                .message_body(body)
+                .send()
+                .await
+                .map_err(sqs_error)?;
+        }
+        self.delete(receipt_handle).await
+    }
+}
+
+impl<T, C> MessageQueue<T> for SqsStorage<T, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+    C: Codec<Compact = String> + Send + Sync,
+{
+    type Error = SqsError;
+
+    async fn enqueue(&mut self, message: T) -> Result<(), SqsError> {
+        self.push(message).await?;
+        Ok(())
+    }
+
+    async fn dequeue(&mut self) -> Result<Option<T>, SqsError> {
+        let Some(req) = self.receive(1).await?.pop() else {
+            return Ok(None);
+        };
+        if let Some(receipt_handle) = req.parts.context.receipt_handle() {
+            self.delete(receipt_handle).await?;
+        }
+        Ok(Some(req.args))
+    }
+
+    async fn size(&mut self) -> Result<usize, SqsError> {
+        let output = self
+            .client
+            .get_queue_attributes()
+            .queue_url(&self.config.queue_url)
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        Ok(output
+            .attributes()
+            .and_then(|attributes| attributes.get(&QueueAttributeName::ApproximateNumberOfMessages))
+            .and_then(|count| count.parse().ok())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Email {
+        to: String,
+    }
+
+    #[test]
+    fn test_decode_message_takes_attempt_and_lease_from_sqs() {
+        let req: Request<Email, SqsContext> = Request::new(Email {
+            to: "test@example.com".to_owned(),
+        });
+        let body = JsonCodec::<String>::encode(&req).unwrap();
+        let message = Message::builder()
+            .message_id("message-1")
+            .receipt_handle("receipt-1")
+            .body(body.clone())
+            .attributes(MessageSystemAttributeName::ApproximateReceiveCount, "3")
+            .build();
+
+        let decoded = decode_message::<Email, JsonCodec<String>>(&message).unwrap();
+        assert_eq!(decoded.args, req.args);
+        assert_eq!(decoded.parts.task_id, req.parts.task_id);
+        assert_eq!(decoded.parts.attempt.current(), 3);
+        assert_eq!(decoded.parts.context.message_id(), Some("message-1"));
+        assert_eq!(decoded.parts.context.receipt_handle(), Some("receipt-1"));
+        assert_eq!(decoded.parts.context.body.as_deref(), Some(body.as_str()));
+
+        let unleased = Message::builder().body(body).build();
+        assert!(matches!(
+            decode_message::<Email, JsonCodec<String>>(&unleased),
+            Err(SqsError::MissingField("receipt handle"))
+        ));
+    }
+
+    #[test]
+    fn test_schedule_delay_is_bounded_by_sqs() {
+        assert_eq!(delay_until(1_000, 1_060).unwrap(), Duration::ZERO);
+        assert_eq!(delay_until(1_900, 1_000).unwrap(), MAX_DELAY);
+        assert!(matches!(
+            delay_until(1_901, 1_000),
+            Err(SqsError::DelayTooLong(delay)) if delay == Duration::from_secs(901)
+        ));
+    }
+
+    #[test]
+    fn test_config_clamps_to_sqs_limits() {
+        let config = Config::new("queue")
+            .set_wait_time(Duration::from_secs(60))
+            .set_buffer_size(100);
+        assert_eq!(config.get_wait_time(), &MAX_WAIT_TIME);
+        assert_eq!(config.get_buffer_size(), MAX_BATCH);
+        assert_eq!(Config::new("queue").set_buffer_size(0).get_buffer_size(), 1);
+    }
+}
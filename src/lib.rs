@@ -66,9 +66,16 @@
 /// apalis fully supports middleware via [`Layer`](https://docs.rs/tower/latest/tower/trait.Layer.html)
 pub mod layers;
 
+#[cfg(feature = "signal")]
+mod run;
+#[cfg(feature = "signal")]
+pub use run::{run, Runner, DEFAULT_SHUTDOWN_TIMEOUT};
+
 /// Common imports
 pub mod prelude {
     pub use crate::layers::WorkerBuilderExt;
+    #[cfg(feature = "signal")]
+    pub use crate::Runner;
     pub use apalis_core::{
         backend::Backend,
         backend::BackendExpose,
@@ -79,7 +86,7 @@ pub mod prelude {
         data::Extensions,
         error::{BoxDynError, Error},
         layers::extensions::{AddExtension, Data},
-        memory::{MemoryStorage, MemoryWrapper},
+        memory::MemoryStorage,
         monitor::Monitor,
         mq::MessageQueue,
         notify::Notify,
@@ -91,6 +98,7 @@ pub mod prelude {
         service_fn::{service_fn, FromRequest, ServiceFn},
         storage::Storage,
         task::attempt::Attempt,
+        task::cancellation::CancellationToken,
         task::task_id::TaskId,
         worker::{Context, Event, Ready, Worker, WorkerError, WorkerId},
     };
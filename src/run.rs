@@ -0,0 +1,186 @@
+use std::{future::Future, time::Duration};
+
+use apalis_core::{
+    backend::Backend,
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    error::BoxDynError,
+    monitor::Monitor,
+    request::Request,
+    service_fn::ServiceFn,
+};
+use serde::Serialize;
+use tower::{Layer, Service};
+
+/// The grace period given to in-flight jobs once a shutdown starts, unless configured
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `handler` against every job in `backend` until the process is asked to stop.
+///
+/// This is the shortest path to a running worker: it registers a single worker on a [`Monitor`],
+/// acks or retries each job through the backend, and returns once a SIGTERM or SIGINT (Ctrl+C)
+/// has been received and in-flight jobs have drained, or [`DEFAULT_SHUTDOWN_TIMEOUT`] has passed.
+/// Use [`Runner`] to name the worker or change the grace period.
+///
+/// ```rust,no_run
+/// use apalis::prelude::*;
+/// use apalis_sql::sqlite::{SqlitePool, SqliteStorage};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, Deserialize, Serialize)]
+/// struct Email {
+///     to: String,
+/// }
+///
+/// async fn send_email(email: Email) -> Result<(), Error> {
+///     Ok(())
+/// }
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+///     SqliteStorage::setup(&pool).await.unwrap();
+///     apalis::run(SqliteStorage::<Email>::new(pool), send_email).await
+/// }
+/// ```
+pub async fn run<B, F, Req, Ctx, Res, FnArgs>(backend: B, handler: F) -> std::io::Result<()>
+where
+    ServiceFn<F, Req, Ctx, FnArgs>: Service<Request<Req, Ctx>, Response = Res> + Send + Sync + 'static,
+    <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Future: Send,
+    <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Error:
+        Send + Sync + 'static + Into<BoxDynError>,
+    B: Backend<Request<Req, Ctx>, Res> + Send + 'static,
+    B::Stream: Unpin + Send + 'static,
+    B::Layer: Layer<ServiceFn<F, Req, Ctx, FnArgs>> + Send,
+    <B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service:
+        Service<Request<Req, Ctx>, Response = Res> + Send,
+    <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<Request<Req, Ctx>>>::Future:
+        Send,
+    <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<Request<Req, Ctx>>>::Error:
+        Send + Sync + Into<BoxDynError>,
+    Req: Send + Sync + 'static,
+    Ctx: Send + Sync + 'static,
+    Res: Send + Sync + Serialize + 'static,
+{
+    Runner::default().run(backend, handler).await
+}
+
+/// Configures and runs a single worker, see [`run`]
+#[derive(Debug, Clone)]
+pub struct Runner {
+    name: String,
+    shutdown_timeout: Duration,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new("apalis")
+    }
+}
+
+impl Runner {
+    /// Creates a runner whose worker is identified by `name`
+    pub fn new<T: AsRef<str>>(name: T) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+
+    /// Sets how long in-flight jobs may keep running once a shutdown starts
+    pub fn shutdown_timeout(mut self, duration: Duration) -> Self {
+        self.shutdown_timeout = duration;
+        self
+    }
+
+    /// Runs `handler` against `backend` until SIGTERM or SIGINT, then drains in-flight jobs
+    pub async fn run<B, F, Req, Ctx, Res, FnArgs>(
+        self,
+        backend: B,
+        handler: F,
+    ) -> std::io::Result<()>
+    where
+        ServiceFn<F, Req, Ctx, FnArgs>:
+            Service<Request<Req, Ctx>, Response = Res> + Send + Sync + 'static,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Future: Send,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Error:
+            Send + Sync + 'static + Into<BoxDynError>,
+        B: Backend<Request<Req, Ctx>, Res> + Send + 'static,
+        B::Stream: Unpin + Send + 'static,
+        B::Layer: Layer<ServiceFn<F, Req, Ctx, FnArgs>> + Send,
+        <B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service:
+            Service<Request<Req, Ctx>, Response = Res> + Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Future: Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Error: Send + Sync + Into<BoxDynError>,
+        Req: Send + Sync + 'static,
+        Ctx: Send + Sync + 'static,
+        Res: Send + Sync + Serialize + 'static,
+    {
+        self.monitor(backend, handler).run().await
+    }
+
+    /// Like [`Runner::run`], but shuts down when `signal` resolves instead of on SIGTERM or SIGINT
+    pub async fn run_with_signal<B, F, Req, Ctx, Res, FnArgs, S>(
+        self,
+        backend: B,
+        handler: F,
+        signal: S,
+    ) -> std::io::Result<()>
+    where
+        ServiceFn<F, Req, Ctx, FnArgs>:
+            Service<Request<Req, Ctx>, Response = Res> + Send + Sync + 'static,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Future: Send,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Error:
+            Send + Sync + 'static + Into<BoxDynError>,
+        B: Backend<Request<Req, Ctx>, Res> + Send + 'static,
+        B::Stream: Unpin + Send + 'static,
+        B::Layer: Layer<ServiceFn<F, Req, Ctx, FnArgs>> + Send,
+        <B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service:
+            Service<Request<Req, Ctx>, Response = Res> + Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Future: Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Error: Send + Sync + Into<BoxDynError>,
+        Req: Send + Sync + 'static,
+        Ctx: Send + Sync + 'static,
+        Res: Send + Sync + Serialize + 'static,
+        S: Send + Future<Output = std::io::Result<()>>,
+    {
+        self.monitor(backend, handler).run_with_signal(signal).await
+    }
+
+    fn monitor<B, F, Req, Ctx, Res, FnArgs>(self, backend: B, handler: F) -> Monitor
+    where
+        ServiceFn<F, Req, Ctx, FnArgs>:
+            Service<Request<Req, Ctx>, Response = Res> + Send + Sync + 'static,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Future: Send,
+        <ServiceFn<F, Req, Ctx, FnArgs> as Service<Request<Req, Ctx>>>::Error:
+            Send + Sync + 'static + Into<BoxDynError>,
+        B: Backend<Request<Req, Ctx>, Res> + Send + 'static,
+        B::Stream: Unpin + Send + 'static,
+        B::Layer: Layer<ServiceFn<F, Req, Ctx, FnArgs>> + Send,
+        <B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service:
+            Service<Request<Req, Ctx>, Response = Res> + Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Future: Send,
+        <<B::Layer as Layer<ServiceFn<F, Req, Ctx, FnArgs>>>::Service as Service<
+            Request<Req, Ctx>,
+        >>::Error: Send + Sync + Into<BoxDynError>,
+        Req: Send + Sync + 'static,
+        Ctx: Send + Sync + 'static,
+        Res: Send + Sync + Serialize + 'static,
+    {
+        let worker = WorkerBuilder::new(&self.name)
+            .backend(backend)
+            .build_fn(handler);
+        Monitor::new()
+            .register(worker)
+            .shutdown_timeout(self.shutdown_timeout)
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use apalis::prelude::*;
+use apalis_sql::sqlite::{SqlitePool, SqliteStorage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Job {
+    id: usize,
+}
+
+#[tokio::test]
+async fn run_processes_jobs_and_drains_on_signal() {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    SqliteStorage::setup(&pool).await.unwrap();
+    let mut storage: SqliteStorage<Job> = SqliteStorage::new(pool);
+    let mut ids = Vec::new();
+    for id in 0..5 {
+        ids.push(storage.push(Job { id }).await.unwrap().task_id);
+    }
+
+    let processed = Arc::new(AtomicUsize::new(0));
+    let handler = {
+        let processed = processed.clone();
+        move |job: Job| {
+            let processed = processed.clone();
+            async move {
+                assert!(job.id < 5);
+                processed.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Error>(())
+            }
+        }
+    };
+    // Stands in for SIGTERM once every job has been handled
+    let signal = {
+        let processed = processed.clone();
+        async move {
+            while processed.load(Ordering::SeqCst) < 5 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        }
+    };
+
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        Runner::new("run-test")
+            .shutdown_timeout(Duration::from_secs(5))
+            .run_with_signal(storage.clone(), handler, signal),
+    )
+    .await
+    .expect("runner did not shut down")
+    .unwrap();
+
+    assert_eq!(processed.load(Ordering::SeqCst), 5);
+    for id in ids {
+        let job = storage.fetch_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(job.parts.context.status(), &State::Done);
+    }
+}
+
+#[tokio::test]
+async fn run_keeps_job_acked_during_shutdown() {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    SqliteStorage::setup(&pool).await.unwrap();
+    let mut storage: SqliteStorage<Job> = SqliteStorage::new(pool);
+    let id = storage.push(Job { id: 0 }).await.unwrap().task_id;
+
+    let started = Arc::new(AtomicUsize::new(0));
+    let handler = {
+        let started = started.clone();
+        move |_: Job| {
+            let started = started.clone();
+            async move {
+                started.fetch_add(1, Ordering::SeqCst);
+                // Still running when the shutdown starts, so the ack lands while draining
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                Ok::<_, Error>(())
+            }
+        }
+    };
+    let signal = {
+        let started = started.clone();
+        async move {
+            while started.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        }
+    };
+
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        Runner::new("run-test")
+            .shutdown_timeout(Duration::from_secs(5))
+            .run_with_signal(storage.clone(), handler, signal),
+    )
+    .await
+    .expect("runner did not shut down")
+    .unwrap();
+
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    let job = storage.fetch_by_id(&id).await.unwrap().unwrap();
+    assert_eq!(job.parts.context.status(), &State::Done);
+    assert_eq!(
+        job.parts.context.lock_by(),
+        &Some(WorkerId::new("run-test"))
+    );
+}